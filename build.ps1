@@ -50,6 +50,11 @@ function CreateArchive([string] $ArchiveName, [string] $Path) {
     # Generate checksum for archive
     $checksum = (Get-FileHash -Path $archivePath -Algorithm SHA256).Hash.ToString()
     Set-Content -Path "$archivePath.sha256.txt" -Encoding utf8NoBOM -Value $checksum -NoNewline
+
+    # Sign archive for fersk self-update, which writes $archivePath.sig
+    if ($env:FERSK_SIGNING_KEY) {
+      Cmd "Signing archive" { ssh-keygen -Y sign -n fersk-release -f $env:FERSK_SIGNING_KEY $archivePath }
+    }
   } finally {
     Pop-Location
   }
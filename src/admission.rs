@@ -0,0 +1,86 @@
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+use serde_derive::{Deserialize, Serialize};
+use sysinfo::{RefreshKind, System, SystemExt};
+use tracing::info;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Limits on system load, checked before running the command
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct AdmissionConfig {
+    /// Maximum 1-minute load average
+    pub max_load_average: Option<f64>,
+    /// Minimum available memory in MiB
+    pub min_free_memory: Option<u64>,
+    /// What to do when the machine is too busy
+    pub policy: AdmissionPolicy,
+    /// Maximum number of seconds to wait before giving up
+    pub max_wait: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AdmissionPolicy {
+    #[default]
+    Wait,
+    Refuse,
+}
+
+/// Check if the machine is too busy, returning the reason if it is
+fn check(cfg: &AdmissionConfig, sys: &mut System) -> Option<String> {
+    if let Some(max_load_average) = cfg.max_load_average {
+        let load = sys.load_average().one;
+
+        if load > max_load_average {
+            return Some(format!("load average {load:.2} exceeds {max_load_average:.2}"));
+        }
+    }
+
+    if let Some(min_free_memory) = cfg.min_free_memory {
+        sys.refresh_memory();
+        let free = sys.available_memory() / 1024 / 1024;
+
+        if free < min_free_memory {
+            return Some(format!(
+                "{free} MiB available memory is less than {min_free_memory} MiB"
+            ));
+        }
+    }
+
+    None
+}
+
+/// Wait until the machine is not too busy to run, or fail if the policy is to refuse
+pub fn admit(cfg: &AdmissionConfig) -> Result<(), anyhow::Error> {
+    if cfg.max_load_average.is_none() && cfg.min_free_memory.is_none() {
+        return Ok(());
+    }
+
+    let mut sys = System::new_with_specifics(RefreshKind::new().with_memory());
+    let started = Instant::now();
+    let mut waiting = false;
+
+    while let Some(reason) = check(cfg, &mut sys) {
+        if let AdmissionPolicy::Refuse = cfg.policy {
+            return Err(anyhow!("Machine is too busy: {reason}"));
+        }
+
+        if let Some(max_wait) = cfg.max_wait {
+            if started.elapsed() >= Duration::from_secs(max_wait) {
+                return Err(anyhow!("Machine is still too busy after waiting {max_wait}s: {reason}"));
+            }
+        }
+
+        if !waiting {
+            info!("Machine is too busy ({reason}). Waiting...");
+            waiting = true;
+        }
+
+        std::thread::sleep(CHECK_INTERVAL);
+    }
+
+    Ok(())
+}
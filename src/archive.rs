@@ -0,0 +1,278 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Format of archives created by fersk
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ArchiveFormat {
+    #[default]
+    TarZst,
+    TarGz,
+    Zip,
+}
+
+impl ArchiveFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::TarZst => "tar.zst",
+            Self::TarGz => "tar.gz",
+            Self::Zip => "zip",
+        }
+    }
+}
+
+/// How archives are created
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct ArchiveConfig {
+    pub format: ArchiveFormat,
+    /// Compression level. The default of the format is used if not specified.
+    pub level: Option<i32>,
+    /// Store files with identical content only once (tar formats only)
+    pub dedup: bool,
+}
+
+/// What went into an archive
+#[derive(Debug, Default)]
+pub struct ArchiveSummary {
+    pub files: usize,
+    /// Files stored as links to an identical file earlier in the archive
+    pub deduplicated: usize,
+}
+
+/// Create an archive from pairs of name in the archive and local path.
+/// Directories are added with everything in them.
+pub fn create(
+    cfg: &ArchiveConfig,
+    archive_path: &Path,
+    entries: &[(String, PathBuf)],
+) -> Result<ArchiveSummary, anyhow::Error> {
+    let mut files = Vec::new();
+    for (name, path) in entries {
+        collect_files(name, path, &mut files).with_context(|| format!("Error reading {}", path.display()))?;
+    }
+
+    let file =
+        File::create(archive_path).with_context(|| format!("Error creating archive: {}", archive_path.display()))?;
+    let writer = BufWriter::new(file);
+
+    let summary = match cfg.format {
+        ArchiveFormat::TarZst => {
+            let mut encoder = zstd::Encoder::new(writer, cfg.level.unwrap_or(zstd::DEFAULT_COMPRESSION_LEVEL))?;
+            let summary = write_tar(&mut encoder, &files, cfg.dedup)?;
+            encoder.finish()?.flush()?;
+            summary
+        }
+        ArchiveFormat::TarGz => {
+            let level = cfg.level.map_or(flate2::Compression::default(), gzip_level);
+            let mut encoder = flate2::write::GzEncoder::new(writer, level);
+            let summary = write_tar(&mut encoder, &files, cfg.dedup)?;
+            encoder.finish()?.flush()?;
+            summary
+        }
+        ArchiveFormat::Zip => write_zip(writer, &files, cfg.level)?,
+    };
+
+    Ok(summary)
+}
+
+/// Collect the files to archive under a name, recursing into directories.
+/// Symbolic links are archived as links, and never followed, so they can't pull in files from elsewhere or loop.
+fn collect_files(name: &str, path: &Path, files: &mut Vec<(String, PathBuf)>) -> io::Result<()> {
+    // Fails early if it doesn't exist
+    if !std::fs::symlink_metadata(path)?.is_dir() {
+        files.push((name.to_owned(), path.to_owned()));
+
+        return Ok(());
+    }
+
+    let mut children: Vec<_> = std::fs::read_dir(path)?.collect::<Result<_, _>>()?;
+    children.sort_by_key(|entry| entry.file_name());
+
+    for child in children {
+        let child_name = format!("{name}/{}", child.file_name().to_string_lossy());
+        collect_files(&child_name, &child.path(), files)?;
+    }
+
+    Ok(())
+}
+
+fn write_tar<W: Write>(writer: W, files: &[(String, PathBuf)], dedup: bool) -> Result<ArchiveSummary, anyhow::Error> {
+    let mut builder = tar::Builder::new(writer);
+    builder.follow_symlinks(false);
+
+    let mut summary = ArchiveSummary::default();
+    let mut stored: HashMap<[u8; 32], &str> = HashMap::new();
+
+    for (name, path) in files {
+        summary.files += 1;
+
+        if dedup && path.symlink_metadata()?.is_file() {
+            let hash = hash_file(path)?;
+
+            if let Some(original) = stored.get(&hash) {
+                let mut header = tar::Header::new_gnu();
+                header.set_entry_type(tar::EntryType::Link);
+                header.set_size(0);
+                header.set_mode(0o644);
+                builder.append_link(&mut header, name, original)?;
+
+                summary.deduplicated += 1;
+                continue;
+            }
+
+            stored.insert(hash, name);
+        }
+
+        builder
+            .append_path_with_name(path, name)
+            .with_context(|| format!("Error archiving {}", path.display()))?;
+    }
+
+    builder.into_inner()?;
+
+    Ok(summary)
+}
+
+fn write_zip<W: Write + io::Seek>(
+    writer: W,
+    files: &[(String, PathBuf)],
+    level: Option<i32>,
+) -> Result<ArchiveSummary, anyhow::Error> {
+    let mut zip = zip::ZipWriter::new(writer);
+    let options = zip::write::FileOptions::default()
+        .compression_method(zip::CompressionMethod::Deflated)
+        .compression_level(level);
+
+    for (name, path) in files {
+        if path.symlink_metadata()?.is_symlink() {
+            let target = std::fs::read_link(path)?;
+            zip.add_symlink(name.as_str(), target.to_string_lossy(), options)?;
+
+            continue;
+        }
+
+        zip.start_file(name.as_str(), options)?;
+
+        let mut file = File::open(path).with_context(|| format!("Error archiving {}", path.display()))?;
+        io::copy(&mut file, &mut zip)?;
+    }
+
+    zip.finish()?.flush()?;
+
+    Ok(ArchiveSummary {
+        files: files.len(),
+        deduplicated: 0,
+    })
+}
+
+fn gzip_level(level: i32) -> flate2::Compression {
+    flate2::Compression::new(level.clamp(0, 9) as u32)
+}
+
+fn hash_file(path: &Path) -> io::Result<[u8; 32]> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+
+    Ok(hasher.finalize().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stores_identical_files_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let content = dir.path().join("content");
+        std::fs::create_dir(&content).unwrap();
+
+        for (name, data) in [("a.txt", "same"), ("b.txt", "same"), ("c.txt", "different")] {
+            std::fs::write(content.join(name), data).unwrap();
+        }
+
+        let cfg = ArchiveConfig {
+            format: ArchiveFormat::TarGz,
+            level: None,
+            dedup: true,
+        };
+
+        let archive_path = dir.path().join("archive.tar.gz");
+        let summary = create(&cfg, &archive_path, &[("content".to_owned(), content)]).unwrap();
+
+        assert_eq!(summary.files, 3);
+        assert_eq!(summary.deduplicated, 1);
+
+        let decoder = flate2::read::GzDecoder::new(File::open(&archive_path).unwrap());
+        let entry_types: Vec<(String, tar::EntryType)> = tar::Archive::new(decoder)
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                let name = entry.path().unwrap().to_string_lossy().to_string();
+
+                (name, entry.header().entry_type())
+            })
+            .collect();
+
+        assert_eq!(
+            entry_types,
+            vec![
+                ("content/a.txt".to_owned(), tar::EntryType::Regular),
+                ("content/b.txt".to_owned(), tar::EntryType::Link),
+                ("content/c.txt".to_owned(), tar::EntryType::Regular),
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn archives_symlinks_as_links() {
+        let dir = tempfile::tempdir().unwrap();
+        let content = dir.path().join("content");
+        std::fs::create_dir(&content).unwrap();
+
+        std::fs::write(dir.path().join("secret.txt"), "secret").unwrap();
+        std::fs::write(content.join("a.txt"), "a").unwrap();
+        std::os::unix::fs::symlink("..", content.join("loop")).unwrap();
+        std::os::unix::fs::symlink("../secret.txt", content.join("secret.txt")).unwrap();
+
+        let cfg = ArchiveConfig::default();
+        let archive_path = dir.path().join("archive.tar.zst");
+        let summary = create(&cfg, &archive_path, &[("content".to_owned(), content.clone())]).unwrap();
+        assert_eq!(summary.files, 3);
+
+        let decoder = zstd::Decoder::new(File::open(&archive_path).unwrap()).unwrap();
+        let entry_types: Vec<tar::EntryType> = tar::Archive::new(decoder)
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().header().entry_type())
+            .collect();
+        assert_eq!(
+            entry_types,
+            vec![
+                tar::EntryType::Regular,
+                tar::EntryType::Symlink,
+                tar::EntryType::Symlink
+            ]
+        );
+
+        let cfg = ArchiveConfig {
+            format: ArchiveFormat::Zip,
+            ..Default::default()
+        };
+        let archive_path = dir.path().join("archive.zip");
+        create(&cfg, &archive_path, &[("content".to_owned(), content)]).unwrap();
+
+        let mut zip = zip::ZipArchive::new(File::open(&archive_path).unwrap()).unwrap();
+        let mut link = zip.by_name("content/secret.txt").unwrap();
+        assert!(link.unix_mode().is_some_and(|mode| mode & 0o170000 == 0o120000));
+        assert_eq!(io::read_to_string(&mut link).unwrap(), "../secret.txt");
+    }
+}
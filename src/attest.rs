@@ -0,0 +1,153 @@
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context};
+use chrono::{TimeZone, Utc};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::command;
+use crate::util;
+
+const STATEMENT_TYPE: &str = "https://in-toto.io/Statement/v1";
+const PREDICATE_TYPE: &str = "https://slsa.dev/provenance/v1";
+const BUILD_TYPE: &str = "https://github.com/forbjok/fersk/run/v1";
+const BUILDER_ID: &str = "https://github.com/forbjok/fersk";
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct AttestationConfig {
+    /// Sign attestations using cosign
+    pub sign: bool,
+    /// Key to sign with, in any form accepted by `cosign sign-blob --key`.
+    /// Sigstore keyless signing is used if not specified.
+    pub key: Option<String>,
+    /// Files produced by the command to record as subjects, relative to the working directory
+    pub outputs: Vec<PathBuf>,
+}
+
+/// Description of a successful run
+pub struct Provenance<'a> {
+    pub source_repository_path: &'a Path,
+    pub working_repository_path: &'a Path,
+    pub branch: &'a str,
+    pub commit: &'a str,
+    pub command: &'a [String],
+    pub run_id: &'a str,
+    pub started_at: u64,
+    pub finished_at: u64,
+    /// Environment variables fersk set or removed (None) for the command, on top of the inherited ones
+    pub env: &'a BTreeMap<String, Option<OsString>>,
+    /// Output files, relative to the working directory
+    pub outputs: &'a [PathBuf],
+}
+
+/// Write an in-toto statement with SLSA provenance for a run, and sign it if configured.
+/// The signature is written as a Sigstore bundle next to the statement.
+pub fn attest(cfg: &AttestationConfig, provenance: &Provenance, path: &Path) -> Result<(), anyhow::Error> {
+    let statement = provenance.statement()?;
+
+    util::create_parent_dir(path).with_context(|| "Error creating attestation directory")?;
+    std::fs::write(path, serde_json::to_string_pretty(&statement)?)
+        .with_context(|| format!("Error writing attestation: {}", path.display()))?;
+
+    if cfg.sign {
+        sign(cfg, path).with_context(|| format!("Error signing attestation: {}", path.display()))?;
+    }
+
+    Ok(())
+}
+
+impl Provenance<'_> {
+    fn statement(&self) -> Result<serde_json::Value, anyhow::Error> {
+        let mut subject = Vec::new();
+
+        for output in self.outputs {
+            let path = self.working_repository_path.join(output);
+            let digest =
+                util::hash::hash_file(&path).with_context(|| format!("Error hashing output: {}", path.display()))?;
+
+            subject.push(json!({
+                "name": output.to_string_lossy().replace('\\', "/"),
+                "digest": { "sha256": digest },
+            }));
+        }
+
+        Ok(json!({
+            "_type": STATEMENT_TYPE,
+            "subject": subject,
+            "predicateType": PREDICATE_TYPE,
+            "predicate": {
+                "buildDefinition": {
+                    "buildType": BUILD_TYPE,
+                    "externalParameters": {
+                        "command": self.command,
+                        "branch": self.branch,
+                    },
+                    "internalParameters": {
+                        "environmentDigest": { "sha256": environment_digest(self.env) },
+                    },
+                    "resolvedDependencies": [{
+                        "uri": format!("git+file://{}", self.source_repository_path.to_string_lossy().replace('\\', "/")),
+                        "digest": { "gitCommit": self.commit },
+                    }],
+                },
+                "runDetails": {
+                    "builder": {
+                        "id": BUILDER_ID,
+                        "version": { "fersk": env!("CARGO_PKG_VERSION") },
+                    },
+                    "metadata": {
+                        "invocationId": self.run_id,
+                        "startedOn": format_time(self.started_at),
+                        "finishedOn": format_time(self.finished_at),
+                    },
+                },
+            },
+        }))
+    }
+}
+
+/// Hash of the environment fersk gave the command, so differences between runs can be detected without exposing
+/// values. Inherited variables are left out, as they belong to whatever started fersk rather than the run.
+fn environment_digest(env: &BTreeMap<String, Option<OsString>>) -> String {
+    let data = env
+        .iter()
+        .map(|(key, value)| match value {
+            Some(value) => format!("{key}={}\0", value.to_string_lossy()),
+            None => format!("-{key}\0"),
+        })
+        .collect::<String>();
+
+    util::hash::hash_bytes(data.as_bytes())
+}
+
+fn format_time(timestamp: u64) -> String {
+    Utc.timestamp_opt(timestamp as i64, 0)
+        .single()
+        .map(|t| t.to_rfc3339())
+        .unwrap_or_default()
+}
+
+fn sign(cfg: &AttestationConfig, path: &Path) -> Result<(), anyhow::Error> {
+    let mut bundle_path = path.as_os_str().to_owned();
+    bundle_path.push(".sigstore.json");
+
+    let status = command::exec_command_timeout("cosign", None, true, |c| {
+        c.args(["sign-blob", "--yes"]);
+
+        if let Some(key) = &cfg.key {
+            c.arg("--key").arg(key);
+        }
+
+        c.arg("--bundle").arg(&bundle_path);
+        c.arg(path);
+    })?;
+
+    if !status.success() {
+        return Err(anyhow!("cosign failed with exit code {:?}", status.code()));
+    }
+
+    Ok(())
+}
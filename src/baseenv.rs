@@ -0,0 +1,161 @@
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::path::{Component, Path};
+
+use anyhow::{anyhow, Context};
+use serde_json::Value;
+
+use crate::envfile;
+use crate::runscript::{self, ScriptCommand};
+use crate::util::quote;
+
+/// Environment captured from the source repository (ex. with `direnv export json` or `nix print-dev-env --json`),
+/// as the base environment of the command. A None value removes the variable.
+#[derive(Debug, Default)]
+pub struct BaseEnv {
+    pub vars: BTreeMap<String, Option<String>>,
+}
+
+impl BaseEnv {
+    /// Capture the environment from a file or the output of a command.
+    /// A command line naming a file in the source repository (or an absolute path) reads that file.
+    /// Anything else is run as a command in the source repository.
+    pub fn capture(source: &ScriptCommand, source_path: &Path) -> Result<Self, anyhow::Error> {
+        let content = match source {
+            ScriptCommand::Shell(line) if source_path.join(line).is_file() => {
+                let path = source_path.join(line);
+
+                std::fs::read_to_string(&path)
+                    .with_context(|| format!("Error reading base environment: {}", path.display()))?
+            }
+            _ => {
+                let output = runscript::script_command(source, &[])?
+                    .current_dir(source_path)
+                    .output()
+                    .with_context(|| format!("Error executing base environment command: {}", describe(source)))?;
+
+                if !output.status.success() {
+                    return Err(anyhow!(
+                        "Base environment command failed: {}\n{}",
+                        describe(source),
+                        String::from_utf8_lossy(&output.stderr).trim_end()
+                    ));
+                }
+
+                String::from_utf8_lossy(&output.stdout).to_string()
+            }
+        };
+
+        Self::parse(&content).with_context(|| format!("Error parsing base environment from: {}", describe(source)))
+    }
+
+    /// Read the environment from a file named in the repository's .fersk.toml, relative to the source repository
+    pub fn from_project_file(file: &Path, source_path: &Path) -> Result<Self, anyhow::Error> {
+        if file.is_absolute() || file.components().any(|c| c == Component::ParentDir) {
+            return Err(anyhow!(
+                "Base environment file in .fersk.toml must be inside the repository: {}",
+                file.display()
+            ));
+        }
+
+        let path = source_path.join(file);
+
+        if !path.is_file() {
+            return Err(anyhow!(
+                "Base environment file not found: {}. Commands can only be given with --base-env-from \
+                 or in the fersk config.",
+                file.display()
+            ));
+        }
+
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("Error reading base environment: {}", path.display()))?;
+
+        Self::parse(&content).with_context(|| format!("Error parsing base environment from: {}", file.display()))
+    }
+
+    /// Parse a JSON object of variables (direnv), nix's JSON development environment, or dotenv-style lines
+    fn parse(content: &str) -> Result<Self, anyhow::Error> {
+        let content = content.trim();
+
+        if content.is_empty() {
+            return Ok(Self::default());
+        }
+
+        if !content.starts_with('{') {
+            let mut vars = BTreeMap::new();
+            envfile::parse(content, false, &mut vars)?;
+
+            return Ok(Self {
+                vars: vars.into_iter().map(|(k, v)| (k, Some(v))).collect(),
+            });
+        }
+
+        let Value::Object(object) = serde_json::from_str(content)? else {
+            return Err(anyhow!("Expected a JSON object"));
+        };
+
+        // nix print-dev-env --json: only exported variables end up in the environment
+        if let Some(Value::Object(variables)) = object.get("variables") {
+            let vars = variables
+                .iter()
+                .filter(|(_, v)| v["type"] == "exported")
+                .filter_map(|(k, v)| Some((k.clone(), Some(v["value"].as_str()?.to_owned()))))
+                .collect();
+
+            return Ok(Self { vars });
+        }
+
+        let mut vars = BTreeMap::new();
+
+        for (key, value) in object {
+            let value = match value {
+                Value::String(value) => Some(value),
+                Value::Null => None,
+                _ => return Err(anyhow!("Value of {key} is not a string")),
+            };
+
+            vars.insert(key, value);
+        }
+
+        Ok(Self { vars })
+    }
+
+    /// Get the PATH of the base environment, if it sets one
+    pub fn path(&self) -> Option<&OsStr> {
+        self.vars.get("PATH")?.as_deref().map(OsStr::new)
+    }
+}
+
+fn describe(source: &ScriptCommand) -> String {
+    match source {
+        ScriptCommand::Shell(line) => line.clone(),
+        ScriptCommand::Args(args) => quote::command(args),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_environment_formats() {
+        let direnv = BaseEnv::parse(r#"{ "GOPATH": "/go", "OLD": null }"#).unwrap();
+        assert_eq!(direnv.vars["GOPATH"].as_deref(), Some("/go"));
+        assert_eq!(direnv.vars["OLD"], None);
+
+        let nix = BaseEnv::parse(
+            r#"{ "variables": {
+                "PATH": { "type": "exported", "value": "/nix/store/x/bin" },
+                "shellHook": { "type": "var", "value": "echo" }
+            } }"#,
+        )
+        .unwrap();
+        assert_eq!(nix.path(), Some(OsStr::new("/nix/store/x/bin")));
+        assert!(!nix.vars.contains_key("shellHook"));
+
+        let dotenv = BaseEnv::parse("export JAVA_HOME=/opt/jdk\nMODE=dev\n").unwrap();
+        assert_eq!(dotenv.vars["JAVA_HOME"].as_deref(), Some("/opt/jdk"));
+        assert_eq!(dotenv.vars.len(), 2);
+    }
+}
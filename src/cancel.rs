@@ -0,0 +1,161 @@
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context};
+use clap::Args;
+use sysinfo::{Pid, PidExt};
+use thiserror::Error;
+
+use crate::config::Config;
+use crate::metadata::WorkMetadata;
+use crate::queue;
+use crate::util::{self, pid, quote};
+use crate::workroot::WorkRoot;
+
+/// How long a cancelled command gets to exit after being asked to terminate, before it is killed
+pub const GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+const WAIT_INTERVAL: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Error)]
+#[error("Run was cancelled.")]
+pub struct Cancelled;
+
+#[derive(Debug, Args)]
+pub struct CancelArgs {
+    #[clap(help = "Id of the run to cancel", required_unless_present = "list")]
+    run_id: Option<String>,
+    #[clap(long = "list", conflicts_with = "run_id", help = "List running and queued runs")]
+    list: bool,
+    #[clap(long = "no-wait", help = "Don't wait for the run to stop")]
+    no_wait: bool,
+}
+
+/// A run that can be cancelled
+struct ActiveRun {
+    run_id: String,
+    command: String,
+    queued: bool,
+}
+
+/// Fail if cancelling a run has been requested
+pub fn check(work_root: &WorkRoot, run_id: &str) -> Result<(), Cancelled> {
+    if work_root.cancel_path(run_id).exists() {
+        return Err(Cancelled);
+    }
+
+    Ok(())
+}
+
+/// Remove the request to cancel a run, once it has stopped
+pub fn clear(work_root: &WorkRoot, run_id: &str) {
+    let _ = std::fs::remove_file(work_root.cancel_path(run_id));
+}
+
+/// Cancel a running or queued run.
+/// The run is asked to stop, which it does by terminating its command and releasing its lock.
+pub fn cancel(cfg: &Config, args: CancelArgs) -> Result<(), anyhow::Error> {
+    let work_root = WorkRoot::from_config(cfg);
+    let runs = active_runs(&work_root)?;
+
+    if args.list {
+        for run in &runs {
+            let state = if run.queued { "queued" } else { "running" };
+            println!("{}  {state:<7}  {}", run.run_id, run.command);
+        }
+
+        return Ok(());
+    }
+
+    let run_id = args.run_id.unwrap_or_default();
+
+    let Some(run) = runs.iter().find(|r| r.run_id == run_id) else {
+        return Err(anyhow!("No running or queued run with id {run_id}."));
+    };
+
+    let cancel_path = work_root.cancel_path(&run_id);
+    util::create_parent_dir(&cancel_path).with_context(|| "Error creating cancel directory")?;
+    std::fs::write(&cancel_path, "").with_context(|| format!("Error writing {}", cancel_path.display()))?;
+
+    if args.no_wait {
+        println!("Requested cancelling run {run_id}.");
+        return Ok(());
+    }
+
+    // The run removes the request once it has stopped
+    let deadline = Instant::now() + GRACE_PERIOD * 2;
+
+    while cancel_path.exists() && is_alive(&run_id) {
+        if Instant::now() >= deadline {
+            return Err(anyhow!("Run {run_id} did not stop in time."));
+        }
+
+        std::thread::sleep(WAIT_INTERVAL);
+    }
+
+    // Runs that died without stopping normally leave the request behind
+    clear(&work_root, &run_id);
+
+    let what = if run.queued { "queued run" } else { "run" };
+    println!("Cancelled {what} {run_id}.");
+
+    Ok(())
+}
+
+/// Get all runs in progress or waiting for a lock
+fn active_runs(work_root: &WorkRoot) -> Result<Vec<ActiveRun>, anyhow::Error> {
+    let mut runs = Vec::new();
+
+    let metadata_dir = work_root.metadata_dir();
+
+    if metadata_dir.exists() {
+        for entry in std::fs::read_dir(&metadata_dir)? {
+            let path = entry?.path();
+
+            if !is_metadata_file(&path) {
+                continue;
+            }
+
+            let Some(metadata) = WorkMetadata::load(&path)? else {
+                continue;
+            };
+
+            let (Some(run_id), None) = (metadata.run_id, metadata.finished_at) else {
+                continue;
+            };
+
+            if is_alive(&run_id) {
+                runs.push(ActiveRun {
+                    run_id,
+                    command: quote::command(&metadata.command),
+                    queued: false,
+                });
+            }
+        }
+    }
+
+    runs.extend(queue::queued_runs(work_root).into_iter().map(|r| ActiveRun {
+        run_id: r.run_id,
+        command: r.command,
+        queued: true,
+    }));
+
+    Ok(runs)
+}
+
+/// Check if a path is the metadata file of a work directory, rather than one of the other files next to it
+fn is_metadata_file(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e == "json")
+        && path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .is_some_and(|s| !s.contains('.') && s.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+/// Check if the process of a run is still running. Run ids end with the PID.
+fn is_alive(run_id: &str) -> bool {
+    run_id
+        .rsplit_once('-')
+        .and_then(|(_, pid)| pid.parse::<u32>().ok())
+        .is_some_and(|pid| pid::process_exists(Pid::from_u32(pid)))
+}
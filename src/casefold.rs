@@ -0,0 +1,224 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+use anyhow::Context;
+use tracing::{info, warn};
+
+use crate::git::{Git, GitVersion};
+use crate::journal::Journal;
+
+/// Maximum number of paths listed in warnings
+const MAX_LISTED_PATHS: usize = 10;
+
+/// Repair a work tree left with phantom changes by case-only renames on a case-insensitive filesystem.
+///
+/// When a file is renamed by changing only its case, checking out the other side can leave the file on disk
+/// with its old name, which git then reports as modified (or deleted and untracked) forever.
+/// Paths whose name on disk differs from the committed one only in case are removed and checked out again,
+/// leaving any other changes for the cleanse.
+/// Commits containing paths that differ only in case can't be checked out correctly at all, which is reported.
+pub fn repair_case_renames(git: &Git, work_path: &Path, journal: &Journal) -> Result<(), anyhow::Error> {
+    let ignore_case = git
+        .get_config_all(work_path, "core.ignorecase")
+        .unwrap_or_default()
+        .last()
+        .is_some_and(|v| v == "true");
+
+    // git checkout --pathspec-from-file requires git 2.25
+    if !ignore_case || !GitVersion::at_least(2, 25) {
+        return Ok(());
+    }
+
+    let dirty = git.status(work_path, false).with_context(|| "Error getting status")?;
+
+    if dirty.is_empty() {
+        return Ok(());
+    }
+
+    let tracked = git
+        .list_files(work_path, "HEAD")
+        .with_context(|| "Error listing files")?;
+
+    let collisions = case_collisions(&tracked);
+    if !collisions.is_empty() {
+        let listed: Vec<String> = collisions
+            .iter()
+            .take(MAX_LISTED_PATHS)
+            .map(|paths| format!("  {}", paths.join(", ")))
+            .collect();
+
+        warn!(
+            "The checked out commit contains paths that differ only in case, which can't all exist on this \
+             case-insensitive filesystem. Rename them in the repository to fix this:\n{}",
+            listed.join("\n")
+        );
+
+        return Ok(());
+    }
+
+    let tracked_by_folded: BTreeMap<String, &String> = tracked.iter().map(|p| (p.to_lowercase(), p)).collect();
+
+    // Paths on disk whose name differs from the committed one only in case
+    let affected: BTreeSet<&String> = dirty
+        .iter()
+        .filter_map(|e| {
+            let tracked = *tracked_by_folded.get(&e.path.to_lowercase())?;
+            let on_disk = path_on_disk(work_path, &e.path)?;
+
+            (on_disk != *tracked && on_disk.to_lowercase() == tracked.to_lowercase()).then_some(tracked)
+        })
+        .collect();
+
+    if affected.is_empty() {
+        return Ok(());
+    }
+
+    // Files left with the old name are seen as untracked on case-sensitive filesystems
+    for entry in dirty.iter().filter(|e| e.is_untracked()) {
+        if tracked_by_folded
+            .get(&entry.path.to_lowercase())
+            .is_some_and(|tracked| affected.contains(tracked) && **tracked != entry.path)
+        {
+            std::fs::remove_file(work_path.join(&entry.path)).ok();
+        }
+    }
+
+    for path in &affected {
+        std::fs::remove_file(work_path.join(path)).ok();
+    }
+
+    let affected: Vec<String> = affected.into_iter().cloned().collect();
+
+    git.restore_from_head(work_path, &affected)
+        .with_context(|| "Error checking out paths affected by case-only renames")?;
+
+    journal.record(
+        "case-rename",
+        format!("removed and checked out {} path(s) again", affected.len()),
+    );
+
+    let remaining = git.status(work_path, false).with_context(|| "Error getting status")?;
+
+    if remaining.is_empty() {
+        info!("Repaired {} path(s) affected by case-only renames.", affected.len());
+    } else {
+        let listed: Vec<String> = remaining
+            .iter()
+            .take(MAX_LISTED_PATHS)
+            .map(|e| format!("  {} {}", e.status, e.path))
+            .collect();
+
+        warn!(
+            "The work directory still has changes after repairing case-only renames. \
+             Purge the work directory to recreate it:\n{}",
+            listed.join("\n")
+        );
+    }
+
+    Ok(())
+}
+
+/// Get the name a path actually has on disk, which can differ in case from the given one on a case-insensitive filesystem
+fn path_on_disk(work_path: &Path, path: &str) -> Option<String> {
+    let mut resolved: Vec<String> = Vec::new();
+
+    for component in path.trim_end_matches('/').split('/') {
+        let names: Vec<String> = std::fs::read_dir(work_path.join(resolved.join("/")))
+            .ok()?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .collect();
+
+        let folded = component.to_lowercase();
+        let name = names
+            .iter()
+            .find(|name| *name == component)
+            .or_else(|| names.iter().find(|name| name.to_lowercase() == folded))?;
+
+        resolved.push(name.clone());
+    }
+
+    Some(resolved.join("/"))
+}
+
+/// Find groups of paths that differ only in case, including paths of files in directories that do
+fn case_collisions(paths: &[String]) -> Vec<Vec<String>> {
+    let mut groups: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+
+    for path in paths {
+        // Directories collide as well (ex. Docs/a.md and docs/b.md)
+        let mut prefix = String::new();
+
+        for (i, component) in path.split('/').enumerate() {
+            if i > 0 {
+                prefix.push('/');
+            }
+
+            prefix.push_str(component);
+            groups.entry(prefix.to_lowercase()).or_default().insert(prefix.clone());
+        }
+    }
+
+    let mut collisions: Vec<Vec<String>> = groups
+        .into_values()
+        .filter(|group| group.len() > 1)
+        .map(|group| group.into_iter().collect())
+        .collect();
+
+    // Only report the outermost colliding directories, not everything in them
+    let mut reported: Vec<String> = Vec::new();
+    collisions.retain(|group| {
+        let covered = reported
+            .iter()
+            .any(|r| group[0].to_lowercase().starts_with(&format!("{}/", r.to_lowercase())));
+
+        if !covered {
+            reported.push(group[0].clone());
+        }
+
+        !covered
+    });
+
+    collisions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(paths: &[&str]) -> Vec<String> {
+        paths.iter().map(|p| p.to_string()).collect()
+    }
+
+    #[test]
+    fn finds_paths_differing_only_in_case() {
+        assert!(case_collisions(&paths(&["README.md", "src/lib.rs", "src/Main.rs"])).is_empty());
+
+        assert_eq!(
+            case_collisions(&paths(&["README.md", "readme.md", "src/lib.rs"])),
+            vec![paths(&["README.md", "readme.md"])]
+        );
+
+        assert_eq!(
+            case_collisions(&paths(&["Docs/a.md", "docs/b.md", "docs/c/d.md"])),
+            vec![paths(&["Docs", "docs"])]
+        );
+    }
+
+    #[test]
+    fn finds_names_on_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("Docs")).unwrap();
+        std::fs::write(dir.path().join("Docs/readme.md"), "").unwrap();
+
+        assert_eq!(
+            path_on_disk(dir.path(), "docs/README.md").as_deref(),
+            Some("Docs/readme.md")
+        );
+        assert_eq!(
+            path_on_disk(dir.path(), "Docs/readme.md").as_deref(),
+            Some("Docs/readme.md")
+        );
+        assert_eq!(path_on_disk(dir.path(), "Docs/other.md"), None);
+    }
+}
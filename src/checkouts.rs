@@ -0,0 +1,66 @@
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::Context;
+
+use crate::git::Git;
+use crate::rev::GitRev;
+use crate::run::FERSK_ORIGIN;
+use crate::util;
+
+/// Additional revision checked out next to the work directory (`--also-checkout`)
+#[derive(Clone, Debug)]
+pub struct ExtraCheckout {
+    pub rev: GitRev,
+    pub name: String,
+}
+
+impl ExtraCheckout {
+    /// Name of the environment variable containing the checkout's location
+    pub fn env_var(&self) -> String {
+        format!("FERSK_CHECKOUT_{}", self.name.to_uppercase().replace(['-', '.'], "_"))
+    }
+
+    /// Check out the revision into a fresh linked worktree of the work directory.
+    /// It is recreated every run, so it is never affected by what a previous run left behind.
+    pub fn prepare(&self, git: &Git, work_path: &Path, checkout_path: &Path) -> Result<(), anyhow::Error> {
+        if checkout_path.exists() {
+            util::remove_dir_all(checkout_path)
+                .with_context(|| format!("Error deleting previous checkout: {}", checkout_path.display()))?;
+        }
+
+        git.prune_worktrees(work_path)
+            .with_context(|| "Error pruning worktrees")?;
+
+        util::create_parent_dir(checkout_path).with_context(|| "Error creating checkouts directory")?;
+
+        git.add_worktree(work_path, checkout_path, &self.rev.work_ref(FERSK_ORIGIN))
+            .with_context(|| format!("Error checking out {} into {}", self.rev, self.name))?;
+
+        Ok(())
+    }
+}
+
+impl FromStr for ExtraCheckout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (rev, name) = s
+            .rsplit_once('=')
+            .ok_or_else(|| "Checkout must be specified as <ref>=<subdir>".to_owned())?;
+
+        if name.is_empty()
+            || name.starts_with('.')
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+        {
+            return Err(format!("Invalid checkout directory name: {name}"));
+        }
+
+        Ok(Self {
+            rev: rev.parse()?,
+            name: name.to_owned(),
+        })
+    }
+}
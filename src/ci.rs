@@ -0,0 +1,108 @@
+use std::io::Write;
+
+use clap::ValueEnum;
+
+use crate::util::time;
+
+/// CI system whose log viewer output is annotated for
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum CiAnnotations {
+    Github,
+    Gitlab,
+    Buildkite,
+}
+
+/// Collapsible sections of output for CI log viewers, written to stderr along with the rest of fersk's output.
+/// Does nothing if no CI system was specified.
+pub struct Sections {
+    ci: Option<CiAnnotations>,
+    /// Id of the section currently open
+    open: Option<&'static str>,
+}
+
+impl Sections {
+    pub fn new(ci: Option<CiAnnotations>) -> Self {
+        Self { ci, open: None }
+    }
+
+    /// Start a section, ending the one currently open.
+    /// The id is used by CI systems that need to match the end of a section to its start.
+    pub fn start(&mut self, id: &'static str, title: &str) {
+        self.end();
+
+        let Some(ci) = self.ci else {
+            return;
+        };
+
+        emit(&match ci {
+            CiAnnotations::Github => format!("::group::{title}"),
+            CiAnnotations::Gitlab => format!(
+                "\x1b[0Ksection_start:{}:fersk_{id}[collapsed=true]\r\x1b[0K{title}",
+                time::unix_now()
+            ),
+            CiAnnotations::Buildkite => format!("--- {title}"),
+        });
+
+        self.open = Some(id);
+    }
+
+    /// End the section currently open, if any
+    pub fn end(&mut self) {
+        let (Some(ci), Some(id)) = (self.ci, self.open.take()) else {
+            return;
+        };
+
+        match ci {
+            CiAnnotations::Github => emit("::endgroup::"),
+            CiAnnotations::Gitlab => emit(&format!("\x1b[0Ksection_end:{}:fersk_{id}\r\x1b[0K", time::unix_now())),
+            // Sections last until the next one starts
+            CiAnnotations::Buildkite => {}
+        }
+    }
+
+    /// Annotate a failure, so it stands out in the log viewer
+    pub fn error(&mut self, message: &str) {
+        self.end();
+
+        let Some(ci) = self.ci else {
+            return;
+        };
+
+        emit(&match ci {
+            CiAnnotations::Github => format!("::error title=fersk::{}", escape_github(message)),
+            CiAnnotations::Gitlab => format!("\x1b[31;1mfersk: {message}\x1b[0m"),
+            // Expand the section the failure happened in
+            CiAnnotations::Buildkite => format!("^^^ +++\n\x1b[31;1mfersk: {message}\x1b[0m"),
+        });
+    }
+}
+
+impl Drop for Sections {
+    fn drop(&mut self) {
+        self.end();
+    }
+}
+
+fn emit(line: &str) {
+    // Anything fersk has printed must go in the section before it ends
+    let _ = std::io::stdout().flush();
+    eprintln!("{line}");
+}
+
+/// Escape a message for a GitHub Actions workflow command, which must be on one line
+fn escape_github(message: &str) -> String {
+    message.replace('%', "%25").replace('\r', "%0D").replace('\n', "%0A")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_github_messages() {
+        assert_eq!(
+            escape_github("2 post-check(s) failed.\n100% done"),
+            "2 post-check(s) failed.%0A100%25 done"
+        );
+    }
+}
@@ -1,21 +1,575 @@
-use std::process::Command;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, Context};
+use tracing::warn;
 
-pub fn exec_command(command: &str, f: impl FnOnce(&mut Command)) -> Result<(), anyhow::Error> {
-    let mut command = Command::new(command);
+use crate::cancel::{self, Cancelled};
+use crate::logcap::{CappedLog, LogLimit};
+use crate::network;
+use crate::pty;
+use crate::secrets;
+use crate::util;
+use crate::util::process::{self, DescendantTracker, ReapedProcess};
 
-    f(&mut command);
+const WAIT_INTERVAL: Duration = Duration::from_millis(50);
 
-    // Execute command
-    let status = command.status().with_context(|| "Error executing command")?;
+/// Resolve a program given as a relative path (ex. ./scripts/ci.sh) in a directory.
+/// Programs given by name only are left to be looked up in PATH.
+/// Where relative paths are resolved from otherwise differs between platforms.
+fn resolve_program(program: &str, dir: &Path) -> Result<PathBuf, anyhow::Error> {
+    let path = Path::new(program);
+
+    if path.is_absolute() || path.components().count() < 2 {
+        return Ok(path.to_path_buf());
+    }
+
+    let resolved = dir.join(path);
+
+    if !resolved.exists() {
+        return Err(anyhow!("Command not found in the work directory: {program}"));
+    }
+
+    if !resolved.is_file() {
+        return Err(anyhow!("Command is not a file: {program}"));
+    }
 
-    if !status.success() {
+    if !util::is_executable(&resolved) {
         return Err(anyhow!(
-            "Command returned with a non-success error code: {}",
-            status.code().unwrap_or(-1)
+            "Command is not executable: {program}. \
+             If it is meant to be, commit it with the executable bit set (git update-index --chmod=+x {program})."
         ));
     }
 
+    Ok(resolved)
+}
+
+/// Options for executing the main command
+#[derive(Default)]
+pub struct ExecOptions<'a> {
+    /// Capture output to this log file
+    pub log_path: Option<&'a Path>,
+    /// Keep only the start and end of the output in the log
+    pub log_limit: Option<LogLimit>,
+    /// Do not forward the command's stdout
+    pub quiet: bool,
+    /// Warn if the command produces no output for this long
+    pub stall_timeout: Option<Duration>,
+    /// Kill the command if it stalls
+    pub kill_on_stall: bool,
+    /// Run the command in a pseudo-terminal
+    pub tty: bool,
+    /// Run the command without network access
+    pub no_network: bool,
+    /// Kill any descendants the command leaves running
+    pub kill_descendants: bool,
+    /// Values (ex. secrets) to redact from the output and log
+    pub redact: &'a [Vec<u8>],
+    /// Stop the command when this file appears
+    pub cancel_path: Option<&'a Path>,
+    /// Resolve relative program paths (ex. ./scripts/ci.sh) in this directory
+    pub program_dir: Option<&'a Path>,
+}
+
+/// Spawned main command
+enum Spawned {
+    Process(Child),
+    Pty(Box<dyn portable_pty::Child + Send + Sync>),
+}
+
+impl Spawned {
+    fn id(&self) -> u32 {
+        match self {
+            Self::Process(child) => child.id(),
+            Self::Pty(child) => child.process_id().unwrap_or_default(),
+        }
+    }
+
+    /// Check if the command has exited, returning its exit code if it has
+    fn try_wait(&mut self) -> io::Result<Option<i32>> {
+        Ok(match self {
+            Self::Process(child) => child.try_wait()?.map(|s| s.code().unwrap_or(-1)),
+            Self::Pty(child) => child.try_wait()?.map(|s| s.exit_code() as i32),
+        })
+    }
+
+    fn kill(&mut self) {
+        match self {
+            Self::Process(child) => {
+                child.kill().ok();
+                child.wait().ok();
+            }
+            Self::Pty(child) => {
+                child.kill().ok();
+                child.wait().ok();
+            }
+        }
+    }
+}
+
+/// Execute command, optionally capturing its output to a log file
+pub fn exec_command(
+    command: &str,
+    options: &ExecOptions,
+    on_spawn: impl FnOnce(u32),
+    f: impl FnOnce(&mut Command),
+) -> Result<(), anyhow::Error> {
+    let program = match options.program_dir {
+        Some(dir) => resolve_program(command, dir)?,
+        None => PathBuf::from(command),
+    };
+    let command = program.to_str().unwrap_or(command);
+
+    let mut command = if options.no_network {
+        network::isolated_command(command)?
+    } else {
+        Command::new(command)
+    };
+
+    f(&mut command);
+
+    let log = if let Some(log_path) = options.log_path {
+        util::create_parent_dir(log_path).with_context(|| "Error creating log directory")?;
+
+        let file =
+            util::create_file(log_path).with_context(|| format!("Error creating log file: {}", log_path.display()))?;
+
+        Some(Arc::new(Mutex::new(CappedLog::new(file, options.log_limit))))
+    } else {
+        None
+    };
+
+    let last_activity = Arc::new(Mutex::new(Instant::now()));
+    let redact = Arc::new(options.redact.to_vec());
+    let mut threads = Vec::new();
+
+    let stdout_writer = move |quiet: bool| -> Option<Box<dyn Write>> {
+        if quiet {
+            None
+        } else {
+            Some(Box::new(io::stdout()))
+        }
+    };
+
+    let mut tracker = options.kill_descendants.then(DescendantTracker::new);
+
+    // Ctrl-C is forwarded to commands that don't share fersk's terminal, until this is dropped
+    let mut _interrupts = None;
+
+    // Stdin is kept in raw mode while the command runs in a terminal, and restored when this is dropped
+    let mut _raw_mode = None;
+
+    // Execute command
+    let mut child = if options.tty {
+        let pty::PtyChild {
+            child,
+            reader,
+            interrupts,
+            raw_mode,
+        } = pty::spawn(&command).with_context(|| "Error executing command")?;
+
+        // A terminal has a single output stream, which is always read so the command doesn't block
+        let log = log.clone();
+        let last_activity = last_activity.clone();
+        let redact = redact.clone();
+        let quiet = options.quiet;
+
+        threads.push(thread::spawn(move || {
+            tee(reader, log, stdout_writer(quiet), last_activity, redact)
+        }));
+
+        _interrupts = Some(interrupts);
+        _raw_mode = raw_mode;
+
+        Spawned::Pty(child)
+    } else {
+        // Output goes through fersk when it is captured or has to be redacted
+        let piped = log.is_some() || !redact.is_empty();
+
+        if piped {
+            command.stdout(Stdio::piped());
+            command.stderr(Stdio::piped());
+        } else if options.quiet {
+            command.stdout(Stdio::null());
+        }
+
+        // In its own process group, so the processes it leaves running can be told apart from others
+        #[cfg(unix)]
+        if options.kill_descendants {
+            use std::os::unix::process::CommandExt;
+
+            crate::interrupt::install()?;
+            command.process_group(0);
+        }
+
+        let mut child = command.spawn().with_context(|| "Error executing command")?;
+
+        #[cfg(unix)]
+        if options.kill_descendants {
+            _interrupts = Some(crate::interrupt::forward(crate::interrupt::Target::ProcessGroup(
+                child.id(),
+            )));
+        }
+
+        if piped {
+            if let Some(stdout) = child.stdout.take() {
+                let log = log.clone();
+                let last_activity = last_activity.clone();
+                let redact = redact.clone();
+                let quiet = options.quiet;
+
+                threads.push(thread::spawn(move || {
+                    tee(stdout, log, stdout_writer(quiet), last_activity, redact)
+                }));
+            }
+
+            if let Some(stderr) = child.stderr.take() {
+                let log = log.clone();
+                let last_activity = last_activity.clone();
+                let redact = redact.clone();
+
+                threads.push(thread::spawn(move || {
+                    tee(stderr, log, Some(Box::new(io::stderr())), last_activity, redact)
+                }));
+            }
+        }
+
+        Spawned::Process(child)
+    };
+
+    on_spawn(child.id());
+
+    if let Some(tracker) = &mut tracker {
+        tracker.set_root(child.id());
+    }
+
+    let mut stalled = false;
+
+    let code = loop {
+        if let Some(code) = child.try_wait().with_context(|| "Error waiting for command")? {
+            break code;
+        }
+
+        if let Some(tracker) = &mut tracker {
+            tracker.observe();
+        }
+
+        if options.cancel_path.is_some_and(Path::exists) {
+            process::terminate_tree(child.id(), cancel::GRACE_PERIOD);
+            child.kill();
+
+            if let Some(tracker) = &mut tracker {
+                report_reaped(tracker.kill_remaining());
+            }
+
+            finish_log(&log);
+
+            return Err(Cancelled.into());
+        }
+
+        if let Some(stall_timeout) = options.stall_timeout {
+            let idle = last_activity.lock().map(|t| t.elapsed()).unwrap_or_default();
+
+            if idle < stall_timeout {
+                stalled = false;
+            } else if !stalled {
+                stalled = true;
+
+                warn!("Command has produced no output for {}s.", idle.as_secs());
+
+                if let Some(sample) = process::stack_sample(child.id()) {
+                    eprintln!("{sample}");
+
+                    if let Some(log) = &log {
+                        if let Ok(mut log) = log.lock() {
+                            writeln!(log, "--- fersk: stack sample after stall ---\n{sample}").ok();
+                        }
+                    }
+                }
+
+                if options.kill_on_stall {
+                    // Output threads are not joined, as orphaned descendants may keep the pipes open
+                    child.kill();
+
+                    if let Some(tracker) = &mut tracker {
+                        report_reaped(tracker.kill_remaining());
+                    }
+
+                    finish_log(&log);
+
+                    return Err(anyhow!(
+                        "Command was killed after producing no output for {}s",
+                        idle.as_secs()
+                    ));
+                }
+            }
+        }
+
+        thread::sleep(WAIT_INTERVAL);
+    };
+
+    // Leftover processes may keep the output pipes open, so they have to be killed before joining
+    if let Some(tracker) = &mut tracker {
+        report_reaped(tracker.kill_remaining());
+    }
+
+    for thread in threads {
+        thread.join().ok();
+    }
+
+    finish_log(&log);
+
+    if code != 0 {
+        return Err(anyhow!("Command returned with a non-success error code: {code}"));
+    }
+
     Ok(())
 }
+
+/// Write what is held back of a size-limited log
+fn finish_log(log: &Option<Arc<Mutex<CappedLog>>>) {
+    if let Some(log) = log {
+        if let Ok(mut log) = log.lock() {
+            if let Err(err) = log.finish() {
+                warn!("Error writing log: {err}");
+            }
+        }
+    }
+}
+
+fn report_reaped(reaped: Vec<ReapedProcess>) {
+    if reaped.is_empty() {
+        return;
+    }
+
+    warn!("Killed {} process(es) left running by the command:", reaped.len());
+
+    for process in reaped {
+        eprintln!("  {} {}", process.pid, process.name);
+    }
+}
+
+/// Copy output from a reader into the log, and optionally to another writer.
+/// When redacting, the end of what has been read is held back while it may be the start of a value.
+fn tee(
+    mut reader: impl Read,
+    log: Option<Arc<Mutex<CappedLog>>>,
+    mut out: Option<Box<dyn Write>>,
+    last_activity: Arc<Mutex<Instant>>,
+    redact: Arc<Vec<Vec<u8>>>,
+) {
+    let mut buf = [0u8; 8192];
+    let mut pending = Vec::new();
+
+    let mut write = |data: &[u8]| {
+        if let Some(log) = &log {
+            if let Ok(mut log) = log.lock() {
+                log.write_all(data).ok();
+                log.flush().ok();
+            }
+        }
+
+        if let Some(out) = &mut out {
+            out.write_all(data).ok();
+            out.flush().ok();
+        }
+    };
+
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => n,
+        };
+
+        if let Ok(mut last_activity) = last_activity.lock() {
+            *last_activity = Instant::now();
+        }
+
+        if redact.is_empty() {
+            write(&buf[..n]);
+        } else {
+            pending.extend_from_slice(&buf[..n]);
+
+            let (output, consumed) = secrets::redact_partial(&pending, &redact);
+            write(&output);
+            pending.drain(..consumed);
+        }
+    }
+
+    if !pending.is_empty() {
+        write(&secrets::redact_bytes(&pending, &redact));
+    }
+}
+
+/// Execute command and wait for it to exit, killing it if it does not finish within the timeout
+pub fn exec_command_timeout(
+    command: &str,
+    timeout: Option<Duration>,
+    quiet: bool,
+    f: impl FnOnce(&mut Command),
+) -> Result<ExitStatus, anyhow::Error> {
+    let mut command = Command::new(command);
+
+    if quiet {
+        command.stdout(Stdio::null());
+    }
+
+    f(&mut command);
+
+    let mut child = command.spawn().with_context(|| "Error executing command")?;
+    let started = Instant::now();
+
+    loop {
+        if let Some(status) = child.try_wait().with_context(|| "Error waiting for command")? {
+            return Ok(status);
+        }
+
+        if let Some(timeout) = timeout {
+            if started.elapsed() >= timeout {
+                child.kill().ok();
+                child.wait().ok();
+
+                return Err(anyhow!("Command timed out after {}s", timeout.as_secs()));
+            }
+        }
+
+        thread::sleep(WAIT_INTERVAL);
+    }
+}
+
+/// Execute command with a timeout, capturing its combined stdout and stderr
+pub fn exec_command_capture_timeout(
+    command: &str,
+    timeout: Option<Duration>,
+    f: impl FnOnce(&mut Command),
+) -> Result<(ExitStatus, String), anyhow::Error> {
+    let mut command = Command::new(command);
+
+    f(&mut command);
+
+    command.stdin(Stdio::null());
+    command.stdout(Stdio::piped());
+    command.stderr(Stdio::piped());
+
+    let mut child = command.spawn().with_context(|| "Error executing command")?;
+    let started = Instant::now();
+
+    let output = Arc::new(Mutex::new(Vec::new()));
+
+    let readers: Vec<Box<dyn Read + Send>> = [
+        child.stdout.take().map(|r| Box::new(r) as Box<dyn Read + Send>),
+        child.stderr.take().map(|r| Box::new(r) as Box<dyn Read + Send>),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+
+    let threads: Vec<_> = readers
+        .into_iter()
+        .map(|mut reader| {
+            let output = output.clone();
+
+            thread::spawn(move || {
+                let mut buf = [0u8; 8192];
+
+                while let Ok(n @ 1..) = reader.read(&mut buf) {
+                    if let Ok(mut output) = output.lock() {
+                        output.extend_from_slice(&buf[..n]);
+                    }
+                }
+            })
+        })
+        .collect();
+
+    let status = loop {
+        if let Some(status) = child.try_wait().with_context(|| "Error waiting for command")? {
+            break status;
+        }
+
+        if let Some(timeout) = timeout {
+            if started.elapsed() >= timeout {
+                // Output threads are not joined, as orphaned descendants may keep the pipes open
+                child.kill().ok();
+                child.wait().ok();
+
+                return Err(anyhow!("Command timed out after {}s", timeout.as_secs()));
+            }
+        }
+
+        thread::sleep(WAIT_INTERVAL);
+    };
+
+    for thread in threads {
+        thread.join().ok();
+    }
+
+    let output = output
+        .lock()
+        .map(|o| String::from_utf8_lossy(&o).to_string())
+        .unwrap_or_default();
+
+    Ok((status, output))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reader returning the data a chunk at a time
+    struct Chunks(Vec<&'static [u8]>);
+
+    impl Read for Chunks {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() {
+                return Ok(0);
+            }
+
+            let chunk = self.0.remove(0);
+            buf[..chunk.len()].copy_from_slice(chunk);
+
+            Ok(chunk.len())
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn tee_redacts_values_split_across_reads() {
+        let temp = tempfile::tempdir().unwrap();
+        let log_path = temp.path().join("output.log");
+        let log = Arc::new(Mutex::new(CappedLog::new(util::create_file(&log_path).unwrap(), None)));
+        let out = SharedBuf::default();
+
+        let reader = Chunks(vec![b"token: hun", b"ter22\nkey: s3cr", b"3t\npartial: s3"]);
+        let redact = Arc::new(vec![b"hunter22".to_vec(), b"s3cr3t".to_vec()]);
+
+        tee(
+            reader,
+            Some(log.clone()),
+            Some(Box::new(out.clone())),
+            Arc::new(Mutex::new(Instant::now())),
+            redact,
+        );
+        finish_log(&Some(log));
+
+        let expected = "token: [REDACTED]\nkey: [REDACTED]\npartial: s3";
+        assert_eq!(String::from_utf8(out.0.lock().unwrap().clone()).unwrap(), expected);
+        assert_eq!(std::fs::read_to_string(&log_path).unwrap(), expected);
+    }
+}
@@ -0,0 +1,204 @@
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Context};
+use serde::Serialize;
+use toml_edit::{ArrayOfTables, Decor, Document, Item, Key, Table, TableLike, Value};
+
+use super::{Config, DEFAULT_TOML};
+use crate::util;
+
+/// Get the effective value of a configuration key
+pub fn get(cfg: &Config, key: &str) -> Result<String, anyhow::Error> {
+    let keys = parse_key(key)?;
+
+    let mut value = toml::Value::try_from(cfg).with_context(|| "Error serializing config")?;
+    for key in &keys {
+        value = value
+            .get(key.get())
+            .cloned()
+            .ok_or_else(|| anyhow!("Unknown or unset config key: {key}"))?;
+    }
+
+    Ok(match value {
+        toml::Value::String(s) => s,
+        v => v.to_string(),
+    })
+}
+
+/// Set a configuration key in the config file, preserving comments and formatting
+pub fn set(key: &str, value: &str) -> Result<(), anyhow::Error> {
+    let keys = parse_key(key)?;
+
+    // Values that are not valid TOML are treated as strings, so paths don't need quoting
+    let value = value.parse::<Value>().unwrap_or_else(|_| Value::from(value.to_owned()));
+
+    set_value(&keys, value)
+}
+
+/// Set a configuration key to a string in the config file
+pub fn set_string(key: &str, value: &str) -> Result<(), anyhow::Error> {
+    let keys = parse_key(key)?;
+
+    set_value(&keys, Value::from(value))
+}
+
+fn set_value(keys: &[Key], value: Value) -> Result<(), anyhow::Error> {
+    edit_config_file(|doc| {
+        let (last, parents) = keys.split_last().ok_or_else(|| anyhow!("Empty config key"))?;
+
+        let mut table: &mut dyn TableLike = doc.as_table_mut();
+        for key in parents {
+            table = table
+                .entry(key.get())
+                .or_insert(toml_edit::table())
+                .as_table_like_mut()
+                .ok_or_else(|| anyhow!("Config key is not a table: {key}"))?;
+        }
+
+        table.insert(last.get(), Item::Value(value));
+
+        Ok(())
+    })
+}
+
+/// Remove a configuration key from the config file
+pub fn unset(key: &str) -> Result<(), anyhow::Error> {
+    let keys = parse_key(key)?;
+
+    edit_config_file(|doc| {
+        let (last, parents) = keys.split_last().ok_or_else(|| anyhow!("Empty config key"))?;
+
+        let mut table: &mut dyn TableLike = doc.as_table_mut();
+        for key in parents {
+            let Some(t) = table.get_mut(key.get()).and_then(|i| i.as_table_like_mut()) else {
+                return Ok(());
+            };
+
+            table = t;
+        }
+
+        table.remove(last.get());
+
+        Ok(())
+    })
+}
+
+/// Edit an array of tables (ex. `[[schedule]]`) in the config file
+pub fn edit_array_of_tables(
+    key: &str,
+    f: impl FnOnce(&mut ArrayOfTables) -> Result<(), anyhow::Error>,
+) -> Result<(), anyhow::Error> {
+    edit_config_file(|doc| {
+        let first_prefix = match doc.get(key) {
+            Some(item) => item
+                .as_array_of_tables()
+                .ok_or_else(|| anyhow!("Config key is not an array of tables: {key}"))?
+                .get(0)
+                .map(|table| decor_prefix(table.decor())),
+            None => None,
+        };
+
+        // Comments before the array (or at the end of the file, if the array is new) stay in place when
+        // tables are added or removed, rather than sticking to whichever table they happened to precede
+        let trailing = doc.trailing().as_str().unwrap_or_default().to_owned();
+        let (leading, from_trailing) = match first_prefix {
+            Some(prefix) => (prefix, false),
+            None => (trailing.clone(), true),
+        };
+        let leading = leading.trim_end();
+
+        let array = doc
+            .as_table_mut()
+            .entry(key)
+            .or_insert(Item::ArrayOfTables(ArrayOfTables::new()))
+            .as_array_of_tables_mut()
+            .ok_or_else(|| anyhow!("Config key is not an array of tables: {key}"))?;
+
+        f(array)?;
+
+        if let Some(table) = array.get_mut(0) {
+            if !leading.is_empty() {
+                table.decor_mut().set_prefix(format!("{leading}\n\n"));
+            }
+
+            if from_trailing {
+                doc.set_trailing("");
+            }
+        } else {
+            doc.remove(key);
+
+            if !from_trailing && !leading.is_empty() {
+                doc.set_trailing(format!("{leading}\n{trailing}"));
+            }
+        }
+
+        Ok(())
+    })
+}
+
+/// Edit a table (ex. `[projects]`) in the config file, removing it if it ends up empty
+pub fn edit_table(key: &str, f: impl FnOnce(&mut Table) -> Result<(), anyhow::Error>) -> Result<(), anyhow::Error> {
+    edit_config_file(|doc| {
+        let table = doc
+            .as_table_mut()
+            .entry(key)
+            .or_insert(toml_edit::table())
+            .as_table_mut()
+            .ok_or_else(|| anyhow!("Config key is not a table: {key}"))?;
+
+        f(table)?;
+
+        if table.is_empty() {
+            doc.remove(key);
+        }
+
+        Ok(())
+    })
+}
+
+fn decor_prefix(decor: &Decor) -> String {
+    decor.prefix().and_then(|p| p.as_str()).unwrap_or_default().to_owned()
+}
+
+/// Convert a value to a TOML table
+pub fn to_table(value: &impl Serialize) -> Result<Table, anyhow::Error> {
+    let doc = toml::to_string(value)?.parse::<Document>()?;
+
+    Ok(doc.as_table().clone())
+}
+
+fn parse_key(key: &str) -> Result<Vec<Key>, anyhow::Error> {
+    Key::parse(key).map_err(|err| anyhow!("Invalid config key: {key}: {err}"))
+}
+
+fn config_file_path() -> Result<PathBuf, anyhow::Error> {
+    Config::default_file_path().with_context(|| "No default config location found")
+}
+
+/// Apply an edit to the config file, validating the result before writing it
+fn edit_config_file(f: impl FnOnce(&mut Document) -> Result<(), anyhow::Error>) -> Result<(), anyhow::Error> {
+    let path = config_file_path()?;
+
+    let toml_str = if path.exists() {
+        std::fs::read_to_string(&path).with_context(|| format!("Error reading config file: {}", path.display()))?
+    } else {
+        DEFAULT_TOML.to_owned()
+    };
+
+    let mut doc = toml_str
+        .parse::<Document>()
+        .with_context(|| format!("Error parsing config file: {}", path.display()))?;
+
+    f(&mut doc)?;
+
+    let toml_str = doc.to_string();
+
+    // Make sure the edited config is still valid
+    toml_str.parse::<Config>().with_context(|| "Invalid config value")?;
+
+    util::create_parent_dir(&path)
+        .with_context(|| format!("Error creating parent directory for: {}", path.display()))?;
+    std::fs::write(&path, toml_str).with_context(|| format!("Error writing config file: {}", path.display()))?;
+
+    Ok(())
+}
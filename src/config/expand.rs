@@ -0,0 +1,119 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Deserializer};
+
+/// Configured paths with `~`, `$VAR`, `${VAR}` and `%VAR%` expanded
+pub trait ExpandPaths {
+    fn expand_paths(self) -> Self;
+}
+
+impl ExpandPaths for PathBuf {
+    fn expand_paths(self) -> Self {
+        expand_path(&self)
+    }
+}
+
+impl ExpandPaths for Option<PathBuf> {
+    fn expand_paths(self) -> Self {
+        self.map(ExpandPaths::expand_paths)
+    }
+}
+
+impl ExpandPaths for Vec<PathBuf> {
+    fn expand_paths(self) -> Self {
+        self.into_iter().map(ExpandPaths::expand_paths).collect()
+    }
+}
+
+impl<K: Ord> ExpandPaths for BTreeMap<K, PathBuf> {
+    fn expand_paths(self) -> Self {
+        self.into_iter().map(|(k, v)| (k, v.expand_paths())).collect()
+    }
+}
+
+/// Deserialize paths, expanding them (`#[serde(deserialize_with = "expand::deserialize")]`)
+pub fn deserialize<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + ExpandPaths,
+{
+    Ok(T::deserialize(deserializer)?.expand_paths())
+}
+
+/// Expand a leading `~` to the home directory, and environment variables written as `$VAR`, `${VAR}` or `%VAR%`.
+/// Variables that are not set are left as they are.
+pub fn expand_path(path: &Path) -> PathBuf {
+    let Some(s) = path.to_str() else {
+        return path.to_owned();
+    };
+
+    let s = expand_vars(s, |name| std::env::var(name).ok());
+
+    match s.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with(['/', '\\']) => match dirs::home_dir() {
+            Some(home) => PathBuf::from(format!("{}{rest}", home.display())),
+            None => PathBuf::from(s),
+        },
+        _ => PathBuf::from(s),
+    }
+}
+
+fn expand_vars(s: &str, lookup: impl Fn(&str) -> Option<String>) -> String {
+    let mut expanded = String::with_capacity(s.len());
+    let mut rest = s;
+
+    while let Some(i) = rest.find(['$', '%']) {
+        expanded.push_str(&rest[..i]);
+        rest = &rest[i..];
+
+        let (name, len) = if let Some(braced) = rest.strip_prefix("${") {
+            match braced.find('}') {
+                Some(end) => (&braced[..end], end + 3),
+                None => ("", 0),
+            }
+        } else if let Some(percent) = rest.strip_prefix('%') {
+            match percent.find('%') {
+                Some(end) => (&percent[..end], end + 2),
+                None => ("", 0),
+            }
+        } else {
+            let end = rest[1..]
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .map_or(rest.len(), |end| end + 1);
+
+            (&rest[1..end], end)
+        };
+
+        match (len, lookup(name).filter(|_| !name.is_empty())) {
+            (0, _) | (_, None) => {
+                // Not a variable, or not set
+                let len = len.max(1);
+                expanded.push_str(&rest[..len]);
+                rest = &rest[len..];
+            }
+            (len, Some(value)) => {
+                expanded.push_str(&value);
+                rest = &rest[len..];
+            }
+        }
+    }
+
+    expanded.push_str(rest);
+    expanded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expands_variables() {
+        let lookup = |name: &str| (name == "HOME" || name == "LOCALAPPDATA").then(|| format!("<{name}>"));
+
+        assert_eq!(expand_vars("$HOME/work", lookup), "<HOME>/work");
+        assert_eq!(expand_vars("${HOME}work", lookup), "<HOME>work");
+        assert_eq!(expand_vars(r"%LOCALAPPDATA%\fersk", lookup), r"<LOCALAPPDATA>\fersk");
+        assert_eq!(expand_vars("$UNSET/100%/${HOME", lookup), "$UNSET/100%/${HOME");
+    }
+}
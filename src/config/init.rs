@@ -0,0 +1,281 @@
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context};
+use clap::Args;
+
+use super::{Config, DEFAULT_TOML};
+use crate::util::{self, quote};
+use crate::workroot::WorkRoot;
+
+#[derive(Debug, Default, Args)]
+pub struct InitArgs {
+    #[clap(long = "output", help = "Config file to use instead of the default location")]
+    output: Option<PathBuf>,
+    #[clap(
+        long = "diff",
+        conflicts_with = "upgrade",
+        help = "Show settings that differ from the defaults"
+    )]
+    diff: bool,
+    #[clap(
+        long = "upgrade",
+        help = "Add documentation for settings missing from the config file"
+    )]
+    upgrade: bool,
+}
+
+/// A documented setting in the default config
+struct DefaultSection<'a> {
+    /// Top-level keys documented by the section
+    keys: Vec<&'a str>,
+    /// Whether the section documents a table
+    is_table: bool,
+    text: &'a str,
+}
+
+pub fn init(args: InitArgs) -> Result<(), anyhow::Error> {
+    let path = match args.output {
+        Some(path) => path,
+        None => Config::default_file_path().with_context(|| "No default config location found")?,
+    };
+
+    if args.diff {
+        return diff(&path);
+    }
+
+    if !path.exists() {
+        write(&path, DEFAULT_TOML)?;
+        println!("Wrote default config: {}", quote::path(&path));
+
+        return Ok(());
+    }
+
+    if !args.upgrade {
+        eprintln!("Config file already exists: {}", quote::path(&path));
+        eprintln!("Use --diff to compare it with the defaults, or --upgrade to add new settings.");
+
+        return Ok(());
+    }
+
+    let toml_str =
+        std::fs::read_to_string(&path).with_context(|| format!("Error reading config file: {}", path.display()))?;
+
+    let missing: Vec<DefaultSection> = default_sections()
+        .filter(|s| !s.keys.iter().any(|key| mentions_key(&toml_str, key)))
+        .collect();
+
+    if missing.is_empty() {
+        println!("Config is up to date.");
+        return Ok(());
+    }
+
+    // Plain settings must go before the first table (and the comments describing it),
+    // or uncommenting them would put them inside it
+    let lines: Vec<&str> = toml_str.lines().collect();
+    let mut first_table = lines
+        .iter()
+        .position(|line| line.trim_start().starts_with('['))
+        .unwrap_or(lines.len());
+
+    while first_table > 0 && lines[first_table - 1].trim_start().starts_with('#') {
+        first_table -= 1;
+    }
+
+    let head = lines[..first_table].join("\n");
+    let tail = lines[first_table..].join("\n");
+
+    let mut upgraded = head.trim_end().to_owned();
+    for section in missing.iter().filter(|s| !s.is_table) {
+        upgraded.push_str("\n\n");
+        upgraded.push_str(section.text);
+    }
+
+    if !tail.is_empty() {
+        upgraded.push_str("\n\n");
+        upgraded.push_str(tail.trim_end());
+    }
+
+    for section in missing.iter().filter(|s| s.is_table) {
+        upgraded.push_str("\n\n");
+        upgraded.push_str(section.text);
+    }
+
+    let mut upgraded = upgraded.trim_start().to_owned();
+    upgraded.push('\n');
+
+    write(&path, &upgraded)?;
+
+    for section in &missing {
+        println!("Added: {}", section.keys.join(", "));
+    }
+
+    Ok(())
+}
+
+/// Tell where work directories will be created if there is no config file, and offer to write the default one.
+/// It is only asked when running interactively. Otherwise it is only written if defaults are accepted up front.
+/// Once the offer has been declined or passed over, it is not made again.
+pub fn first_run(cfg: &Config, accept_defaults: bool) -> Result<(), anyhow::Error> {
+    let Some(path) = Config::default_file_path() else {
+        return Ok(());
+    };
+
+    let work_root = WorkRoot::from_config(cfg);
+    let notice_path = work_root.first_run_notice_path();
+
+    if path.exists() || (notice_path.exists() && !accept_defaults) {
+        return Ok(());
+    }
+
+    eprintln!("No config file found at: {}", quote::path(&path));
+    eprintln!("Work directories will be created in: {}", quote::path(&cfg.work_path));
+
+    let accepted = if accept_defaults {
+        true
+    } else if io::stdin().is_terminal() && io::stderr().is_terminal() {
+        confirm("Write the default config file now? [Y/n] ")?
+    } else {
+        eprintln!("Run `fersk config init` to write the default config file, or pass --accept-defaults.");
+        false
+    };
+
+    if accepted {
+        write(&path, DEFAULT_TOML)?;
+        eprintln!("Wrote default config: {}", quote::path(&path));
+    } else {
+        work_root.create(cfg).with_context(|| "Error creating work root")?;
+        util::create_parent_dir(&notice_path)
+            .with_context(|| format!("Error creating parent directory for: {}", notice_path.display()))?;
+        std::fs::write(&notice_path, "").with_context(|| format!("Error writing file: {}", notice_path.display()))?;
+    }
+
+    Ok(())
+}
+
+/// Ask a yes/no question, defaulting to yes
+fn confirm(question: &str) -> Result<bool, anyhow::Error> {
+    eprint!("{question}");
+    io::stderr().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "" | "y" | "yes"))
+}
+
+/// Print effective settings that differ from the defaults
+fn diff(path: &Path) -> Result<(), anyhow::Error> {
+    let cfg = if path.exists() {
+        Config::from_file(path)?
+    } else {
+        Config::default()
+    };
+
+    let defaults = flatten(&toml::Value::try_from(Config::default())?);
+    let current = flatten(&toml::Value::try_from(&cfg)?);
+
+    let keys: std::collections::BTreeSet<&String> = defaults.keys().chain(current.keys()).collect();
+
+    for key in keys {
+        let default = defaults.get(key);
+        let value = current.get(key);
+
+        if default == value {
+            continue;
+        }
+
+        if let Some(default) = default {
+            println!("- {key} = {default}");
+        }
+
+        if let Some(value) = value {
+            println!("+ {key} = {value}");
+        }
+    }
+
+    Ok(())
+}
+
+/// Flatten a TOML table into dotted keys and values
+fn flatten(value: &toml::Value) -> BTreeMap<String, String> {
+    fn visit(prefix: &str, value: &toml::Value, out: &mut BTreeMap<String, String>) {
+        match value {
+            toml::Value::Table(table) => {
+                for (key, value) in table {
+                    let key = if prefix.is_empty() {
+                        key.clone()
+                    } else {
+                        format!("{prefix}.{key}")
+                    };
+
+                    visit(&key, value, out);
+                }
+            }
+            value => {
+                out.insert(prefix.to_owned(), value.to_string());
+            }
+        }
+    }
+
+    let mut out = BTreeMap::new();
+    visit("", value, &mut out);
+
+    out
+}
+
+/// Split the default config into sections, separated by blank lines
+fn default_sections() -> impl Iterator<Item = DefaultSection<'static>> {
+    DEFAULT_TOML.split("\n\n").filter_map(|text| {
+        let text = text.trim();
+
+        let mut keys = Vec::new();
+        let mut is_table = false;
+        for line in text.lines() {
+            // Documented settings are commented out without a space, unlike their descriptions
+            let Some(setting) = line.strip_prefix('#').filter(|s| !s.starts_with([' ', '#'])) else {
+                continue;
+            };
+
+            if let Some(table) = setting.strip_prefix('[') {
+                // Keys following a table header belong to the table
+                keys.push(table.trim_matches(['[', ']']));
+                is_table = true;
+                break;
+            }
+
+            if let Some((key, _)) = setting.split_once('=') {
+                keys.push(key.trim());
+            }
+        }
+
+        (!keys.is_empty()).then_some(DefaultSection { keys, is_table, text })
+    })
+}
+
+/// Check if a config file sets or documents a top-level key, commented out or not
+fn mentions_key(toml_str: &str, key: &str) -> bool {
+    toml_str.lines().any(|line| {
+        let line = line.trim_start_matches(|c: char| c == '#' || c.is_whitespace());
+
+        if let Some(table) = line.strip_prefix('[') {
+            let table = table.trim_start_matches('[');
+            return table.strip_prefix(key).is_some_and(|r| r.starts_with([']', '.']));
+        }
+
+        line.strip_prefix(key).is_some_and(|r| r.trim_start().starts_with('='))
+    })
+}
+
+fn write(path: &Path, toml_str: &str) -> Result<(), anyhow::Error> {
+    toml_str
+        .parse::<Config>()
+        .map_err(|err| anyhow!("Resulting config is invalid: {err:#}"))?;
+
+    util::create_parent_dir(path)
+        .with_context(|| format!("Error creating parent directory for: {}", path.display()))?;
+    std::fs::write(path, toml_str).with_context(|| format!("Error writing config file: {}", path.display()))?;
+
+    Ok(())
+}
@@ -1,22 +1,98 @@
-use std::io::{Read, Write};
+pub mod edit;
+pub mod expand;
+pub mod init;
+pub mod project;
+
+use std::collections::BTreeMap;
+use std::io::Read;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 use anyhow::Context;
-use serde_derive::Deserialize;
+use serde::Deserialize;
+use serde_derive::{Deserialize, Serialize};
 use tracing::error;
 
+use crate::admission::AdmissionConfig;
+use crate::archive::ArchiveConfig;
+use crate::attest::AttestationConfig;
+use crate::depcache::DependencyCacheConfig;
+use crate::git::{CleanseMode, CloneMode, GitBackend};
+use crate::logcap::LogLimit;
+use crate::maintain::MaintenanceConfig;
+use crate::materialize::Materialization;
+use crate::runscript::ScriptCommand;
+use crate::sandbox::SandboxBackend;
+use crate::schedule::ScheduledJob;
+use crate::secrets::SecretsConfig;
+use crate::selfupdate::SelfUpdateConfig;
+use crate::upload::UploadConfig;
 use crate::util;
+use crate::verifytag::ReleaseConfig;
 
 pub const CONFIG_DIR: &str = "fersk";
 pub const CONFIG_FILENAME: &str = "config.toml";
 
 pub const DEFAULT_TOML: &str = include_str!("default.toml");
 
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "kebab-case")]
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
 pub struct Config {
+    #[serde(deserialize_with = "expand::deserialize")]
     pub work_path: PathBuf,
+    pub shared_work_root: bool,
+    pub capture_log: bool,
+    pub log_limit: Option<LogLimit>,
+    pub run_history: usize,
+    pub usage_stats: bool,
+    pub context_file: bool,
+    pub auto_prune_branches: bool,
+    pub protected_branches: Vec<String>,
+    pub warn_behind_upstream: bool,
+    pub recurse_submodules: bool,
+    pub submodule_url_rewrite: BTreeMap<String, String>,
+    pub sandbox_backend: Option<SandboxBackend>,
+    pub materialization: Materialization,
+    pub clone_depth: Option<u32>,
+    pub clone_filter: Option<String>,
+    #[serde(deserialize_with = "expand::deserialize")]
+    pub clone_reference: Option<PathBuf>,
+    pub clone_mode: CloneMode,
+    pub git_backend: GitBackend,
+    pub isolated_git: bool,
+    pub disable_repository_hooks: bool,
+    pub cleanse_mode: CleanseMode,
+    pub preserve_paths: Vec<String>,
+    pub fsmonitor: bool,
+    pub untracked_cache: bool,
+    pub redirect_temp_dir: bool,
+    pub reproducible: bool,
+    #[serde(deserialize_with = "expand::deserialize")]
+    pub tool_paths: Vec<PathBuf>,
+    #[serde(deserialize_with = "expand::deserialize")]
+    pub env_files: Vec<PathBuf>,
+    pub env_file_interpolation: bool,
+    pub base_env_from: Option<ScriptCommand>,
+    pub fetch_prune: bool,
+    pub fetch_prune_tags: bool,
+    pub fetch_jobs: usize,
+    pub fetch_jobs_per_host: usize,
+    pub archive: ArchiveConfig,
+    pub upload: UploadConfig,
+    pub admission: AdmissionConfig,
+    pub dependency_cache: DependencyCacheConfig,
+    pub attestation: AttestationConfig,
+    pub release: ReleaseConfig,
+    pub maintenance: MaintenanceConfig,
+    pub self_update: SelfUpdateConfig,
+    pub secrets: SecretsConfig,
+    #[serde(deserialize_with = "expand::deserialize")]
+    pub script: Option<PathBuf>,
+    #[serde(deserialize_with = "expand::deserialize")]
+    pub projects: BTreeMap<String, PathBuf>,
+    pub auto_commands: BTreeMap<String, Vec<String>>,
+    pub concurrency_groups: BTreeMap<String, usize>,
+    pub schedule: Vec<ScheduledJob>,
 }
 
 impl Default for Config {
@@ -25,6 +101,54 @@ impl Default for Config {
             work_path: dirs::cache_dir()
                 .expect("No default cache directory found. Create a config and specify it.")
                 .join(CONFIG_DIR),
+            shared_work_root: false,
+            capture_log: false,
+            log_limit: None,
+            run_history: 10,
+            usage_stats: false,
+            context_file: false,
+            auto_prune_branches: false,
+            protected_branches: Vec::new(),
+            warn_behind_upstream: true,
+            recurse_submodules: false,
+            submodule_url_rewrite: BTreeMap::new(),
+            sandbox_backend: None,
+            materialization: Materialization::default(),
+            clone_depth: None,
+            clone_filter: None,
+            clone_reference: None,
+            clone_mode: CloneMode::default(),
+            git_backend: GitBackend::default(),
+            isolated_git: false,
+            disable_repository_hooks: true,
+            cleanse_mode: CleanseMode::default(),
+            preserve_paths: Vec::new(),
+            fsmonitor: false,
+            untracked_cache: false,
+            redirect_temp_dir: true,
+            reproducible: false,
+            tool_paths: Vec::new(),
+            env_files: Vec::new(),
+            env_file_interpolation: true,
+            base_env_from: None,
+            fetch_prune: true,
+            fetch_prune_tags: false,
+            fetch_jobs: 4,
+            fetch_jobs_per_host: 2,
+            archive: ArchiveConfig::default(),
+            upload: UploadConfig::default(),
+            admission: AdmissionConfig::default(),
+            dependency_cache: DependencyCacheConfig::default(),
+            attestation: AttestationConfig::default(),
+            release: ReleaseConfig::default(),
+            maintenance: MaintenanceConfig::default(),
+            self_update: SelfUpdateConfig::default(),
+            secrets: SecretsConfig::default(),
+            script: None,
+            projects: BTreeMap::new(),
+            auto_commands: BTreeMap::new(),
+            concurrency_groups: BTreeMap::new(),
+            schedule: Vec::new(),
         }
     }
 }
@@ -45,6 +169,10 @@ impl Config {
         get_default_config_path()
     }
 
+    pub fn default_file_path() -> Option<PathBuf> {
+        Self::default_location().map(|location| location.join(CONFIG_FILENAME))
+    }
+
     fn path_from_location(path: &Path) -> Result<PathBuf, anyhow::Error> {
         Ok(path.join(CONFIG_FILENAME))
     }
@@ -64,35 +192,50 @@ impl Config {
 
         Self::from_location(&location)
     }
+}
 
-    pub fn write_default() -> Result<(), anyhow::Error> {
-        if let Some(config_location) = Self::default_location() {
-            let config_file_path = Self::path_from_location(&config_location)?;
+impl FromStr for Config {
+    type Err = anyhow::Error;
 
-            if !config_file_path.exists() {
-                // Create config directory if necessary.
-                util::create_parent_dir(&config_file_path)
-                    .with_context(|| format!("Error creating parent directory for: {}", config_file_path.display()))?;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut table: toml::Table = toml::from_str(s)?;
+        apply_target_sections(&mut table);
 
-                // Write config file.
-                let mut file = util::create_file(&config_file_path)
-                    .with_context(|| format!("Error creating config file at: {}", config_file_path.display()))?;
-                file.write_all(DEFAULT_TOML.as_bytes())
-                    .with_context(|| format!("Error writing to config file at: {}", config_file_path.display()))?;
-            }
-        }
+        let config = Self::deserialize(toml::Value::Table(table))?;
 
-        Ok(())
+        Ok(config)
     }
 }
 
-impl FromStr for Config {
-    type Err = anyhow::Error;
+/// Names of the `[target.<name>]` sections that apply to this platform, least specific first
+fn target_names() -> impl Iterator<Item = &'static str> {
+    cfg!(unix)
+        .then_some("unix")
+        .into_iter()
+        .chain(std::iter::once(std::env::consts::OS))
+}
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let config: Self = toml::from_str(s)?;
+/// Merge the settings in `[target.<name>]` sections for this platform into the top level, overriding it
+fn apply_target_sections(table: &mut toml::Table) {
+    let Some(toml::Value::Table(mut targets)) = table.remove("target") else {
+        return;
+    };
 
-        Ok(config)
+    for name in target_names() {
+        if let Some(toml::Value::Table(section)) = targets.remove(name) {
+            merge_tables(table, section);
+        }
+    }
+}
+
+fn merge_tables(table: &mut toml::Table, overrides: toml::Table) {
+    for (key, value) in overrides {
+        match (table.get_mut(&key), value) {
+            (Some(toml::Value::Table(existing)), toml::Value::Table(value)) => merge_tables(existing, value),
+            (_, value) => {
+                table.insert(key, value);
+            }
+        }
     }
 }
 
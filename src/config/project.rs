@@ -0,0 +1,72 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use serde_derive::Deserialize;
+
+use crate::runscript::Script;
+
+pub const PROJECT_CONFIG_FILENAME: &str = ".fersk.toml";
+
+/// Repository-defined configuration, read from the checked out working tree
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct ProjectConfig {
+    pub preflight: Option<PreflightConfig>,
+    /// Checks run after the command succeeds, all of which must pass for the run to succeed
+    pub post_checks: Vec<PostCheckConfig>,
+    /// Directories with tools (ex. node_modules/.bin) to prepend to PATH for the command, relative to the repository
+    pub tool_paths: Vec<PathBuf>,
+    /// Named commands for `fersk run-script`, with optional pre<name> and post<name> scripts run around them
+    pub scripts: BTreeMap<String, Script>,
+    /// File in the source repository with the base environment of the command (ex. the output of direnv export json).
+    /// Commands are not allowed, as they would run outside any sandbox.
+    pub base_env_from: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PreflightConfig {
+    pub command: Vec<String>,
+    /// Timeout in seconds
+    pub timeout: Option<u64>,
+    /// Exit code signalling that the run should be skipped rather than failed
+    #[serde(default = "default_skip_exit_code")]
+    pub skip_exit_code: i32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PostCheckConfig {
+    pub name: String,
+    pub command: Vec<String>,
+    /// Timeout in seconds
+    pub timeout: Option<u64>,
+}
+
+fn default_skip_exit_code() -> i32 {
+    78
+}
+
+impl ProjectConfig {
+    /// Load project configuration from a working tree, if it has one
+    pub fn from_work_path(work_path: &Path) -> Result<Self, anyhow::Error> {
+        let path = work_path.join(PROJECT_CONFIG_FILENAME);
+
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let toml_str = std::fs::read_to_string(&path).with_context(|| format!("Error reading {}", path.display()))?;
+
+        toml::from_str(&toml_str).with_context(|| format!("Error parsing {}", path.display()))
+    }
+
+    /// Check if a script, or its pre- or post-script, is marked as destructive
+    pub fn is_destructive_script(&self, name: &str) -> bool {
+        [name.to_owned(), format!("pre{name}"), format!("post{name}")]
+            .iter()
+            .filter_map(|name| self.scripts.get(name))
+            .any(Script::destructive)
+    }
+}
@@ -0,0 +1,63 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use serde_derive::Serialize;
+
+use crate::util;
+
+pub const CONTEXT_FILENAME: &str = ".fersk-context.json";
+
+/// Provenance information written into the work directory for the command to use
+#[derive(Debug, Serialize)]
+pub struct RunContext<'a> {
+    pub run_id: &'a str,
+    pub branch: &'a str,
+    pub commit: &'a str,
+    pub source_repository_path: &'a Path,
+    pub working_repository_path: &'a Path,
+    pub started_at: u64,
+    pub labels: &'a BTreeMap<String, String>,
+}
+
+impl RunContext<'_> {
+    /// Write the context file into the work directory, excluding it from git
+    pub fn write(&self) -> Result<PathBuf, anyhow::Error> {
+        exclude(self.working_repository_path).with_context(|| "Error excluding context file from git")?;
+
+        let path = self.working_repository_path.join(CONTEXT_FILENAME);
+        util::json::write_json_file(&path, self)?;
+
+        Ok(path)
+    }
+}
+
+/// Parse a label in the form key=value
+pub fn parse_label(s: &str) -> Result<(String, String), String> {
+    s.split_once('=')
+        .map(|(k, v)| (k.to_owned(), v.to_owned()))
+        .ok_or_else(|| format!("Invalid label (expected key=value): {s}"))
+}
+
+/// Add the context file to the repository's info/exclude, so it never shows up as an untracked file
+fn exclude(work_path: &Path) -> std::io::Result<()> {
+    let exclude_path = work_path.join(".git/info/exclude");
+    let pattern = format!("/{CONTEXT_FILENAME}");
+
+    let existing = fs::read_to_string(&exclude_path).unwrap_or_default();
+    if existing.lines().any(|l| l == pattern) {
+        return Ok(());
+    }
+
+    util::create_parent_dir(&exclude_path)?;
+
+    let mut file = fs::OpenOptions::new().create(true).append(true).open(&exclude_path)?;
+    if !existing.is_empty() && !existing.ends_with('\n') {
+        writeln!(file)?;
+    }
+    writeln!(file, "{pattern}")?;
+
+    Ok(())
+}
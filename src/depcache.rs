@@ -0,0 +1,256 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use serde_derive::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::util;
+use crate::workroot::WorkRoot;
+
+/// Dependency directories cached by the hash of their lockfile
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct DependencyCacheConfig {
+    pub enabled: bool,
+    /// Dependency directory for each lockfile, relative to the repository root
+    pub directories: BTreeMap<String, String>,
+}
+
+impl Default for DependencyCacheConfig {
+    fn default() -> Self {
+        let directories = [
+            ("package-lock.json", "node_modules"),
+            ("yarn.lock", "node_modules"),
+            ("pnpm-lock.yaml", "node_modules"),
+            ("Cargo.lock", "target"),
+        ]
+        .into_iter()
+        .map(|(lockfile, dir)| (lockfile.to_owned(), dir.to_owned()))
+        .collect();
+
+        Self {
+            enabled: false,
+            directories,
+        }
+    }
+}
+
+/// A dependency directory and the cache entry it belongs to
+pub struct CacheEntry {
+    directory: PathBuf,
+    cache_path: PathBuf,
+    /// Whether the cache had an entry for the lockfile
+    pub hit: bool,
+    /// Fingerprint of the directory after restoring, to tell if the run changed it
+    restored: Option<String>,
+}
+
+/// Restore cached dependency directories for all lockfiles present in the work directory.
+/// Returns the entries, so they can be updated after a successful run.
+pub fn restore(
+    cfg: &DependencyCacheConfig,
+    work_root: &WorkRoot,
+    work_path: &Path,
+) -> Result<Vec<CacheEntry>, anyhow::Error> {
+    let mut entries = Vec::new();
+
+    for (lockfile, directory) in &cfg.directories {
+        let lockfile_path = work_path.join(lockfile);
+        if !lockfile_path.is_file() {
+            continue;
+        }
+
+        let lockfile_data =
+            fs::read(&lockfile_path).with_context(|| format!("Error reading lockfile: {}", lockfile_path.display()))?;
+
+        let cache_path = work_root.dependency_cache_path(&cache_key(lockfile, &lockfile_data));
+        let mut entry = CacheEntry {
+            directory: work_path.join(directory),
+            hit: cache_path.exists(),
+            cache_path,
+            restored: None,
+        };
+
+        if entry.hit && !entry.directory.exists() {
+            info!("Restoring {directory} from dependency cache ({lockfile})");
+
+            util::copy_dir_all(&entry.cache_path, &entry.directory)
+                .with_context(|| format!("Error restoring {directory} from dependency cache"))?;
+
+            entry.restored = fingerprint(&entry.directory);
+        }
+
+        entries.push(entry);
+    }
+
+    Ok(entries)
+}
+
+/// Key of the cache entry for a lockfile.
+/// The lockfile name is included, so different ecosystems never share an entry.
+fn cache_key(lockfile: &str, lockfile_data: &[u8]) -> String {
+    util::hash::hash_bytes(&[lockfile.as_bytes(), b"\0", lockfile_data].concat())
+}
+
+/// Store dependency directories in the cache, replacing any previous version.
+/// Entries the run left as they were restored are not copied again.
+pub fn update(entries: &[CacheEntry]) {
+    for entry in entries {
+        if !entry.directory.is_dir() {
+            continue;
+        }
+
+        if entry.restored.is_some() && fingerprint(&entry.directory) == entry.restored {
+            continue;
+        }
+
+        if let Err(err) = store(entry) {
+            warn!("Error updating dependency cache: {err:#}");
+        }
+    }
+}
+
+fn store(entry: &CacheEntry) -> Result<(), anyhow::Error> {
+    let temp_path = entry.cache_path.with_extension(format!("tmp{}", std::process::id()));
+
+    // Copy to a temporary location first, so concurrent runs never restore a partial entry
+    util::remove_dir_all(&temp_path)?;
+    util::copy_dir_all(&entry.directory, &temp_path)
+        .with_context(|| format!("Error copying {}", entry.directory.display()))?;
+
+    util::remove_dir_all(&entry.cache_path)?;
+    fs::rename(&temp_path, &entry.cache_path)
+        .with_context(|| format!("Error moving cache entry into place: {}", entry.cache_path.display()))?;
+
+    Ok(())
+}
+
+/// Fingerprint of the paths, sizes and modification times in a directory, which is much cheaper than comparing
+/// contents. Returns None if the directory can't be read.
+fn fingerprint(dir: &Path) -> Option<String> {
+    fn visit(dir: &Path, prefix: &str, out: &mut String) -> std::io::Result<()> {
+        let mut entries = fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+        entries.sort_by_key(|e| e.file_name());
+
+        for entry in entries {
+            let name = format!("{prefix}/{}", entry.file_name().to_string_lossy());
+            let metadata = entry.path().symlink_metadata()?;
+            let modified = metadata
+                .modified()?
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default();
+
+            out.push_str(&format!("{name}\0{}\0{}\n", metadata.len(), modified.as_nanos()));
+
+            if metadata.is_dir() {
+                visit(&entry.path(), &name, out)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    let mut listing = String::new();
+    visit(dir, "", &mut listing).ok()?;
+
+    Some(util::hash::hash_bytes(listing.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> DependencyCacheConfig {
+        DependencyCacheConfig {
+            enabled: true,
+            directories: [("deps.lock".to_owned(), "deps".to_owned())].into_iter().collect(),
+        }
+    }
+
+    fn cache_entries(path: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(path.join(".cache/dependencies"))
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn key_depends_on_lockfile_name_and_content() {
+        assert_eq!(cache_key("Cargo.lock", b"a"), cache_key("Cargo.lock", b"a"));
+        assert_ne!(cache_key("Cargo.lock", b"a"), cache_key("Cargo.lock", b"b"));
+        assert_ne!(cache_key("Cargo.lock", b"a"), cache_key("yarn.lock", b"a"));
+    }
+
+    #[test]
+    fn restores_on_hit_only() {
+        let temp = tempfile::tempdir().unwrap();
+        let work_root = WorkRoot::new(temp.path().join("root"));
+        let work_path = temp.path().join("work");
+        fs::create_dir_all(&work_path).unwrap();
+        fs::write(work_path.join("deps.lock"), "v1").unwrap();
+
+        let entries = restore(&config(), &work_root, &work_path).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert!(!entries[0].hit);
+        assert!(!work_path.join("deps").exists());
+
+        let cache_path = work_root.dependency_cache_path(&cache_key("deps.lock", b"v1"));
+        fs::create_dir_all(cache_path.join("pkg")).unwrap();
+        fs::write(cache_path.join("pkg/file"), "cached").unwrap();
+
+        let entries = restore(&config(), &work_root, &work_path).unwrap();
+        assert!(entries[0].hit);
+        assert_eq!(fs::read_to_string(work_path.join("deps/pkg/file")).unwrap(), "cached");
+    }
+
+    #[test]
+    fn stores_entries_by_replacing_them() {
+        let temp = tempfile::tempdir().unwrap();
+        let work_root = WorkRoot::new(temp.path().join("root"));
+        let work_path = temp.path().join("work");
+        fs::create_dir_all(work_path.join("deps")).unwrap();
+        fs::write(work_path.join("deps.lock"), "v1").unwrap();
+        fs::write(work_path.join("deps/new"), "new").unwrap();
+
+        let cache_path = work_root.dependency_cache_path(&cache_key("deps.lock", b"v1"));
+        fs::create_dir_all(&cache_path).unwrap();
+        fs::write(cache_path.join("old"), "old").unwrap();
+
+        // The directory exists, so nothing is restored and the run's version replaces the cached one
+        let entries = restore(&config(), &work_root, &work_path).unwrap();
+        update(&entries);
+
+        assert!(!cache_path.join("old").exists());
+        assert_eq!(fs::read_to_string(cache_path.join("new")).unwrap(), "new");
+        assert_eq!(cache_entries(work_root.path()), [cache_key("deps.lock", b"v1")]);
+    }
+
+    #[test]
+    fn only_stores_restored_entries_if_changed() {
+        let temp = tempfile::tempdir().unwrap();
+        let work_root = WorkRoot::new(temp.path().join("root"));
+        let work_path = temp.path().join("work");
+        fs::create_dir_all(&work_path).unwrap();
+        fs::write(work_path.join("deps.lock"), "v1").unwrap();
+
+        let cache_path = work_root.dependency_cache_path(&cache_key("deps.lock", b"v1"));
+        fs::create_dir_all(&cache_path).unwrap();
+        fs::write(cache_path.join("file"), "cached").unwrap();
+
+        // Unchanged entries are not copied back
+        let entries = restore(&config(), &work_root, &work_path).unwrap();
+        fs::write(cache_path.join("marker"), "").unwrap();
+        update(&entries);
+        assert!(cache_path.join("marker").exists());
+
+        // Changed entries are
+        fs::write(work_path.join("deps/added"), "added").unwrap();
+        update(&entries);
+        assert!(!cache_path.join("marker").exists());
+        assert!(cache_path.join("added").exists());
+    }
+}
@@ -0,0 +1,122 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::git::Git;
+
+/// Type of project, detected from the files at the root of the repository
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ProjectType {
+    Cargo,
+    Pnpm,
+    Yarn,
+    Npm,
+    Go,
+    Cmake,
+}
+
+impl ProjectType {
+    /// All project types, in the order they are detected in
+    const ALL: [Self; 6] = [Self::Cargo, Self::Pnpm, Self::Yarn, Self::Npm, Self::Go, Self::Cmake];
+
+    /// Name used in the config (ex. in `auto-commands`)
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Cargo => "cargo",
+            Self::Pnpm => "pnpm",
+            Self::Yarn => "yarn",
+            Self::Npm => "npm",
+            Self::Go => "go",
+            Self::Cmake => "cmake",
+        }
+    }
+
+    /// Files that all have to be present for the project to be of this type
+    fn markers(self) -> &'static [&'static str] {
+        match self {
+            Self::Cargo => &["Cargo.toml"],
+            Self::Pnpm => &["package.json", "pnpm-lock.yaml"],
+            Self::Yarn => &["package.json", "yarn.lock"],
+            Self::Npm => &["package.json"],
+            Self::Go => &["go.mod"],
+            Self::Cmake => &["CMakeLists.txt"],
+        }
+    }
+
+    /// Command building and testing a project of this type from a clean checkout
+    fn default_command(self) -> Vec<String> {
+        let command: &[&str] = match self {
+            Self::Cargo => &["cargo", "test"],
+            Self::Pnpm => &["pnpm", "install-test"],
+            Self::Yarn => &["sh", "-c", "yarn install --frozen-lockfile && yarn test"],
+            Self::Npm => &["npm", "install-ci-test"],
+            Self::Go => &["go", "test", "./..."],
+            Self::Cmake => &[
+                "sh",
+                "-c",
+                "cmake -B build && cmake --build build && ctest --test-dir build --output-on-failure",
+            ],
+        };
+
+        command.iter().map(|s| s.to_string()).collect()
+    }
+
+    /// Get the command to run for this type of project, from the config if overridden
+    pub fn command(self, auto_commands: &BTreeMap<String, Vec<String>>) -> Vec<String> {
+        auto_commands
+            .get(self.name())
+            .cloned()
+            .unwrap_or_else(|| self.default_command())
+    }
+
+    /// Detect the type of a project from the names of the files at the root of the repository
+    pub fn detect(files: &[String]) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|t| t.markers().iter().all(|m| files.iter().any(|f| f == m)))
+    }
+}
+
+/// Detect the type of project in a repository at a commit.
+/// If the commit is not known, the checked out files are used instead.
+pub fn detect_project_type(git: &Git, repository_path: &Path, commit: Option<&str>) -> Option<ProjectType> {
+    let files = match commit.map(|c| git.list_tree(repository_path, c)) {
+        Some(Ok(files)) => files,
+        _ => std::fs::read_dir(repository_path)
+            .ok()?
+            .filter_map(|e| e.ok())
+            .map(|e| e.file_name().to_string_lossy().to_string())
+            .collect(),
+    };
+
+    ProjectType::detect(&files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files(names: &[&str]) -> Vec<String> {
+        names.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn detects_package_manager_from_lockfile() {
+        assert_eq!(
+            ProjectType::detect(&files(&["package.json", "yarn.lock"])),
+            Some(ProjectType::Yarn)
+        );
+        assert_eq!(
+            ProjectType::detect(&files(&["package.json", "package-lock.json"])),
+            Some(ProjectType::Npm)
+        );
+        assert_eq!(ProjectType::detect(&files(&["README.md", "src"])), None);
+    }
+
+    #[test]
+    fn configured_command_overrides_default() {
+        let auto_commands = BTreeMap::from([("go".to_owned(), vec!["make".to_owned(), "test".to_owned()])]);
+
+        assert_eq!(ProjectType::Go.command(&auto_commands), ["make", "test"]);
+        assert_eq!(ProjectType::Cargo.command(&auto_commands), ["cargo", "test"]);
+    }
+}
@@ -0,0 +1,109 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde_derive::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::config::Config;
+use crate::git::{CleanseMode, Git};
+use crate::materialize::Materialization;
+use crate::util::record::{self, Record};
+
+/// Config settings that affect state kept in a work directory between runs,
+/// as they were when the work directory was last used
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct WorkConfig {
+    pub materialization: Materialization,
+    pub cleanse_mode: CleanseMode,
+    pub preserve_paths: Vec<String>,
+    pub fsmonitor: bool,
+    pub untracked_cache: bool,
+    pub submodule_url_rewrite: BTreeMap<String, String>,
+}
+
+impl Record for WorkConfig {
+    const VERSION: u32 = 1;
+}
+
+impl WorkConfig {
+    pub fn from_config(cfg: &Config) -> Self {
+        Self {
+            materialization: cfg.materialization,
+            cleanse_mode: cfg.cleanse_mode,
+            preserve_paths: cfg.preserve_paths.clone(),
+            fsmonitor: cfg.fsmonitor,
+            untracked_cache: cfg.untracked_cache,
+            submodule_url_rewrite: cfg.submodule_url_rewrite.clone(),
+        }
+    }
+
+    /// Load the config a work directory was last used with, if it has been recorded
+    pub fn load(path: impl AsRef<Path>) -> Result<Option<Self>, anyhow::Error> {
+        record::load(path)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), anyhow::Error> {
+        record::save(path, self)
+    }
+
+    /// Bring state left in a work directory by a previous config in line with this one where possible,
+    /// and warn about state that can't be
+    pub fn reconcile(&self, previous: &Self, git: &Git, work_path: &Path) {
+        if self.materialization != previous.materialization {
+            warn!(
+                "Materialization changed from {:?} to {:?}. The existing work directory will be used as it is. \
+                 Purge it to recreate it.",
+                previous.materialization, self.materialization
+            );
+        }
+
+        if self.cleanse_mode != previous.cleanse_mode {
+            info!(
+                "Cleanse mode changed from {:?} to {:?}.",
+                previous.cleanse_mode, self.cleanse_mode
+            );
+        }
+
+        let unpreserved: Vec<&String> = previous
+            .preserve_paths
+            .iter()
+            .filter(|p| !self.preserve_paths.contains(p))
+            .collect();
+
+        if !unpreserved.is_empty() {
+            info!("No longer preserving {unpreserved:?}. Removing them with the cleanse.");
+        }
+
+        let newly_preserved: Vec<&String> = self
+            .preserve_paths
+            .iter()
+            .filter(|p| !previous.preserve_paths.contains(p))
+            .collect();
+
+        if !newly_preserved.is_empty() {
+            info!(
+                "Now preserving {newly_preserved:?}. They were removed by earlier cleanses, so they start out empty."
+            );
+        }
+
+        if previous.untracked_cache && !self.untracked_cache && git.remove_untracked_cache(work_path).is_ok() {
+            info!("Untracked cache was disabled. Removed it from the work directory.");
+        }
+
+        if previous.fsmonitor && !self.fsmonitor && git.stop_fsmonitor(work_path) {
+            info!("Fsmonitor was disabled. Stopped its daemon for the work directory.");
+        }
+
+        if self.submodule_url_rewrite != previous.submodule_url_rewrite && has_submodules(work_path) {
+            warn!(
+                "Submodule URL rewrites changed. Submodules already cloned in the work directory keep their \
+                 previous URLs. Purge it if they need to be cloned from the new location."
+            );
+        }
+    }
+}
+
+fn has_submodules(work_path: &Path) -> bool {
+    work_path.join(".git/modules").is_dir()
+}
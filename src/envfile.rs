@@ -0,0 +1,217 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context};
+
+/// Load dotenv-style files for the command's environment.
+/// Relative paths are looked up in the work directory, then in the source repository, as .env files are usually
+/// not committed. Later files override earlier ones.
+/// Explicitly specified files must exist, while the ones from the config are skipped if they don't.
+pub fn load_env_files<'a>(
+    configured: impl IntoIterator<Item = &'a PathBuf>,
+    explicit: impl IntoIterator<Item = &'a PathBuf>,
+    work_path: &Path,
+    source_path: &Path,
+    interpolate: bool,
+) -> Result<BTreeMap<String, String>, anyhow::Error> {
+    let mut vars = BTreeMap::new();
+
+    let files = configured
+        .into_iter()
+        .map(|p| (p, false))
+        .chain(explicit.into_iter().map(|p| (p, true)));
+
+    for (env_file, required) in files {
+        let path = if env_file.is_absolute() {
+            Some(env_file.clone())
+        } else {
+            [work_path.join(env_file), source_path.join(env_file)]
+                .into_iter()
+                .find(|p| p.is_file())
+        };
+
+        let Some(path) = path.filter(|p| p.is_file()) else {
+            if required {
+                return Err(anyhow!("Env file not found: {}", env_file.display()));
+            }
+
+            continue;
+        };
+
+        let content =
+            std::fs::read_to_string(&path).with_context(|| format!("Error reading env file: {}", path.display()))?;
+
+        parse(&content, interpolate, &mut vars)
+            .with_context(|| format!("Error parsing env file: {}", path.display()))?;
+    }
+
+    Ok(vars)
+}
+
+/// Parse the contents of a dotenv-style file into vars.
+///
+/// Lines are `KEY=value`, optionally prefixed with `export`. Values can be single-quoted (literal),
+/// double-quoted (with escapes like `\n`) or unquoted (with trailing ` # comments` removed).
+/// If interpolating, `$VAR` and `${VAR}` in double-quoted and unquoted values are replaced with variables defined
+/// earlier, or from fersk's environment.
+pub fn parse(content: &str, interpolate: bool, vars: &mut BTreeMap<String, String>) -> Result<(), anyhow::Error> {
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let line = line.strip_prefix("export ").unwrap_or(line);
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| anyhow!("Line {}: Expected KEY=value", i + 1))?;
+
+        let key = key.trim();
+        if !is_valid_key(key) {
+            return Err(anyhow!("Line {}: Invalid variable name: {key}", i + 1));
+        }
+
+        let value = value.trim();
+
+        let value = if let Some(quoted) = value.strip_prefix('\'') {
+            let (literal, _) = quoted
+                .split_once('\'')
+                .ok_or_else(|| anyhow!("Line {}: Unterminated single quote", i + 1))?;
+
+            literal.to_owned()
+        } else if let Some(quoted) = value.strip_prefix('"') {
+            parse_double_quoted(quoted, interpolate, vars)
+                .ok_or_else(|| anyhow!("Line {}: Unterminated double quote", i + 1))?
+        } else {
+            let value = value.split_once(" #").map_or(value, |(v, _)| v).trim_end();
+
+            if interpolate {
+                expand(&mut value.chars().peekable(), vars)
+            } else {
+                value.to_owned()
+            }
+        };
+
+        vars.insert(key.to_owned(), value);
+    }
+
+    Ok(())
+}
+
+fn is_valid_key(key: &str) -> bool {
+    let mut chars = key.chars();
+
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Parse a double-quoted value, up to the closing quote. Returns None if there is none.
+fn parse_double_quoted(s: &str, interpolate: bool, vars: &BTreeMap<String, String>) -> Option<String> {
+    let mut value = String::new();
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return Some(value),
+            '\\' => match chars.next()? {
+                'n' => value.push('\n'),
+                'r' => value.push('\r'),
+                't' => value.push('\t'),
+                c => value.push(c),
+            },
+            '$' if interpolate => value.push_str(&expand_var(&mut chars, vars)),
+            c => value.push(c),
+        }
+    }
+
+    None
+}
+
+/// Replace all variable references in a value
+fn expand(chars: &mut std::iter::Peekable<std::str::Chars>, vars: &BTreeMap<String, String>) -> String {
+    let mut value = String::new();
+
+    while let Some(c) = chars.next() {
+        if c == '$' {
+            value.push_str(&expand_var(chars, vars));
+        } else {
+            value.push(c);
+        }
+    }
+
+    value
+}
+
+/// Expand a variable reference following a `$`
+fn expand_var(chars: &mut std::iter::Peekable<std::str::Chars>, vars: &BTreeMap<String, String>) -> String {
+    let braced = chars.next_if_eq(&'{').is_some();
+
+    let mut name = String::new();
+    while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+        name.push(c);
+    }
+
+    if braced && chars.next_if_eq(&'}').is_none() {
+        // Not a valid reference, so keep it as it was
+        return format!("${{{name}");
+    }
+
+    if name.is_empty() {
+        return if braced { "${}".to_owned() } else { "$".to_owned() };
+    }
+
+    vars.get(&name)
+        .cloned()
+        .or_else(|| std::env::var(&name).ok())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_str(content: &str) -> BTreeMap<String, String> {
+        let mut vars = BTreeMap::new();
+        parse(content, true, &mut vars).unwrap();
+
+        vars
+    }
+
+    #[test]
+    fn parses_quoting_styles() {
+        let vars = parse_str(
+            "# comment\n\
+             export PLAIN=value # trailing comment\n\
+             SINGLE='literal $PLAIN # not a comment'\n\
+             DOUBLE=\"line\\nbreak \\\"quoted\\\"\"\n\
+             EMPTY=\n",
+        );
+
+        assert_eq!(vars["PLAIN"], "value");
+        assert_eq!(vars["SINGLE"], "literal $PLAIN # not a comment");
+        assert_eq!(vars["DOUBLE"], "line\nbreak \"quoted\"");
+        assert_eq!(vars["EMPTY"], "");
+    }
+
+    #[test]
+    fn interpolates_earlier_variables() {
+        let vars = parse_str(
+            "HOST=localhost\nURL=http://${HOST}:8080/$HOST\nESCAPED=\"\\$HOST\"\nMISSING=${FERSK_TEST_UNSET_VAR}x\n",
+        );
+
+        assert_eq!(vars["URL"], "http://localhost:8080/localhost");
+        assert_eq!(vars["ESCAPED"], "$HOST");
+        assert_eq!(vars["MISSING"], "x");
+    }
+
+    #[test]
+    fn rejects_invalid_lines() {
+        let mut vars = BTreeMap::new();
+
+        assert!(parse("NOT A VARIABLE", true, &mut vars).is_err());
+        assert!(parse("1X=value", true, &mut vars).is_err());
+        assert!(parse("X='unterminated", true, &mut vars).is_err());
+    }
+}
@@ -0,0 +1,335 @@
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::{Condvar, Mutex};
+use std::time::Instant;
+
+use anyhow::Context;
+use clap::Args;
+
+use crate::config::Config;
+use crate::git::{Git, Prune};
+use crate::policy::FailurePolicyArgs;
+use crate::run::{self, COPIED_REMOTE_CONFIG_KEY, FERSK_ORIGIN};
+use crate::util::pid::PidLock;
+use crate::util::quote;
+use crate::workroot::WorkRoot;
+
+/// Host name used for remotes on the local filesystem
+const LOCAL_HOST: &str = "local";
+
+#[derive(Debug, Args)]
+pub struct FetchArgs {
+    #[clap(
+        long = "path",
+        help = "Only fetch into the work directory of this repository. Can be specified multiple times."
+    )]
+    paths: Vec<PathBuf>,
+    #[clap(long = "jobs", short = 'j', help = "Number of fetches to run at the same time")]
+    jobs: Option<usize>,
+    #[clap(flatten)]
+    policy: FailurePolicyArgs,
+}
+
+/// A remote to fetch in a work directory
+struct FetchTask {
+    work_path: PathBuf,
+    remote: String,
+    host: String,
+}
+
+/// Queue of fetches, handing out tasks without exceeding the number of concurrent fetches per host
+struct FetchQueue {
+    state: Mutex<QueueState>,
+    available: Condvar,
+    jobs_per_host: usize,
+}
+
+struct QueueState {
+    pending: VecDeque<FetchTask>,
+    active: HashMap<String, usize>,
+}
+
+/// Fetch updates from the remotes of work directories ahead of runs, several at a time
+pub fn fetch(cfg: &Config, args: FetchArgs) -> Result<(), anyhow::Error> {
+    let work_root = WorkRoot::from_config(cfg);
+    work_root.validate(cfg)?;
+
+    cfg.git_backend.check()?;
+
+    let mut git = Git {
+        silent: true,
+        backend: cfg.git_backend,
+        prune: Prune::new(cfg.fetch_prune, cfg.fetch_prune_tags),
+        ..Default::default()
+    };
+
+    if cfg.isolated_git {
+        git.isolate(&work_root.git_config_dir())
+            .with_context(|| "Error writing isolated git config")?;
+    }
+
+    let ids = if args.paths.is_empty() {
+        work_root.work_ids().with_context(|| "Error listing work directories")?
+    } else {
+        args.paths
+            .into_iter()
+            .map(|path| Ok(work_root.source_id(run::resolve_source_repository(&git, Some(path))?)))
+            .collect::<Result<Vec<_>, anyhow::Error>>()?
+    };
+
+    // Hold the locks of all work directories being fetched into, so runs don't start in them meanwhile
+    let mut locks = Vec::new();
+    let mut tasks = VecDeque::new();
+
+    for id in ids {
+        let work_path = work_root.work_path(&id);
+        if !work_path.exists() {
+            continue;
+        }
+
+        let Some(lock) = PidLock::acquire(work_root.lock_path(&id)) else {
+            eprintln!("Skipping {}: Work directory is in use", quote::path(&work_path));
+            continue;
+        };
+
+        locks.push(lock);
+
+        let copied_remotes = git
+            .get_config_all(&work_path, COPIED_REMOTE_CONFIG_KEY)
+            .unwrap_or_default();
+
+        for remote in std::iter::once(FERSK_ORIGIN.to_owned()).chain(copied_remotes) {
+            let Ok(url) = git.get_remote_url(&work_path, &remote) else {
+                continue;
+            };
+
+            tasks.push_back(FetchTask {
+                work_path: work_path.clone(),
+                remote,
+                host: remote_host(&url),
+            });
+        }
+    }
+
+    let total = tasks.len();
+    let jobs = args.jobs.unwrap_or(cfg.fetch_jobs).clamp(1, total.max(1));
+
+    let queue = FetchQueue {
+        state: Mutex::new(QueueState {
+            pending: tasks,
+            active: HashMap::new(),
+        }),
+        available: Condvar::new(),
+        jobs_per_host: cfg.fetch_jobs_per_host.max(1),
+    };
+
+    let started = Instant::now();
+    let outcomes = Mutex::new(args.policy.outcomes());
+
+    std::thread::scope(|s| {
+        for _ in 0..jobs {
+            s.spawn(|| {
+                while let Some(task) = queue.next() {
+                    let stop = {
+                        let mut outcomes = outcomes.lock().unwrap();
+                        let stop = outcomes.should_stop();
+                        outcomes.cancelled += usize::from(stop);
+                        stop
+                    };
+
+                    if stop {
+                        queue.done(&task);
+                        continue;
+                    }
+
+                    let task_started = Instant::now();
+                    let result = git.fetch(&task.work_path, &task.remote);
+
+                    queue.done(&task);
+
+                    // Report progress across all fetches as each one completes
+                    let mut outcomes = outcomes.lock().unwrap();
+                    outcomes.record(result.is_ok());
+
+                    let progress = format!("[{}/{total}]", outcomes.finished());
+                    match result {
+                        Ok(()) => println!(
+                            "{progress} Fetched {} into {} ({:.1}s)",
+                            task.remote,
+                            task.work_path.display(),
+                            task_started.elapsed().as_secs_f64()
+                        ),
+                        Err(err) => {
+                            eprintln!(
+                                "{progress} Error fetching {} into {}: {err}",
+                                task.remote,
+                                task.work_path.display()
+                            );
+                        }
+                    }
+                }
+            });
+        }
+    });
+
+    drop(locks);
+
+    let outcomes = outcomes.into_inner().unwrap();
+
+    println!(
+        "Fetched {} of {total} remote(s) in {:.1}s.",
+        outcomes.succeeded,
+        started.elapsed().as_secs_f64()
+    );
+
+    outcomes.finish("fetch(es)")
+}
+
+impl FetchQueue {
+    /// Take the next task whose host has a free slot, waiting for one if necessary.
+    /// Returns None when there are no more tasks.
+    fn next(&self) -> Option<FetchTask> {
+        let mut state = self.state.lock().unwrap();
+
+        loop {
+            if state.pending.is_empty() {
+                return None;
+            }
+
+            let QueueState { pending, active } = &mut *state;
+            let position = pending
+                .iter()
+                .position(|t| active.get(&t.host).copied().unwrap_or(0) < self.jobs_per_host);
+
+            if let Some(task) = position.and_then(|i| pending.remove(i)) {
+                *active.entry(task.host.clone()).or_default() += 1;
+                return Some(task);
+            }
+
+            state = self.available.wait(state).unwrap();
+        }
+    }
+
+    /// Release the host slot of a finished task
+    fn done(&self, task: &FetchTask) {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(active) = state.active.get_mut(&task.host) {
+            *active -= 1;
+        }
+
+        self.available.notify_all();
+    }
+}
+
+/// Get the host of a remote URL, for throttling fetches per host
+fn remote_host(url: &str) -> String {
+    // URL (ex. https://user@host:port/path or ssh://host/path)
+    if let Some((_, rest)) = url.split_once("://") {
+        let authority = rest.split('/').next().unwrap_or_default();
+        let host = authority.rsplit('@').next().unwrap_or_default();
+
+        return if host.is_empty() {
+            LOCAL_HOST.to_owned()
+        } else {
+            host.to_owned()
+        };
+    }
+
+    // SCP-like syntax (ex. git@host:path). A single letter before the colon is a Windows drive.
+    if let Some((authority, _)) = url.split_once(':') {
+        if authority.len() > 1 && !authority.contains(['/', '\\']) {
+            return authority.rsplit('@').next().unwrap_or_default().to_owned();
+        }
+    }
+
+    LOCAL_HOST.to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use super::*;
+
+    fn queue(hosts: &[&str], jobs_per_host: usize) -> FetchQueue {
+        let pending = hosts
+            .iter()
+            .map(|host| FetchTask {
+                work_path: PathBuf::new(),
+                remote: FERSK_ORIGIN.to_owned(),
+                host: (*host).to_owned(),
+            })
+            .collect();
+
+        FetchQueue {
+            state: Mutex::new(QueueState {
+                pending,
+                active: HashMap::new(),
+            }),
+            available: Condvar::new(),
+            jobs_per_host,
+        }
+    }
+
+    #[test]
+    fn gets_host_of_remote() {
+        assert_eq!(remote_host("https://example.com/repo.git"), "example.com");
+        assert_eq!(
+            remote_host("https://user:p@ss@example.com:8443/repo.git"),
+            "example.com:8443"
+        );
+        assert_eq!(remote_host("ssh://git@example.com/repo.git"), "example.com");
+        assert_eq!(remote_host("git@example.com:org/repo.git"), "example.com");
+        assert_eq!(remote_host("example.com:repo.git"), "example.com");
+        assert_eq!(remote_host("file:///srv/repo.git"), LOCAL_HOST);
+        assert_eq!(remote_host("/srv/repo.git"), LOCAL_HOST);
+        assert_eq!(remote_host("../repo"), LOCAL_HOST);
+        assert_eq!(remote_host("./dir:with:colons"), LOCAL_HOST);
+        assert_eq!(remote_host("C:\\repos\\repo"), LOCAL_HOST);
+        assert_eq!(remote_host("C:/repos/repo"), LOCAL_HOST);
+    }
+
+    #[test]
+    fn skips_tasks_of_busy_hosts() {
+        let queue = queue(&["a", "a", "a", "b"], 2);
+
+        let first = queue.next().unwrap();
+        assert_eq!(first.host, "a");
+        assert_eq!(queue.next().unwrap().host, "a");
+        assert_eq!(queue.next().unwrap().host, "b");
+
+        // The last task for a is handed out once one of the others is done
+        queue.done(&first);
+        assert_eq!(queue.next().unwrap().host, "a");
+    }
+
+    #[test]
+    fn never_exceeds_jobs_per_host() {
+        let queue = queue(&["a", "b", "a", "a", "b", "a", "a", "b", "a", "b", "a", "a"], 2);
+        let active: HashMap<&str, AtomicUsize> = [("a", AtomicUsize::new(0)), ("b", AtomicUsize::new(0))].into();
+        let max_active = AtomicUsize::new(0);
+        let finished = AtomicUsize::new(0);
+
+        std::thread::scope(|s| {
+            for _ in 0..6 {
+                s.spawn(|| {
+                    while let Some(task) = queue.next() {
+                        let count = &active[task.host.as_str()];
+                        max_active.fetch_max(count.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+
+                        std::thread::sleep(Duration::from_millis(5));
+
+                        count.fetch_sub(1, Ordering::SeqCst);
+                        finished.fetch_add(1, Ordering::SeqCst);
+                        queue.done(&task);
+                    }
+                });
+            }
+        });
+
+        assert_eq!(finished.into_inner(), 12);
+        assert_eq!(max_active.into_inner(), 2);
+    }
+}
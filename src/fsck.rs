@@ -0,0 +1,213 @@
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::Context;
+use clap::Args;
+use serde_derive::Serialize;
+
+use crate::config::Config;
+use crate::git::Git;
+use crate::journal::Journal;
+use crate::metadata::WorkMetadata;
+use crate::run::{self, COPIED_REMOTE_CONFIG_KEY, FERSK_ORIGIN};
+use crate::schema::SCHEMA_VERSION;
+use crate::util::{self, json::JsonOut, pid::PidLock, quote};
+use crate::workroot::WorkRoot;
+
+#[derive(Debug, Args)]
+pub struct FsckArgs {
+    #[clap(long = "path", help = "Only check the work directory of this repository")]
+    path: Option<PathBuf>,
+    #[clap(long = "repair", help = "Repair problems where possible")]
+    repair: bool,
+
+    #[clap(
+        long = "json-out",
+        value_name = "FORMAT",
+        value_parser = JsonOut::from_str,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "pretty",
+        help = "Output json information (pretty, compact, or a file path to write it to)"
+    )]
+    json_out: Option<JsonOut>,
+}
+
+#[derive(Serialize)]
+struct WorkDirReport {
+    schema_version: u32,
+    working_repository_path: PathBuf,
+    problems: Vec<Problem>,
+}
+
+#[derive(Serialize)]
+struct Problem {
+    description: String,
+    repaired: bool,
+}
+
+pub fn fsck(cfg: &Config, args: FsckArgs) -> Result<(), anyhow::Error> {
+    let work_root = WorkRoot::from_config(cfg);
+
+    let git = Git {
+        silent: true,
+        ..Default::default()
+    };
+
+    let mut reports = Vec::new();
+
+    let ids = if let Some(path) = args.path {
+        let repository_root_path = run::resolve_source_repository(&git, Some(path))?;
+
+        vec![work_root.source_id(repository_root_path)]
+    } else {
+        work_root.work_ids().with_context(|| "Error listing work directories")?
+    };
+
+    for id in ids {
+        let working_repository_path = work_root.work_path(&id);
+
+        let problems = match PidLock::acquire(work_root.lock_path(&id)) {
+            Some(_pidlock) => check_work_dir(&git, &work_root, &id, args.repair),
+            None => vec![Problem {
+                description: "Work directory is in use, not checked".to_owned(),
+                repaired: false,
+            }],
+        };
+
+        reports.push(WorkDirReport {
+            schema_version: SCHEMA_VERSION,
+            working_repository_path,
+            problems,
+        });
+    }
+
+    if let Some(json_out) = &args.json_out {
+        util::json::write_json_output(&reports, json_out)?;
+
+        return Ok(());
+    }
+
+    for report in &reports {
+        if report.problems.is_empty() {
+            println!("{}: OK", quote::path(&report.working_repository_path));
+            continue;
+        }
+
+        println!("{}:", quote::path(&report.working_repository_path));
+
+        for problem in &report.problems {
+            let repaired = if problem.repaired { " (repaired)" } else { "" };
+            println!("  {}{repaired}", problem.description);
+        }
+    }
+
+    Ok(())
+}
+
+/// Check a single work directory for problems
+fn check_work_dir(git: &Git, work_root: &WorkRoot, id: &str, repair: bool) -> Vec<Problem> {
+    let work_path = work_root.work_path(id);
+    let mut problems = Vec::new();
+
+    let journal = Journal::new(work_root, id);
+
+    let mut problem = |description: String, repaired: bool| {
+        if repaired {
+            journal.record("repair", &description);
+        }
+
+        problems.push(Problem { description, repaired })
+    };
+
+    if git.fsck(&work_path).is_err() {
+        problem(
+            "Repository is corrupt (git fsck failed). Purge the work directory to recreate it.".to_owned(),
+            false,
+        );
+    }
+
+    let metadata = match WorkMetadata::load(work_root.metadata_path(id)) {
+        Ok(metadata) => metadata,
+        Err(err) => {
+            let repaired = repair && std::fs::remove_file(work_root.metadata_path(id)).is_ok();
+            problem(format!("Metadata is unreadable: {err:#}"), repaired);
+
+            None
+        }
+    };
+
+    let remote_url = git.get_remote_url(&work_path, FERSK_ORIGIN).ok();
+
+    // The expected source is the one the work directory id was derived from
+    let expected_source = metadata
+        .as_ref()
+        .map(|m| m.source_repository_path.clone())
+        .or_else(|| remote_url.as_ref().map(PathBuf::from))
+        .filter(|source| work_root.source_id(source) == id);
+
+    match (&expected_source, &remote_url) {
+        (Some(source), Some(url)) if Path::new(url) != source => {
+            let repaired = repair && git.force_remote_url(&work_path, FERSK_ORIGIN, source).is_ok();
+            problem(
+                format!("{FERSK_ORIGIN} points to {url}, expected {}", source.display()),
+                repaired,
+            );
+        }
+        (Some(source), None) => {
+            let repaired = repair && git.force_remote_url(&work_path, FERSK_ORIGIN, source).is_ok();
+            problem(format!("{FERSK_ORIGIN} remote is missing"), repaired);
+        }
+        (None, _) => problem(
+            "Source repository could not be determined from metadata or remote".to_owned(),
+            false,
+        ),
+        _ => {}
+    }
+
+    if let Some(metadata) = &metadata {
+        if util::normalize_path(&metadata.working_repository_path) != util::normalize_path(&work_path) {
+            problem(
+                format!(
+                    "Metadata refers to a different work directory: {}",
+                    metadata.working_repository_path.display()
+                ),
+                false,
+            );
+        }
+    }
+
+    let copied_remotes = git
+        .get_config_all(&work_path, COPIED_REMOTE_CONFIG_KEY)
+        .unwrap_or_default();
+
+    for remote in git.list_remotes(&work_path).unwrap_or_default() {
+        if remote == FERSK_ORIGIN || copied_remotes.contains(&remote) {
+            continue;
+        }
+
+        let repaired = repair && git.remove_remote(&work_path, &remote).is_ok();
+        problem(format!("Unexpected remote: {remote}"), repaired);
+    }
+
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::{self, Output};
+
+    #[test]
+    fn json_output_matches_schema() {
+        let reports = vec![WorkDirReport {
+            schema_version: SCHEMA_VERSION,
+            working_repository_path: PathBuf::from("/work/abc"),
+            problems: vec![Problem {
+                description: "Missing fersk-origin remote".to_owned(),
+                repaired: true,
+            }],
+        }];
+        schema::validate(Output::Fsck, &serde_json::to_value(&reports).unwrap()).unwrap();
+    }
+}
@@ -1,65 +1,555 @@
-use std::ffi::OsStr;
+use std::ffi::{OsStr, OsString};
 use std::fmt::Display;
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
-use std::process::{Command, Output, Stdio};
+use std::process::{Command, ExitStatus, Output, Stdio};
 use std::str::FromStr;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
 
+use serde_derive::{Deserialize, Serialize};
 use thiserror::Error;
+use tracing::trace;
+
+use crate::rev::GitRev;
+use crate::util::{self, quote};
 
 #[derive(Debug, Error)]
 pub enum GitError {
     #[error("error executing git")]
-    Execute,
-    #[error("unknown error")]
-    Unknown(Option<i32>),
+    Execute(#[source] std::io::Error),
+    #[error("not a git repository: {0}")]
+    NotARepository(String),
+    #[error("git {0} is not supported. fersk requires git {MINIMUM_VERSION} or newer.")]
+    UnsupportedVersion(GitVersion),
+    #[error("merge conflict in: {}", .0.join(", "))]
+    MergeConflict(Vec<String>),
+    #[error("authentication failed: {0}")]
+    AuthFailed(String),
+    #[error("ref not found: {0}")]
+    RefNotFound(String),
+    #[error("network error: {0}")]
+    NetworkError(String),
+    #[error("repository is corrupt: {0}")]
+    Corrupt(String),
+    #[error("{}", unknown_error_message(*.0, .1))]
+    Unknown(Option<i32>, String),
+    #[cfg(feature = "libgit2")]
+    #[error("libgit2: {0}")]
+    Library(String),
 }
 
-#[derive(Clone)]
-pub enum GitRev {
-    Branch(String),
-    Commit(String),
+/// Implementation used for cloning, fetching, checking out and cleaning work directories.
+/// Everything else always uses the git executable.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum GitBackend {
+    #[default]
+    Cli,
+    Libgit2,
 }
 
-#[derive(Default)]
-pub struct Git {
-    pub silent: bool,
+impl GitBackend {
+    /// Make sure the backend is available in this build
+    pub fn check(&self) -> Result<(), anyhow::Error> {
+        if *self == Self::Libgit2 && !cfg!(feature = "libgit2") {
+            return Err(anyhow::anyhow!(
+                "Cannot use the libgit2 git backend: fersk was built without the `libgit2` feature."
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// How objects are copied from the source repository when cloning it into a new work directory
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CloneMode {
+    /// Hardlink object files, falling back to copying them across filesystems
+    #[default]
+    Hardlink,
+    /// Use the source repository's object store through alternates, without copying anything
+    Shared,
+    /// Copy all object files
+    FullCopy,
+}
+
+/// How work directories are cleansed between runs
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CleanseMode {
+    /// Only restore or remove the paths that differ from HEAD, if there are few of them
+    #[default]
+    Differential,
+    /// Always reset and remove all untracked and ignored files
+    Full,
+}
+
+/// Refs fetches remove from the work repository when they no longer exist in the source repository
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Prune {
+    None,
+    /// Remote-tracking branches
+    #[default]
+    Branches,
+    /// Remote-tracking branches and tags
+    BranchesAndTags,
+}
+
+impl Prune {
+    pub fn new(prune: bool, prune_tags: bool) -> Self {
+        match (prune, prune_tags) {
+            (false, _) => Self::None,
+            (true, false) => Self::Branches,
+            (true, true) => Self::BranchesAndTags,
+        }
+    }
 }
 
-impl AsRef<str> for GitRev {
-    fn as_ref(&self) -> &str {
-        match self {
-            Self::Branch(v) => v,
-            Self::Commit(v) => v,
+impl GitError {
+    /// Classify a git failure by its error output
+    fn from_stderr(code: Option<i32>, stderr: &[u8]) -> Self {
+        let stderr = String::from_utf8_lossy(stderr);
+        let message = error_snippet(&stderr);
+        let lowercase = stderr.to_lowercase();
+
+        let matches = |patterns: &[&str]| patterns.iter().any(|p| lowercase.contains(p));
+
+        if matches(&["not a git repository", "does not appear to be a git repository"]) {
+            Self::NotARepository(message)
+        } else if matches(&[
+            "authentication failed",
+            "permission denied (publickey",
+            "could not read username",
+            "could not read password",
+            "terminal prompts disabled",
+            "the requested url returned error: 401",
+            "the requested url returned error: 403",
+        ]) {
+            Self::AuthFailed(message)
+        } else if matches(&[
+            "could not resolve host",
+            "connection refused",
+            "connection timed out",
+            "operation timed out",
+            "network is unreachable",
+            "the remote end hung up",
+            "early eof",
+            "unable to access",
+        ]) {
+            Self::NetworkError(message)
+        } else if matches(&[
+            "unknown revision",
+            "did not match any file(s) known to git",
+            "couldn't find remote ref",
+            "not a valid object name",
+            "invalid reference",
+            "bad revision",
+            "needed a single revision",
+        ]) {
+            Self::RefNotFound(message)
+        } else if matches(&[
+            "corrupt",
+            "bad object",
+            "missing blob",
+            "missing tree",
+            "missing commit",
+            "loose object",
+            "index file smaller than expected",
+            "bad signature",
+        ]) {
+            Self::Corrupt(message)
+        } else {
+            Self::Unknown(code, message)
         }
     }
 }
 
-impl Display for GitRev {
+fn unknown_error_message(code: Option<i32>, message: &str) -> String {
+    let code = code.map(|c| c.to_string()).unwrap_or_else(|| "none".to_owned());
+
+    if message.is_empty() {
+        format!("git failed with exit code {code}")
+    } else {
+        format!("git failed with exit code {code}: {message}")
+    }
+}
+
+/// Get the relevant part of git's error output
+fn error_snippet(stderr: &str) -> String {
+    let lines: Vec<&str> = stderr.lines().map(str::trim).filter(|l| !l.is_empty()).collect();
+
+    // Prefer actual error messages over progress and hints
+    let errors: Vec<&str> = lines
+        .iter()
+        .copied()
+        .filter(|l| l.starts_with("fatal:") || l.starts_with("error:"))
+        .collect();
+
+    let lines = if errors.is_empty() { lines } else { errors };
+    let skip = lines.len().saturating_sub(ERROR_SNIPPET_LINES);
+
+    lines[skip..].join("\n")
+}
+
+/// Describe a git command line for logging, with the directory it runs in and the environment fersk sets for it
+fn describe_command(command: &Command) -> String {
+    let args: Vec<String> = std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|arg| arg.to_string_lossy().to_string())
+        .collect();
+
+    let mut description = quote::command(&args);
+
+    if let Some(dir) = command.get_current_dir() {
+        description.push_str(&format!(" (in {})", quote::path(dir)));
+    }
+
+    let env: Vec<String> = command
+        .get_envs()
+        .map(|(key, value)| match value {
+            Some(value) => format!("{}={}", key.to_string_lossy(), value.to_string_lossy()),
+            None => format!("-{}", key.to_string_lossy()),
+        })
+        .collect();
+
+    if !env.is_empty() {
+        description.push_str(&format!(" [{}]", env.join(" ")));
+    }
+
+    description
+}
+
+/// Log how a git command exited, and how long it took
+fn trace_exit(status: ExitStatus, started: Instant) {
+    let code = status
+        .code()
+        .map(|c| c.to_string())
+        .unwrap_or_else(|| "none".to_owned());
+
+    trace!("git exited with code {code} after {} ms", started.elapsed().as_millis());
+}
+
+/// Oldest git version fersk works with
+pub const MINIMUM_VERSION: GitVersion = GitVersion(2, 5, 0);
+
+/// Version of the git executable, probed once
+static VERSION: OnceLock<Option<GitVersion>> = OnceLock::new();
+
+/// Maximum number of dirty paths to cleanse individually, before falling back to a full reset and clean
+const DIFFERENTIAL_CLEANSE_MAX_PATHS: usize = 1000;
+
+/// Maximum number of lines of git error output to include in errors
+const ERROR_SNIPPET_LINES: usize = 5;
+
+/// Global config used for git operations when isolated from the user's config
+const ISOLATED_GLOBAL_CONFIG: &str =
+    "# Generated by fersk. Used instead of the user's global config for internal git operations.
+[advice]
+\tdetachedHead = false
+[maintenance]
+\tauto = false
+";
+
+/// Config disabling automatic background maintenance, which would otherwise leave detached processes holding
+/// locks and handles in work directories
+const NO_MAINTENANCE_CONFIG: &[(&str, &str)] = &[
+    ("maintenance.auto", "false"),
+    ("gc.auto", "0"),
+    ("gc.autoDetach", "false"),
+];
+
+/// Upload pack command allowing partial clones and fetching of their missing objects from local repositories,
+/// which don't allow it by default
+const PARTIAL_CLONE_UPLOAD_PACK: &str =
+    "git -c uploadpack.allowFilter=true -c uploadpack.allowAnySHA1InWant=true upload-pack";
+
+/// How long a detached gc gets to exit before it is killed
+const GC_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+/// Files in the git directory indicating that an operation is in progress
+const IN_PROGRESS_STATE_FILES: &[&str] = &[
+    "MERGE_HEAD",
+    "CHERRY_PICK_HEAD",
+    "REVERT_HEAD",
+    "rebase-merge",
+    "rebase-apply",
+];
+
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct GitVersion(pub u32, pub u32, pub u32);
+
+impl Display for GitVersion {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let s = match self {
-            Self::Branch(v) => v,
-            Self::Commit(v) => v,
-        };
+        write!(f, "{}.{}.{}", self.0, self.1, self.2)
+    }
+}
+
+impl GitVersion {
+    /// Parse the output of `git --version` (ex. "git version 2.39.2.windows.1" or "git version 2.37.1 (Apple Git-137.1)")
+    fn parse(s: &str) -> Option<Self> {
+        let version = s.trim().strip_prefix("git version ")?.split_whitespace().next()?;
+        let mut parts = version.split('.').map(|p| p.parse::<u32>().ok());
+
+        Some(Self(
+            parts.next()??,
+            parts.next()??,
+            parts.next().flatten().unwrap_or(0),
+        ))
+    }
+
+    /// Check if the git executable is at least the given version
+    pub fn at_least(major: u32, minor: u32) -> bool {
+        // If the version could not be determined, assume a recent one
+        version().map(|v| v >= Self(major, minor, 0)).unwrap_or(true)
+    }
+}
+
+/// Get the version of the git executable, if it could be determined
+pub fn version() -> Option<GitVersion> {
+    *VERSION.get_or_init(|| {
+        let output = Command::new("git").arg("--version").output().ok()?;
+
+        GitVersion::parse(&String::from_utf8_lossy(&output.stdout))
+    })
+}
+
+/// Make sure git is available and recent enough
+pub fn check_version() -> Result<(), GitError> {
+    // Distinguish a missing git from one with unrecognized version output
+    Command::new("git")
+        .arg("--version")
+        .stdout(Stdio::null())
+        .status()
+        .map_err(GitError::Execute)?;
+
+    match version() {
+        Some(version) if version < MINIMUM_VERSION => Err(GitError::UnsupportedVersion(version)),
+        _ => Ok(()),
+    }
+}
+
+#[derive(Default)]
+pub struct Git {
+    pub silent: bool,
+    /// Skip optional writes (ex. refreshing the index during status), for repositories fersk must not modify
+    pub read_only: bool,
+    /// Global config file used instead of the user's, if isolated
+    pub isolated_config: Option<PathBuf>,
+    /// Hooks directory used instead of the repository's (ex. an empty one to disable hooks)
+    pub hooks_path: Option<PathBuf>,
+    /// Number of commits of history to clone, and fetch into shallow repositories
+    pub depth: Option<u32>,
+    /// Object filter for partial clones (ex. blob:none)
+    pub filter: Option<String>,
+    /// Repository to borrow objects from through alternates when cloning
+    pub reference: Option<PathBuf>,
+    pub backend: GitBackend,
+    /// What fetches prune
+    pub prune: Prune,
+    pub clone_mode: CloneMode,
+}
+
+/// A path with changes, as reported by git status
+pub struct StatusEntry {
+    /// Two-letter status code (ex. " M", "??")
+    pub status: String,
+    pub path: String,
+}
 
-        f.write_str(s)
+/// A submodule, as listed in .gitmodules
+pub struct Submodule {
+    pub name: String,
+    pub path: String,
+    pub url: String,
+}
+
+impl StatusEntry {
+    /// Check if the path is untracked or ignored
+    pub fn is_untracked(&self) -> bool {
+        self.status == "??" || self.status == "!!"
     }
 }
 
 impl Git {
-    /// Cleanse repository
-    pub fn cleanse(&self, path: impl AsRef<Path>) -> Result<(), GitError> {
+    /// Isolate git operations from the user's global and system config,
+    /// using config files controlled by fersk in the specified directory instead
+    pub fn isolate(&mut self, config_dir: &Path) -> std::io::Result<()> {
+        std::fs::create_dir_all(config_dir)?;
+
+        let global_path = config_dir.join("gitconfig");
+        std::fs::write(&global_path, ISOLATED_GLOBAL_CONFIG)?;
+
+        let system_path = config_dir.join("gitconfig-system");
+        std::fs::write(&system_path, "")?;
+
+        // Git versions without GIT_CONFIG_GLOBAL read the global config from the home directory
+        std::fs::write(config_dir.join(".gitconfig"), ISOLATED_GLOBAL_CONFIG)?;
+
+        self.isolated_config = Some(global_path);
+
+        Ok(())
+    }
+
+    /// Get git with the same settings, for operations on repositories fersk must not modify
+    pub fn to_read_only(&self) -> Self {
+        Self {
+            silent: self.silent,
+            read_only: true,
+            isolated_config: self.isolated_config.clone(),
+            hooks_path: self.hooks_path.clone(),
+            depth: self.depth,
+            filter: self.filter.clone(),
+            reference: self.reference.clone(),
+            backend: self.backend,
+            prune: self.prune,
+            clone_mode: self.clone_mode,
+        }
+    }
+
+    /// Cleanse repository.
+    /// If only a few paths differ from HEAD, only those are restored or removed,
+    /// which is much faster than a full reset and clean in very large repositories.
+    /// Untracked and ignored paths under `preserve` (relative to the repository root) are kept.
+    /// Returns the number of paths that were restored or removed, if known.
+    pub fn cleanse(
+        &self,
+        path: impl AsRef<Path>,
+        mode: CleanseMode,
+        preserve: &[String],
+    ) -> Result<Option<usize>, GitError> {
+        let path = path.as_ref();
+
+        // git restore --pathspec-from-file requires git 2.26
+        if mode == CleanseMode::Full || self.backend == GitBackend::Libgit2 || !GitVersion::at_least(2, 26) {
+            self.cleanse_full(path, preserve)?;
+            return Ok(None);
+        }
+
+        let Some(entries) = self
+            .dirty_paths(path)?
+            .and_then(|entries| differential_cleanse_entries(entries, preserve))
+        else {
+            self.cleanse_full(path, preserve)?;
+            return Ok(None);
+        };
+
+        if self.cleanse_paths(path, &entries).is_err() {
+            self.cleanse_full(path, preserve)?;
+            return Ok(None);
+        }
+
+        Ok(Some(entries.len()))
+    }
+
+    fn cleanse_full(&self, path: &Path, preserve: &[String]) -> Result<(), GitError> {
+        // libgit2 doesn't support sparse checkout, and would fill in the whole working tree
+        #[cfg(feature = "libgit2")]
+        if self.backend == GitBackend::Libgit2 && !self.is_sparse(path) && preserve.is_empty() {
+            return crate::libgit2::cleanse(path);
+        }
+
         self.exec(|c| {
-            c.current_dir(&path);
+            c.current_dir(path);
 
             c.args(["reset", "--hard"]);
         })?;
 
         self.exec(|c| {
-            c.current_dir(&path);
+            c.current_dir(path);
 
+            // Ignore rules given with -e still apply with -x
             c.args(["clean", "-fdx"]);
+            for preserved in preserve {
+                c.arg("-e").arg(format!("/{}", preserved.trim_matches('/')));
+            }
+        })?;
+
+        Ok(())
+    }
+
+    /// Get all paths that differ from HEAD, including untracked and ignored ones.
+    /// Returns None if an operation (ex. a merge) is in progress, as that requires a full reset.
+    fn dirty_paths(&self, path: &Path) -> Result<Option<Vec<StatusEntry>>, GitError> {
+        for state in IN_PROGRESS_STATE_FILES {
+            if self.git_path(path, state)?.exists() {
+                return Ok(None);
+            }
+        }
+
+        let entries = self.status(path, true)?;
+
+        // Unmerged paths
+        if entries
+            .iter()
+            .any(|e| e.status.contains('U') || e.status == "AA" || e.status == "DD")
+        {
+            return Ok(None);
+        }
+
+        Ok(Some(entries))
+    }
+
+    /// Get paths with uncommitted changes, including untracked (and optionally ignored) paths.
+    /// Untracked directories are reported as a whole.
+    pub fn status(&self, path: impl AsRef<Path>, include_ignored: bool) -> Result<Vec<StatusEntry>, GitError> {
+        let output = self.exec_output(|c| {
+            c.current_dir(path);
+
+            c.args([
+                "status",
+                "--porcelain",
+                "-z",
+                "--no-renames",
+                "--untracked-files=normal",
+            ]);
+
+            if include_ignored {
+                c.arg("--ignored");
+            }
         })?;
 
+        Ok(output
+            .stdout
+            .split(|b| *b == 0)
+            .filter(|e| e.len() > 3)
+            .map(|entry| StatusEntry {
+                status: String::from_utf8_lossy(&entry[..2]).to_string(),
+                path: String::from_utf8_lossy(&entry[3..]).to_string(),
+            })
+            .collect())
+    }
+
+    /// Restore tracked paths and remove untracked ones
+    fn cleanse_paths(&self, path: &Path, entries: &[StatusEntry]) -> Result<(), GitError> {
+        let tracked: Vec<&str> = entries
+            .iter()
+            .filter(|e| !e.is_untracked())
+            .map(|e| e.path.as_str())
+            .collect();
+
+        if !tracked.is_empty() {
+            self.exec_with_pathspecs(path, &["restore", "--source=HEAD", "--staged", "--worktree"], &tracked)?;
+        }
+
+        for entry in entries.iter().filter(|e| e.is_untracked()) {
+            let entry_path = path.join(entry.path.trim_end_matches('/'));
+
+            let result = if entry.path.ends_with('/') {
+                // Like git clean, leave nested repositories alone
+                if entry_path.join(".git").exists() {
+                    continue;
+                }
+
+                util::remove_dir_all(&entry_path)
+            } else {
+                std::fs::remove_file(&entry_path)
+            };
+
+            result.map_err(GitError::Execute)?;
+        }
+
         Ok(())
     }
 
@@ -68,6 +558,11 @@ impl Git {
     where
         B: AsRef<str>,
     {
+        #[cfg(feature = "libgit2")]
+        if self.backend == GitBackend::Libgit2 && !self.is_sparse(&path) {
+            return crate::libgit2::checkout(path.as_ref(), rev.as_ref());
+        }
+
         self.exec(|c| {
             c.current_dir(&path);
 
@@ -77,6 +572,106 @@ impl Git {
         Ok(())
     }
 
+    /// Check out only the paths matching pathspecs from a revision, with HEAD detached at it.
+    /// The index and working tree are emptied first, which makes a separate cleanse unnecessary.
+    /// Everything outside the pathspecs shows up as deleted afterwards, so the next regular cleanse
+    /// will be a full one. Untracked and ignored paths under `preserve` are kept.
+    pub fn checkout_paths<B>(
+        &self,
+        path: impl AsRef<Path>,
+        rev: B,
+        pathspecs: &[String],
+        preserve: &[String],
+    ) -> Result<(), GitError>
+    where
+        B: AsRef<str>,
+    {
+        let path = path.as_ref();
+
+        // An operation in progress (ex. a merge) would survive emptying the index
+        for state in IN_PROGRESS_STATE_FILES {
+            if self.git_path(path, state)?.exists() {
+                self.cleanse_full(path, preserve)?;
+                break;
+            }
+        }
+
+        self.exec(|c| {
+            c.current_dir(path);
+
+            c.args(["read-tree", "--empty"]);
+        })?;
+
+        // With an empty index, everything in the working tree is untracked
+        self.exec(|c| {
+            c.current_dir(path);
+
+            c.args(["clean", "-fdxq"]);
+            for preserved in preserve {
+                c.arg("-e").arg(format!("/{}", preserved.trim_matches('/')));
+            }
+        })?;
+
+        self.exec(|c| {
+            c.current_dir(path);
+
+            c.args(["update-ref", "--no-deref", "HEAD", rev.as_ref()]);
+        })?;
+
+        self.exec(|c| {
+            c.current_dir(path);
+
+            c.args(["checkout", rev.as_ref(), "--"]);
+            c.args(pathspecs);
+        })?;
+
+        Ok(())
+    }
+
+    /// Merge a revision into the current HEAD, aborting the merge if it conflicts
+    pub fn merge<B>(&self, path: impl AsRef<Path>, rev: B) -> Result<(), GitError>
+    where
+        B: AsRef<str>,
+    {
+        let path = path.as_ref();
+
+        let result = self.exec(|c| {
+            c.current_dir(path);
+
+            // Use a fixed identity, since the merge commit is only temporary
+            c.args(["-c", "user.name=Fersk", "-c", "user.email=fersk@localhost"]);
+            c.args(["merge", "--no-ff", "--no-edit", rev.as_ref()]);
+        });
+
+        if result.is_ok() {
+            return Ok(());
+        }
+
+        let output = self.exec_output(|c| {
+            c.current_dir(path);
+
+            c.args(["diff", "--name-only", "--diff-filter=U"]);
+        })?;
+
+        let conflicts: Vec<String> = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(|l| l.to_owned())
+            .collect();
+
+        self.exec(|c| {
+            c.current_dir(path);
+
+            c.args(["merge", "--abort"]);
+        })
+        .ok();
+
+        if conflicts.is_empty() {
+            result
+        } else {
+            Err(GitError::MergeConflict(conflicts))
+        }
+    }
+
     /// Clone repository
     pub fn clone(
         &self,
@@ -84,6 +679,22 @@ impl Git {
         destination: impl AsRef<Path>,
         origin_name: Option<&str>,
     ) -> Result<(), GitError> {
+        // Shallow, partial, shared and reference clones are left to the git executable
+        #[cfg(feature = "libgit2")]
+        if self.backend == GitBackend::Libgit2
+            && self.depth.is_none()
+            && self.filter.is_none()
+            && self.reference.is_none()
+            && self.clone_mode != CloneMode::Shared
+        {
+            return crate::libgit2::clone(
+                &source.as_ref().to_string_lossy(),
+                destination.as_ref(),
+                origin_name.unwrap_or("origin"),
+                self.clone_mode == CloneMode::Hardlink,
+            );
+        }
+
         self.exec(|c| {
             c.arg("clone");
 
@@ -91,6 +702,52 @@ impl Git {
                 c.args(["--origin", origin_name]);
             }
 
+            if let Some(depth) = self.depth {
+                c.args(["--depth", &depth.to_string(), "--no-single-branch"]);
+            }
+
+            if let Some(filter) = &self.filter {
+                // Missing objects are fetched later with the remote's upload pack command
+                let remote_name = origin_name.unwrap_or("origin");
+
+                c.arg(format!("--filter={filter}"));
+                c.args(["--upload-pack", PARTIAL_CLONE_UPLOAD_PACK]);
+                c.arg("-c");
+                c.arg(format!("remote.{remote_name}.uploadpack={PARTIAL_CLONE_UPLOAD_PACK}"));
+            }
+
+            if let Some(reference) = &self.reference {
+                c.arg("--reference");
+                c.arg(reference);
+            }
+
+            // Local clones ignore --depth, --filter and --reference, unless they use the regular transport
+            if self.depth.is_some() || self.filter.is_some() || self.reference.is_some() {
+                c.arg("--no-local");
+            } else {
+                match self.clone_mode {
+                    CloneMode::Hardlink => c.arg("--local"),
+                    CloneMode::Shared => c.arg("--shared"),
+                    CloneMode::FullCopy => c.args(["--local", "--no-hardlinks"]),
+                };
+            }
+
+            c.arg(source);
+            c.arg(destination.as_ref());
+        })?;
+
+        Ok(())
+    }
+
+    /// Clone a local repository, using its object store (through alternates) instead of copying or linking objects
+    pub fn clone_shared(
+        &self,
+        source: impl AsRef<OsStr>,
+        destination: impl AsRef<Path>,
+        origin_name: &str,
+    ) -> Result<(), GitError> {
+        self.exec(|c| {
+            c.args(["clone", "--shared", "--origin", origin_name]);
             c.arg(source);
             c.arg(destination.as_ref());
         })?;
@@ -103,7 +760,12 @@ impl Git {
         match self.exec_output(|c| {
             c.current_dir(path);
 
-            c.args(["remote", "get-url", remote_name]);
+            // git remote get-url requires git 2.7
+            if GitVersion::at_least(2, 7) {
+                c.args(["remote", "get-url", remote_name]);
+            } else {
+                c.args(["config", "--get", &format!("remote.{remote_name}.url")]);
+            }
         }) {
             Ok(output) => Ok(String::from_utf8_lossy(&output.stdout).trim_end().to_owned()),
             Err(err) => Err(err),
@@ -118,11 +780,9 @@ impl Git {
         url: impl AsRef<OsStr>,
     ) -> Result<(), GitError> {
         // Remove remote if it already exists
-        self.exec(|c| {
-            c.current_dir(&path);
-            c.args(["remote", "remove", remote_name]);
-        })
-        .ok();
+        if self.list_remotes(&path)?.iter().any(|r| r == remote_name) {
+            self.remove_remote(&path, remote_name)?;
+        }
 
         // Add remote
         self.exec(|c| {
@@ -135,68 +795,928 @@ impl Git {
         Ok(())
     }
 
-    /// Fetch repository
-    pub fn fetch(&self, path: impl AsRef<Path>, remote_name: &str) -> Result<(), GitError> {
+    /// Set config value in repository
+    pub fn set_config(&self, path: impl AsRef<Path>, key: &str, value: &str) -> Result<(), GitError> {
         self.exec(|c| {
             c.current_dir(path);
 
-            c.args(["fetch", remote_name, "--prune"]);
+            c.args(["config", key, value]);
         })?;
 
         Ok(())
     }
 
-    /// Get root path of repository
-    pub fn get_repository_root(&self, path: impl AsRef<Path>) -> Result<PathBuf, GitError> {
+    /// Add a value to a multi-valued config key
+    pub fn add_config(&self, path: impl AsRef<Path>, key: &str, value: &str) -> Result<(), GitError> {
+        self.exec(|c| {
+            c.current_dir(path);
+
+            c.args(["config", "--add", key, value]);
+        })?;
+
+        Ok(())
+    }
+
+    /// Get all values of a config key
+    pub fn get_config_all(&self, path: impl AsRef<Path>, key: &str) -> Result<Vec<String>, GitError> {
         match self.exec_output(|c| {
             c.current_dir(path);
 
-            c.args(["rev-parse", "--show-toplevel"]);
+            c.args(["config", "--get-all", key]);
         }) {
-            Ok(output) => Ok(PathBuf::from_str(String::from_utf8_lossy(&output.stdout).trim_end()).unwrap()),
+            Ok(output) => Ok(String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .map(|l| l.to_owned())
+                .collect()),
+            // Exit code 1 means the key was not found
+            Err(GitError::Unknown(Some(1), _)) => Ok(Vec::new()),
             Err(err) => Err(err),
         }
     }
 
-    /// Get current branch or commit hash
-    pub fn get_current_head(&self, path: impl AsRef<Path>) -> Result<GitRev, GitError> {
+    /// List remotes
+    pub fn list_remotes(&self, path: impl AsRef<Path>) -> Result<Vec<String>, GitError> {
         let output = self.exec_output(|c| {
-            c.current_dir(&path);
+            c.current_dir(path);
 
-            c.args(["rev-parse", "--abbrev-ref", "HEAD"]);
+            c.arg("remote");
         })?;
 
-        let out = String::from_utf8_lossy(&output.stdout).trim_end().to_string();
-        if out != "HEAD" {
-            return Ok(GitRev::Branch(out));
-        }
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(|l| l.to_owned())
+            .collect())
+    }
 
-        let output = self.exec_output(|c| {
-            c.current_dir(&path);
+    /// Remove remote
+    pub fn remove_remote(&self, path: impl AsRef<Path>, remote_name: &str) -> Result<(), GitError> {
+        self.exec(|c| {
+            c.current_dir(path);
 
-            c.args(["rev-parse", "HEAD"]);
+            c.args(["remote", "remove", remote_name]);
         })?;
 
-        Ok(GitRev::Commit(
-            String::from_utf8_lossy(&output.stdout).trim_end().to_string(),
+        Ok(())
+    }
+
+    /// Verify the integrity of the repository's objects
+    pub fn fsck(&self, path: impl AsRef<Path>) -> Result<(), GitError> {
+        self.exec(|c| {
+            c.current_dir(path);
+
+            c.args(["fsck", "--no-progress"]);
+        })?;
+
+        Ok(())
+    }
+
+    /// Remove the untracked cache from the index
+    pub fn remove_untracked_cache(&self, path: impl AsRef<Path>) -> Result<(), GitError> {
+        self.exec(|c| {
+            c.current_dir(path);
+
+            c.args(["update-index", "--no-untracked-cache"]);
+        })?;
+
+        Ok(())
+    }
+
+    /// Disable automatic maintenance and gc in repository
+    pub fn disable_maintenance(&self, path: impl AsRef<Path>) -> Result<(), GitError> {
+        let path = path.as_ref();
+
+        for (key, value) in NO_MAINTENANCE_CONFIG {
+            self.set_config(path, key, value)?;
+        }
+
+        Ok(())
+    }
+
+    /// Stop all background processes working in a repository, before deleting or rewriting its files
+    pub fn stop_daemons(&self, path: impl AsRef<Path>) {
+        let path = path.as_ref();
+
+        self.stop_fsmonitor(path);
+        self.stop_detached_gc(path);
+    }
+
+    /// Stop a gc running detached in the background, if there is one.
+    /// Its PID is recorded in `gc.pid` in the git directory, followed by the hostname.
+    pub fn stop_detached_gc(&self, path: impl AsRef<Path>) {
+        let Ok(git_dirs) = self.git_dirs(path) else {
+            return;
+        };
+
+        for git_dir in git_dirs {
+            let Some(pid) = std::fs::read_to_string(git_dir.join("gc.pid"))
+                .ok()
+                .and_then(|s| s.split_whitespace().next()?.parse::<u32>().ok())
+            else {
+                continue;
+            };
+
+            // The file is left behind if gc was killed, so the PID may have been reused
+            if util::process::process_name(pid).is_some_and(|name| name.starts_with("git")) {
+                util::process::terminate_tree(pid, GC_GRACE_PERIOD);
+            }
+        }
+    }
+
+    /// Stop the builtin fsmonitor daemon, if it is running.
+    /// Returns whether a daemon was stopped.
+    pub fn stop_fsmonitor(&self, path: impl AsRef<Path>) -> bool {
+        self.exec(|c| {
+            c.current_dir(path);
+            c.stderr(Stdio::null());
+
+            c.args(["fsmonitor--daemon", "stop"]);
+        })
+        .is_ok()
+    }
+
+    /// Replace all URL rewrite rules (`url.<base>.insteadOf`) in repository
+    pub fn set_url_rewrites<'a>(
+        &self,
+        path: impl AsRef<Path>,
+        rules: impl IntoIterator<Item = (&'a String, &'a String)>,
+    ) -> Result<(), GitError> {
+        let path = path.as_ref();
+
+        // Remove any previously configured rules, leaving the user's global and system ones alone
+        let output = self.exec_output(|c| {
+            c.current_dir(path);
+
+            c.args([
+                "config",
+                "--local",
+                "--name-only",
+                "--get-regexp",
+                r"^url\..*\.insteadof$",
+            ]);
+        });
+
+        let existing = match output {
+            Ok(output) => String::from_utf8_lossy(&output.stdout).to_string(),
+            // Exit code 1 means no matching keys were found
+            Err(GitError::Unknown(Some(1), _)) => String::new(),
+            Err(err) => return Err(err),
+        };
+
+        for key in existing.lines() {
+            self.exec(|c| {
+                c.current_dir(path);
+
+                c.args(["config", "--local", "--unset-all", key]);
+            })
+            .ok();
+        }
+
+        for (prefix, replacement) in rules {
+            self.exec(|c| {
+                c.current_dir(path);
+
+                c.args(["config", "--add", &format!("url.{replacement}.insteadOf"), prefix]);
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// List refs starting with a prefix (ex. `refs/heads/`)
+    pub fn list_refs(&self, path: impl AsRef<Path>, prefix: &str) -> Result<Vec<String>, GitError> {
+        let output = self.exec_output(|c| {
+            c.current_dir(path);
+
+            c.args(["for-each-ref", "--format=%(refname)", prefix]);
+        })?;
+
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(|l| l.to_owned())
+            .collect())
+    }
+
+    /// Get the short names of the local branches pointing at a commit
+    pub fn branches_at(&self, path: impl AsRef<Path>, commit: &str) -> Result<Vec<String>, GitError> {
+        let output = self.exec_output(|c| {
+            c.current_dir(path);
+
+            c.args([
+                "for-each-ref",
+                "--format=%(refname:short)",
+                "--points-at",
+                commit,
+                "refs/heads/",
+            ]);
+        })?;
+
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(|l| l.to_owned())
+            .collect())
+    }
+
+    /// Delete ref
+    pub fn delete_ref(&self, path: impl AsRef<Path>, refname: &str) -> Result<(), GitError> {
+        self.exec(|c| {
+            c.current_dir(path);
+
+            c.args(["update-ref", "-d", refname]);
+        })?;
+
+        Ok(())
+    }
+
+    /// Pack objects and remove unreachable ones, as automatic gc is disabled in work directories
+    pub fn gc(&self, path: impl AsRef<Path>) -> Result<(), GitError> {
+        self.exec(|c| {
+            c.current_dir(path);
+
+            c.args(["gc", "--quiet"]);
+        })?;
+
+        Ok(())
+    }
+
+    /// Fetch repository
+    pub fn fetch(&self, path: impl AsRef<Path>, remote_name: &str) -> Result<(), GitError> {
+        let depth = self.shallow_depth(path.as_ref());
+
+        #[cfg(feature = "libgit2")]
+        if self.backend == GitBackend::Libgit2
+            && depth.is_empty()
+            && self.prune != Prune::BranchesAndTags
+            && crate::libgit2::is_local_remote(path.as_ref(), remote_name)
+        {
+            return crate::libgit2::fetch(path.as_ref(), remote_name, self.prune == Prune::Branches);
+        }
+
+        self.exec(|c| {
+            c.current_dir(path);
+
+            c.args(["fetch", remote_name]);
+
+            if self.prune != Prune::None {
+                c.arg("--prune");
+            }
+
+            // git fetch --prune-tags requires git 2.17, so older versions only prune branches
+            if self.prune == Prune::BranchesAndTags && GitVersion::at_least(2, 17) {
+                c.arg("--prune-tags");
+            }
+
+            c.args(&depth);
+        })?;
+
+        Ok(())
+    }
+
+    /// Get the arguments limiting the depth of a fetch, if the repository is shallow
+    fn shallow_depth(&self, path: &Path) -> Vec<String> {
+        match self.depth {
+            Some(depth) if self.is_shallow(path) => vec!["--depth".to_owned(), depth.to_string()],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Check if a repository only has part of its history
+    pub fn is_shallow(&self, path: impl AsRef<Path>) -> bool {
+        self.exec_output(|c| {
+            c.current_dir(path);
+
+            c.args(["rev-parse", "--is-shallow-repository"]);
+        })
+        .is_ok_and(|output| output.stdout.starts_with(b"true"))
+    }
+
+    /// Fetch this many more commits of history into a shallow repository
+    pub fn deepen(&self, path: impl AsRef<Path>, remote_name: &str, commits: u32) -> Result<(), GitError> {
+        self.exec(|c| {
+            c.current_dir(path);
+
+            c.args(["fetch", remote_name, &format!("--deepen={commits}")]);
+        })?;
+
+        Ok(())
+    }
+
+    /// Fetch the complete history into a shallow repository
+    pub fn unshallow(&self, path: impl AsRef<Path>, remote_name: &str) -> Result<(), GitError> {
+        self.exec(|c| {
+            c.current_dir(path);
+
+            c.args(["fetch", remote_name, "--unshallow"]);
+        })?;
+
+        Ok(())
+    }
+
+    /// Fetch a single branch from a remote, updating its remote-tracking branch
+    pub fn fetch_branch(&self, path: impl AsRef<Path>, remote_name: &str, branch: &str) -> Result<(), GitError> {
+        let depth = self.shallow_depth(path.as_ref());
+
+        self.exec(|c| {
+            c.current_dir(path);
+
+            c.args(["fetch", remote_name]);
+            c.args(&depth);
+            c.arg(format!("+refs/heads/{branch}:refs/remotes/{remote_name}/{branch}"));
+        })?;
+
+        Ok(())
+    }
+
+    /// Fetch a single ref from a remote into a local ref
+    pub fn fetch_ref(&self, path: impl AsRef<Path>, remote_name: &str, src: &str, dst: &str) -> Result<(), GitError> {
+        let depth = self.shallow_depth(path.as_ref());
+
+        self.exec(|c| {
+            c.current_dir(path);
+
+            c.args(["fetch", remote_name]);
+            c.args(&depth);
+            c.arg(format!("+{src}:{dst}"));
+        })?;
+
+        Ok(())
+    }
+
+    /// Get root path of repository
+    pub fn get_repository_root(&self, path: impl AsRef<Path>) -> Result<PathBuf, GitError> {
+        match self.exec_output(|c| {
+            c.current_dir(path);
+
+            c.args(["rev-parse", "--show-toplevel"]);
+        }) {
+            Ok(output) => Ok(PathBuf::from_str(String::from_utf8_lossy(&output.stdout).trim_end()).unwrap()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Get the path of a file inside the git directory (ex. "hooks"), respecting settings like core.hooksPath
+    pub fn git_path(&self, path: impl AsRef<Path>, name: &str) -> Result<PathBuf, GitError> {
+        let path = path.as_ref();
+
+        let output = self.exec_output(|c| {
+            c.current_dir(path);
+
+            c.args(["rev-parse", "--git-path", name]);
+        })?;
+
+        // The path is relative to the working directory, unless it is absolute
+        Ok(path.join(String::from_utf8_lossy(&output.stdout).trim_end()))
+    }
+
+    /// Get the git directory of a repository, followed by the common git directory if it is a worktree
+    pub fn git_dirs(&self, path: impl AsRef<Path>) -> Result<Vec<PathBuf>, GitError> {
+        let path = path.as_ref();
+
+        let output = self.exec_output(|c| {
+            c.current_dir(path);
+
+            c.args(["rev-parse", "--absolute-git-dir", "--git-common-dir"]);
+        })?;
+
+        let mut dirs: Vec<PathBuf> = Vec::new();
+
+        // The common directory is relative to the working directory, unless it is absolute
+        for line in String::from_utf8_lossy(&output.stdout).lines() {
+            let dir = util::normalize_path(path.join(line));
+
+            if !dirs.contains(&dir) {
+                dirs.push(dir);
+            }
+        }
+
+        Ok(dirs)
+    }
+
+    /// Get current branch or commit hash
+    pub fn get_current_head(&self, path: impl AsRef<Path>) -> Result<GitRev, GitError> {
+        let output = self.exec_output(|c| {
+            c.current_dir(&path);
+
+            c.args(["rev-parse", "--abbrev-ref", "HEAD"]);
+        })?;
+
+        let out = String::from_utf8_lossy(&output.stdout).trim_end().to_string();
+        if out != "HEAD" {
+            return Ok(GitRev::Branch(out));
+        }
+
+        let output = self.exec_output(|c| {
+            c.current_dir(&path);
+
+            c.args(["rev-parse", "HEAD"]);
+        })?;
+
+        Ok(GitRev::Commit(
+            String::from_utf8_lossy(&output.stdout).trim_end().to_string(),
+        ))
+    }
+
+    /// Resolve revision to a commit hash
+    pub fn rev_parse(&self, path: impl AsRef<Path>, rev: &str) -> Result<String, GitError> {
+        let output = self.exec_output(|c| {
+            c.current_dir(path);
+
+            c.args(["rev-parse", "--verify", "--quiet"]);
+            c.arg(format!("{rev}^{{commit}}"));
+        })?;
+
+        Ok(String::from_utf8_lossy(&output.stdout).trim_end().to_string())
+    }
+
+    /// Get the upstream of a local branch (ex. origin/main), if it has one
+    pub fn upstream(&self, path: impl AsRef<Path>, branch: &str) -> Option<String> {
+        let output = self
+            .exec_output(|c| {
+                c.current_dir(path);
+
+                c.args(["rev-parse", "--abbrev-ref", "--symbolic-full-name"]);
+                c.arg(format!("{branch}@{{upstream}}"));
+            })
+            .ok()?;
+
+        let upstream = String::from_utf8_lossy(&output.stdout).trim_end().to_owned();
+
+        (!upstream.is_empty()).then_some(upstream)
+    }
+
+    /// Count the commits in a range (ex. `a..b`)
+    pub fn count_commits(&self, path: impl AsRef<Path>, range: &str) -> Result<usize, GitError> {
+        let output = self.exec_output(|c| {
+            c.current_dir(path);
+
+            c.args(["rev-list", "--count", range, "--"]);
+        })?;
+
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .trim_end()
+            .parse()
+            .unwrap_or_default())
+    }
+
+    /// Get the best common ancestor of two commits
+    pub fn merge_base(&self, path: impl AsRef<Path>, a: &str, b: &str) -> Result<String, GitError> {
+        let output = self.exec_output(|c| {
+            c.current_dir(path);
+
+            c.args(["merge-base", a, b]);
+        })?;
+
+        Ok(String::from_utf8_lossy(&output.stdout).trim_end().to_string())
+    }
+
+    /// Get the root commits (commits without parents) reachable from a revision
+    pub fn root_commits(&self, path: impl AsRef<Path>, rev: &str) -> Result<Vec<String>, GitError> {
+        let output = self.exec_output(|c| {
+            c.current_dir(path);
+
+            c.args(["rev-list", "--max-parents=0", rev, "--"]);
+        })?;
+
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(|line| line.to_owned())
+            .collect())
+    }
+
+    /// Verify the signature of a tag, using the specified SSH allowed signers file and GnuPG home if any.
+    /// Returns whether the signature is valid, and git's report of it.
+    pub fn verify_tag(
+        &self,
+        path: impl AsRef<Path>,
+        tag: &str,
+        allowed_signers: Option<&Path>,
+        gnupg_home: Option<&Path>,
+    ) -> Result<(bool, String), GitError> {
+        let mut command = self.command();
+        command.current_dir(path);
+
+        if let Some(allowed_signers) = allowed_signers {
+            let mut arg = OsString::from("gpg.ssh.allowedSignersFile=");
+            arg.push(allowed_signers);
+
+            command.arg("-c").arg(arg);
+        }
+
+        if let Some(gnupg_home) = gnupg_home {
+            command.env("GNUPGHOME", gnupg_home);
+        }
+
+        command.args(["verify-tag", "--raw", tag]);
+
+        trace!("Running {}", describe_command(&command));
+        let started = Instant::now();
+
+        let output = command.output().map_err(GitError::Execute)?;
+        trace_exit(output.status, started);
+
+        Ok((
+            output.status.success(),
+            String::from_utf8_lossy(&output.stderr).trim_end().to_owned(),
         ))
     }
 
+    /// Check if git-lfs is installed
+    pub fn lfs_available(&self) -> bool {
+        self.exec_output(|c| {
+            c.args(["lfs", "version"]);
+        })
+        .is_ok()
+    }
+
+    /// Set up Git LFS in a repository, without downloading content during checkout
+    pub fn lfs_install_local(&self, path: impl AsRef<Path>) -> Result<(), GitError> {
+        self.exec_output(|c| {
+            c.current_dir(path);
+
+            c.args(["lfs", "install", "--local", "--skip-smudge"]);
+        })?;
+
+        Ok(())
+    }
+
+    /// Fetch the LFS objects of a revision from a remote
+    pub fn lfs_fetch(&self, path: impl AsRef<Path>, remote_name: &str, rev: &str) -> Result<(), GitError> {
+        self.exec(|c| {
+            c.current_dir(path);
+
+            c.args(["lfs", "fetch", remote_name, rev]);
+        })?;
+
+        Ok(())
+    }
+
+    /// Replace LFS pointer files in the working tree with their content, from objects already fetched
+    pub fn lfs_checkout(&self, path: impl AsRef<Path>) -> Result<(), GitError> {
+        self.exec(|c| {
+            c.current_dir(path);
+
+            c.args(["lfs", "checkout"]);
+        })?;
+
+        Ok(())
+    }
+
+    /// Get the names, paths and URLs of the submodules listed in .gitmodules
+    pub fn submodules(&self, path: impl AsRef<Path>) -> Result<Vec<Submodule>, GitError> {
+        let output = self.exec_output(|c| {
+            c.current_dir(path);
+
+            // Exits with 1 if there are no matches
+            c.args([
+                "config",
+                "--file",
+                ".gitmodules",
+                "--get-regexp",
+                r"^submodule\..*\.(path|url)$",
+            ]);
+        });
+
+        let output = match output {
+            Ok(output) => output,
+            Err(GitError::Unknown(Some(1), _)) => return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        };
+
+        let mut submodules: Vec<Submodule> = Vec::new();
+
+        for line in String::from_utf8_lossy(&output.stdout).lines() {
+            let Some((key, value)) = line.split_once(' ') else {
+                continue;
+            };
+            let Some((name, setting)) = key.strip_prefix("submodule.").and_then(|k| k.rsplit_once('.')) else {
+                continue;
+            };
+
+            let index = match submodules.iter().position(|s| s.name == name) {
+                Some(index) => index,
+                None => {
+                    submodules.push(Submodule {
+                        name: name.to_owned(),
+                        path: String::new(),
+                        url: String::new(),
+                    });
+                    submodules.len() - 1
+                }
+            };
+
+            match setting {
+                "path" => submodules[index].path = value.to_owned(),
+                _ => submodules[index].url = value.to_owned(),
+            }
+        }
+
+        Ok(submodules)
+    }
+
+    /// Initialize submodules and check out their recorded commits, recursively, discarding changes in them.
+    /// The file transport is only allowed for the submodules at `local_paths`, and not for their own submodules.
+    pub fn update_submodules(&self, path: impl AsRef<Path>, local_paths: &[String]) -> Result<(), GitError> {
+        let path = path.as_ref();
+
+        // Cloning with the file transport is disallowed by default, as repositories could use it to get at
+        // any repository on the machine
+        if !local_paths.is_empty() {
+            self.exec(|c| {
+                c.current_dir(path);
+
+                c.args(["-c", "protocol.file.allow=always"]);
+                c.args(["submodule", "update", "--init", "--force", "--"]);
+                c.args(local_paths);
+            })?;
+        }
+
+        self.exec(|c| {
+            c.current_dir(path);
+
+            c.args(["submodule", "update", "--init", "--recursive", "--force"]);
+        })?;
+
+        self.exec(|c| {
+            c.current_dir(path);
+
+            c.args(["submodule", "foreach", "--quiet", "--recursive", "git clean -ffdxq"]);
+        })?;
+
+        Ok(())
+    }
+
+    /// Check if sparse checkout is enabled
+    pub fn is_sparse(&self, path: impl AsRef<Path>) -> bool {
+        self.get_config_all(path, "core.sparseCheckout")
+            .is_ok_and(|values| values.last().is_some_and(|v| v == "true"))
+    }
+
+    /// Check if the index of a sparse checkout is sparse, with directories outside the sparse checkout
+    /// as single entries instead of all their files
+    pub fn is_sparse_index(&self, path: impl AsRef<Path>) -> bool {
+        self.get_config_all(path, "index.sparse")
+            .is_ok_and(|values| values.last().is_some_and(|v| v == "true"))
+    }
+
+    /// Check if sparse checkouts should have a sparse index, which speeds up status, clean and checkout
+    /// in large repositories. It requires git 2.32, and libgit2 can't read it.
+    pub fn wants_sparse_index(&self) -> bool {
+        self.backend == GitBackend::Cli && GitVersion::at_least(2, 32)
+    }
+
+    /// Get the directories included in a sparse checkout
+    pub fn sparse_checkout_list(&self, path: impl AsRef<Path>) -> Result<Vec<String>, GitError> {
+        let output = self.exec_output(|c| {
+            c.current_dir(path);
+
+            c.args(["sparse-checkout", "list"]);
+        })?;
+
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(|l| l.to_owned())
+            .collect())
+    }
+
+    /// Limit the working tree to the specified directories (and files at the root), in cone mode
+    pub fn set_sparse_checkout(&self, path: impl AsRef<Path>, dirs: &[String]) -> Result<(), GitError> {
+        self.exec(|c| {
+            c.current_dir(path);
+
+            c.args(["sparse-checkout", "set", "--cone"]);
+
+            if GitVersion::at_least(2, 32) {
+                c.arg(if self.wants_sparse_index() {
+                    "--sparse-index"
+                } else {
+                    "--no-sparse-index"
+                });
+            }
+
+            c.args(dirs);
+        })?;
+
+        Ok(())
+    }
+
+    /// Restore the full working tree of a sparse checkout
+    pub fn disable_sparse_checkout(&self, path: impl AsRef<Path>) -> Result<(), GitError> {
+        self.exec(|c| {
+            c.current_dir(path);
+
+            c.args(["sparse-checkout", "disable"]);
+        })?;
+
+        Ok(())
+    }
+
+    /// Create a linked worktree with a revision checked out, with HEAD detached at it
+    pub fn add_worktree(
+        &self,
+        path: impl AsRef<Path>,
+        worktree_path: impl AsRef<Path>,
+        rev: &str,
+    ) -> Result<(), GitError> {
+        self.exec(|c| {
+            c.current_dir(path);
+
+            c.args(["worktree", "add", "--quiet", "--detach", "--force"]);
+            c.arg(worktree_path.as_ref());
+            c.arg(rev);
+        })?;
+
+        Ok(())
+    }
+
+    /// Remove information about linked worktrees that no longer exist
+    pub fn prune_worktrees(&self, path: impl AsRef<Path>) -> Result<(), GitError> {
+        self.exec(|c| {
+            c.current_dir(path);
+
+            c.args(["worktree", "prune"]);
+        })?;
+
+        Ok(())
+    }
+
+    /// Get the paths of all files in a commit
+    pub fn list_files(&self, path: impl AsRef<Path>, rev: &str) -> Result<Vec<String>, GitError> {
+        let output = self.exec_output(|c| {
+            c.current_dir(path);
+
+            c.args(["ls-tree", "-r", "--name-only", "-z", rev]);
+        })?;
+
+        Ok(output
+            .stdout
+            .split(|b| *b == 0)
+            .filter(|name| !name.is_empty())
+            .map(|name| String::from_utf8_lossy(name).to_string())
+            .collect())
+    }
+
+    /// Check out paths from HEAD, overwriting them in the index and working tree.
+    /// Requires git 2.25.
+    pub fn restore_from_head(&self, path: impl AsRef<Path>, paths: &[String]) -> Result<(), GitError> {
+        let paths: Vec<&str> = paths.iter().map(String::as_str).collect();
+
+        self.exec_with_pathspecs(path.as_ref(), &["checkout", "HEAD"], &paths)
+    }
+
+    /// Execute a git command taking literal paths, passing them on stdin so there is no limit to how many there are
+    fn exec_with_pathspecs(&self, path: &Path, args: &[&str], paths: &[&str]) -> Result<(), GitError> {
+        let mut command = self.command();
+        command
+            .current_dir(path)
+            .arg("--literal-pathspecs")
+            .args(args)
+            .args(["--pathspec-from-file=-", "--pathspec-file-nul"])
+            .stdin(Stdio::piped())
+            .stderr(Stdio::piped());
+
+        if self.silent {
+            command.stdout(Stdio::null());
+        }
+
+        let mut child = command.spawn().map_err(GitError::Execute)?;
+
+        if let Some(mut stdin) = child.stdin.take() {
+            for p in paths {
+                stdin.write_all(p.as_bytes()).map_err(GitError::Execute)?;
+                stdin.write_all(b"\0").map_err(GitError::Execute)?;
+            }
+        }
+
+        let output = child.wait_with_output().map_err(GitError::Execute)?;
+        if !output.status.success() {
+            return Err(GitError::from_stderr(output.status.code(), &output.stderr));
+        }
+
+        Ok(())
+    }
+
+    /// Get the names of the files and directories at the root of a commit
+    pub fn list_tree(&self, path: impl AsRef<Path>, rev: &str) -> Result<Vec<String>, GitError> {
+        let output = self.exec_output(|c| {
+            c.current_dir(path);
+
+            c.args(["ls-tree", "--name-only", "-z", rev]);
+        })?;
+
+        Ok(output
+            .stdout
+            .split(|b| *b == 0)
+            .filter(|name| !name.is_empty())
+            .map(|name| String::from_utf8_lossy(name).to_string())
+            .collect())
+    }
+
+    /// Get the committer time of a commit, as a unix timestamp
+    pub fn commit_time(&self, path: impl AsRef<Path>, rev: &str) -> Result<u64, GitError> {
+        let output = self.exec_output(|c| {
+            c.current_dir(path);
+
+            c.args(["log", "-1", "--format=%ct", rev]);
+        })?;
+
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .trim_end()
+            .parse()
+            .unwrap_or_default())
+    }
+
+    /// Get paths changed between two commits, optionally limited to pathspecs
+    pub fn changed_paths(
+        &self,
+        path: impl AsRef<Path>,
+        from: &str,
+        to: &str,
+        pathspecs: &[String],
+    ) -> Result<Vec<String>, GitError> {
+        let output = self.exec_output(|c| {
+            c.current_dir(path);
+
+            c.args(["diff", "--name-only", from, to, "--"]);
+            c.args(pathspecs);
+        })?;
+
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(|l| l.to_owned())
+            .collect())
+    }
+
+    /// Create a git command, applying config isolation
+    fn command(&self) -> Command {
+        let mut command = Command::new("git");
+
+        if let Some(global_path) = &self.isolated_config {
+            // GIT_CONFIG_GLOBAL and GIT_CONFIG_SYSTEM require git 2.32
+            if GitVersion::at_least(2, 32) {
+                command.env("GIT_CONFIG_GLOBAL", global_path);
+                command.env("GIT_CONFIG_SYSTEM", global_path.with_file_name("gitconfig-system"));
+            } else if let Some(config_dir) = global_path.parent() {
+                command.env("GIT_CONFIG_NOSYSTEM", "1");
+                command.env("HOME", config_dir);
+                command.env("XDG_CONFIG_HOME", config_dir);
+            }
+        }
+
+        if self.read_only {
+            command.env("GIT_OPTIONAL_LOCKS", "0");
+        }
+
+        if let Some(hooks_path) = &self.hooks_path {
+            let mut arg = OsString::from("core.hooksPath=");
+            arg.push(hooks_path);
+
+            command.arg("-c").arg(arg);
+        }
+
+        command
+    }
+
     /// Execute git command and get status
     fn exec(&self, f: impl FnOnce(&mut Command)) -> Result<(), GitError> {
-        let mut command = Command::new("git");
+        let mut command = self.command();
 
         if self.silent {
             command.stdout(Stdio::null());
         }
 
+        command.stderr(Stdio::piped());
+
         f(&mut command);
 
         // Execute command
-        let status = command.status().map_err(|_| GitError::Execute)?;
+        trace!("Running {}", describe_command(&command));
+        let started = Instant::now();
+
+        let mut child = command.spawn().map_err(GitError::Execute)?;
+
+        // Capture error output for error reporting, while still showing it unless silent
+        let mut stderr = Vec::new();
+        if let Some(mut child_stderr) = child.stderr.take() {
+            let mut buf = [0u8; 4096];
+
+            loop {
+                let n = match child_stderr.read(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => n,
+                };
+
+                if !self.silent {
+                    std::io::stderr().write_all(&buf[..n]).ok();
+                }
+
+                stderr.extend_from_slice(&buf[..n]);
+            }
+        }
+
+        let status = child.wait().map_err(GitError::Execute)?;
+        trace_exit(status, started);
 
         if !status.success() {
-            return Err(GitError::Unknown(status.code()));
+            return Err(GitError::from_stderr(status.code(), &stderr));
         }
 
         Ok(())
@@ -204,18 +1724,228 @@ impl Git {
 
     /// Execute git command and get output
     fn exec_output(&self, f: impl FnOnce(&mut Command)) -> Result<Output, GitError> {
-        let mut command = Command::new("git");
-        command.stderr(Stdio::inherit());
+        let mut command = self.command();
+        command.stderr(Stdio::piped());
 
         f(&mut command);
 
         // Execute command
-        let output = command.output().map_err(|_| GitError::Execute)?;
+        trace!("Running {}", describe_command(&command));
+        let started = Instant::now();
+
+        let output = command.output().map_err(GitError::Execute)?;
+        trace_exit(output.status, started);
 
         if !output.status.success() {
-            return Err(GitError::Unknown(output.status.code()));
+            return Err(GitError::from_stderr(output.status.code(), &output.stderr));
         }
 
         Ok(output)
     }
 }
+
+/// Get the paths to cleanse individually, leaving out preserved ones.
+/// Returns None if a full reset and clean is needed instead.
+fn differential_cleanse_entries(entries: Vec<StatusEntry>, preserve: &[String]) -> Option<Vec<StatusEntry>> {
+    // Untracked directories are reported as a whole, and may contain preserved paths
+    if entries
+        .iter()
+        .any(|e| e.is_untracked() && contains_preserved(&e.path, preserve))
+    {
+        return None;
+    }
+
+    let entries: Vec<StatusEntry> = entries
+        .into_iter()
+        .filter(|e| !(e.is_untracked() && is_preserved(&e.path, preserve)))
+        .collect();
+
+    (entries.len() <= DIFFERENTIAL_CLEANSE_MAX_PATHS).then_some(entries)
+}
+
+/// Check if a path (as reported by git status) is, or is inside, a preserved path
+fn is_preserved(path: &str, preserve: &[String]) -> bool {
+    let path = path.trim_end_matches('/');
+
+    preserve.iter().map(|p| p.trim_matches('/')).any(|p| {
+        path.strip_prefix(p)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    })
+}
+
+/// Check if a directory (as reported by git status) has a preserved path inside it
+fn contains_preserved(path: &str, preserve: &[String]) -> bool {
+    let Some(dir) = path.strip_suffix('/') else {
+        return false;
+    };
+
+    preserve.iter().any(|p| {
+        p.trim_matches('/')
+            .strip_prefix(dir)
+            .is_some_and(|rest| rest.starts_with('/'))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Create a repository with a few committed files
+    fn repository() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+
+        for (name, content) in [
+            ("a.txt", "a"),
+            ("b.txt", "b"),
+            ("c.txt", "c"),
+            (".gitignore", "*.log\ntarget/\n"),
+        ] {
+            std::fs::write(dir.path().join(name), content).unwrap();
+        }
+
+        git(dir.path(), &["init", "-q"]);
+        git(dir.path(), &["add", "."]);
+        git(dir.path(), &["commit", "-q", "-m", "Initial commit"]);
+
+        dir
+    }
+
+    fn git(path: &Path, args: &[&str]) {
+        let status = Command::new("git")
+            .current_dir(path)
+            .args(args)
+            .env("GIT_CONFIG_NOSYSTEM", "1")
+            .env("GIT_CONFIG_GLOBAL", "/dev/null")
+            .env("GIT_AUTHOR_NAME", "Test")
+            .env("GIT_AUTHOR_EMAIL", "test@example.com")
+            .env("GIT_COMMITTER_NAME", "Test")
+            .env("GIT_COMMITTER_EMAIL", "test@example.com")
+            .status()
+            .unwrap();
+
+        assert!(status.success(), "git {args:?} failed");
+    }
+
+    fn entry(status: &str, path: &str) -> StatusEntry {
+        StatusEntry {
+            status: status.to_owned(),
+            path: path.to_owned(),
+        }
+    }
+
+    #[test]
+    fn cleanses_only_dirty_paths() {
+        let repo = repository();
+        let path = repo.path();
+
+        std::fs::write(path.join("a.txt"), "changed").unwrap();
+        std::fs::remove_file(path.join("b.txt")).unwrap();
+        git(path, &["mv", "c.txt", "d.txt"]);
+        std::fs::write(path.join("e.txt"), "untracked").unwrap();
+        std::fs::create_dir_all(path.join("new")).unwrap();
+        std::fs::write(path.join("new/file.txt"), "untracked").unwrap();
+        std::fs::write(path.join("build.log"), "ignored").unwrap();
+        std::fs::create_dir_all(path.join("target")).unwrap();
+        std::fs::write(path.join("target/app"), "preserved").unwrap();
+
+        let git = Git::default();
+        let cleansed = git
+            .cleanse(path, CleanseMode::Differential, &["target".to_owned()])
+            .unwrap();
+
+        // The rename is a deletion and an addition
+        assert_eq!(cleansed, Some(7));
+        assert_eq!(std::fs::read_to_string(path.join("a.txt")).unwrap(), "a");
+        assert!(path.join("b.txt").exists());
+        assert!(path.join("c.txt").exists());
+        assert!(!path.join("d.txt").exists());
+        assert!(!path.join("e.txt").exists());
+        assert!(!path.join("new").exists());
+        assert!(!path.join("build.log").exists());
+        assert!(path.join("target/app").exists());
+
+        let status = git.status(path, true).unwrap();
+        assert_eq!(status.iter().map(|e| e.path.as_str()).collect::<Vec<_>>(), ["target/"]);
+    }
+
+    #[test]
+    fn cleanses_fully_when_untracked_directory_contains_preserved_path() {
+        let repo = repository();
+        let path = repo.path();
+
+        std::fs::create_dir_all(path.join("new/keep")).unwrap();
+        std::fs::write(path.join("new/keep/file.txt"), "preserved").unwrap();
+        std::fs::write(path.join("new/other.txt"), "untracked").unwrap();
+
+        let cleansed = Git::default()
+            .cleanse(path, CleanseMode::Differential, &["new/keep".to_owned()])
+            .unwrap();
+
+        assert_eq!(cleansed, None);
+        assert!(path.join("new/keep/file.txt").exists());
+        assert!(!path.join("new/other.txt").exists());
+    }
+
+    #[test]
+    fn falls_back_to_full_cleanse_for_many_paths() {
+        let preserve = ["target".to_owned()];
+
+        let many = |n: usize| (0..n).map(|i| entry("??", &format!("file{i}"))).collect::<Vec<_>>();
+
+        assert!(differential_cleanse_entries(many(DIFFERENTIAL_CLEANSE_MAX_PATHS), &preserve).is_some());
+        assert!(differential_cleanse_entries(many(DIFFERENTIAL_CLEANSE_MAX_PATHS + 1), &preserve).is_none());
+
+        // Preserved paths don't count
+        let mut entries = many(DIFFERENTIAL_CLEANSE_MAX_PATHS);
+        entries.push(entry("!!", "target/"));
+        let entries = differential_cleanse_entries(entries, &preserve).unwrap();
+        assert_eq!(entries.len(), DIFFERENTIAL_CLEANSE_MAX_PATHS);
+
+        // Tracked paths under them are still restored
+        let entries = differential_cleanse_entries(vec![entry(" M", "target/tracked.txt")], &preserve).unwrap();
+        assert_eq!(entries.len(), 1);
+    }
+
+    #[test]
+    fn matches_preserved_paths() {
+        let preserve = ["target".to_owned(), "build/cache/".to_owned()];
+
+        assert!(is_preserved("target/", &preserve));
+        assert!(is_preserved("target/debug/app", &preserve));
+        assert!(is_preserved("build/cache/", &preserve));
+        assert!(!is_preserved("targets/", &preserve));
+        assert!(!is_preserved("build/", &preserve));
+
+        assert!(contains_preserved("build/", &preserve));
+        assert!(!contains_preserved("target/", &preserve));
+        assert!(!contains_preserved("build", &preserve));
+    }
+
+    #[test]
+    fn classifies_git_failures() {
+        let classify = |stderr: &str| GitError::from_stderr(Some(128), stderr.as_bytes());
+
+        assert!(matches!(
+            classify("fatal: not a git repository (or any of the parent directories): .git"),
+            GitError::NotARepository(_)
+        ));
+        assert!(matches!(
+            classify("fatal: Authentication failed for 'https://example.com/repo.git/'"),
+            GitError::AuthFailed(_)
+        ));
+        assert!(matches!(
+            classify("fatal: unable to access 'https://example.com/': Could not resolve host: example.com"),
+            GitError::NetworkError(_)
+        ));
+        assert!(matches!(
+            classify("fatal: couldn't find remote ref refs/heads/missing"),
+            GitError::RefNotFound(_)
+        ));
+
+        let unknown = classify("hint: something\nfatal: something else went wrong");
+        assert_eq!(
+            unknown.to_string(),
+            "git failed with exit code 128: fatal: something else went wrong"
+        );
+    }
+}
@@ -0,0 +1,106 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context};
+use tracing::info;
+
+use crate::cancel;
+use crate::config::Config;
+use crate::util::{self, pid::PidLock};
+use crate::workroot::WorkRoot;
+
+const WAIT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Slots held in concurrency groups, released when dropped
+pub struct GroupSlots {
+    _locks: Vec<PidLock>,
+}
+
+/// Wait for a free slot in each of the groups, in a consistent order so runs in several groups can't deadlock
+pub fn acquire(
+    cfg: &Config,
+    work_root: &WorkRoot,
+    groups: &[String],
+    run_id: &str,
+    quiet: bool,
+) -> Result<GroupSlots, anyhow::Error> {
+    validate(cfg, groups)?;
+
+    let groups: BTreeMap<&str, usize> = groups
+        .iter()
+        .filter_map(|group| Some((group.as_str(), (*cfg.concurrency_groups.get(group)?).max(1))))
+        .collect();
+
+    let mut locks = Vec::new();
+
+    for (group, max) in groups {
+        let path = WorkRoot::group_path(cfg, group);
+
+        if !path.exists() {
+            std::fs::create_dir_all(&path)
+                .with_context(|| format!("Error creating concurrency group directory: {}", path.display()))?;
+
+            // Allow all users of a shared work root to take slots
+            if cfg.shared_work_root {
+                util::set_mode(&path, 0o1777)?;
+            }
+        }
+
+        let mut waiting = false;
+
+        let lock = loop {
+            let lock = (0..max).find_map(|slot| {
+                let slot_path = path.join(format!("{slot}.pid"));
+                let lock = PidLock::acquire(&slot_path)?;
+
+                // Stale slots left by other users must be writable to be taken over
+                if cfg.shared_work_root {
+                    util::set_mode(&slot_path, 0o666).ok();
+                }
+
+                Some(lock)
+            });
+
+            if let Some(lock) = lock {
+                break lock;
+            }
+
+            if !waiting {
+                if !quiet {
+                    eprintln!("Waiting for a slot in concurrency group {group} ({max} running)...");
+                } else {
+                    info!("Waiting for a slot in concurrency group {group}");
+                }
+
+                waiting = true;
+            }
+
+            cancel::check(work_root, run_id)?;
+            std::thread::sleep(WAIT_INTERVAL);
+        };
+
+        locks.push(lock);
+    }
+
+    Ok(GroupSlots { _locks: locks })
+}
+
+/// Fail if any of the groups is not defined in the config
+pub fn validate(cfg: &Config, groups: &[String]) -> Result<(), anyhow::Error> {
+    let Some(group) = groups.iter().find(|g| !cfg.concurrency_groups.contains_key(*g)) else {
+        return Ok(());
+    };
+
+    if cfg.concurrency_groups.is_empty() {
+        return Err(anyhow!(
+            "Unknown concurrency group: {group}. Define it in [concurrency-groups] in the config."
+        ));
+    }
+
+    let available: Vec<&str> = cfg.concurrency_groups.keys().map(|g| g.as_str()).collect();
+
+    Err(anyhow!(
+        "Unknown concurrency group: {group}. Available groups: {}",
+        available.join(", ")
+    ))
+}
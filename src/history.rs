@@ -0,0 +1,570 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context};
+use clap::Args;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::git::Git;
+use crate::metadata::WorkMetadata;
+use crate::postcheck::PostCheckResult;
+use crate::run;
+use crate::util;
+use crate::util::record::{self, Record};
+use crate::workroot::WorkRoot;
+
+const REPORT_FILENAME: &str = "report.json";
+const OUTPUT_FILENAME: &str = "output.log";
+
+/// A past run, as stored in the run history
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RunRecord {
+    pub metadata: WorkMetadata,
+    pub post_checks: Vec<PostCheckResult>,
+}
+
+impl Record for RunRecord {
+    const VERSION: u32 = 1;
+}
+
+#[derive(Debug, Args)]
+pub struct DiffOutputArgs {
+    #[clap(long = "path", help = "Specify repository path")]
+    path: Option<PathBuf>,
+    #[clap(long = "list", help = "List recorded runs instead of comparing them")]
+    list: bool,
+    #[clap(long = "lines", help = "Also show output lines only present in one of the runs")]
+    lines: bool,
+    #[clap(long = "fail-on-regression", help = "Exit with an error if the newer run regressed")]
+    fail_on_regression: bool,
+    #[clap(help = "Older run, by run id or commit-ish. Defaults to the second most recent run.")]
+    old: Option<String>,
+    #[clap(help = "Newer run, by run id or commit-ish. Defaults to the most recent run.")]
+    new: Option<String>,
+}
+
+/// Figures extracted from the output of a run
+#[derive(Debug, Default)]
+struct OutputSummary {
+    tests_passed: u64,
+    tests_failed: u64,
+    tests_ignored: u64,
+    warnings: u64,
+    errors: u64,
+}
+
+/// Store a finished run in the history of a work directory, removing the oldest runs beyond `keep`
+pub fn record(
+    work_root: &WorkRoot,
+    id: &str,
+    record: &RunRecord,
+    log_path: Option<&Path>,
+    keep: usize,
+) -> Result<(), anyhow::Error> {
+    let run_id = record.metadata.run_id.as_deref().unwrap_or_default();
+    let run_path = work_root.history_path(id).join(run_id);
+
+    record::save(run_path.join(REPORT_FILENAME), record)?;
+
+    if let Some(log_path) = log_path.filter(|p| p.exists()) {
+        std::fs::copy(log_path, run_path.join(OUTPUT_FILENAME))
+            .with_context(|| format!("Error copying output to run history: {}", run_path.display()))?;
+    }
+
+    trim(work_root, id, keep)?;
+
+    Ok(())
+}
+
+/// Remove the oldest runs beyond `keep` from the history of a work directory, returning how many were removed
+pub fn trim(work_root: &WorkRoot, id: &str, keep: usize) -> Result<usize, anyhow::Error> {
+    let runs = run_ids(work_root, id)?;
+    let excess = runs.len().saturating_sub(keep);
+
+    for run_id in runs.iter().take(excess) {
+        util::remove_dir_all(work_root.history_path(id).join(run_id))
+            .with_context(|| format!("Error removing run {run_id} from history"))?;
+    }
+
+    Ok(excess)
+}
+
+pub fn diff_output(cfg: &Config, args: DiffOutputArgs) -> Result<(), anyhow::Error> {
+    let work_root = WorkRoot::from_config(cfg);
+
+    let git = Git {
+        silent: true,
+        ..Default::default()
+    };
+
+    let repository_root_path = run::resolve_source_repository(&git, args.path)?;
+    let id = work_root.source_id(&repository_root_path);
+
+    let runs = run_ids(&work_root, &id)?;
+    let history_path = work_root.history_path(&id);
+
+    if args.list {
+        for run_id in runs {
+            let record = load(&history_path.join(&run_id))?;
+            let metadata = &record.metadata;
+
+            println!(
+                "{run_id}  {}  {}  {}",
+                short_commit(metadata),
+                result_name(metadata),
+                metadata.branch.as_deref().unwrap_or_default()
+            );
+        }
+
+        return Ok(());
+    }
+
+    let select = |selector: Option<&str>, default_offset: usize| -> Result<String, anyhow::Error> {
+        match selector {
+            Some(selector) => find_run(&git, &repository_root_path, &history_path, &runs, selector),
+            None => runs
+                .iter()
+                .rev()
+                .nth(default_offset)
+                .cloned()
+                .ok_or_else(|| anyhow!("Not enough runs in history to compare. Enable capture-log and run again.")),
+        }
+    };
+
+    let new_id = select(args.new.as_deref(), 0)?;
+    let old_id = select(args.old.as_deref(), 1)?;
+
+    let old = load(&history_path.join(&old_id))?;
+    let new = load(&history_path.join(&new_id))?;
+
+    let old_output = read_output(&history_path.join(&old_id));
+    let new_output = read_output(&history_path.join(&new_id));
+
+    println!(
+        "Old: {old_id} ({}, {})",
+        short_commit(&old.metadata),
+        result_name(&old.metadata)
+    );
+    println!(
+        "New: {new_id} ({}, {})",
+        short_commit(&new.metadata),
+        result_name(&new.metadata)
+    );
+    println!();
+
+    let mut regressions = 0;
+
+    if old.metadata.success != new.metadata.success {
+        let worse = old.metadata.success == Some(true);
+        let marker = if worse { "  REGRESSION" } else { "" };
+        if worse {
+            regressions += 1;
+        }
+
+        println!(
+            "Result: {} -> {}{marker}",
+            result_name(&old.metadata),
+            result_name(&new.metadata)
+        );
+    }
+
+    let mut compare = |name: &str, old: u64, new: u64, worse: bool| {
+        if old == new {
+            return;
+        }
+
+        let marker = if worse {
+            regressions += 1;
+            "  REGRESSION"
+        } else {
+            ""
+        };
+
+        println!("{name}: {old} -> {new} ({:+}){marker}", new as i64 - old as i64);
+    };
+
+    let old_summary = old_output
+        .as_deref()
+        .map(OutputSummary::from_output)
+        .unwrap_or_default();
+    let new_summary = new_output
+        .as_deref()
+        .map(OutputSummary::from_output)
+        .unwrap_or_default();
+
+    compare(
+        "Tests passed",
+        old_summary.tests_passed,
+        new_summary.tests_passed,
+        new_summary.tests_passed < old_summary.tests_passed,
+    );
+    compare(
+        "Tests failed",
+        old_summary.tests_failed,
+        new_summary.tests_failed,
+        new_summary.tests_failed > old_summary.tests_failed,
+    );
+    compare(
+        "Tests ignored",
+        old_summary.tests_ignored,
+        new_summary.tests_ignored,
+        false,
+    );
+    compare(
+        "Warnings",
+        old_summary.warnings,
+        new_summary.warnings,
+        new_summary.warnings > old_summary.warnings,
+    );
+    compare(
+        "Errors",
+        old_summary.errors,
+        new_summary.errors,
+        new_summary.errors > old_summary.errors,
+    );
+
+    // Small differences in timing are noise
+    if let (Some(old_duration), Some(new_duration)) = (duration(&old.metadata), duration(&new.metadata)) {
+        compare(
+            "Duration (s)",
+            old_duration,
+            new_duration,
+            new_duration > old_duration + 5 && new_duration * 10 > old_duration * 12,
+        );
+    }
+
+    let old_checks: HashMap<&str, bool> = old.post_checks.iter().map(|c| (c.name.as_str(), c.success)).collect();
+    for check in &new.post_checks {
+        match old_checks.get(check.name.as_str()) {
+            Some(true) if !check.success => {
+                regressions += 1;
+                println!("Post-check {}: passed -> failed  REGRESSION", check.name);
+            }
+            Some(false) if check.success => println!("Post-check {}: failed -> passed", check.name),
+            _ => {}
+        }
+    }
+
+    if args.lines {
+        if let (Some(old_output), Some(new_output)) = (&old_output, &new_output) {
+            println!();
+            print_line_diff(old_output, new_output);
+        } else {
+            eprintln!("Output was not captured for both runs.");
+        }
+    }
+
+    if regressions == 0 {
+        println!("No regressions.");
+    }
+
+    if args.fail_on_regression && regressions > 0 {
+        return Err(anyhow!("{regressions} regression(s) found."));
+    }
+
+    Ok(())
+}
+
+/// Get the ids of the runs in the history of a work directory, oldest first
+fn run_ids(work_root: &WorkRoot, id: &str) -> Result<Vec<String>, anyhow::Error> {
+    let history_path = work_root.history_path(id);
+
+    if !history_path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut run_ids: Vec<String> = std::fs::read_dir(&history_path)
+        .with_context(|| format!("Error reading run history: {}", history_path.display()))?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_dir())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .collect();
+
+    // Run ids start with the time the run started
+    run_ids.sort_by_key(|run_id| {
+        let (started_at, pid) = run_id.split_once('-').unwrap_or((run_id, ""));
+        (started_at.parse::<u64>().unwrap_or(0), pid.parse::<u64>().unwrap_or(0))
+    });
+
+    Ok(run_ids)
+}
+
+/// Find a run by run id, or the most recent run of the commit a commit-ish resolves to
+fn find_run(
+    git: &Git,
+    source_path: &Path,
+    history_path: &Path,
+    runs: &[String],
+    selector: &str,
+) -> Result<String, anyhow::Error> {
+    if runs.iter().any(|r| r == selector) {
+        return Ok(selector.to_owned());
+    }
+
+    let commit = git
+        .rev_parse(source_path, selector)
+        .map_err(|_| anyhow!("No run or commit found matching: {selector}"))?;
+
+    for run_id in runs.iter().rev() {
+        let record = load(&history_path.join(run_id))?;
+
+        if record.metadata.commit.as_deref() == Some(commit.as_str()) {
+            return Ok(run_id.clone());
+        }
+    }
+
+    Err(anyhow!("No recorded run of {selector} ({commit})"))
+}
+
+fn load(run_path: &Path) -> Result<RunRecord, anyhow::Error> {
+    Ok(record::load(run_path.join(REPORT_FILENAME))?.unwrap_or_default())
+}
+
+fn read_output(run_path: &Path) -> Option<String> {
+    let output = std::fs::read(run_path.join(OUTPUT_FILENAME)).ok()?;
+
+    Some(String::from_utf8_lossy(&output).into_owned())
+}
+
+fn short_commit(metadata: &WorkMetadata) -> &str {
+    let commit = metadata.commit.as_deref().unwrap_or("unknown");
+
+    commit.get(..10).unwrap_or(commit)
+}
+
+fn result_name(metadata: &WorkMetadata) -> &'static str {
+    match metadata.success {
+        Some(true) => "succeeded",
+        Some(false) => "failed",
+        None => "unfinished",
+    }
+}
+
+fn duration(metadata: &WorkMetadata) -> Option<u64> {
+    Some(metadata.finished_at?.saturating_sub(metadata.started_at?))
+}
+
+/// Print lines that only occur in one of the outputs, or occur a different number of times
+fn print_line_diff(old: &str, new: &str) {
+    let mut counts: HashMap<&str, i64> = HashMap::new();
+
+    for line in old.lines() {
+        *counts.entry(line).or_default() += 1;
+    }
+
+    for line in new.lines() {
+        *counts.entry(line).or_default() -= 1;
+    }
+
+    let mut removed = counts.clone();
+    for line in old.lines() {
+        if let Some(count) = removed.get_mut(line).filter(|c| **c > 0) {
+            *count -= 1;
+            println!("- {line}");
+        }
+    }
+
+    for line in new.lines() {
+        if let Some(count) = counts.get_mut(line).filter(|c| **c < 0) {
+            *count += 1;
+            println!("+ {line}");
+        }
+    }
+}
+
+impl OutputSummary {
+    /// Extract test counts and diagnostics from output. Test counts are read from summary lines
+    /// in the formats used by common test runners (ex. cargo test, pytest and jest).
+    fn from_output(output: &str) -> Self {
+        let mut summary = Self::default();
+
+        for line in output.lines() {
+            let lower = line.to_lowercase();
+            let trimmed = lower.trim_start();
+
+            // Totals of diagnostics already counted (ex. "warning: `app` (lib) generated 2 warnings")
+            if is_diagnostic_total(trimmed) {
+                continue;
+            }
+
+            if is_diagnostic(trimmed, &lower, "warning") {
+                summary.warnings += 1;
+            }
+
+            if is_diagnostic(trimmed, &lower, "error") {
+                summary.errors += 1;
+            }
+
+            // Jest reports test suites separately from tests
+            if trimmed.starts_with("test suites:") {
+                continue;
+            }
+
+            let words: Vec<&str> = trimmed
+                .split(|c: char| c.is_whitespace() || matches!(c, ',' | ';' | '=' | '.'))
+                .filter(|w| !w.is_empty())
+                .collect();
+
+            for pair in words.windows(2) {
+                let Ok(count) = pair[0].parse::<u64>() else {
+                    continue;
+                };
+
+                match pair[1] {
+                    "passed" => summary.tests_passed += count,
+                    "failed" => summary.tests_failed += count,
+                    "ignored" | "skipped" => summary.tests_ignored += count,
+                    _ => {}
+                }
+            }
+        }
+
+        summary
+    }
+}
+
+/// Check if a line is a diagnostic of a kind (ex. "warning: unused variable", "error[E0308]: mismatched types"
+/// or "src/main.c:3:5: warning: unused variable")
+fn is_diagnostic(trimmed: &str, line: &str, kind: &str) -> bool {
+    trimmed
+        .strip_prefix(kind)
+        .is_some_and(|rest| rest.starts_with(':') || rest.starts_with('['))
+        || line.contains(&format!(" {kind}:"))
+}
+
+/// Check if a line sums up diagnostics reported on their own lines
+fn is_diagnostic_total(line: &str) -> bool {
+    let generated = line
+        .split_once(" generated ")
+        .is_some_and(|(_, rest)| rest.split_whitespace().nth(1).is_some_and(|w| w.starts_with("warning")));
+
+    generated || line.contains(" previous error") || line.starts_with("error: aborting due to")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counts(output: &str) -> [u64; 5] {
+        let summary = OutputSummary::from_output(output);
+
+        [
+            summary.tests_passed,
+            summary.tests_failed,
+            summary.tests_ignored,
+            summary.warnings,
+            summary.errors,
+        ]
+    }
+
+    #[test]
+    fn summarizes_cargo_output() {
+        let output = r#"   Compiling demo v0.1.0 (/src/demo)
+warning: unused variable: `x`
+ --> src/lib.rs:2:9
+  |
+2 |     let x = 1;
+  |         ^ help: if this is intentional, prefix it with an underscore: `_x`
+  |
+  = note: `#[warn(unused_variables)]` on by default
+
+warning: function `helper` is never used
+ --> src/lib.rs:5:4
+
+warning: `demo` (lib) generated 2 warnings
+warning: `demo` (lib test) generated 2 warnings (2 duplicates)
+    Finished test [unoptimized + debuginfo] target(s) in 0.51s
+     Running unittests src/lib.rs (target/debug/deps/demo-1234)
+
+running 4 tests
+test tests::a ... ok
+test tests::b ... ignored
+test tests::c ... FAILED
+test tests::d ... ok
+
+failures:
+
+---- tests::c stdout ----
+thread 'tests::c' panicked at src/lib.rs:20:9:
+assertion failed
+
+failures:
+    tests::c
+
+test result: FAILED. 2 passed; 1 failed; 1 ignored; 0 measured; 0 filtered out; finished in 0.00s
+
+error: test failed, to rerun pass `--lib`
+"#;
+
+        assert_eq!(counts(output), [2, 1, 1, 2, 1]);
+
+        let output = r#"error[E0308]: mismatched types
+ --> src/main.rs:2:18
+warning: unused import: `std::fs`
+error: could not compile `demo` (bin "demo") due to 1 previous error; 1 warning emitted
+"#;
+
+        assert_eq!(counts(output), [0, 0, 0, 1, 1]);
+    }
+
+    #[test]
+    fn summarizes_pytest_output() {
+        let output = r#"============================= test session starts ==============================
+platform linux -- Python 3.11.4, pytest-7.4.0, pluggy-1.2.0
+rootdir: /src/demo
+collected 13 items
+
+tests/test_app.py ..F.s.....F.s                                          [100%]
+
+=================================== FAILURES ===================================
+___________________________________ test_add ___________________________________
+
+    def test_add():
+>       assert add(1, 1) == 3
+E       assert 2 == 3
+
+tests/test_app.py:4: AssertionError
+=========================== short test summary info ============================
+FAILED tests/test_app.py::test_add - assert 2 == 3
+FAILED tests/test_app.py::test_sub - assert 0 == 1
+============= 2 failed, 9 passed, 2 skipped, 1 warning in 0.12s ==============
+"#;
+
+        assert_eq!(counts(output), [9, 2, 2, 0, 0]);
+    }
+
+    #[test]
+    fn summarizes_jest_output() {
+        let output = r#" PASS  src/sum.test.js
+ FAIL  src/app.test.js
+  ● app › renders
+
+    expect(received).toBe(expected) // Object.is equality
+
+    Expected: 2
+    Received: 1
+
+      at Object.toBe (src/app.test.js:5:17)
+
+Test Suites: 1 failed, 1 passed, 2 total
+Tests:       1 failed, 1 skipped, 4 passed, 6 total
+Snapshots:   0 total
+Time:        1.234 s
+Ran all test suites.
+"#;
+
+        assert_eq!(counts(output), [4, 1, 1, 0, 0]);
+    }
+
+    #[test]
+    fn counts_compiler_diagnostics() {
+        let output = "src/main.c:3:5: warning: unused variable 'x' [-Wunused-variable]\n\
+                      src/main.c:9:1: error: expected ';' after expression\n\
+                      1 warning and 1 error generated.\n\
+                      warnings.warn(\"deprecated\")\n";
+
+        assert_eq!(counts(output), [0, 0, 0, 1, 1]);
+    }
+}
@@ -0,0 +1,4 @@
+# {marker}
+# Remove with `fersk install-hook --uninstall --hook {hook}`
+fersk run --path (git rev-parse --show-toplevel) -- {command}
+exit $LASTEXITCODE
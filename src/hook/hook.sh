@@ -0,0 +1,4 @@
+#!/bin/sh
+# {marker}
+# Remove with `fersk install-hook --uninstall --hook {hook}`
+exec fersk run --path "$(git rev-parse --show-toplevel)" -- {command}
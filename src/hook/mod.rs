@@ -0,0 +1,173 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context};
+use clap::{Args, ValueEnum};
+
+use crate::git::Git;
+use crate::run;
+use crate::util::{self, quote};
+
+const SH_TEMPLATE: &str = include_str!("hook.sh");
+const POWERSHELL_TEMPLATE: &str = include_str!("hook.ps1");
+const POWERSHELL_WRAPPER_TEMPLATE: &str = include_str!("powershell.sh");
+
+/// Identifies hooks installed by fersk, so they are never mistaken for user hooks
+const MARKER: &str = "Installed by fersk";
+
+const BACKUP_EXTENSION: &str = "fersk-backup";
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum HookKind {
+    PrePush,
+    PostCommit,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum HookShell {
+    Sh,
+    Powershell,
+}
+
+#[derive(Debug, Args)]
+pub struct InstallHookArgs {
+    #[clap(long = "path", help = "Specify repository path")]
+    path: Option<PathBuf>,
+    #[clap(long = "hook", value_enum, default_value = "pre-push", help = "Hook to install")]
+    hook: HookKind,
+    #[clap(
+        long = "shell",
+        value_enum,
+        default_value = "sh",
+        help = "Shell to write the hook script for"
+    )]
+    shell: HookShell,
+    #[clap(long = "force", help = "Replace an existing hook, keeping a backup of it")]
+    force: bool,
+    #[clap(
+        long = "uninstall",
+        conflicts_with = "args",
+        help = "Remove a hook installed by fersk"
+    )]
+    uninstall: bool,
+    #[clap(last = true, required_unless_present = "uninstall")]
+    args: Vec<String>,
+}
+
+impl HookKind {
+    fn name(self) -> &'static str {
+        match self {
+            Self::PrePush => "pre-push",
+            Self::PostCommit => "post-commit",
+        }
+    }
+}
+
+pub fn install_hook(args: InstallHookArgs) -> Result<(), anyhow::Error> {
+    let git = Git {
+        silent: true,
+        ..Default::default()
+    };
+
+    let repository_root_path = run::resolve_source_repository(&git, args.path)?;
+
+    let hooks_path = git
+        .git_path(&repository_root_path, "hooks")
+        .with_context(|| "Error getting hooks directory")?;
+
+    let name = args.hook.name();
+    let hook_path = hooks_path.join(name);
+    let ps1_path = hooks_path.join(format!("{name}.ps1"));
+    let backup_path = hook_path.with_extension(BACKUP_EXTENSION);
+
+    if args.uninstall {
+        if !is_fersk_hook(&hook_path) {
+            return Err(anyhow!("No hook installed by fersk: {}", hook_path.display()));
+        }
+
+        fs::remove_file(&hook_path).with_context(|| format!("Error removing hook: {}", hook_path.display()))?;
+
+        if is_fersk_hook(&ps1_path) {
+            fs::remove_file(&ps1_path).with_context(|| format!("Error removing hook: {}", ps1_path.display()))?;
+        }
+
+        println!("Removed {name} hook.");
+
+        if backup_path.exists() {
+            fs::rename(&backup_path, &hook_path)
+                .with_context(|| format!("Error restoring previous hook: {}", hook_path.display()))?;
+
+            println!("Restored previous {name} hook.");
+        }
+
+        return Ok(());
+    }
+
+    // Never overwrite a user's own hook without keeping it
+    if hook_path.exists() && !is_fersk_hook(&hook_path) {
+        if !args.force {
+            return Err(anyhow!(
+                "A {name} hook already exists: {}. Use --force to replace it.",
+                hook_path.display()
+            ));
+        }
+
+        fs::rename(&hook_path, &backup_path)
+            .with_context(|| format!("Error backing up existing hook: {}", hook_path.display()))?;
+
+        println!("Existing hook backed up to {}", quote::path(&backup_path));
+    }
+
+    fs::create_dir_all(&hooks_path)
+        .with_context(|| format!("Error creating hooks directory: {}", hooks_path.display()))?;
+
+    match args.shell {
+        HookShell::Sh => {
+            let command = args.args.iter().map(|a| sh_quote(a)).collect::<Vec<_>>().join(" ");
+
+            write_hook(&hook_path, SH_TEMPLATE, name, &command)?;
+        }
+        HookShell::Powershell => {
+            let command = args
+                .args
+                .iter()
+                .map(|a| powershell_quote(a))
+                .collect::<Vec<_>>()
+                .join(" ");
+
+            // Git always runs hooks with sh, so a wrapper is needed to start PowerShell
+            write_hook(&ps1_path, POWERSHELL_TEMPLATE, name, &command)?;
+            write_hook(&hook_path, POWERSHELL_WRAPPER_TEMPLATE, name, "")?;
+        }
+    }
+
+    println!("Installed {name} hook: {}", quote::path(&hook_path));
+
+    Ok(())
+}
+
+fn write_hook(path: &Path, template: &str, name: &str, command: &str) -> Result<(), anyhow::Error> {
+    let script = template
+        .replace("{marker}", MARKER)
+        .replace("{hook}", name)
+        .replace("{command}", command);
+
+    fs::write(path, script).with_context(|| format!("Error writing hook: {}", path.display()))?;
+    util::set_mode(path, 0o755).with_context(|| format!("Error making hook executable: {}", path.display()))?;
+
+    Ok(())
+}
+
+fn is_fersk_hook(path: &Path) -> bool {
+    fs::read_to_string(path)
+        .map(|script| script.contains(MARKER))
+        .unwrap_or(false)
+}
+
+fn sh_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+fn powershell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', "''"))
+}
@@ -0,0 +1,4 @@
+#!/bin/sh
+# {marker}
+# Remove with `fersk install-hook --uninstall --hook {hook}`
+exec powershell.exe -NoProfile -ExecutionPolicy Bypass -File "$(dirname "$0")/{hook}.ps1" "$@"
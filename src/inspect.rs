@@ -0,0 +1,293 @@
+use std::fs;
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::Context;
+use clap::Args;
+use serde_derive::Serialize;
+use sysinfo::PidExt;
+
+use crate::config::Config;
+use crate::git::Git;
+use crate::metadata::WorkMetadata;
+use crate::run;
+use crate::schema::SCHEMA_VERSION;
+use crate::util::pid::PidLock;
+use crate::util::process::{self, ProcessUsage};
+use crate::util::{self, json::JsonOut, quote};
+use crate::workroot::WorkRoot;
+
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(500);
+const LOG_TAIL_CHUNK_SIZE: u64 = 8192;
+
+#[derive(Debug, Args)]
+pub struct InspectArgs {
+    #[clap(long = "path", help = "Specify repository path")]
+    path: Option<PathBuf>,
+    #[clap(
+        long = "into",
+        help = "Inspect the work directory created at this path with `run --into`"
+    )]
+    into: Option<PathBuf>,
+    #[clap(long = "lines", default_value = "20", help = "Number of captured log lines to show")]
+    lines: usize,
+    #[clap(long = "follow", help = "Keep printing captured output until the run finishes")]
+    follow: bool,
+
+    #[clap(
+        long = "json-out",
+        value_name = "FORMAT",
+        value_parser = JsonOut::from_str,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "pretty",
+        help = "Output json information (pretty, compact, or a file path to write it to)"
+    )]
+    json_out: Option<JsonOut>,
+}
+
+#[derive(Serialize)]
+struct JsonOutput {
+    schema_version: u32,
+    metadata: Option<WorkMetadata>,
+    running: bool,
+    usage: Option<ProcessUsage>,
+    log_tail: Vec<String>,
+}
+
+/// Inspect a work directory without acquiring its lock
+pub fn inspect(cfg: &Config, args: InspectArgs) -> Result<(), anyhow::Error> {
+    let work_root = WorkRoot::from_config(cfg);
+
+    let git = Git {
+        silent: true,
+        ..Default::default()
+    };
+
+    let repository_root_path = run::resolve_source_repository(&git, args.path)?;
+
+    // Work directories at custom paths are identified by their path, as in `run`
+    let into = args.into.map(util::normalize_path);
+    let source_id = work_root.source_id(into.as_deref().unwrap_or(&repository_root_path));
+
+    let metadata = WorkMetadata::load(work_root.metadata_path(&source_id))?;
+    let running = PidLock::holder(work_root.lock_path(&source_id)).is_some();
+
+    let usage = if running {
+        metadata
+            .as_ref()
+            .and_then(|m| m.command_pid)
+            .and_then(process::process_usage)
+    } else {
+        None
+    };
+
+    let log_path = work_root.log_path(&source_id);
+    let log_tail = read_log_tail(&log_path, args.lines)?;
+
+    if let Some(json_out) = &args.json_out {
+        let output = JsonOutput {
+            schema_version: SCHEMA_VERSION,
+            metadata,
+            running,
+            usage,
+            log_tail,
+        };
+
+        util::json::write_json_output(&output, json_out)?;
+
+        return Ok(());
+    }
+
+    println!("Source repository: {}", quote::path(&repository_root_path));
+    let work_path = match &metadata {
+        Some(metadata) => metadata.working_repository_path.clone(),
+        None => into.unwrap_or_else(|| work_root.work_path(&source_id)),
+    };
+    println!("Working directory: {}", quote::path(&work_path));
+
+    let journal_path = work_root.journal_path(&source_id);
+    if journal_path.exists() {
+        println!("Journal: {}", quote::path(&journal_path));
+    }
+
+    let Some(metadata) = metadata else {
+        println!("No runs recorded.");
+        return Ok(());
+    };
+
+    if let Some(branch) = &metadata.branch {
+        println!("Branch: {branch}");
+    }
+
+    println!("Command: {}", quote::command(&metadata.command));
+    println!("Status: {}", status_text(running, &metadata));
+
+    if let Some(usage) = &usage {
+        println!(
+            "Resource usage: {} process(es), {:.1}% CPU, {} MiB memory, running for {}s",
+            usage.processes,
+            usage.cpu_usage,
+            usage.memory / 1024 / 1024,
+            usage.run_time
+        );
+    }
+
+    if !log_path.exists() {
+        return Ok(());
+    }
+
+    println!("--- {} ---", quote::path(&log_path));
+    for line in &log_tail {
+        println!("{line}");
+    }
+
+    if args.follow && running {
+        follow_log(&log_path, || PidLock::holder(work_root.lock_path(&source_id)).is_some())?;
+    }
+
+    Ok(())
+}
+
+fn status_text(running: bool, metadata: &WorkMetadata) -> String {
+    if running {
+        match metadata.command_pid {
+            Some(pid) => format!("running (PID {})", sysinfo::Pid::from_u32(pid)),
+            None => "preparing".to_owned(),
+        }
+    } else {
+        match metadata.success {
+            Some(true) => "succeeded".to_owned(),
+            Some(false) => "failed".to_owned(),
+            None => "interrupted".to_owned(),
+        }
+    }
+}
+
+/// Read the last lines of a log file, reading backwards from the end so large logs aren't read in full
+fn read_log_tail(path: &Path, lines: usize) -> Result<Vec<String>, anyhow::Error> {
+    let mut file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err).with_context(|| format!("Error opening log file: {}", path.display())),
+    };
+
+    let mut pos = file.seek(SeekFrom::End(0))?;
+    let mut bytes = Vec::new();
+
+    // One more line break than lines is needed, as the log usually ends with one
+    while pos > 0 && bytes.iter().filter(|&&b| b == b'\n').count() <= lines {
+        let size = pos.min(LOG_TAIL_CHUNK_SIZE);
+        pos -= size;
+
+        let mut chunk = vec![0; size as usize];
+        file.seek(SeekFrom::Start(pos))?;
+        file.read_exact(&mut chunk)
+            .with_context(|| format!("Error reading log file: {}", path.display()))?;
+
+        chunk.extend_from_slice(&bytes);
+        bytes = chunk;
+    }
+
+    let text = String::from_utf8_lossy(&bytes);
+
+    let all_lines: Vec<&str> = text.lines().collect();
+    let skip = all_lines.len().saturating_sub(lines);
+
+    Ok(all_lines[skip..].iter().map(|l| l.to_string()).collect())
+}
+
+/// Print data appended to a log file for as long as the run is active
+fn follow_log(path: &Path, is_running: impl Fn() -> bool) -> Result<(), anyhow::Error> {
+    let mut file = fs::File::open(path).with_context(|| format!("Error opening log file: {}", path.display()))?;
+    file.seek(SeekFrom::End(0))?;
+
+    let stdout = std::io::stdout();
+    let mut buf = Vec::new();
+
+    loop {
+        let running = is_running();
+
+        buf.clear();
+        file.read_to_end(&mut buf)?;
+
+        if !buf.is_empty() {
+            let mut stdout = stdout.lock();
+            stdout.write_all(&buf)?;
+            stdout.flush()?;
+        }
+
+        if !running {
+            break;
+        }
+
+        std::thread::sleep(FOLLOW_INTERVAL);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::{self, Output};
+
+    #[test]
+    fn reads_last_lines_of_log() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("output.log");
+
+        assert!(read_log_tail(&path, 3).unwrap().is_empty());
+
+        let log: String = (0..10000).map(|i| format!("line {i}\n")).collect();
+        fs::write(&path, &log).unwrap();
+        assert_eq!(
+            read_log_tail(&path, 3).unwrap(),
+            ["line 9997", "line 9998", "line 9999"]
+        );
+        assert_eq!(read_log_tail(&path, 2000).unwrap().first().unwrap(), "line 8000");
+
+        // Without a trailing line break, and with fewer lines than requested
+        fs::write(&path, "first\nsecond").unwrap();
+        assert_eq!(read_log_tail(&path, 1).unwrap(), ["second"]);
+        assert_eq!(read_log_tail(&path, 5).unwrap(), ["first", "second"]);
+    }
+
+    #[test]
+    fn json_output_matches_schema() {
+        let idle = JsonOutput {
+            schema_version: SCHEMA_VERSION,
+            metadata: None,
+            running: false,
+            usage: None,
+            log_tail: Vec::new(),
+        };
+        schema::validate(Output::Inspect, &serde_json::to_value(&idle).unwrap()).unwrap();
+
+        let running = JsonOutput {
+            metadata: Some(WorkMetadata {
+                branch: Some("main".to_owned()),
+                command: vec!["make".to_owned()],
+                pid: Some(1),
+                started_at: Some(1),
+                success: Some(true),
+                run_id: Some("1-1".to_owned()),
+                labels: [("ci".to_owned(), "true".to_owned())].into(),
+                ..Default::default()
+            }),
+            running: true,
+            usage: Some(ProcessUsage {
+                pid: 1,
+                processes: 2,
+                cpu_usage: 12.5,
+                memory: 1024,
+                run_time: 10,
+            }),
+            log_tail: vec!["output".to_owned()],
+            ..idle
+        };
+        schema::validate(Output::Inspect, &serde_json::to_value(&running).unwrap()).unwrap();
+    }
+}
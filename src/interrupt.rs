@@ -0,0 +1,70 @@
+use std::io::Write;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use anyhow::anyhow;
+
+/// Writer of a pseudo-terminal
+pub type SharedWriter = Arc<Mutex<Box<dyn Write + Send>>>;
+
+/// Command that Ctrl-C is forwarded to, as it doesn't get it from fersk's terminal
+pub enum Target {
+    /// Pseudo-terminal the command runs in, which is sent an interrupt character
+    Terminal(SharedWriter),
+    /// Process group the command runs in, which is sent SIGINT
+    #[cfg(unix)]
+    ProcessGroup(u32),
+}
+
+/// Forwards Ctrl-C to a command until dropped
+pub struct Forwarding(());
+
+static TARGET: Mutex<Option<Target>> = Mutex::new(None);
+
+/// Handle Ctrl-C in fersk, so it can be forwarded to commands.
+/// This should be done before spawning them, so a failure doesn't leave them running.
+/// While nothing is forwarded to, Ctrl-C exits fersk as usual.
+pub fn install() -> Result<(), anyhow::Error> {
+    // The handler can only be set once per process
+    static HANDLER: OnceLock<Result<(), String>> = OnceLock::new();
+
+    HANDLER
+        .get_or_init(|| ctrlc::set_handler(handle).map_err(|err| err.to_string()))
+        .clone()
+        .map_err(|err| anyhow!("Error setting Ctrl-C handler: {err}"))
+}
+
+/// Forward Ctrl-C to a command
+pub fn forward(target: Target) -> Forwarding {
+    if let Ok(mut current) = TARGET.lock() {
+        *current = Some(target);
+    }
+
+    Forwarding(())
+}
+
+impl Drop for Forwarding {
+    fn drop(&mut self) {
+        if let Ok(mut current) = TARGET.lock() {
+            *current = None;
+        }
+    }
+}
+
+fn handle() {
+    let Ok(target) = TARGET.lock() else {
+        return;
+    };
+
+    match &*target {
+        Some(Target::Terminal(writer)) => {
+            if let Ok(mut writer) = writer.lock() {
+                writer.write_all(b"\x03").ok();
+            }
+        }
+        #[cfg(unix)]
+        Some(Target::ProcessGroup(pgid)) => unsafe {
+            libc::kill(-(*pgid as libc::pid_t), libc::SIGINT);
+        },
+        None => std::process::exit(130),
+    }
+}
@@ -0,0 +1,54 @@
+use std::fmt::Display;
+use std::io::Write;
+use std::path::PathBuf;
+
+use chrono::Local;
+use tracing::warn;
+
+use crate::util;
+use crate::workroot::WorkRoot;
+
+/// Append-only record of the destructive operations fersk performed on a work directory,
+/// for finding out what happened to files that went missing.
+/// It is kept in the work root, so it outlives the work directory itself.
+pub struct Journal {
+    path: PathBuf,
+    run_id: Option<String>,
+}
+
+impl Journal {
+    pub fn new(work_root: &WorkRoot, id: &str) -> Self {
+        Self {
+            path: work_root.journal_path(id),
+            run_id: None,
+        }
+    }
+
+    /// Include the id of the run performing the operations in entries
+    pub fn for_run(mut self, run_id: &str) -> Self {
+        self.run_id = Some(run_id.to_owned());
+        self
+    }
+
+    /// Append an entry. The journal is only informational, so errors are only logged.
+    pub fn record(&self, operation: &str, detail: impl Display) {
+        let timestamp = Local::now().format("%Y-%m-%dT%H:%M:%S%:z");
+
+        let entry = match &self.run_id {
+            Some(run_id) => format!("{timestamp} [{run_id}] {operation}: {detail}\n"),
+            None => format!("{timestamp} {operation}: {detail}\n"),
+        };
+
+        let result = util::create_parent_dir(&self.path).and_then(|_| {
+            std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?
+                .write_all(entry.as_bytes())
+        });
+
+        if let Err(err) = result {
+            warn!("Error writing journal {}: {err}", self.path.display());
+        }
+    }
+}
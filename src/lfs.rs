@@ -0,0 +1,45 @@
+use std::path::Path;
+
+use anyhow::Context;
+use tracing::warn;
+
+use crate::git::Git;
+use crate::journal::Journal;
+use crate::run::FERSK_ORIGIN;
+
+/// Replace LFS pointer files in a work directory with their content, if the repository uses Git LFS.
+/// The objects are fetched from the source repository, so they must have been fetched there first.
+pub fn materialize(git: &Git, work_path: &Path, journal: &Journal) -> Result<(), anyhow::Error> {
+    if !uses_lfs(git, work_path) {
+        return Ok(());
+    }
+
+    if !git.lfs_available() {
+        warn!("The repository uses Git LFS, but git-lfs is not installed. LFS files are left as pointer files.");
+        return Ok(());
+    }
+
+    // Checkouts leave pointer files from now on, rather than downloading content one file at a time
+    git.lfs_install_local(work_path)
+        .with_context(|| "Error configuring Git LFS")?;
+
+    git.lfs_fetch(work_path, FERSK_ORIGIN, "HEAD")
+        .with_context(|| "Error fetching LFS objects. Use --no-lfs to leave LFS files as pointer files.")?;
+
+    git.lfs_checkout(work_path)
+        .with_context(|| "Error checking out LFS files")?;
+
+    journal.record("lfs", "replaced pointer files with their content");
+
+    Ok(())
+}
+
+/// Check if any .gitattributes file in the checked out commit puts files in LFS
+fn uses_lfs(git: &Git, work_path: &Path) -> bool {
+    git.list_files(work_path, "HEAD")
+        .unwrap_or_default()
+        .iter()
+        .filter(|path| *path == ".gitattributes" || path.ends_with("/.gitattributes"))
+        .filter_map(|path| std::fs::read_to_string(work_path.join(path)).ok())
+        .any(|attributes| attributes.contains("filter=lfs"))
+}
@@ -0,0 +1,140 @@
+use std::path::Path;
+
+use git2::build::{CheckoutBuilder, CloneLocal, RepoBuilder};
+use git2::{ErrorClass, ErrorCode, FetchOptions, FetchPrune, Repository, ResetType, StatusOptions};
+use tracing::trace;
+
+use crate::git::GitError;
+use crate::util;
+
+impl From<git2::Error> for GitError {
+    fn from(err: git2::Error) -> Self {
+        let message = err.message().to_owned();
+
+        match (err.code(), err.class()) {
+            (ErrorCode::Auth | ErrorCode::Certificate, _) => Self::AuthFailed(message),
+            (ErrorCode::NotFound, ErrorClass::Repository) => Self::NotARepository(message),
+            (ErrorCode::NotFound, ErrorClass::Reference | ErrorClass::Object) => Self::RefNotFound(message),
+            (_, ErrorClass::Net | ErrorClass::Http | ErrorClass::Ssh | ErrorClass::Ssl) => Self::NetworkError(message),
+            (_, ErrorClass::Odb | ErrorClass::Zlib) => Self::Corrupt(message),
+            _ => Self::Library(message),
+        }
+    }
+}
+
+/// Clone a repository, checking out its HEAD. Objects of local repositories are hardlinked if possible.
+pub fn clone(source: &str, destination: &Path, origin_name: &str, hardlink: bool) -> Result<(), GitError> {
+    trace!("libgit2: clone {source} into {}", destination.display());
+
+    RepoBuilder::new()
+        .clone_local(if hardlink {
+            CloneLocal::Local
+        } else {
+            CloneLocal::NoLinks
+        })
+        .remote_create(|repo, _, url| repo.remote(origin_name, url))
+        .clone(source, destination)?;
+
+    Ok(())
+}
+
+/// Fetch from a remote with its configured refspecs, optionally pruning deleted branches
+pub fn fetch(path: &Path, remote_name: &str, prune: bool) -> Result<(), GitError> {
+    trace!("libgit2: fetch {remote_name} in {}", path.display());
+
+    let repo = Repository::open(path)?;
+    let mut remote = repo.find_remote(remote_name)?;
+
+    let mut options = FetchOptions::new();
+    options.prune(if prune { FetchPrune::On } else { FetchPrune::Off });
+
+    remote.fetch(&[] as &[&str], Some(&mut options), None)?;
+
+    Ok(())
+}
+
+/// Check if a remote is a local repository. Network transports are left to the git executable,
+/// to not depend on TLS and SSH libraries.
+pub fn is_local_remote(path: &Path, remote_name: &str) -> bool {
+    let Ok(repo) = Repository::open(path) else {
+        return false;
+    };
+
+    let Some(url) = repo
+        .find_remote(remote_name)
+        .ok()
+        .and_then(|r| r.url().map(str::to_owned))
+    else {
+        return false;
+    };
+
+    url.starts_with("file://") || (!url.contains("://") && Path::new(&url).exists())
+}
+
+/// Check out a revision. Local branches are checked out as such, everything else with HEAD detached.
+pub fn checkout(path: &Path, rev: &str) -> Result<(), GitError> {
+    trace!("libgit2: checkout {rev} in {}", path.display());
+
+    let repo = Repository::open(path)?;
+
+    // Plain names are local branches first, as for git checkout
+    let (object, reference) = match repo.revparse_ext(&format!("refs/heads/{rev}")) {
+        Ok(found) => found,
+        Err(_) => repo.revparse_ext(rev)?,
+    };
+
+    repo.checkout_tree(&object, Some(CheckoutBuilder::new().safe()))?;
+
+    match reference
+        .filter(|r| r.is_branch())
+        .and_then(|r| r.name().map(str::to_owned))
+    {
+        Some(name) => repo.set_head(&name)?,
+        None => repo.set_head_detached(object.peel_to_commit()?.id())?,
+    }
+
+    Ok(())
+}
+
+/// Reset the working tree and index to HEAD, and remove all untracked and ignored files,
+/// leaving nested repositories alone like git clean -fdx
+pub fn cleanse(path: &Path) -> Result<(), GitError> {
+    trace!("libgit2: cleanse {}", path.display());
+
+    let repo = Repository::open(path)?;
+
+    let head = repo.head()?.peel(git2::ObjectType::Commit)?;
+    repo.reset(&head, ResetType::Hard, None)?;
+
+    let mut options = StatusOptions::new();
+    options
+        .include_untracked(true)
+        .include_ignored(true)
+        .recurse_untracked_dirs(false)
+        .exclude_submodules(true);
+
+    let untracked: Vec<String> = repo
+        .statuses(Some(&mut options))?
+        .iter()
+        .filter(|entry| entry.status().is_wt_new() || entry.status().is_ignored())
+        .filter_map(|entry| entry.path().map(str::to_owned))
+        .collect();
+
+    for relative_path in untracked {
+        let path = path.join(relative_path.trim_end_matches('/'));
+
+        let result = if path.symlink_metadata().is_ok_and(|m| m.is_dir()) {
+            if path.join(".git").exists() {
+                continue;
+            }
+
+            util::remove_dir_all(&path)
+        } else {
+            std::fs::remove_file(&path)
+        };
+
+        result.map_err(|err| GitError::Library(format!("error removing {}: {err}", path.display())))?;
+    }
+
+    Ok(())
+}
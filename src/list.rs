@@ -0,0 +1,218 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context};
+use clap::Args;
+use serde_derive::Serialize;
+
+use crate::config::Config;
+use crate::git::Git;
+use crate::metadata::WorkMetadata;
+use crate::run::FERSK_ORIGIN;
+use crate::schema::SCHEMA_VERSION;
+use crate::util::{self, json::JsonOut, pid::PidLock};
+use crate::workroot::WorkRoot;
+
+#[derive(Debug, Args)]
+pub struct ListArgs {
+    #[clap(long = "size", help = "Show disk usage of each work directory")]
+    size: bool,
+    #[clap(long = "all-users", help = "Show disk usage per user in a shared work root")]
+    all_users: bool,
+
+    #[clap(
+        long = "json-out",
+        value_name = "FORMAT",
+        value_parser = JsonOut::from_str,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "pretty",
+        help = "Output json information (pretty, compact, or a file path to write it to)"
+    )]
+    json_out: Option<JsonOut>,
+}
+
+#[derive(Serialize)]
+struct WorkDirInfo {
+    schema_version: u32,
+    id: String,
+    working_repository_path: PathBuf,
+    source_repository_path: Option<PathBuf>,
+    running: bool,
+    last_success: Option<bool>,
+    size: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct UserInfo {
+    schema_version: u32,
+    user: String,
+    work_dirs: Option<usize>,
+    size: Option<u64>,
+}
+
+pub fn list(cfg: &Config, args: ListArgs) -> Result<(), anyhow::Error> {
+    if args.all_users {
+        return list_users(cfg, args.json_out.as_ref());
+    }
+
+    let work_root = WorkRoot::from_config(cfg);
+
+    let git = Git {
+        silent: true,
+        ..Default::default()
+    };
+
+    let mut work_dirs = Vec::new();
+
+    for id in work_root.work_ids().with_context(|| "Error listing work directories")? {
+        let working_repository_path = work_root.work_path(&id);
+        let metadata = WorkMetadata::load(work_root.metadata_path(&id)).ok().flatten();
+
+        // Fall back to the remote URL for work directories without metadata
+        let source_repository_path = match &metadata {
+            Some(metadata) => Some(metadata.source_repository_path.clone()),
+            None => git
+                .get_remote_url(&working_repository_path, FERSK_ORIGIN)
+                .ok()
+                .map(PathBuf::from),
+        };
+
+        let size = if args.size {
+            util::dir_size(&working_repository_path).ok()
+        } else {
+            None
+        };
+
+        work_dirs.push(WorkDirInfo {
+            schema_version: SCHEMA_VERSION,
+            running: PidLock::holder(work_root.lock_path(&id)).is_some(),
+            last_success: metadata.and_then(|m| m.success),
+            id,
+            working_repository_path,
+            source_repository_path,
+            size,
+        });
+    }
+
+    if let Some(json_out) = &args.json_out {
+        util::json::write_json_output(&work_dirs, json_out)?;
+
+        return Ok(());
+    }
+
+    for info in &work_dirs {
+        let source = info
+            .source_repository_path
+            .as_ref()
+            .map(|p| p.display().to_string())
+            .unwrap_or_else(|| "<unknown source>".to_owned());
+
+        let status = match (info.running, info.last_success) {
+            (true, _) => "running",
+            (false, Some(true)) => "succeeded",
+            (false, Some(false)) => "failed",
+            (false, None) => "-",
+        };
+
+        print!("{}  {status:<9}  {source}", &info.id[..12.min(info.id.len())]);
+
+        if let Some(size) = info.size {
+            print!("  ({})", format_size(size));
+        }
+
+        println!();
+    }
+
+    Ok(())
+}
+
+/// Show usage per user in a shared work root
+fn list_users(cfg: &Config, json_out: Option<&JsonOut>) -> Result<(), anyhow::Error> {
+    if !cfg.shared_work_root {
+        return Err(anyhow!(
+            "The work root is not shared. Set shared-work-root in the config."
+        ));
+    }
+
+    let mut users = Vec::new();
+
+    // Nobody has used the shared work root yet
+    let entries: Vec<_> = if cfg.work_path.exists() {
+        std::fs::read_dir(&cfg.work_path)
+            .with_context(|| "Error reading shared work root")?
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    for entry in entries {
+        let entry = entry?;
+
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+
+        let user = entry.file_name().to_string_lossy().to_string();
+        let work_root = WorkRoot::new(entry.path());
+
+        // Other users' directories can only be read with administrator privileges
+        users.push(UserInfo {
+            schema_version: SCHEMA_VERSION,
+            user,
+            work_dirs: work_root.work_ids().ok().map(|ids| ids.len()),
+            size: util::dir_size(entry.path()).ok(),
+        });
+    }
+
+    users.sort_by(|a, b| a.user.cmp(&b.user));
+
+    if let Some(json_out) = json_out {
+        util::json::write_json_output(&users, json_out)?;
+
+        return Ok(());
+    }
+
+    for info in &users {
+        match (info.work_dirs, info.size) {
+            (Some(work_dirs), Some(size)) => {
+                println!("{:<16}  {work_dirs} work dir(s)  {}", info.user, format_size(size))
+            }
+            _ => println!("{:<16}  <permission denied>", info.user),
+        }
+    }
+
+    Ok(())
+}
+
+pub fn format_size(size: u64) -> String {
+    format!("{:.1} MiB", size as f64 / 1024.0 / 1024.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::{self, Output};
+
+    #[test]
+    fn json_output_matches_schema() {
+        let work_dirs = vec![WorkDirInfo {
+            schema_version: SCHEMA_VERSION,
+            id: "abc".to_owned(),
+            working_repository_path: PathBuf::from("/work/abc"),
+            source_repository_path: Some(PathBuf::from("/src")),
+            running: false,
+            last_success: None,
+            size: Some(1024),
+        }];
+        schema::validate(Output::List, &serde_json::to_value(&work_dirs).unwrap()).unwrap();
+
+        let users = vec![UserInfo {
+            schema_version: SCHEMA_VERSION,
+            user: "user".to_owned(),
+            work_dirs: None,
+            size: Some(1024),
+        }];
+        schema::validate(Output::ListUsers, &serde_json::to_value(&users).unwrap()).unwrap();
+    }
+}
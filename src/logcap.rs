@@ -0,0 +1,121 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, Write};
+
+use serde_derive::{Deserialize, Serialize};
+
+/// Limits on the size of captured logs, keeping the start and end of the output
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct LogLimit {
+    /// KiB kept from the start of the output
+    pub head: u64,
+    /// KiB kept from the end of the output
+    pub tail: u64,
+}
+
+/// Log file that keeps only the start and end of the output written to it, if limited.
+/// Once the start is written, the end is held in memory until the log is finished, as it is not known until then.
+pub struct CappedLog {
+    file: File,
+    limit: Option<LogLimit>,
+    head_written: u64,
+    tail: VecDeque<u8>,
+    truncated: u64,
+}
+
+impl CappedLog {
+    pub fn new(file: File, limit: Option<LogLimit>) -> Self {
+        Self {
+            file,
+            limit,
+            head_written: 0,
+            tail: VecDeque::new(),
+            truncated: 0,
+        }
+    }
+
+    /// Write the end of the output, with a marker where output was left out.
+    /// Finishing again only writes what was written since.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if self.truncated > 0 {
+            writeln!(
+                self.file,
+                "\n--- fersk: {} bytes of output truncated ---",
+                self.truncated
+            )?;
+        }
+
+        let (a, b) = self.tail.as_slices();
+        self.file.write_all(a)?;
+        self.file.write_all(b)?;
+        self.file.flush()?;
+
+        self.tail.clear();
+        self.truncated = 0;
+
+        Ok(())
+    }
+}
+
+impl Write for CappedLog {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let Some(limit) = self.limit else {
+            return self.file.write(data);
+        };
+
+        let head_room = (limit.head * 1024).saturating_sub(self.head_written);
+        let (head, rest) = data.split_at((head_room as usize).min(data.len()));
+
+        self.file.write_all(head)?;
+        self.head_written += head.len() as u64;
+
+        if !rest.is_empty() {
+            self.tail.extend(rest);
+
+            let excess = self.tail.len().saturating_sub((limit.tail * 1024) as usize);
+            if excess > 0 {
+                self.tail.drain(..excess);
+                self.truncated += excess as u64;
+            }
+        }
+
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    #[test]
+    fn keeps_head_and_tail() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log");
+
+        let mut log = CappedLog::new(File::create(&path).unwrap(), Some(LogLimit { head: 1, tail: 1 }));
+
+        log.write_all(&[b'h'; 1024]).unwrap();
+        log.write_all(&[b'x'; 4096]).unwrap();
+        log.write_all(&[b't'; 1024]).unwrap();
+        log.finish().unwrap();
+
+        let mut content = String::new();
+        File::open(&path).unwrap().read_to_string(&mut content).unwrap();
+
+        assert_eq!(
+            content,
+            format!(
+                "{}\n--- fersk: 4096 bytes of output truncated ---\n{}",
+                "h".repeat(1024),
+                "t".repeat(1024)
+            )
+        );
+    }
+}
@@ -1,191 +1,332 @@
+mod admission;
+mod archive;
+mod attest;
+mod baseenv;
+mod cancel;
+mod casefold;
+mod checkouts;
+mod ci;
 mod command;
 mod config;
+mod context;
+mod depcache;
+mod detect;
+mod drift;
+mod envfile;
+mod fetch;
+mod fsck;
 mod git;
+mod group;
+mod history;
+mod hook;
+mod inspect;
+mod interrupt;
+mod journal;
+mod lfs;
+#[cfg(feature = "libgit2")]
+mod libgit2;
+mod list;
+mod logcap;
+mod maintain;
+mod materialize;
+mod metadata;
+mod mount;
+mod network;
+mod policy;
+mod postcheck;
+mod projects;
+mod prune;
+mod pty;
+mod purge;
+mod queue;
+mod readonly;
+mod relocate;
+mod repro;
+mod rev;
+mod run;
+mod runscript;
+mod sandbox;
+mod schedule;
+mod schema;
+mod script;
+mod secrets;
+mod selfupdate;
+mod shallow;
+mod sparse;
+mod stats;
+mod submodule;
+mod toolpath;
+mod upload;
+mod upstream;
 mod util;
+mod verifytag;
+mod workroot;
 
-use std::{path::PathBuf, process::Stdio};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
 
-use anyhow::{anyhow, Context};
-use clap::Parser;
+use anyhow::Context;
+use clap::{CommandFactory, FromArgMatches, Parser};
 use config::Config;
-use serde_derive::Serialize;
+use git::GitError;
 use tracing_subscriber::{EnvFilter, FmtSubscriber};
 
-use crate::{
-    git::{Git, GitRev},
-    util::pid::PidLock,
-};
-
-const FERSK_ORIGIN: &str = "fersk-origin";
+const MERGE_CONFLICT_EXIT_CODE: i32 = 2;
+/// Same as for commands interrupted with Ctrl+C
+const CANCELLED_EXIT_CODE: i32 = 130;
 
 #[derive(Debug, Parser)]
 #[clap(name = "fersk", version = env!("CARGO_PKG_VERSION"), author = env!("CARGO_PKG_AUTHORS"))]
 struct Opt {
+    #[clap(
+        short = 'C',
+        value_name = "PATH",
+        global = true,
+        help = "Run as if fersk was started in this directory"
+    )]
+    directory: Option<PathBuf>,
+    #[clap(
+        short = 'v',
+        long = "verbose",
+        action = clap::ArgAction::Count,
+        global = true,
+        help = "Show more details (-vv to also show every git command fersk runs, with its duration and exit code)"
+    )]
+    verbose: u8,
+    #[clap(
+        long = "accept-defaults",
+        global = true,
+        help = "Write the default config file without asking, if there is none"
+    )]
+    accept_defaults: bool,
     #[clap(subcommand)]
     command: Command,
 }
 
 #[derive(Debug, Parser)]
 enum Command {
-    #[clap(name = "generate-config", about = "Generate default configuration files")]
+    #[clap(
+        name = "generate-config",
+        about = "Generate default configuration files (same as `config init`)"
+    )]
     GenerateConfig,
 
-    #[clap(name = "run", about = "Run a command")]
-    Run {
-        #[clap(long = "path", help = "Specify repository path")]
-        path: Option<PathBuf>,
-        #[clap(long = "branch", help = "Specify branch to check out")]
-        branch: Option<String>,
-        #[clap(long = "commit", help = "Specify commit to check out")]
-        commit: Option<String>,
-        #[clap(long = "copy-remote", help = "Specify remote to copy to the working repository")]
-        copy_remote: Option<String>,
-        #[clap(last = true)]
-        args: Vec<String>,
-
-        #[clap(long = "json-out", help = "Output json information on success")]
-        json_out: bool,
+    #[clap(name = "cancel", about = "Cancel a running or queued run")]
+    Cancel(cancel::CancelArgs),
+
+    #[clap(name = "config", about = "Get or set configuration values")]
+    Config {
+        #[clap(subcommand)]
+        command: ConfigCommand,
     },
-}
 
-#[derive(Serialize)]
-struct JsonOutput {
-    source_repository_path: PathBuf,
-    working_repository_path: PathBuf,
-    branch: String,
-}
+    #[clap(name = "run", about = "Run a command")]
+    Run(Box<run::RunArgs>),
 
-fn main() -> Result<(), anyhow::Error> {
-    let opt = Opt::parse();
+    #[clap(name = "run-script", about = "Run a script defined in the repository's .fersk.toml")]
+    RunScript(Box<runscript::RunScriptArgs>),
 
-    // Initialize logging
-    initialize_logging();
+    #[clap(name = runscript::EXEC_SCRIPT_COMMAND, hide = true)]
+    ExecScript(runscript::ExecScriptArgs),
 
-    let cfg = Config::from_default_location().unwrap();
+    #[clap(name = "inspect", about = "Inspect a work directory without acquiring its lock")]
+    Inspect(inspect::InspectArgs),
 
-    let work_root = cfg.work_path;
+    #[clap(name = "diff-output", about = "Compare the output of two past runs")]
+    DiffOutput(history::DiffOutputArgs),
 
-    match opt.command {
-        Command::GenerateConfig => {
-            Config::write_default().with_context(|| "Error writing default config")?;
-        }
-        Command::Run {
-            path,
-            branch,
-            commit,
-            copy_remote,
-            args,
-            json_out,
-        } => {
-            if args.is_empty() {
-                return Err(anyhow!("No command specified."));
-            }
+    #[clap(name = "fetch", about = "Fetch updates into work directories ahead of runs")]
+    Fetch(fetch::FetchArgs),
 
-            let path = if let Some(path) = path {
-                path
-            } else {
-                std::env::current_dir().with_context(|| "Error getting current directory")?
-            };
+    #[clap(name = "fsck", about = "Verify the integrity of work directories")]
+    Fsck(fsck::FsckArgs),
 
-            let git = Git { silent: json_out };
+    #[clap(name = "install-hook", about = "Install a git hook that runs a command with fersk")]
+    InstallHook(hook::InstallHookArgs),
 
-            // Determine repository root path
-            let repository_root_path = git.get_repository_root(path).with_context(|| "Not a git repository.")?;
+    #[clap(name = "list", about = "List work directories")]
+    List(list::ListArgs),
 
-            // Normalize repository root path
-            let repository_root_path = util::normalize_path(repository_root_path);
+    #[clap(
+        name = "maintain",
+        about = "Run all maintenance on the work root, within the configured budgets"
+    )]
+    Maintain,
 
-            let source_path_hash = util::hash::hash_bytes(repository_root_path.to_string_lossy().as_bytes());
+    #[clap(name = "project", about = "Manage named projects")]
+    Project(projects::ProjectArgs),
 
-            let pidlock_path = work_root.join(format!(".locks/{source_path_hash}.pid"));
-            util::create_parent_dir(&pidlock_path).with_context(|| "Cannot create PID lock directory.")?;
-            let _pidlock = PidLock::acquire(pidlock_path).with_context(|| {
-                "Could not acquire PID lock. Another process is already running in this repository."
-            })?;
+    #[clap(
+        name = "prune-branches",
+        about = "Delete stale remote-tracking branches in work directories"
+    )]
+    PruneBranches(prune::PruneBranchesArgs),
 
-            // If a branch is specified, use that. Otherwise, use the branch we're currently in.
-            let branch = if let Some(branch) = branch {
-                GitRev::Branch(branch)
-            } else if let Some(commit) = commit {
-                GitRev::Commit(commit)
-            } else {
-                git.get_current_head(&repository_root_path)
-                    .with_context(|| "Error getting current branch")?
-            };
+    #[clap(
+        name = "move-workroot",
+        about = "Move all work directories and their state to a new work root"
+    )]
+    MoveWorkRoot(relocate::MoveWorkRootArgs),
 
-            let work_path = work_root.join(source_path_hash);
+    #[clap(name = "purge", about = "Delete work directories")]
+    Purge(purge::PurgeArgs),
 
-            if !json_out {
-                println!("Source repository: {}", repository_root_path.display());
-                println!("Working directory: {}", work_path.display());
-                println!("Branch: {branch}");
-            }
+    #[clap(name = "schedule", about = "Manage and execute scheduled runs")]
+    Schedule(schedule::ScheduleArgs),
 
-            let branch = match branch {
-                // If it's a branch, add remote specification
-                GitRev::Branch(branch) => GitRev::Branch(format!("{FERSK_ORIGIN}/{branch}")),
-                v => v,
-            };
+    #[clap(name = "stats", about = "Show local usage statistics")]
+    Stats(stats::StatsArgs),
 
-            if work_path.exists() {
-                git.force_remote_url(&work_path, FERSK_ORIGIN, &repository_root_path)
-                    .with_context(|| "Error setting Fersk remote URL")?;
+    #[clap(
+        name = "verify-tag",
+        about = "Verify the signature of a release tag and build it with the release command"
+    )]
+    VerifyTag(verifytag::VerifyTagArgs),
 
-                git.fetch(&work_path, FERSK_ORIGIN)
-                    .with_context(|| "Error fetching repository")?;
-            } else {
-                std::fs::create_dir_all(&work_path)
-                    .with_context(|| format!("Error creating work directory: {}", work_path.display()))?;
+    #[clap(name = "schema", about = "Print the JSON Schema of a command's json output")]
+    Schema(schema::SchemaArgs),
 
-                git.clone(&repository_root_path, &work_path, Some(FERSK_ORIGIN))
-                    .with_context(|| "Error cloning git repository")?;
-            }
+    #[clap(name = "self-update", about = "Update fersk to the latest release")]
+    SelfUpdate(selfupdate::SelfUpdateArgs),
+}
+
+#[derive(Debug, Parser)]
+enum ConfigCommand {
+    #[clap(name = "init", about = "Write the default config file, or upgrade an existing one")]
+    Init(config::init::InitArgs),
 
-            if let Some(copy_remote) = copy_remote {
-                let remote_url = git
-                    .get_remote_url(&repository_root_path, &copy_remote)
-                    .with_context(|| "Error getting copy remote URL")?;
+    #[clap(name = "get", about = "Print the effective value of a configuration key")]
+    Get { key: String },
 
-                git.force_remote_url(&work_path, &copy_remote, remote_url)
-                    .with_context(|| "Error setting copy remote URL")?;
-            }
+    #[clap(name = "set", about = "Set a configuration key")]
+    Set { key: String, value: String },
 
-            // Cleanse repository
-            git.cleanse(&work_path).with_context(|| "Error cleansing repository")?;
+    #[clap(name = "unset", about = "Remove a configuration key, restoring its default")]
+    Unset { key: String },
+}
 
-            // Check out branch in working directory
-            git.checkout(&work_path, &branch)
-                .with_context(|| "Error checking out branch")?;
+fn main() -> Result<(), anyhow::Error> {
+    let (args, bin_name) = command_line();
+    let opt = Opt::from_arg_matches(&Opt::command().bin_name(bin_name).get_matches_from(args))
+        .unwrap_or_else(|err| err.exit());
 
-            // Run command
-            command::exec_command(&args[0], |c| {
-                if json_out {
-                    c.stdout(Stdio::null());
-                }
+    if let Some(directory) = &opt.directory {
+        std::env::set_current_dir(directory)
+            .with_context(|| format!("Cannot change to directory: {}", directory.display()))?;
+    }
 
-                c.current_dir(&work_path);
-                c.args(&args[1..]);
-            })?;
+    // Initialize logging
+    initialize_logging(opt.verbose);
+
+    let cfg = Config::from_default_location().with_context(|| "Error loading config")?;
+
+    // Fail early with a clear error if git is missing or too old
+    if !matches!(
+        opt.command,
+        Command::GenerateConfig | Command::Config { .. } | Command::Schema(_) | Command::SelfUpdate(_)
+    ) {
+        git::check_version()?;
+    }
+
+    // Scripts are executed by fersk itself, which has already been through this
+    if !matches!(
+        opt.command,
+        Command::GenerateConfig | Command::Config { .. } | Command::Schema(_) | Command::ExecScript(_)
+    ) {
+        config::init::first_run(&cfg, opt.accept_defaults)?;
+    }
 
-            if json_out {
-                let output = JsonOutput {
-                    source_repository_path: repository_root_path,
-                    working_repository_path: work_path,
-                    branch: branch.to_string(),
-                };
+    match opt.command {
+        Command::GenerateConfig => {
+            config::init::init(Default::default()).with_context(|| "Error writing default config")?;
+        }
+        Command::Config { command } => match command {
+            ConfigCommand::Init(args) => config::init::init(args)?,
+            ConfigCommand::Get { key } => println!("{}", config::edit::get(&cfg, &key)?),
+            ConfigCommand::Set { key, value } => config::edit::set(&key, &value)?,
+            ConfigCommand::Unset { key } => config::edit::unset(&key)?,
+        },
+        Command::Run(args) => exit_on_run_error(run::run(&cfg, *args))?,
+        Command::RunScript(args) => exit_on_run_error(runscript::run_script(&cfg, *args))?,
+        Command::ExecScript(args) => runscript::exec_script(args)?,
+        Command::Cancel(args) => cancel::cancel(&cfg, args)?,
+        Command::Inspect(args) => inspect::inspect(&cfg, args)?,
+        Command::DiffOutput(args) => history::diff_output(&cfg, args)?,
+        Command::Fetch(args) => fetch::fetch(&cfg, args)?,
+        Command::Fsck(args) => fsck::fsck(&cfg, args)?,
+        Command::InstallHook(args) => hook::install_hook(args)?,
+        Command::List(args) => list::list(&cfg, args)?,
+        Command::Maintain => maintain::maintain(&cfg)?,
+        Command::Project(args) => projects::project(&cfg, args)?,
+        Command::PruneBranches(args) => prune::prune_branches(&cfg, args)?,
+        Command::MoveWorkRoot(args) => relocate::move_work_root(&cfg, args)?,
+        Command::Purge(args) => purge::purge(&cfg, args)?,
+        Command::Schedule(args) => schedule::schedule(&cfg, args)?,
+        Command::Stats(args) => stats::stats(&cfg, args)?,
+        Command::VerifyTag(args) => exit_on_run_error(verifytag::verify_tag(&cfg, args))?,
+        Command::Schema(args) => schema::schema(args)?,
+        Command::SelfUpdate(args) => selfupdate::self_update(&cfg, args)?,
+    };
+
+    Ok(())
+}
 
-                let stdio = std::io::stdout();
-                serde_json::to_writer_pretty(stdio.lock(), &output)?;
+/// Get the command line arguments and the name to show in usage,
+/// adapted for being invoked as an external subcommand of git (`git fersk`) or cargo (`cargo fersk`)
+fn command_line() -> (Vec<OsString>, &'static str) {
+    let mut args: Vec<OsString> = std::env::args_os().collect();
+
+    let invoked_as = args
+        .first()
+        .and_then(|arg| Path::new(arg).file_stem())
+        .map(|stem| stem.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    match invoked_as.as_str() {
+        // Git has already applied its own -C before running fersk
+        "git-fersk" => (args, "git fersk"),
+        "cargo-fersk" => {
+            // Cargo passes the name of the subcommand as the first argument
+            if args.get(1).is_some_and(|arg| arg == "fersk") {
+                args.remove(1);
             }
+
+            (args, "cargo fersk")
         }
+        _ => (args, "fersk"),
+    }
+}
+
+/// Exit with a distinct exit code for run errors that need to be told apart from command failures
+fn exit_on_run_error(result: Result<(), anyhow::Error>) -> Result<(), anyhow::Error> {
+    let Err(err) = result else {
+        return Ok(());
     };
 
-    Ok(())
+    if let Some(GitError::MergeConflict(_)) = err.downcast_ref::<GitError>() {
+        eprintln!("Error: {err:#}");
+        std::process::exit(MERGE_CONFLICT_EXIT_CODE);
+    }
+
+    if err.is::<cancel::Cancelled>() {
+        eprintln!("Error: {err:#}");
+        std::process::exit(CANCELLED_EXIT_CODE);
+    }
+
+    Err(err)
 }
 
-fn initialize_logging() {
+/// Set up logging to stderr. RUST_LOG takes precedence over the verbosity given on the command line.
+fn initialize_logging(verbose: u8) {
+    let default_filter = match verbose {
+        0 => "info",
+        1 => "info,fersk=debug",
+        _ => "info,fersk=trace",
+    };
+
     let subscriber = FmtSubscriber::builder()
-        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(default_filter)))
+        .with_writer(std::io::stderr)
         .finish();
 
     tracing::subscriber::set_global_default(subscriber).expect("Setting default tracing subscriber failed!");
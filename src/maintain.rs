@@ -0,0 +1,247 @@
+use std::collections::BTreeSet;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::git::Git;
+use crate::history;
+use crate::list::format_size;
+use crate::metadata::WorkMetadata;
+use crate::prune;
+use crate::purge;
+use crate::queue;
+use crate::util::{self, pid::PidLock, quote};
+use crate::workroot::{is_work_id, WorkRoot};
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Budgets and policies of `fersk maintain`
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct MaintenanceConfig {
+    /// Seconds after which no more work directories are maintained
+    pub max_runtime: Option<u64>,
+    /// MiB of repository data garbage collected, after which no more repositories are garbage collected
+    pub max_io: Option<u64>,
+    /// Days since the last run after which work directories are deleted
+    pub evict_after: Option<u64>,
+}
+
+/// What was done, for the summary report
+#[derive(Default)]
+struct Summary {
+    evicted: usize,
+    pruned_branches: usize,
+    collected: usize,
+    collected_bytes: u64,
+    gc_deferred: usize,
+    in_use: usize,
+    not_reached: usize,
+    failed: usize,
+    stale_locks: usize,
+    compacted: usize,
+}
+
+/// Run all maintenance on the work root: eviction of unused work directories, pruning of stale branches,
+/// garbage collection, removal of stale locks and compaction of metadata
+pub fn maintain(cfg: &Config) -> Result<(), anyhow::Error> {
+    let work_root = WorkRoot::from_config(cfg);
+    work_root.validate(cfg)?;
+    let budget = &cfg.maintenance;
+
+    let git = Git {
+        silent: true,
+        ..Default::default()
+    };
+
+    let started = Instant::now();
+    let mut summary = Summary::default();
+
+    let ids = work_root.work_ids().with_context(|| "Error listing work directories")?;
+
+    for (index, id) in ids.iter().enumerate() {
+        if budget
+            .max_runtime
+            .is_some_and(|max| started.elapsed() >= Duration::from_secs(max))
+        {
+            summary.not_reached = ids.len() - index;
+            break;
+        }
+
+        let work_path = work_root.work_path(id);
+
+        if PidLock::holder(work_root.lock_path(id)).is_some() {
+            println!("Skipping {}: Work directory is in use", quote::path(&work_path));
+            summary.in_use += 1;
+            continue;
+        }
+
+        if let Err(err) = maintain_work_dir(cfg, &git, &work_root, id, &mut summary) {
+            eprintln!("Error maintaining {}: {err:#}", quote::path(&work_path));
+            summary.failed += 1;
+        }
+    }
+
+    summary.stale_locks = queue::remove_stale(&work_root);
+    summary.compacted = compact_metadata(cfg, &work_root)?;
+
+    print_summary(&summary, started.elapsed());
+
+    Ok(())
+}
+
+/// Evict a work directory if it is no longer used, or prune and garbage collect it
+fn maintain_work_dir(
+    cfg: &Config,
+    git: &Git,
+    work_root: &WorkRoot,
+    id: &str,
+    summary: &mut Summary,
+) -> Result<(), anyhow::Error> {
+    let work_path = work_root.work_path(id);
+    let budget = &cfg.maintenance;
+
+    if let Some(days) = budget.evict_after {
+        let last_used = WorkMetadata::load(work_root.metadata_path(id))?.and_then(|m| m.finished_at.or(m.started_at));
+
+        if last_used.is_some_and(|t| util::time::unix_now().saturating_sub(t) >= days * SECONDS_PER_DAY) {
+            purge::purge_work_dir(git, work_root, id)?;
+
+            println!("Evicted {}", quote::path(&work_path));
+            summary.evicted += 1;
+
+            return Ok(());
+        }
+    }
+
+    // Branches can't be pruned if the source repository is gone, but the work directory can still be collected
+    match prune::prune_work_dir(git, work_root, id, false) {
+        Ok(pruned) => summary.pruned_branches += pruned.len(),
+        Err(err) => println!("Not pruning branches in {}: {err:#}", quote::path(&work_path)),
+    }
+
+    if budget
+        .max_io
+        .is_some_and(|max| summary.collected_bytes >= max * 1024 * 1024)
+    {
+        summary.gc_deferred += 1;
+        return Ok(());
+    }
+
+    let _pidlock = PidLock::acquire(work_root.lock_path(id)).with_context(|| "Work directory is in use")?;
+
+    let size = util::dir_size(work_path.join(".git")).unwrap_or(0);
+    git.gc(&work_path).with_context(|| "Error collecting garbage")?;
+
+    summary.collected += 1;
+    summary.collected_bytes += size;
+
+    Ok(())
+}
+
+/// Remove state left behind for work directories that no longer exist according to their metadata, damaged record files,
+/// temporary files of interrupted runs and history beyond the configured number of runs.
+/// Returns the number of files and directories removed.
+fn compact_metadata(cfg: &Config, work_root: &WorkRoot) -> Result<usize, anyhow::Error> {
+    let mut removed = 0;
+
+    let mut known_ids = BTreeSet::new();
+    for dir in [".meta", ".history", ".logs", ".tmp", ".checkouts"] {
+        let Ok(entries) = std::fs::read_dir(work_root.path().join(dir)) else {
+            continue;
+        };
+
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().to_string();
+
+            if name.ends_with(".corrupt") {
+                std::fs::remove_file(&path).with_context(|| format!("Error deleting {}", path.display()))?;
+                removed += 1;
+                continue;
+            }
+
+            // Files are named after the id, optionally followed by an extension or suffix
+            let id = name.get(..64).unwrap_or(&name);
+            if is_work_id(id) {
+                known_ids.insert(id.to_owned());
+            }
+        }
+    }
+
+    std::fs::create_dir_all(work_root.locks_dir()).with_context(|| "Error creating lock directory")?;
+
+    for id in known_ids {
+        // A new work directory may be in the middle of being created, so nothing is removed without holding its lock
+        let Some(_pidlock) = PidLock::acquire(work_root.lock_path(&id)) else {
+            continue;
+        };
+
+        // Work directories at custom paths (--into) are outside the work root, so only their metadata tells where they are
+        let work_path = WorkMetadata::load(work_root.metadata_path(&id))
+            .ok()
+            .flatten()
+            .map(|metadata| metadata.working_repository_path);
+
+        if work_path.is_some_and(|path| !path.exists()) {
+            purge::remove_state(work_root, &id)?;
+            removed += 1;
+            continue;
+        }
+
+        let temp_path = work_root.temp_path(&id);
+        if temp_path.exists() {
+            util::remove_dir_all(&temp_path).with_context(|| "Error deleting temporary directory")?;
+            removed += 1;
+        }
+
+        removed += history::trim(work_root, &id, cfg.run_history)?;
+    }
+
+    Ok(removed)
+}
+
+fn print_summary(summary: &Summary, elapsed: Duration) {
+    println!("Maintenance finished in {}s:", elapsed.as_secs());
+    println!("  Evicted work directories: {}", summary.evicted);
+    println!("  Pruned branches: {}", summary.pruned_branches);
+    println!(
+        "  Garbage collected: {} repositories ({})",
+        summary.collected,
+        format_size(summary.collected_bytes)
+    );
+    println!("  Removed stale locks: {}", summary.stale_locks);
+    println!("  Compacted metadata: {} entries", summary.compacted);
+
+    if summary.in_use > 0 {
+        println!("  Skipped, in use: {}", summary.in_use);
+    }
+
+    if summary.gc_deferred > 0 {
+        println!("  Not garbage collected, max-io reached: {}", summary.gc_deferred);
+    }
+
+    if summary.not_reached > 0 {
+        println!("  Not maintained, max-runtime reached: {}", summary.not_reached);
+    }
+
+    if summary.failed > 0 {
+        println!("  Failed: {}", summary.failed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognizes_work_ids() {
+        let work_root = WorkRoot::new("/work");
+
+        assert!(is_work_id(&work_root.source_id("/src/repository")));
+        assert!(!is_work_id("stats"));
+        assert!(!is_work_id(&"g".repeat(64)));
+    }
+}
@@ -0,0 +1,122 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Context};
+use serde_derive::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::git::Git;
+use crate::run::FERSK_ORIGIN;
+
+/// How new work directories are created
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Materialization {
+    /// Clone the source repository
+    #[default]
+    Clone,
+    /// Copy-on-write copy of the source checkout (macOS APFS only), fixed up to look like a clone
+    Clonefile,
+    /// Clone using the source repository's object store, like a worktree, without copying any objects.
+    /// Unlike `git worktree add`, nothing is registered in the source repository, and refs are kept separate.
+    Worktree,
+}
+
+/// Create a new work directory for a source repository
+pub fn create_work_dir(
+    git: &Git,
+    materialization: Materialization,
+    source_path: &Path,
+    work_path: &Path,
+) -> Result<(), anyhow::Error> {
+    if let Materialization::Clonefile = materialization {
+        match clonefile_work_dir(git, source_path, work_path) {
+            Ok(()) => return Ok(()),
+            Err(err) => {
+                warn!("Could not create work directory using clonefile, cloning instead: {err:#}");
+
+                if work_path.exists() {
+                    crate::util::remove_dir_all(work_path)
+                        .with_context(|| format!("Error removing partial work directory: {}", work_path.display()))?;
+                }
+            }
+        }
+    }
+
+    std::fs::create_dir_all(work_path)
+        .with_context(|| format!("Error creating work directory: {}", work_path.display()))?;
+
+    if let Materialization::Worktree = materialization {
+        // There is nothing to copy, so the history is complete and no objects need filtering
+        if git.depth.is_some() || git.filter.is_some() || git.reference.is_some() {
+            warn!(
+                "Shallow, partial and reference clones don't apply to worktree work directories. \
+                 Ignoring --depth, --filter and --reference."
+            );
+        }
+
+        git.clone_shared(source_path, work_path, FERSK_ORIGIN)
+            .with_context(|| "Error creating worktree work directory")?;
+
+        return Ok(());
+    }
+
+    git.clone(source_path, work_path, Some(FERSK_ORIGIN))
+        .with_context(|| "Error cloning git repository")?;
+
+    Ok(())
+}
+
+/// Create a work directory as a copy-on-write clone of the source checkout,
+/// then turn it into something equivalent to a fresh clone
+fn clonefile_work_dir(git: &Git, source_path: &Path, work_path: &Path) -> Result<(), anyhow::Error> {
+    // Worktrees and submodules only have a .git file pointing elsewhere, which can't be copied meaningfully
+    if !source_path.join(".git").is_dir() {
+        return Err(anyhow!("Source repository does not have a .git directory"));
+    }
+
+    if let Some(parent) = work_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    clonefile(source_path, work_path).with_context(|| "clonefile failed")?;
+
+    // A lock left by a git process running in the source at the time of copying would block everything
+    let index_lock = work_path.join(".git/index.lock");
+    if index_lock.exists() {
+        std::fs::remove_file(&index_lock)?;
+    }
+
+    // Replace the source's remotes with the internal one, as a clone would have
+    for remote in git.list_remotes(work_path)? {
+        git.remove_remote(work_path, &remote)?;
+    }
+
+    git.force_remote_url(work_path, FERSK_ORIGIN, source_path)?;
+    git.fetch(work_path, FERSK_ORIGIN)?;
+
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+fn clonefile(from: &Path, to: &Path) -> std::io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let from = CString::new(from.as_os_str().as_bytes())?;
+    let to = CString::new(to.as_os_str().as_bytes())?;
+
+    // Directories are cloned recursively
+    if unsafe { libc::clonefile(from.as_ptr(), to.as_ptr(), 0) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "macos"))]
+fn clonefile(_from: &Path, _to: &Path) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "clonefile is only supported on macOS",
+    ))
+}
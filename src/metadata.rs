@@ -0,0 +1,66 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::util::record::{self, Record};
+
+/// Information about the last run in a work directory
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct WorkMetadata {
+    pub source_repository_path: PathBuf,
+    pub working_repository_path: PathBuf,
+    pub branch: Option<String>,
+    pub commit: Option<String>,
+    pub command: Vec<String>,
+    pub pid: Option<u32>,
+    pub command_pid: Option<u32>,
+    pub started_at: Option<u64>,
+    pub finished_at: Option<u64>,
+    pub success: Option<bool>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub cancelled: bool,
+    pub run_id: Option<String>,
+    /// Seed passed to the command in FERSK_SEED
+    pub seed: Option<u64>,
+    pub labels: BTreeMap<String, String>,
+}
+
+/// Last commit each command succeeded for, keyed by command hash
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct SuccessRecord {
+    pub commands: BTreeMap<String, String>,
+}
+
+impl WorkMetadata {
+    /// Load metadata, if it exists
+    pub fn load(path: impl AsRef<Path>) -> Result<Option<Self>, anyhow::Error> {
+        record::load(path)
+    }
+
+    /// Save metadata
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), anyhow::Error> {
+        record::save(path, self)
+    }
+}
+
+impl Record for WorkMetadata {
+    const VERSION: u32 = 1;
+}
+
+impl SuccessRecord {
+    /// Load success record, or an empty one if it does not exist
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, anyhow::Error> {
+        Ok(record::load(path)?.unwrap_or_default())
+    }
+
+    /// Save success record
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), anyhow::Error> {
+        record::save(path, self)
+    }
+}
+
+impl Record for SuccessRecord {
+    const VERSION: u32 = 1;
+}
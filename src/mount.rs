@@ -0,0 +1,43 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::util;
+
+/// Additional directory exposed to the command, which is read-only in a sandbox
+#[derive(Clone, Debug)]
+pub struct Mount {
+    pub host_path: PathBuf,
+    pub name: String,
+}
+
+impl Mount {
+    /// Name of the environment variable containing the mount's location
+    pub fn env_var(&self) -> String {
+        format!("FERSK_MOUNT_{}", self.name.to_uppercase().replace('-', "_"))
+    }
+}
+
+impl FromStr for Mount {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Split on the last colon, since Windows paths can contain one
+        let (host_path, name) = s
+            .rsplit_once(':')
+            .ok_or_else(|| "Mount must be specified as <host-path>:<name>".to_owned())?;
+
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            return Err(format!("Invalid mount name: {name}"));
+        }
+
+        let host_path = util::normalize_path(host_path);
+        if !host_path.is_dir() {
+            return Err(format!("Mount directory does not exist: {}", host_path.display()));
+        }
+
+        Ok(Self {
+            host_path,
+            name: name.to_owned(),
+        })
+    }
+}
@@ -0,0 +1,116 @@
+use std::process::Command;
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+use anyhow::anyhow;
+
+/// Create a command that runs without network access.
+/// On Linux, it is started in a new network namespace with only loopback available.
+/// On macOS, it is run under a sandbox profile denying network access.
+pub fn isolated_command(program: &str) -> Result<Command, anyhow::Error> {
+    isolated_command_impl(program)
+}
+
+#[cfg(target_os = "linux")]
+fn isolated_command_impl(program: &str) -> Result<Command, anyhow::Error> {
+    use std::os::unix::process::CommandExt;
+
+    let mut command = Command::new(program);
+
+    let uid = unsafe { libc::getuid() };
+    let gid = unsafe { libc::getgid() };
+
+    // Prepared up front, as nothing should be allocated between fork and exec
+    let uid_map = format!("{uid} {uid} 1\n");
+    let gid_map = format!("{gid} {gid} 1\n");
+
+    unsafe {
+        command.pre_exec(move || {
+            // Unprivileged users need a user namespace to be allowed to create a network namespace
+            if uid == 0 {
+                unshare(libc::CLONE_NEWNET)?;
+            } else {
+                unshare(libc::CLONE_NEWUSER | libc::CLONE_NEWNET)?;
+
+                // Keep the same user and group inside the namespace
+                write_proc(b"/proc/self/setgroups\0", b"deny")?;
+                write_proc(b"/proc/self/uid_map\0", uid_map.as_bytes())?;
+                write_proc(b"/proc/self/gid_map\0", gid_map.as_bytes())?;
+            }
+
+            // Loopback starts out down, and local servers are fair game
+            bring_up_loopback();
+
+            Ok(())
+        });
+    }
+
+    Ok(command)
+}
+
+#[cfg(target_os = "linux")]
+fn unshare(flags: libc::c_int) -> std::io::Result<()> {
+    if unsafe { libc::unshare(flags) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn write_proc(path: &[u8], content: &[u8]) -> std::io::Result<()> {
+    unsafe {
+        let fd = libc::open(path.as_ptr() as *const libc::c_char, libc::O_WRONLY);
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        let written = libc::write(fd, content.as_ptr() as *const libc::c_void, content.len());
+        libc::close(fd);
+
+        if written < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn bring_up_loopback() {
+    unsafe {
+        let fd = libc::socket(libc::AF_INET, libc::SOCK_DGRAM, 0);
+        if fd < 0 {
+            return;
+        }
+
+        let mut ifreq: libc::ifreq = std::mem::zeroed();
+        for (i, c) in b"lo".iter().enumerate() {
+            ifreq.ifr_name[i] = *c as libc::c_char;
+        }
+
+        if libc::ioctl(fd, libc::SIOCGIFFLAGS as _, &mut ifreq) == 0 {
+            ifreq.ifr_ifru.ifru_flags |= libc::IFF_UP as libc::c_short;
+            libc::ioctl(fd, libc::SIOCSIFFLAGS as _, &ifreq);
+        }
+
+        libc::close(fd);
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn isolated_command_impl(program: &str) -> Result<Command, anyhow::Error> {
+    const PROFILE: &str = "(version 1) (allow default) (deny network*) (allow network* (local ip \"localhost:*\")) \
+                           (allow network* (remote ip \"localhost:*\")) (allow network* (remote unix-socket))";
+
+    let mut command = Command::new("sandbox-exec");
+    command.args(["-p", PROFILE, program]);
+
+    Ok(command)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn isolated_command_impl(_program: &str) -> Result<Command, anyhow::Error> {
+    Err(anyhow!(
+        "Running without network access is not supported on this platform."
+    ))
+}
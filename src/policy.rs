@@ -0,0 +1,171 @@
+use anyhow::anyhow;
+use clap::Args;
+
+use crate::rev::GitRev;
+
+/// How commands performing several runs or operations react to failures
+#[derive(Debug, Default, Args)]
+pub struct FailurePolicyArgs {
+    #[clap(
+        long = "fail-fast",
+        conflicts_with_all = ["keep_going", "max_failures"],
+        help = "Stop at the first failure, cancelling the rest"
+    )]
+    fail_fast: bool,
+    #[clap(
+        long = "keep-going",
+        conflicts_with = "max_failures",
+        help = "Carry on regardless of failures (default)"
+    )]
+    keep_going: bool,
+    #[clap(long = "max-failures", help = "Stop after this many failures, cancelling the rest")]
+    max_failures: Option<usize>,
+}
+
+/// Tally of the outcomes of several operations, deciding when to stop according to the failure policy
+#[derive(Debug)]
+pub struct Outcomes {
+    max_failures: Option<usize>,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub cancelled: usize,
+}
+
+impl FailurePolicyArgs {
+    pub fn outcomes(&self) -> Outcomes {
+        let max_failures = if self.fail_fast {
+            Some(1)
+        } else if self.keep_going {
+            None
+        } else {
+            self.max_failures
+        };
+
+        Outcomes {
+            max_failures,
+            succeeded: 0,
+            failed: 0,
+            cancelled: 0,
+        }
+    }
+}
+
+impl Outcomes {
+    pub fn record(&mut self, success: bool) {
+        if success {
+            self.succeeded += 1;
+        } else {
+            self.failed += 1;
+        }
+    }
+
+    /// Number of operations that have finished
+    pub fn finished(&self) -> usize {
+        self.succeeded + self.failed
+    }
+
+    /// Check if the remaining operations should be cancelled
+    pub fn should_stop(&self) -> bool {
+        self.max_failures.is_some_and(|max| self.failed >= max.max(1))
+    }
+
+    /// Print a summary, and turn failures into an error.
+    /// `what` describes the operations (ex. "scheduled run(s)").
+    pub fn finish(self, what: &str) -> Result<(), anyhow::Error> {
+        if self.failed == 0 && self.cancelled == 0 {
+            return Ok(());
+        }
+
+        eprintln!(
+            "{} succeeded, {} failed, {} cancelled.",
+            self.succeeded, self.failed, self.cancelled
+        );
+
+        if self.cancelled > 0 {
+            return Err(anyhow!(
+                "{} {what} failed. Stopped after reaching the failure limit.",
+                self.failed
+            ));
+        }
+
+        Err(anyhow!("{} {what} failed.", self.failed))
+    }
+}
+
+/// Refuse running a destructive script on a branch matching one of the protected branch patterns.
+/// Revisions that aren't branches (ex. a commit or a detached HEAD) are on the branches whose tip they are (`tip_of`).
+pub fn check_protected_branch(
+    protected: &[String],
+    rev: &GitRev,
+    tip_of: &[String],
+    script: &str,
+) -> Result<(), anyhow::Error> {
+    let branches = match branch_name(rev) {
+        Some(branch) => vec![branch],
+        None => tip_of.iter().map(String::as_str).collect(),
+    };
+
+    for branch in branches {
+        if let Some(pattern) = protected.iter().find(|p| matches_pattern(p, branch)) {
+            return Err(anyhow!(
+                "Not running destructive script {script} on {branch}, which is protected ({pattern}). \
+                 Use --confirm-protected to run it anyway."
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Get the name of the branch a revision is on, if it is a branch
+fn branch_name(rev: &GitRev) -> Option<&str> {
+    match rev {
+        GitRev::Branch(branch) | GitRev::RemoteBranch { branch, .. } => Some(branch),
+        GitRev::Ref(r) | GitRev::RemoteRef { name: r, .. } => r.strip_prefix("refs/heads/"),
+        GitRev::Tag(_) | GitRev::Commit(_) => None,
+    }
+}
+
+/// Match a name against a pattern, where * matches any number of characters
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == name,
+        Some((prefix, rest)) => {
+            let Some(name) = name.strip_prefix(prefix) else {
+                return false;
+            };
+
+            (0..=name.len())
+                .filter(|&i| name.is_char_boundary(i))
+                .any(|i| matches_pattern(rest, &name[i..]))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_protected_branches() {
+        let protected = vec!["main".to_owned(), "release/*".to_owned()];
+        let check = |rev: GitRev| check_protected_branch(&protected, &rev, &[], "deploy").is_ok();
+
+        assert!(!check(GitRev::Branch("main".to_owned())));
+        assert!(!check(GitRev::Branch("release/1.0".to_owned())));
+        assert!(!check(GitRev::RemoteBranch {
+            remote: "origin".to_owned(),
+            branch: "main".to_owned()
+        }));
+        assert!(check(GitRev::Branch("maintenance".to_owned())));
+        assert!(check(GitRev::Branch("feature/release/1.0".to_owned())));
+        assert!(check(GitRev::Tag("main".to_owned())));
+
+        let tip_of = vec!["feature".to_owned(), "release/2.0".to_owned()];
+        assert!(check_protected_branch(&protected, &GitRev::Commit("abc".to_owned()), &tip_of, "deploy").is_err());
+        assert!(check_protected_branch(&protected, &GitRev::Branch("feature".to_owned()), &tip_of, "deploy").is_ok());
+
+        assert!(matches_pattern("*-hotfix-*", "v1-hotfix-2"));
+        assert!(!matches_pattern("*-hotfix", "v1-hotfix-2"));
+    }
+}
@@ -0,0 +1,92 @@
+use std::ffi::OsStr;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::command;
+use crate::config::project::PostCheckConfig;
+
+/// Result of a post-check
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PostCheckResult {
+    pub name: String,
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub duration_ms: u64,
+    pub output: String,
+}
+
+/// Run all post-checks in the working directory.
+/// Every check is run, even if an earlier one failed, so all problems are reported at once.
+pub fn run_post_checks(
+    checks: &[PostCheckConfig],
+    work_path: &Path,
+    path_env: Option<&OsStr>,
+    quiet: bool,
+) -> Vec<PostCheckResult> {
+    checks
+        .iter()
+        .map(|check| {
+            let result = run_post_check(check, work_path, path_env);
+
+            if !quiet {
+                if result.success {
+                    eprintln!("Post-check passed: {}", result.name);
+                } else {
+                    eprintln!("Post-check failed: {}", result.name);
+
+                    if let Some(error) = &result.error {
+                        eprintln!("{error}");
+                    }
+
+                    eprint!("{}", result.output);
+                }
+            }
+
+            result
+        })
+        .collect()
+}
+
+fn run_post_check(check: &PostCheckConfig, work_path: &Path, path_env: Option<&OsStr>) -> PostCheckResult {
+    let started = Instant::now();
+
+    let result = match check.command.split_first() {
+        Some((program, args)) => {
+            command::exec_command_capture_timeout(program, check.timeout.map(Duration::from_secs), |c| {
+                c.current_dir(work_path);
+                c.args(args);
+
+                if let Some(path_env) = path_env {
+                    c.env("PATH", path_env);
+                }
+            })
+        }
+        None => Err(anyhow::anyhow!("Post-check command is empty.")),
+    };
+
+    let duration_ms = started.elapsed().as_millis() as u64;
+
+    match result {
+        Ok((status, output)) => PostCheckResult {
+            name: check.name.clone(),
+            success: status.success(),
+            exit_code: status.code(),
+            error: None,
+            duration_ms,
+            output,
+        },
+        Err(err) => PostCheckResult {
+            name: check.name.clone(),
+            success: false,
+            exit_code: None,
+            error: Some(format!("{err:#}")),
+            duration_ms,
+            output: String::new(),
+        },
+    }
+}
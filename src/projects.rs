@@ -0,0 +1,102 @@
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use clap::{Args, Parser};
+
+use crate::config::{edit, Config};
+use crate::git::Git;
+use crate::run;
+use crate::util::quote;
+
+/// Config table mapping project names to repository paths
+const PROJECTS_KEY: &str = "projects";
+
+#[derive(Debug, Args)]
+pub struct ProjectArgs {
+    #[clap(subcommand)]
+    command: ProjectCommand,
+}
+
+#[derive(Debug, Parser)]
+enum ProjectCommand {
+    #[clap(
+        name = "add",
+        about = "Register a repository under a name, for use with `run --project`"
+    )]
+    Add {
+        name: String,
+        #[clap(long = "path", help = "Specify repository path")]
+        path: Option<PathBuf>,
+    },
+
+    #[clap(name = "list", about = "List registered projects")]
+    List,
+
+    #[clap(name = "remove", about = "Remove a registered project")]
+    Remove { name: String },
+}
+
+pub fn project(cfg: &Config, args: ProjectArgs) -> Result<(), anyhow::Error> {
+    match args.command {
+        ProjectCommand::Add { name, path } => add(cfg, &name, path),
+        ProjectCommand::List => list(cfg),
+        ProjectCommand::Remove { name } => remove(cfg, &name),
+    }
+}
+
+/// Get the repository path of a registered project
+pub fn resolve(cfg: &Config, name: &str) -> Result<PathBuf, anyhow::Error> {
+    cfg.projects.get(name).cloned().ok_or_else(|| {
+        let known = cfg.projects.keys().cloned().collect::<Vec<_>>().join(", ");
+
+        if known.is_empty() {
+            anyhow!("Unknown project: {name}. Register it with `fersk project add {name}`.")
+        } else {
+            anyhow!("Unknown project: {name}. Registered projects: {known}")
+        }
+    })
+}
+
+fn add(cfg: &Config, name: &str, path: Option<PathBuf>) -> Result<(), anyhow::Error> {
+    if cfg.projects.contains_key(name) {
+        return Err(anyhow!("A project named {name} already exists."));
+    }
+
+    let git = Git {
+        silent: true,
+        ..Default::default()
+    };
+
+    let path = run::resolve_source_repository(&git, path)?;
+    let path_str = path
+        .to_str()
+        .ok_or_else(|| anyhow!("Repository path is not valid UTF-8: {}", path.display()))?;
+
+    edit::edit_table(PROJECTS_KEY, |table| {
+        table.insert(name, toml_edit::value(path_str));
+        Ok(())
+    })?;
+
+    println!("Added {name}: {}", quote::path(&path));
+
+    Ok(())
+}
+
+fn remove(cfg: &Config, name: &str) -> Result<(), anyhow::Error> {
+    if !cfg.projects.contains_key(name) {
+        return Err(anyhow!("No project named {name}."));
+    }
+
+    edit::edit_table(PROJECTS_KEY, |table| {
+        table.remove(name);
+        Ok(())
+    })
+}
+
+fn list(cfg: &Config) -> Result<(), anyhow::Error> {
+    for (name, path) in &cfg.projects {
+        println!("{name}: {}", quote::path(path));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,151 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context};
+use clap::Args;
+use tracing::{debug, warn};
+
+use crate::config::Config;
+use crate::git::Git;
+use crate::journal::Journal;
+use crate::run::{self, FERSK_ORIGIN};
+use crate::util::pid::PidLock;
+use crate::util::quote;
+use crate::workroot::WorkRoot;
+
+#[derive(Debug, Args)]
+pub struct PruneBranchesArgs {
+    #[clap(long = "path", help = "Only prune the work directory of this repository")]
+    path: Option<PathBuf>,
+    #[clap(long = "dry-run", help = "Only show which branches would be pruned")]
+    dry_run: bool,
+}
+
+pub fn prune_branches(cfg: &Config, args: PruneBranchesArgs) -> Result<(), anyhow::Error> {
+    let work_root = WorkRoot::from_config(cfg);
+    work_root.validate(cfg)?;
+
+    let git = Git {
+        silent: true,
+        ..Default::default()
+    };
+
+    let ids = if let Some(path) = args.path {
+        let repository_root_path = run::resolve_source_repository(&git, Some(path))?;
+
+        vec![work_root.source_id(repository_root_path)]
+    } else {
+        work_root.work_ids().with_context(|| "Error listing work directories")?
+    };
+
+    for id in ids {
+        let work_path = work_root.work_path(&id);
+
+        match prune_work_dir(&git, &work_root, &id, args.dry_run) {
+            Ok(pruned) => {
+                for branch in pruned {
+                    if args.dry_run {
+                        println!("Would prune {branch} in {}", quote::path(&work_path));
+                    } else {
+                        println!("Pruned {branch} in {}", quote::path(&work_path));
+                    }
+                }
+            }
+            Err(err) => eprintln!("Skipping {}: {err:#}", quote::path(&work_path)),
+        }
+    }
+
+    Ok(())
+}
+
+/// Prune stale branches in the work directory of the current run, under the lock it already holds,
+/// and in all other work directories that are not currently in use
+pub fn auto_prune_branches(work_root: &WorkRoot, current_id: &str, current_path: &Path) {
+    let git = Git {
+        silent: true,
+        ..Default::default()
+    };
+
+    if let Err(err) = prune_locked_work_dir(&git, work_root, current_id, current_path, false) {
+        debug!("Not pruning branches in {}: {err:#}", current_path.display());
+    }
+
+    let ids = match work_root.work_ids() {
+        Ok(ids) => ids,
+        Err(err) => {
+            warn!("Error listing work directories: {err}");
+            return;
+        }
+    };
+
+    for id in ids.iter().filter(|id| *id != current_id) {
+        if let Err(err) = prune_work_dir(&git, work_root, id, false) {
+            debug!("Not pruning branches in {}: {err:#}", work_root.work_path(id).display());
+        }
+    }
+}
+
+/// Delete remote-tracking branches that no longer exist in the source repository
+pub fn prune_work_dir(git: &Git, work_root: &WorkRoot, id: &str, dry_run: bool) -> Result<Vec<String>, anyhow::Error> {
+    let work_path = work_root.work_path(id);
+
+    if !work_path.exists() {
+        return Err(anyhow!("Work directory does not exist"));
+    }
+
+    let _pidlock = PidLock::acquire(work_root.lock_path(id)).with_context(|| "Work directory is in use")?;
+
+    prune_locked_work_dir(git, work_root, id, &work_path, dry_run)
+}
+
+/// Delete remote-tracking branches that no longer exist in the source repository,
+/// in a work directory the lock of which is held
+fn prune_locked_work_dir(
+    git: &Git,
+    work_root: &WorkRoot,
+    id: &str,
+    work_path: &Path,
+    dry_run: bool,
+) -> Result<Vec<String>, anyhow::Error> {
+    let source_path = git
+        .get_remote_url(work_path, FERSK_ORIGIN)
+        .with_context(|| "Error getting Fersk remote URL")?;
+
+    if !Path::new(&source_path).exists() {
+        return Err(anyhow!("Source repository no longer exists: {source_path}"));
+    }
+
+    let source_branches: HashSet<String> = git
+        .list_refs(&source_path, "refs/heads/")
+        .with_context(|| "Error listing source branches")?
+        .into_iter()
+        .filter_map(|r| r.strip_prefix("refs/heads/").map(|b| b.to_owned()))
+        .collect();
+
+    let remote_prefix = format!("refs/remotes/{FERSK_ORIGIN}/");
+    let mut pruned = Vec::new();
+
+    for refname in git
+        .list_refs(work_path, &remote_prefix)
+        .with_context(|| "Error listing remote-tracking branches")?
+    {
+        let Some(branch) = refname.strip_prefix(&remote_prefix) else {
+            continue;
+        };
+
+        if branch == "HEAD" || source_branches.contains(branch) {
+            continue;
+        }
+
+        if !dry_run {
+            git.delete_ref(work_path, &refname)
+                .with_context(|| format!("Error deleting {refname}"))?;
+
+            Journal::new(work_root, id).record("prune", format!("deleted {FERSK_ORIGIN}/{branch}"));
+        }
+
+        pruned.push(format!("{FERSK_ORIGIN}/{branch}"));
+    }
+
+    Ok(pruned)
+}
@@ -0,0 +1,170 @@
+use std::io::{self, Read, Write};
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use portable_pty::{native_pty_system, Child, CommandBuilder, MasterPty, PtySize};
+
+use crate::interrupt::{self, SharedWriter, Target};
+
+const RESIZE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Command running in a pseudo-terminal
+pub struct PtyChild {
+    pub child: Box<dyn Child + Send + Sync>,
+    pub reader: Box<dyn Read + Send>,
+    /// Forwards Ctrl-C to the command until dropped
+    pub interrupts: interrupt::Forwarding,
+    /// Restores the terminal fersk runs in when dropped
+    pub raw_mode: Option<RawMode>,
+}
+
+/// Keeps stdin in raw mode, so input (ex. Ctrl-D, arrow keys) reaches the command unprocessed.
+/// The previous mode is restored when dropped, which also happens when unwinding from a panic.
+pub struct RawMode {
+    #[cfg(unix)]
+    original: libc::termios,
+}
+
+impl RawMode {
+    /// Put stdin in raw mode, if it is a terminal
+    #[cfg(unix)]
+    fn enable() -> Option<Self> {
+        let fd = libc::STDIN_FILENO;
+
+        unsafe {
+            if libc::isatty(fd) != 1 {
+                return None;
+            }
+
+            let mut original = std::mem::MaybeUninit::<libc::termios>::uninit();
+            if libc::tcgetattr(fd, original.as_mut_ptr()) != 0 {
+                return None;
+            }
+
+            let original = original.assume_init();
+            let mut raw = original;
+            libc::cfmakeraw(&mut raw);
+
+            // Keep output processing, so fersk's own messages still start on a new line
+            raw.c_oflag = original.c_oflag;
+
+            if libc::tcsetattr(fd, libc::TCSANOW, &raw) != 0 {
+                return None;
+            }
+
+            Some(Self { original })
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn enable() -> Option<Self> {
+        None
+    }
+}
+
+#[cfg(unix)]
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original);
+        }
+    }
+}
+
+/// Spawn a command in a new pseudo-terminal.
+/// Input, Ctrl-C and terminal size changes are forwarded to the command.
+pub fn spawn(command: &Command) -> Result<PtyChild, anyhow::Error> {
+    let pty_system = native_pty_system();
+    let pair = pty_system.openpty(terminal_size())?;
+
+    let mut builder = CommandBuilder::new(command.get_program());
+    builder.args(command.get_args());
+
+    if let Some(dir) = command.get_current_dir() {
+        builder.cwd(dir);
+    }
+
+    for (key, value) in command.get_envs() {
+        match value {
+            Some(value) => builder.env(key, value),
+            None => builder.env_remove(key),
+        }
+    }
+
+    let reader = pair.master.try_clone_reader()?;
+    let writer: SharedWriter = Arc::new(Mutex::new(pair.master.take_writer()?));
+
+    interrupt::install()?;
+
+    let child = pair.slave.spawn_command(builder)?;
+
+    // The slave must be closed in this process, or reading from the master never ends
+    drop(pair.slave);
+
+    // Forward input
+    {
+        let writer = writer.clone();
+        thread::spawn(move || {
+            let mut buf = [0u8; 1024];
+            let mut stdin = io::stdin();
+
+            while let Ok(n @ 1..) = stdin.read(&mut buf) {
+                let Ok(mut writer) = writer.lock() else {
+                    break;
+                };
+
+                if writer.write_all(&buf[..n]).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    // Forward terminal size changes
+    thread::spawn(move || forward_resize(pair.master));
+
+    if child.process_id().is_none() {
+        return Err(anyhow!("Could not get process ID of command"));
+    }
+
+    Ok(PtyChild {
+        child,
+        reader,
+        // Ctrl-C is sent as an interrupt character, so it reaches the command's foreground process
+        interrupts: interrupt::forward(Target::Terminal(writer)),
+        raw_mode: RawMode::enable(),
+    })
+}
+
+fn forward_resize(master: Box<dyn MasterPty + Send>) {
+    let mut size = terminal_size();
+
+    loop {
+        thread::sleep(RESIZE_INTERVAL);
+
+        let new_size = terminal_size();
+        if (new_size.rows, new_size.cols) != (size.rows, size.cols) {
+            if master.resize(new_size).is_err() {
+                break;
+            }
+
+            size = new_size;
+        }
+    }
+}
+
+fn terminal_size() -> PtySize {
+    let (cols, rows) = terminal_size::terminal_size()
+        .map(|(w, h)| (w.0, h.0))
+        .unwrap_or((80, 24));
+
+    PtySize {
+        rows,
+        cols,
+        pixel_width: 0,
+        pixel_height: 0,
+    }
+}
@@ -0,0 +1,105 @@
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Context};
+use clap::Args;
+
+use crate::config::Config;
+use crate::git::Git;
+use crate::journal::Journal;
+use crate::run;
+use crate::util::{self, pid::PidLock, quote};
+use crate::workroot::WorkRoot;
+
+#[derive(Debug, Args)]
+pub struct PurgeArgs {
+    #[clap(long = "path", help = "Specify repository path")]
+    path: Option<PathBuf>,
+    #[clap(long = "all", conflicts_with = "path", help = "Purge all work directories")]
+    all: bool,
+}
+
+pub fn purge(cfg: &Config, args: PurgeArgs) -> Result<(), anyhow::Error> {
+    let work_root = WorkRoot::from_config(cfg);
+    work_root.validate(cfg)?;
+
+    let git = Git {
+        silent: true,
+        ..Default::default()
+    };
+
+    let ids = if args.all {
+        work_root.work_ids().with_context(|| "Error listing work directories")?
+    } else {
+        let repository_root_path = run::resolve_source_repository(&git, args.path)?;
+
+        vec![work_root.source_id(repository_root_path)]
+    };
+
+    for id in ids {
+        let work_path = work_root.work_path(&id);
+
+        match purge_work_dir(&git, &work_root, &id) {
+            Ok(()) => println!("Purged {}", quote::path(&work_path)),
+            Err(err) => eprintln!("Skipping {}: {err:#}", quote::path(&work_path)),
+        }
+    }
+
+    Ok(())
+}
+
+/// Delete a work directory and all its associated state
+pub fn purge_work_dir(git: &Git, work_root: &WorkRoot, id: &str) -> Result<(), anyhow::Error> {
+    let work_path = work_root.work_path(id);
+
+    if !work_path.exists() {
+        return Err(anyhow!("Work directory does not exist"));
+    }
+
+    let _pidlock = PidLock::acquire(work_root.lock_path(id)).with_context(|| "Work directory is in use")?;
+
+    // Background daemons hold handles in the work directory, preventing it from being deleted
+    git.stop_daemons(&work_path);
+
+    util::remove_dir_all(&work_path).with_context(|| "Error deleting work directory")?;
+
+    // The journal is kept, as a record of what happened to the work directory
+    Journal::new(work_root, id).record("purge", format!("deleted {}", quote::path(&work_path)));
+
+    remove_state(work_root, id)
+}
+
+/// Delete the state kept in the work root for a work directory (metadata, history, logs, ...), except the journal
+pub fn remove_state(work_root: &WorkRoot, id: &str) -> Result<(), anyhow::Error> {
+    // Left behind if a run was interrupted
+    let temp_path = work_root.temp_path(id);
+    if temp_path.exists() {
+        util::remove_dir_all(&temp_path).with_context(|| "Error deleting temporary directory")?;
+    }
+
+    let checkouts_path = work_root.checkouts_path(id);
+    if checkouts_path.exists() {
+        util::remove_dir_all(&checkouts_path).with_context(|| "Error deleting additional checkouts")?;
+    }
+
+    let history_path = work_root.history_path(id);
+    if history_path.exists() {
+        util::remove_dir_all(&history_path).with_context(|| "Error deleting run history")?;
+    }
+
+    for path in [
+        work_root.metadata_path(id),
+        work_root.success_path(id),
+        work_root.work_config_path(id),
+        work_root.log_path(id),
+        work_root.lock_info_path(id),
+    ] {
+        if path.exists() {
+            std::fs::remove_file(&path).with_context(|| format!("Error deleting {}", path.display()))?;
+        }
+    }
+
+    // Processes may still be waiting in the lock queue, in which case it is left alone
+    let _ = std::fs::remove_dir(work_root.lock_queue_path(id));
+
+    Ok(())
+}
@@ -0,0 +1,311 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{anyhow, Context};
+use serde_derive::{Deserialize, Serialize};
+use sysinfo::{Pid, PidExt};
+use tracing::{debug, info, warn};
+
+use crate::cancel;
+use crate::metadata::WorkMetadata;
+use crate::util::{
+    self,
+    pid::{self, PidLock},
+    quote,
+};
+use crate::workroot::WorkRoot;
+
+const WAIT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What is being run, used to recognize identical runs
+pub struct RunRequest<'a> {
+    pub rev_name: &'a str,
+    pub commit: &'a str,
+    pub command: &'a [String],
+}
+
+/// Information about the process holding the lock of a work directory, so waiters can tell what they wait for
+#[derive(Debug, Deserialize, Serialize)]
+pub struct LockInfo {
+    pub pid: u32,
+    pub command: Vec<String>,
+    pub acquired_at: u64,
+}
+
+/// A run waiting for the lock of a work directory
+pub struct QueuedRun {
+    pub run_id: String,
+    pub command: String,
+}
+
+/// A place in the queue of processes waiting for a lock, given up when dropped
+struct Ticket {
+    path: PathBuf,
+}
+
+pub enum Acquired {
+    /// The lock was acquired, and the run can proceed
+    Lock(PidLock),
+    /// An identical run was already in progress. Its metadata is returned after it finished.
+    Coalesced(Box<WorkMetadata>),
+}
+
+/// Acquire the lock of a work directory.
+/// Waiters are granted the lock in the order they arrived in.
+/// If waiting, an identical run already in progress is waited for and its result used instead.
+pub fn acquire(
+    work_root: &WorkRoot,
+    source_id: &str,
+    wait: bool,
+    run_id: &str,
+    command: &[String],
+    request: Option<&RunRequest>,
+) -> Result<Acquired, anyhow::Error> {
+    let lock_path = work_root.lock_path(source_id);
+    let queue_path = work_root.lock_queue_path(source_id);
+    let metadata_path = work_root.metadata_path(source_id);
+
+    let mut ticket: Option<Ticket> = None;
+    let mut reported = None;
+
+    loop {
+        cancel::check(work_root, run_id)?;
+
+        // The lock can only be taken when nobody arrived before us
+        let position = match &ticket {
+            Some(ticket) => ticket.position(&queue_path),
+            None => queued(&queue_path).len() + 1,
+        };
+
+        if position == 1 {
+            if let Some(pidlock) = PidLock::acquire(&lock_path) {
+                write_lock_info(work_root, source_id, command);
+
+                return Ok(Acquired::Lock(pidlock));
+            }
+        }
+
+        if !wait {
+            return Err(anyhow!(
+                "Could not acquire PID lock. Another process is already running in this repository."
+            ));
+        }
+
+        if ticket.is_none() {
+            ticket = Some(Ticket::take(&queue_path, run_id, command).with_context(|| "Error joining the lock queue")?);
+            continue;
+        }
+
+        if let Some(request) = request {
+            if let Some(pid) = identical_run(&lock_path, &metadata_path, request) {
+                info!("Identical run in progress (PID {pid}), waiting for its result...");
+
+                while PidLock::holder(&lock_path).is_some() {
+                    cancel::check(work_root, run_id)?;
+                    std::thread::sleep(WAIT_INTERVAL);
+                }
+
+                if let Some(metadata) = WorkMetadata::load(&metadata_path)? {
+                    if metadata.pid == Some(pid) && metadata.finished_at.is_some() {
+                        return Ok(Acquired::Coalesced(Box::new(metadata)));
+                    }
+                }
+
+                // The run was interrupted, so go ahead with our own
+                continue;
+            }
+        }
+
+        let holder = PidLock::holder(&lock_path);
+
+        if reported != Some((position, holder)) {
+            match holder {
+                Some(pid) => info!(
+                    "Waiting for the lock, position {position} in queue. Held by {}",
+                    describe_holder(work_root, source_id, pid)
+                ),
+                None => info!("Waiting for the lock, position {position} in queue..."),
+            }
+
+            reported = Some((position, holder));
+        }
+
+        std::thread::sleep(WAIT_INTERVAL);
+    }
+}
+
+impl Ticket {
+    /// Join the end of a lock queue
+    fn take(queue_path: &Path, run_id: &str, command: &[String]) -> Result<Self, anyhow::Error> {
+        std::fs::create_dir_all(queue_path)?;
+
+        // Tickets are ordered by name, so the arrival time goes first.
+        // Run ids end with the PID, which is used to detect abandoned tickets.
+        let arrived_at = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?.as_nanos();
+        let path = queue_path.join(format!("{arrived_at:024}-{run_id}"));
+
+        std::fs::write(&path, quote::command(command))?;
+
+        Ok(Self { path })
+    }
+
+    /// Get the position in the queue, starting at 1
+    fn position(&self, queue_path: &Path) -> usize {
+        let tickets = queued(queue_path);
+
+        tickets.iter().position(|t| *t == self.path).unwrap_or(tickets.len()) + 1
+    }
+}
+
+impl Drop for Ticket {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Get the tickets in a lock queue, in arrival order.
+/// Tickets left behind by processes that no longer exist are removed.
+fn queued(queue_path: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(queue_path) else {
+        return Vec::new();
+    };
+
+    let mut tickets: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            let pid = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .and_then(|n| n.rsplit_once('-'))
+                .and_then(|(_, pid)| pid.parse::<Pid>().ok());
+
+            if pid.is_some_and(pid::process_exists) {
+                return true;
+            }
+
+            let _ = std::fs::remove_file(path);
+            false
+        })
+        .collect();
+
+    tickets.sort();
+
+    tickets
+}
+
+/// Get the runs waiting in the lock queues of all work directories
+pub fn queued_runs(work_root: &WorkRoot) -> Vec<QueuedRun> {
+    let Ok(entries) = std::fs::read_dir(work_root.locks_dir()) else {
+        return Vec::new();
+    };
+
+    entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|e| e == "queue"))
+        .flat_map(|queue_path| queued(&queue_path))
+        .filter_map(|ticket| {
+            let name = ticket.file_name()?.to_str()?;
+            let (_, run_id) = name.split_once('-')?;
+
+            Some(QueuedRun {
+                run_id: run_id.to_owned(),
+                command: std::fs::read_to_string(&ticket).unwrap_or_default(),
+            })
+        })
+        .collect()
+}
+
+/// Remove lock files, lock information and queue tickets left behind by processes that no longer exist,
+/// returning how many were removed
+pub fn remove_stale(work_root: &WorkRoot) -> usize {
+    let Ok(entries) = std::fs::read_dir(work_root.locks_dir()) else {
+        return 0;
+    };
+
+    let mut removed = 0;
+
+    for path in entries.filter_map(|entry| entry.ok().map(|e| e.path())) {
+        let stale = match path.extension().and_then(|e| e.to_str()) {
+            // Only PIDs that were completely written, as the lock may be in the middle of being acquired
+            Some("pid") => {
+                let stale = std::fs::read_to_string(&path)
+                    .ok()
+                    .and_then(|pid| pid.parse::<Pid>().ok())
+                    .is_some_and(|pid| !pid::process_exists(pid));
+
+                // Taken over and released, rather than removed, so a process that acquired it in the meantime keeps it
+                if stale && pid::PidLock::acquire(&path).is_some() {
+                    debug!("Released stale lock {}", path.display());
+                    removed += 1;
+                }
+
+                false
+            }
+            Some("json") => util::json::read_json_file::<LockInfo>(&path)
+                .ok()
+                .flatten()
+                .is_some_and(|info| !pid::process_exists(Pid::from_u32(info.pid))),
+            Some("queue") => {
+                let before = std::fs::read_dir(&path).map_or(0, |entries| entries.count());
+                removed += before - queued(&path).len().min(before);
+
+                // Only removed if empty, so it is left alone if someone joined the queue in the meantime
+                if std::fs::remove_dir(&path).is_ok() {
+                    debug!("Removed empty lock queue {}", path.display());
+                }
+
+                false
+            }
+            _ => false,
+        };
+
+        if stale && std::fs::remove_file(&path).is_ok() {
+            debug!("Removed stale lock file {}", path.display());
+            removed += 1;
+        }
+    }
+
+    removed
+}
+
+/// Record what the lock was acquired for.
+/// This is only informational, so errors are only logged.
+fn write_lock_info(work_root: &WorkRoot, source_id: &str, command: &[String]) {
+    let info = LockInfo {
+        pid: std::process::id(),
+        command: command.to_vec(),
+        acquired_at: util::time::unix_now(),
+    };
+
+    if let Err(err) = util::json::write_json_file(work_root.lock_info_path(source_id), &info) {
+        warn!("Error writing lock information: {err:#}");
+    }
+}
+
+/// Describe the process holding a lock, including its command if known
+fn describe_holder(work_root: &WorkRoot, source_id: &str, pid: Pid) -> String {
+    let info = util::json::read_json_file::<LockInfo>(work_root.lock_info_path(source_id))
+        .ok()
+        .flatten()
+        .filter(|info| info.pid == pid.as_u32() && !info.command.is_empty());
+
+    match info {
+        Some(info) => format!("PID {pid}: {}", quote::command(&info.command)),
+        None => format!("PID {pid}"),
+    }
+}
+
+/// Get the PID of the process holding the lock, if it is running the same command on the same commit
+fn identical_run(lock_path: &Path, metadata_path: &Path, request: &RunRequest) -> Option<u32> {
+    let pid = PidLock::holder(lock_path)?.as_u32();
+    let metadata = WorkMetadata::load(metadata_path).ok()??;
+
+    // Metadata is only written once the run has started, so make sure it is not from a previous run
+    let identical = metadata.pid == Some(pid)
+        && metadata.finished_at.is_none()
+        && metadata.branch.as_deref() == Some(request.rev_name)
+        && metadata.commit.as_deref() == Some(request.commit)
+        && metadata.command == request.command;
+
+    identical.then_some(pid)
+}
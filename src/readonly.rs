@@ -0,0 +1,94 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::{anyhow, Context};
+
+use crate::git::Git;
+
+/// Maximum number of modified paths listed in the error
+const MAX_LISTED_PATHS: usize = 10;
+
+/// Modification times and sizes of the files in a source repository's git directories,
+/// for verifying that nothing was written to it
+pub struct SourceFingerprint {
+    entries: BTreeMap<PathBuf, (Option<SystemTime>, u64)>,
+}
+
+impl SourceFingerprint {
+    pub fn capture(git: &Git, source_path: &Path) -> Result<Self, anyhow::Error> {
+        let mut entries = BTreeMap::new();
+
+        // Files created at the top of the working tree show up in the mtime of the directory itself
+        add_entry(&mut entries, source_path)?;
+
+        let git_dirs = git
+            .git_dirs(source_path)
+            .with_context(|| "Error getting git directories of source repository")?;
+
+        for git_dir in git_dirs {
+            add_dir(&mut entries, &git_dir, true).with_context(|| format!("Error reading {}", git_dir.display()))?;
+        }
+
+        Ok(Self { entries })
+    }
+
+    /// Fail if anything changed in the source repository since the fingerprint was captured
+    pub fn verify(&self, git: &Git, source_path: &Path) -> Result<(), anyhow::Error> {
+        let current = Self::capture(git, source_path)?;
+
+        let modified: BTreeSet<&PathBuf> = self
+            .entries
+            .keys()
+            .chain(current.entries.keys())
+            .filter(|path| self.entries.get(*path) != current.entries.get(*path))
+            .collect();
+
+        if modified.is_empty() {
+            return Ok(());
+        }
+
+        let mut paths: Vec<String> = modified
+            .iter()
+            .take(MAX_LISTED_PATHS)
+            .map(|p| format!("  {}", p.display()))
+            .collect();
+
+        if modified.len() > MAX_LISTED_PATHS {
+            paths.push(format!("  ... and {} more", modified.len() - MAX_LISTED_PATHS));
+        }
+
+        Err(anyhow!("The source repository was modified:\n{}", paths.join("\n")))
+    }
+}
+
+fn add_entry(entries: &mut BTreeMap<PathBuf, (Option<SystemTime>, u64)>, path: &Path) -> std::io::Result<()> {
+    let metadata = std::fs::symlink_metadata(path)?;
+    entries.insert(path.to_path_buf(), (metadata.modified().ok(), metadata.len()));
+
+    Ok(())
+}
+
+/// Add a directory and everything in it.
+/// Git writes files by renaming lock files into place, so new and rewritten files show up in the mtime of
+/// their directory. Object files are never changed once written, so only their directories are included.
+fn add_dir(
+    entries: &mut BTreeMap<PathBuf, (Option<SystemTime>, u64)>,
+    path: &Path,
+    include_files: bool,
+) -> std::io::Result<()> {
+    add_entry(entries, path)?;
+
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        let path = entry.path();
+
+        if entry.file_type()?.is_dir() {
+            add_dir(entries, &path, include_files && entry.file_name() != "objects")?;
+        } else if include_files {
+            add_entry(entries, &path)?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,168 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context};
+use clap::Args;
+
+use crate::config::{edit, Config};
+use crate::git::Git;
+use crate::journal::Journal;
+use crate::metadata::WorkMetadata;
+use crate::util::{self, pid::PidLock, quote};
+use crate::workroot::WorkRoot;
+
+/// Marker written to the new work root while copying, so an interrupted move can be resumed
+const MOVE_MARKER: &str = ".fersk-move-from";
+
+#[derive(Debug, Args)]
+pub struct MoveWorkRootArgs {
+    #[clap(help = "New work root path")]
+    path: PathBuf,
+    #[clap(long = "no-update-config", help = "Don't update work-path in the config file")]
+    no_update_config: bool,
+}
+
+/// Move the work root, with all work directories and their state, to a new location.
+/// The directory is renamed if possible, and copied otherwise.
+pub fn move_work_root(cfg: &Config, args: MoveWorkRootArgs) -> Result<(), anyhow::Error> {
+    if cfg.shared_work_root {
+        return Err(anyhow!(
+            "Moving a shared work root is not supported, as it contains the work directories of other users."
+        ));
+    }
+
+    let old_root = WorkRoot::from_config(cfg);
+    let old_path = util::normalize_path(old_root.path());
+
+    let new_path = if args.path.is_absolute() {
+        args.path
+    } else {
+        std::env::current_dir()?.join(args.path)
+    };
+    let new_path = util::normalize_path(new_path);
+
+    if new_path.starts_with(&old_path) || old_path.starts_with(&new_path) {
+        return Err(anyhow!("The new work root can't overlap the current one."));
+    }
+
+    let resuming = new_path.join(MOVE_MARKER).exists();
+
+    if old_path.exists() {
+        if new_path.exists() && !resuming && new_path.read_dir()?.next().is_some() {
+            return Err(anyhow!("Destination is not empty: {}", new_path.display()));
+        }
+
+        let ids = old_root.work_ids().with_context(|| "Error listing work directories")?;
+
+        // Work directories can't be moved out from under running commands,
+        // and no runs can start in them until they have been moved.
+        // This includes ones at custom paths (--into), whose locks are in the work root.
+        let state_ids = old_root.state_ids().with_context(|| "Error listing work directories")?;
+        let mut locks = Vec::new();
+
+        for id in &state_ids {
+            let lock_path = old_root.lock_path(id);
+            util::create_parent_dir(&lock_path).with_context(|| "Cannot create PID lock directory.")?;
+
+            let Some(lock) = PidLock::acquire(&lock_path) else {
+                let holder = PidLock::holder(&lock_path)
+                    .map_or_else(|| "another process".to_owned(), |pid| format!("process {pid}"));
+
+                let work_path = WorkMetadata::load(old_root.metadata_path(id))
+                    .ok()
+                    .flatten()
+                    .map_or_else(|| old_root.work_path(id), |metadata| metadata.working_repository_path);
+
+                return Err(anyhow!("Work directory is in use by {holder}: {}", work_path.display()));
+            };
+
+            locks.push((id, lock));
+        }
+
+        let schedule_lock_path = old_root.schedule_lock_path();
+        util::create_parent_dir(&schedule_lock_path).with_context(|| "Cannot create PID lock directory.")?;
+
+        let Some(mut schedule_lock) = PidLock::acquire(schedule_lock_path) else {
+            return Err(anyhow!("Scheduled runs are being executed."));
+        };
+
+        // Background daemons hold handles in the work directories
+        let git = Git {
+            silent: true,
+            ..Default::default()
+        };
+
+        for id in &ids {
+            git.stop_daemons(old_root.work_path(id));
+        }
+
+        move_dir(&old_path, &new_path, resuming)?;
+
+        // The locks were moved along with the rest of the work root, and are released there
+        let new_root = WorkRoot::new(&new_path);
+        for (id, lock) in &mut locks {
+            lock.moved_to(new_root.lock_path(id));
+        }
+        schedule_lock.moved_to(new_root.schedule_lock_path());
+
+        // Metadata refers to work directories by their full path
+        for id in &ids {
+            let metadata_path = new_root.metadata_path(id);
+
+            if let Some(mut metadata) = WorkMetadata::load(&metadata_path)? {
+                metadata.working_repository_path = new_root.work_path(id);
+                metadata.save(&metadata_path)?;
+            }
+
+            Journal::new(&new_root, id).record(
+                "relocate",
+                format!(
+                    "moved from {} to {}",
+                    quote::path(&old_root.work_path(id)),
+                    quote::path(&new_root.work_path(id))
+                ),
+            );
+        }
+
+        println!("Moved {} to {}", quote::path(&old_path), quote::path(&new_path));
+    }
+
+    if !args.no_update_config {
+        let new_path_str = new_path
+            .to_str()
+            .ok_or_else(|| anyhow!("Path is not valid UTF-8: {}", new_path.display()))?;
+
+        edit::set_string("work-path", new_path_str)?;
+        println!("Updated work-path in config.");
+    }
+
+    Ok(())
+}
+
+/// Move a directory, by renaming it if possible or copying it if it's on a different volume
+fn move_dir(from: &Path, to: &Path, resuming: bool) -> Result<(), anyhow::Error> {
+    if !resuming {
+        util::create_parent_dir(to).with_context(|| format!("Error creating directory: {}", to.display()))?;
+
+        if std::fs::rename(from, to).is_ok() {
+            return Ok(());
+        }
+
+        std::fs::create_dir_all(to).with_context(|| format!("Error creating directory: {}", to.display()))?;
+        std::fs::write(to.join(MOVE_MARKER), from.to_string_lossy().as_bytes())?;
+    } else {
+        println!("Resuming interrupted move.");
+    }
+
+    util::copy_dir_resumable(from, to).with_context(|| {
+        format!(
+            "Error copying work root. Run the command again to resume copying to {}",
+            to.display()
+        )
+    })?;
+
+    // The copy is complete once the marker is gone, so it must be removed before the original
+    std::fs::remove_file(to.join(MOVE_MARKER))?;
+    util::remove_dir_all(from).with_context(|| format!("Error removing old work root: {}", from.display()))?;
+
+    Ok(())
+}
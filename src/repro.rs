@@ -0,0 +1,49 @@
+use std::collections::hash_map::RandomState;
+use std::collections::BTreeMap;
+use std::hash::{BuildHasher, Hasher};
+use std::path::Path;
+
+use anyhow::Context;
+
+use crate::git::Git;
+
+/// Environment variable the seed of a run is passed to the command in
+pub const SEED_VAR: &str = "FERSK_SEED";
+
+/// Locale used for reproducible runs. Only Linux has a UTF-8 variant of the C locale.
+const REPRODUCIBLE_LOCALE: &str = if cfg!(target_os = "linux") { "C.UTF-8" } else { "C" };
+
+/// Generate a seed for a run that didn't specify one.
+/// It fits in 32 bits, as some random number generators don't accept larger seeds.
+pub fn random_seed() -> u64 {
+    // The standard library seeds hashers randomly for each process
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos(),
+    );
+
+    hasher.finish() & u64::from(u32::MAX)
+}
+
+/// Get environment variables pinning down sources of nondeterminism in builds and tests:
+/// timestamps (to the commit time, see https://reproducible-builds.org/specs/source-date-epoch/),
+/// time zone and locale
+pub fn reproducible_env(
+    git: &Git,
+    work_path: &Path,
+    commit: &str,
+) -> Result<BTreeMap<&'static str, String>, anyhow::Error> {
+    let commit_time = git
+        .commit_time(work_path, commit)
+        .with_context(|| "Error getting commit time")?;
+
+    Ok(BTreeMap::from([
+        ("SOURCE_DATE_EPOCH", commit_time.to_string()),
+        ("TZ", "UTC".to_owned()),
+        ("LC_ALL", REPRODUCIBLE_LOCALE.to_owned()),
+        ("LANG", REPRODUCIBLE_LOCALE.to_owned()),
+    ]))
+}
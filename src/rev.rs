@@ -0,0 +1,242 @@
+use std::fmt::Display;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::anyhow;
+
+use crate::git::{Git, GitError};
+
+/// Maximum number of suggestions given for a revision that doesn't exist
+const MAX_SUGGESTIONS: usize = 5;
+
+/// A revision to check out
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GitRev {
+    /// Local branch in the source repository
+    Branch(String),
+    /// Branch as it is on a remote of the source repository
+    RemoteBranch {
+        remote: String,
+        branch: String,
+    },
+    Tag(String),
+    Commit(String),
+    /// Any other fully qualified ref (ex. refs/pull/1/head)
+    Ref(String),
+    /// Fully qualified ref as it is on a remote of the source repository (ex. a pull request on origin)
+    RemoteRef {
+        remote: String,
+        name: String,
+    },
+}
+
+impl GitRev {
+    /// Get the remote of the source repository the revision is on, if it is not local
+    pub fn remote(&self) -> Option<&str> {
+        match self {
+            Self::RemoteBranch { remote, .. } | Self::RemoteRef { remote, .. } => Some(remote),
+            _ => None,
+        }
+    }
+
+    /// Resolve the revision to a commit hash in the source repository
+    pub fn resolve(&self, git: &Git, source_path: &Path) -> Result<String, GitError> {
+        git.rev_parse(source_path, &self.source_ref())
+    }
+
+    /// Get the revision as it is named in the source repository
+    pub fn source_ref(&self) -> String {
+        match self {
+            Self::Branch(branch) => format!("refs/heads/{branch}"),
+            Self::RemoteBranch { remote, branch } => format!("refs/remotes/{remote}/{branch}"),
+            Self::Tag(tag) => format!("refs/tags/{tag}"),
+            Self::Commit(commit) => commit.clone(),
+            Self::Ref(r) | Self::RemoteRef { name: r, .. } => r.clone(),
+        }
+    }
+
+    /// Get the revision as it is named in a work directory, where `origin` is the remote
+    /// pointing at the source repository. Remote branches are named after the remote
+    /// they were copied from.
+    pub fn work_ref(&self, origin: &str) -> String {
+        match self {
+            Self::Branch(branch) => format!("{origin}/{branch}"),
+            Self::RemoteBranch { remote, branch } => format!("{remote}/{branch}"),
+            Self::Tag(tag) => format!("refs/tags/{tag}"),
+            Self::Commit(commit) => commit.clone(),
+            Self::Ref(r) => format!("refs/{origin}/{}", r.strip_prefix("refs/").unwrap_or(r)),
+            Self::RemoteRef { remote, name } => format!("refs/{remote}/{}", name.strip_prefix("refs/").unwrap_or(name)),
+        }
+    }
+
+    /// Fetch refs that are not included in a regular fetch from `origin` into a work directory
+    pub fn fetch(&self, git: &Git, work_path: &Path, origin: &str) -> Result<(), GitError> {
+        match self {
+            Self::Tag(_) | Self::Ref(_) => git.fetch_ref(work_path, origin, &self.source_ref(), &self.work_ref(origin)),
+            Self::RemoteBranch { remote, branch } => git.fetch_branch(work_path, remote, branch),
+            Self::RemoteRef { remote, .. } => {
+                git.fetch_ref(work_path, remote, &self.source_ref(), &self.work_ref(origin))
+            }
+            Self::Branch(_) | Self::Commit(_) => Ok(()),
+        }
+    }
+
+    /// Create an error for a revision that doesn't exist,
+    /// suggesting similarly named revisions of the same kind in the source repository
+    pub fn not_found_error(&self, git: &Git, source_path: &Path) -> anyhow::Error {
+        let prefix = match self {
+            Self::Branch(_) => "refs/heads/".to_owned(),
+            Self::RemoteBranch { remote, .. } => format!("refs/remotes/{remote}/"),
+            Self::Tag(_) => "refs/tags/".to_owned(),
+            Self::RemoteRef { remote, name } => return anyhow!("{name} does not exist on {remote}."),
+            _ => return anyhow!("{self} does not exist in the source repository."),
+        };
+
+        let name = self.to_string();
+
+        let mut candidates: Vec<(usize, String)> = git
+            .list_refs(source_path, &prefix)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|r| r.parse::<GitRev>().ok())
+            .map(|r| r.to_string())
+            .filter(|candidate| candidate != &name && !candidate.ends_with("/HEAD"))
+            .filter_map(|candidate| similarity(&name, &candidate).map(|distance| (distance, candidate)))
+            .collect();
+
+        candidates.sort();
+
+        if candidates.is_empty() {
+            return anyhow!("{self} does not exist in the source repository.");
+        }
+
+        let suggestions = candidates
+            .into_iter()
+            .take(MAX_SUGGESTIONS)
+            .map(|(_, candidate)| format!("\n    {candidate}"))
+            .collect::<String>();
+
+        anyhow!("{self} does not exist in the source repository. Did you mean:{suggestions}")
+    }
+}
+
+/// Get how far apart two names are, if they are similar enough to suggest one for the other
+fn similarity(name: &str, candidate: &str) -> Option<usize> {
+    let name = name.to_lowercase();
+    let candidate = candidate.to_lowercase();
+
+    let distance = edit_distance(&name, &candidate);
+
+    // Allow roughly one typo per three characters, or a name that is part of the other
+    if distance <= (name.chars().count() / 3).max(2) || candidate.contains(&name) || name.contains(&candidate) {
+        Some(distance)
+    } else {
+        None
+    }
+}
+
+/// Levenshtein distance between two strings
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous + usize::from(ca != *cb);
+            previous = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(previous + 1);
+        }
+    }
+
+    row[b.len()]
+}
+
+/// Kind of review request (pull request or merge request) to check out
+#[derive(Clone, Copy, Debug)]
+pub enum ReviewRequest {
+    /// GitHub style pull request (also used by Gitea, Forgejo and others)
+    Pull(u64),
+    /// GitLab merge request
+    Merge(u64),
+}
+
+impl ReviewRequest {
+    /// Get the ref of a review request on a remote.
+    /// Pull requests on GitLab remotes are taken to mean merge requests.
+    pub fn rev(self, remote: &str, remote_url: &str) -> GitRev {
+        let name = match self {
+            Self::Pull(number) if !remote_url.contains("gitlab") => format!("refs/pull/{number}/head"),
+            Self::Pull(number) | Self::Merge(number) => format!("refs/merge-requests/{number}/head"),
+        };
+
+        GitRev::RemoteRef {
+            remote: remote.to_owned(),
+            name,
+        }
+    }
+}
+
+impl FromStr for GitRev {
+    type Err = String;
+
+    /// Parse a revision. Fully qualified refs and full commit hashes are recognized.
+    /// Anything else is taken to be a branch.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err("Empty revision".to_owned());
+        }
+
+        // Ranges can't be checked out, and ".." is not allowed in ref names
+        if s.contains("..") {
+            return Err(format!(
+                "Revision ranges are not supported: {s}. Specify a single revision."
+            ));
+        }
+
+        if let Some(branch) = s.strip_prefix("refs/heads/") {
+            return Ok(Self::Branch(branch.to_owned()));
+        }
+
+        if let Some(tag) = s.strip_prefix("refs/tags/") {
+            return Ok(Self::Tag(tag.to_owned()));
+        }
+
+        if let Some(remote_branch) = s.strip_prefix("refs/remotes/") {
+            let (remote, branch) = remote_branch
+                .split_once('/')
+                .ok_or_else(|| format!("Invalid remote branch ref: {s}"))?;
+
+            return Ok(Self::RemoteBranch {
+                remote: remote.to_owned(),
+                branch: branch.to_owned(),
+            });
+        }
+
+        if s.starts_with("refs/") {
+            return Ok(Self::Ref(s.to_owned()));
+        }
+
+        // Only full hashes, as abbreviated ones can't be told apart from branch names
+        if matches!(s.len(), 40 | 64) && s.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Ok(Self::Commit(s.to_owned()));
+        }
+
+        Ok(Self::Branch(s.to_owned()))
+    }
+}
+
+impl Display for GitRev {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Branch(branch) => f.write_str(branch),
+            Self::RemoteBranch { remote, branch } => write!(f, "{remote}/{branch}"),
+            Self::Tag(tag) => write!(f, "tags/{tag}"),
+            Self::Commit(commit) => f.write_str(commit),
+            Self::Ref(r) => f.write_str(r),
+            Self::RemoteRef { remote, name } => write!(f, "{name} ({remote})"),
+        }
+    }
+}
@@ -0,0 +1,166 @@
+use std::process::Command;
+
+use anyhow::{anyhow, Context};
+use clap::Args;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::config::project::ProjectConfig;
+use crate::config::Config;
+use crate::run::{self, RunArgs};
+use crate::util::quote;
+
+/// Name of the hidden subcommand executing a script inside the work directory
+pub const EXEC_SCRIPT_COMMAND: &str = "exec-script";
+
+/// Command of a script defined in .fersk.toml
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum ScriptCommand {
+    /// Command line run by the shell (sh, or PowerShell on Windows)
+    Shell(String),
+    /// Program and arguments, run directly
+    Args(Vec<String>),
+}
+
+/// Script defined in .fersk.toml, either only its command, or a table with its command and settings
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum Script {
+    Command(ScriptCommand),
+    Table {
+        command: ScriptCommand,
+        /// Performs destructive operations (ex. pushing or deploying), so it is refused on protected branches
+        #[serde(default)]
+        destructive: bool,
+    },
+}
+
+impl Script {
+    pub fn command(&self) -> &ScriptCommand {
+        match self {
+            Self::Command(command) | Self::Table { command, .. } => command,
+        }
+    }
+
+    pub fn destructive(&self) -> bool {
+        matches!(self, Self::Table { destructive: true, .. })
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct RunScriptArgs {
+    #[clap(help = "Name of the script in the repository's .fersk.toml")]
+    name: String,
+    #[clap(flatten)]
+    run: RunArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct ExecScriptArgs {
+    name: String,
+    #[clap(last = true)]
+    args: Vec<String>,
+}
+
+/// Run a script defined by the repository in a fresh checkout.
+/// The script is looked up in the checked out .fersk.toml, so it always matches the revision being run.
+pub fn run_script(cfg: &Config, args: RunScriptArgs) -> Result<(), anyhow::Error> {
+    let RunScriptArgs { name, mut run } = args;
+
+    let exe = std::env::current_exe().with_context(|| "Error getting path of fersk executable")?;
+
+    let mut command = vec![
+        exe.to_string_lossy().to_string(),
+        EXEC_SCRIPT_COMMAND.to_owned(),
+        name.clone(),
+        "--".to_owned(),
+    ];
+    command.extend(run.take_command());
+
+    run.set_command(command);
+    run.set_script(&name);
+
+    run::run(cfg, run)
+}
+
+/// Execute a script with its pre- and post-scripts in the current directory, stopping at the first one that fails.
+/// Extra arguments are only passed to the script itself.
+pub fn exec_script(args: ExecScriptArgs) -> Result<(), anyhow::Error> {
+    let cwd = std::env::current_dir()?;
+    let project_cfg = ProjectConfig::from_work_path(&cwd)?;
+
+    let Some(script) = project_cfg.scripts.get(&args.name) else {
+        if project_cfg.scripts.is_empty() {
+            return Err(anyhow!(
+                "Script not found: {}. No scripts are defined in .fersk.toml.",
+                args.name
+            ));
+        }
+
+        let available: Vec<&str> = project_cfg.scripts.keys().map(|s| s.as_str()).collect();
+
+        return Err(anyhow!(
+            "Script not found: {}. Available scripts: {}",
+            args.name,
+            available.join(", ")
+        ));
+    };
+
+    let pre = project_cfg.scripts.get(&format!("pre{}", args.name));
+    let post = project_cfg.scripts.get(&format!("post{}", args.name));
+
+    for (script, extra_args) in [(pre, &[][..]), (Some(script), &args.args[..]), (post, &[][..])] {
+        let Some(script) = script else {
+            continue;
+        };
+
+        let status = script_command(script.command(), extra_args)?
+            .status()
+            .with_context(|| format!("Error executing script: {}", args.name))?;
+
+        if !status.success() {
+            std::process::exit(status.code().unwrap_or(1));
+        }
+    }
+
+    Ok(())
+}
+
+/// Build the command running a script, with extra arguments appended
+pub fn script_command(script: &ScriptCommand, extra_args: &[String]) -> Result<Command, anyhow::Error> {
+    Ok(match script {
+        ScriptCommand::Shell(line) => {
+            if cfg!(windows) {
+                let mut command = Command::new("powershell");
+                command.args(["-NoProfile", "-Command"]);
+
+                if extra_args.is_empty() {
+                    command.arg(line);
+                } else {
+                    command.arg(format!("{line} {}", quote::command(extra_args)));
+                }
+
+                command
+            } else {
+                let mut command = Command::new("sh");
+                command.arg("-c");
+
+                // Extra arguments are passed as positional parameters, so the shell doesn't need to parse them
+                if extra_args.is_empty() {
+                    command.arg(line);
+                } else {
+                    command.arg(format!("{line} \"$@\"")).arg("sh").args(extra_args);
+                }
+
+                command
+            }
+        }
+        ScriptCommand::Args(args) => {
+            let (program, args) = args.split_first().ok_or_else(|| anyhow!("Script command is empty."))?;
+
+            let mut command = Command::new(program);
+            command.args(args).args(extra_args);
+            command
+        }
+    })
+}
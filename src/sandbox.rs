@@ -0,0 +1,97 @@
+use std::path::Path;
+
+use anyhow::anyhow;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::mount::Mount;
+use crate::util;
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SandboxBackend {
+    Bubblewrap,
+    Firejail,
+}
+
+impl SandboxBackend {
+    fn executable(&self) -> &'static str {
+        match self {
+            Self::Bubblewrap => "bwrap",
+            Self::Firejail => "firejail",
+        }
+    }
+
+    /// Find the first available sandbox backend
+    pub fn detect() -> Option<Self> {
+        [Self::Bubblewrap, Self::Firejail]
+            .into_iter()
+            .find(|b| util::find_in_path(b.executable()).is_some())
+    }
+}
+
+/// Wrap a command so that it runs in a sandbox.
+/// The system is mounted read-only, with only the work directory and a private
+/// scratch /tmp writable. Additional mounts are always read-only.
+/// Network access is removed unless explicitly allowed.
+pub fn wrap_command(
+    backend: Option<SandboxBackend>,
+    work_path: &Path,
+    allow_network: bool,
+    mounts: &[Mount],
+    args: &[String],
+) -> Result<Vec<String>, anyhow::Error> {
+    if !cfg!(target_os = "linux") {
+        return Err(anyhow!("Sandboxing is not supported on this platform."));
+    }
+
+    let backend = backend
+        .or_else(SandboxBackend::detect)
+        .ok_or_else(|| anyhow!("No sandbox backend found. Install bubblewrap or firejail."))?;
+
+    let work_path = work_path.to_string_lossy().to_string();
+
+    let mut wrapped: Vec<String> = vec![backend.executable().to_owned()];
+
+    match backend {
+        SandboxBackend::Bubblewrap => {
+            wrapped.extend(strings(&["--ro-bind", "/", "/"]));
+            wrapped.extend(strings(&["--dev", "/dev"]));
+            wrapped.extend(strings(&["--proc", "/proc"]));
+            wrapped.extend(strings(&["--tmpfs", "/tmp"]));
+            wrapped.extend(strings(&["--bind", &work_path, &work_path]));
+
+            for mount in mounts {
+                let host_path = mount.host_path.to_string_lossy();
+                wrapped.extend(strings(&["--ro-bind", &host_path, &host_path]));
+            }
+
+            wrapped.extend(strings(&["--chdir", &work_path]));
+            wrapped.push("--die-with-parent".to_owned());
+
+            if !allow_network {
+                wrapped.push("--unshare-net".to_owned());
+            }
+        }
+        SandboxBackend::Firejail => {
+            wrapped.extend(strings(&["--quiet", "--read-only=/", "--private-tmp"]));
+            wrapped.push(format!("--read-write={work_path}"));
+
+            for mount in mounts {
+                wrapped.push(format!("--read-only={}", mount.host_path.display()));
+            }
+
+            if !allow_network {
+                wrapped.push("--net=none".to_owned());
+            }
+        }
+    }
+
+    wrapped.push("--".to_owned());
+    wrapped.extend(args.iter().cloned());
+
+    Ok(wrapped)
+}
+
+fn strings(args: &[&str]) -> Vec<String> {
+    args.iter().map(|a| a.to_string()).collect()
+}
@@ -0,0 +1,365 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context};
+use chrono::{DateTime, Local, TimeZone, Utc};
+use chrono_tz::Tz;
+use clap::{Args, Parser};
+use cron::Schedule;
+use serde_derive::{Deserialize, Serialize};
+use toml_edit::Item;
+
+use crate::command;
+use crate::config::{edit, expand, Config};
+use crate::git::Git;
+use crate::policy::FailurePolicyArgs;
+use crate::run;
+use crate::util::record::{self, Record};
+use crate::util::{self, pid::PidLock, quote};
+use crate::workroot::WorkRoot;
+
+/// A recurring run
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ScheduledJob {
+    pub name: String,
+    /// Cron expression, with or without a seconds field
+    pub cron: String,
+    /// IANA time zone the cron expression is evaluated in. Local time if not specified.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+    #[serde(deserialize_with = "expand::deserialize")]
+    pub path: PathBuf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+    pub command: Vec<String>,
+}
+
+/// Last time each job was run, keyed by job name
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct ScheduleState {
+    last_run: BTreeMap<String, i64>,
+}
+
+#[derive(Debug, Args)]
+pub struct ScheduleArgs {
+    #[clap(subcommand)]
+    command: ScheduleCommand,
+}
+
+#[derive(Debug, Parser)]
+enum ScheduleCommand {
+    #[clap(name = "add", about = "Add a scheduled run")]
+    Add(AddArgs),
+
+    #[clap(name = "list", about = "List scheduled runs")]
+    List,
+
+    #[clap(name = "remove", about = "Remove a scheduled run")]
+    Remove { name: String },
+
+    #[clap(name = "run", about = "Execute scheduled runs that are due")]
+    Run(FailurePolicyArgs),
+}
+
+#[derive(Debug, Args)]
+struct AddArgs {
+    #[clap(help = "Cron expression (ex. \"0 2 * * *\")")]
+    cron: String,
+    #[clap(long = "name", help = "Name of the scheduled run")]
+    name: Option<String>,
+    #[clap(long = "timezone", help = "Time zone to evaluate the cron expression in")]
+    timezone: Option<String>,
+    #[clap(long = "path", help = "Specify repository path")]
+    path: Option<PathBuf>,
+    #[clap(long = "branch", help = "Specify branch to check out")]
+    branch: Option<String>,
+    #[clap(last = true, required = true)]
+    args: Vec<String>,
+}
+
+pub fn schedule(cfg: &Config, args: ScheduleArgs) -> Result<(), anyhow::Error> {
+    match args.command {
+        ScheduleCommand::Add(args) => add(cfg, args),
+        ScheduleCommand::List => list(cfg),
+        ScheduleCommand::Remove { name } => remove(cfg, &name),
+        ScheduleCommand::Run(policy) => run_due(cfg, &policy),
+    }
+}
+
+fn add(cfg: &Config, args: AddArgs) -> Result<(), anyhow::Error> {
+    let git = Git {
+        silent: true,
+        ..Default::default()
+    };
+
+    let path = run::resolve_source_repository(&git, args.path)?;
+
+    let name = args.name.unwrap_or_else(|| {
+        (1..)
+            .map(|i| format!("job{i}"))
+            .find(|name| !cfg.schedule.iter().any(|j| j.name == *name))
+            .unwrap()
+    });
+
+    if cfg.schedule.iter().any(|j| j.name == name) {
+        return Err(anyhow!("A scheduled run named {name} already exists."));
+    }
+
+    let job = ScheduledJob {
+        name,
+        cron: args.cron,
+        timezone: args.timezone,
+        path,
+        branch: args.branch,
+        command: args.args,
+    };
+
+    // Validate before saving
+    let now = Utc::now();
+    let next = next_occurrence(&job, now)?;
+
+    let table = edit::to_table(&job)?;
+    edit::edit_array_of_tables("schedule", |array| {
+        array.push(table);
+        Ok(())
+    })?;
+
+    // Only occurrences after this point are due
+    let work_root = WorkRoot::from_config(cfg);
+    let mut state = ScheduleState::load(&work_root)?;
+    state.last_run.insert(job.name.clone(), now.timestamp());
+    state.save(&work_root)?;
+
+    println!("Added {}. Next run: {}", job.name, format_time(next));
+
+    Ok(())
+}
+
+fn remove(cfg: &Config, name: &str) -> Result<(), anyhow::Error> {
+    if !cfg.schedule.iter().any(|j| j.name == name) {
+        return Err(anyhow!("No scheduled run named {name}."));
+    }
+
+    edit::edit_array_of_tables("schedule", |array| {
+        array.retain(|table| table.get("name").and_then(Item::as_str) != Some(name));
+        Ok(())
+    })?;
+
+    let work_root = WorkRoot::from_config(cfg);
+    let mut state = ScheduleState::load(&work_root)?;
+    if state.last_run.remove(name).is_some() {
+        state.save(&work_root)?;
+    }
+
+    Ok(())
+}
+
+fn list(cfg: &Config) -> Result<(), anyhow::Error> {
+    let now = Utc::now();
+
+    for job in &cfg.schedule {
+        let next = match next_occurrence(job, now) {
+            Ok(next) => format_time(next),
+            Err(err) => format!("invalid ({err:#})"),
+        };
+
+        println!(
+            "{}: {} ({})",
+            job.name,
+            job.cron,
+            job.timezone.as_deref().unwrap_or("local")
+        );
+        println!("  Repository: {}", quote::path(&job.path));
+        if let Some(branch) = &job.branch {
+            println!("  Branch: {branch}");
+        }
+        println!("  Command: {}", quote::command(&job.command));
+        println!("  Next run: {next}");
+    }
+
+    Ok(())
+}
+
+/// Run all jobs that have become due since they were last run.
+/// Missed occurrences are caught up with a single run.
+/// Jobs cancelled by the failure policy are left due, and will run next time.
+fn run_due(cfg: &Config, policy: &FailurePolicyArgs) -> Result<(), anyhow::Error> {
+    let work_root = WorkRoot::from_config(cfg);
+    work_root
+        .create(cfg)
+        .with_context(|| format!("Error creating work root: {}", work_root.path().display()))?;
+
+    let lock_path = work_root.schedule_lock_path();
+    util::create_parent_dir(&lock_path).with_context(|| "Cannot create PID lock directory.")?;
+    let Some(_pidlock) = PidLock::acquire(lock_path) else {
+        println!("Scheduled runs are already being executed.");
+        return Ok(());
+    };
+
+    let git = Git {
+        silent: true,
+        ..Default::default()
+    };
+    let exe = std::env::current_exe().with_context(|| "Error getting fersk executable path")?;
+
+    let mut state = ScheduleState::load(&work_root)?;
+    let mut outcomes = policy.outcomes();
+
+    for job in &cfg.schedule {
+        let now = Utc::now();
+
+        let Some(last_run) = state
+            .last_run
+            .get(&job.name)
+            .and_then(|t| Utc.timestamp_opt(*t, 0).single())
+        else {
+            // Jobs added by editing the config start counting from the first time they are seen
+            state.last_run.insert(job.name.clone(), now.timestamp());
+            continue;
+        };
+
+        let due = match last_due(job, last_run, now) {
+            Ok(Some(due)) => due,
+            Ok(None) => continue,
+            Err(err) => {
+                eprintln!("Skipping {}: {err:#}", job.name);
+                continue;
+            }
+        };
+
+        // Don't overlap with a run that is still in progress. It will be caught up next time.
+        if let Ok(source_path) = run::resolve_source_repository(&git, Some(job.path.clone())) {
+            if PidLock::holder(work_root.lock_path(&work_root.source_id(source_path))).is_some() {
+                println!("Skipping {}: repository is busy.", job.name);
+                continue;
+            }
+        }
+
+        if outcomes.should_stop() {
+            println!("Cancelled {}.", job.name);
+            outcomes.cancelled += 1;
+            continue;
+        }
+
+        println!("Running {} (due {})", job.name, format_time(due));
+
+        let result = command::exec_command_timeout(&exe.to_string_lossy(), None, false, |c| {
+            c.arg("run");
+            c.arg("--path").arg(&job.path);
+
+            if let Some(branch) = &job.branch {
+                c.arg("--branch").arg(branch);
+            }
+
+            c.arg("--");
+            c.args(&job.command);
+        });
+
+        let success = match result {
+            Ok(status) if status.success() => true,
+            Ok(status) => {
+                eprintln!("{} failed with exit code {:?}", job.name, status.code());
+                false
+            }
+            Err(err) => {
+                eprintln!("{} failed: {err:#}", job.name);
+                false
+            }
+        };
+
+        outcomes.record(success);
+
+        // Failed runs are not retried until the next occurrence
+        state.last_run.insert(job.name.clone(), now.timestamp());
+        state.save(&work_root)?;
+    }
+
+    // Forget jobs that have been removed from the config
+    state
+        .last_run
+        .retain(|name, _| cfg.schedule.iter().any(|j| j.name == *name));
+    state.save(&work_root)?;
+
+    outcomes.finish("scheduled run(s)")
+}
+
+/// Parse a cron expression. Standard 5-field expressions are accepted, as well as ones with seconds.
+fn parse_cron(expr: &str) -> Result<Schedule, anyhow::Error> {
+    let expr = if expr.split_whitespace().count() == 5 {
+        format!("0 {expr}")
+    } else {
+        expr.to_owned()
+    };
+
+    Schedule::from_str(&expr).map_err(|err| anyhow!("Invalid cron expression: {expr}: {err}"))
+}
+
+fn parse_timezone(job: &ScheduledJob) -> Result<Option<Tz>, anyhow::Error> {
+    job.timezone
+        .as_deref()
+        .map(|tz| {
+            tz.parse::<Tz>()
+                .map_err(|err| anyhow!("Invalid time zone: {tz}: {err}"))
+        })
+        .transpose()
+}
+
+/// Get the next time a job will run
+fn next_occurrence(job: &ScheduledJob, after: DateTime<Utc>) -> Result<DateTime<Utc>, anyhow::Error> {
+    let schedule = parse_cron(&job.cron)?;
+
+    let next = match parse_timezone(job)? {
+        Some(tz) => schedule
+            .after(&after.with_timezone(&tz))
+            .next()
+            .map(|t| t.with_timezone(&Utc)),
+        None => schedule
+            .after(&after.with_timezone(&Local))
+            .next()
+            .map(|t| t.with_timezone(&Utc)),
+    };
+
+    next.ok_or_else(|| anyhow!("Cron expression never matches: {}", job.cron))
+}
+
+/// Get the latest occurrence of a job after `since`, up to and including `now`
+fn last_due(
+    job: &ScheduledJob,
+    since: DateTime<Utc>,
+    now: DateTime<Utc>,
+) -> Result<Option<DateTime<Utc>>, anyhow::Error> {
+    let schedule = parse_cron(&job.cron)?;
+
+    Ok(match parse_timezone(job)? {
+        Some(tz) => last_occurrence(&schedule, since.with_timezone(&tz), now),
+        None => last_occurrence(&schedule, since.with_timezone(&Local), now),
+    })
+}
+
+fn last_occurrence<Z: TimeZone>(schedule: &Schedule, since: DateTime<Z>, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    schedule
+        .after(&since)
+        .map(|t| t.with_timezone(&Utc))
+        .take_while(|t| *t <= now)
+        .last()
+}
+
+fn format_time(time: DateTime<Utc>) -> String {
+    time.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S %Z").to_string()
+}
+
+impl Record for ScheduleState {
+    const VERSION: u32 = 1;
+}
+
+impl ScheduleState {
+    fn load(work_root: &WorkRoot) -> Result<Self, anyhow::Error> {
+        Ok(record::load(work_root.schedule_state_path())?.unwrap_or_default())
+    }
+
+    fn save(&self, work_root: &WorkRoot) -> Result<(), anyhow::Error> {
+        record::save(work_root.schedule_state_path(), self)
+    }
+}
@@ -0,0 +1,284 @@
+use clap::{Args, ValueEnum};
+
+/// Version of the JSON output formats, included in every output as `schema_version`.
+///
+/// Compatibility policy: fields may be added to an output without changing the version,
+/// but removing or renaming a field, changing its type or making it optional requires
+/// bumping the version. This is enforced by the tests below.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Command outputs with a JSON format
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum Output {
+    Run,
+    Inspect,
+    List,
+    ListUsers,
+    Fsck,
+    VerifyTag,
+}
+
+#[derive(Debug, Args)]
+pub struct SchemaArgs {
+    #[clap(value_enum, help = "Output to print the JSON Schema of")]
+    output: Output,
+}
+
+impl Output {
+    /// Get the JSON Schema of the output
+    pub fn schema(self) -> &'static str {
+        match self {
+            Self::Run => include_str!("run.json"),
+            Self::Inspect => include_str!("inspect.json"),
+            Self::List => include_str!("list.json"),
+            Self::ListUsers => include_str!("list-users.json"),
+            Self::Fsck => include_str!("fsck.json"),
+            Self::VerifyTag => include_str!("verify-tag.json"),
+        }
+    }
+}
+
+pub fn schema(args: SchemaArgs) -> Result<(), anyhow::Error> {
+    print!("{}", args.output.schema());
+
+    Ok(())
+}
+
+/// Validate a value against the subset of JSON Schema used by the output schemas.
+/// Properties not described by the schema are rejected, so every field has to be documented.
+#[cfg(test)]
+pub fn validate(output: Output, value: &serde_json::Value) -> Result<(), String> {
+    let schema: serde_json::Value = serde_json::from_str(output.schema()).map_err(|err| err.to_string())?;
+
+    validate_value(&schema, value, "$")
+}
+
+#[cfg(test)]
+fn validate_value(schema: &serde_json::Value, value: &serde_json::Value, path: &str) -> Result<(), String> {
+    use serde_json::Value;
+
+    if let Some(expected) = schema.get("const") {
+        if value != expected {
+            return Err(format!("{path}: expected {expected}, got {value}"));
+        }
+    }
+
+    if let Some(types) = schema.get("type") {
+        let types: Vec<&str> = match types {
+            Value::Array(types) => types.iter().filter_map(Value::as_str).collect(),
+            t => t.as_str().into_iter().collect(),
+        };
+
+        let matches = types.iter().any(|t| match *t {
+            "object" => value.is_object(),
+            "array" => value.is_array(),
+            "string" => value.is_string(),
+            "boolean" => value.is_boolean(),
+            "integer" => value.is_u64() || value.is_i64(),
+            "number" => value.is_number(),
+            "null" => value.is_null(),
+            _ => false,
+        });
+
+        if !matches {
+            return Err(format!("{path}: expected {}, got {value}", types.join(" or ")));
+        }
+    }
+
+    match value {
+        Value::Object(object) => {
+            let properties = schema.get("properties").and_then(Value::as_object);
+
+            for required in schema.get("required").and_then(Value::as_array).into_iter().flatten() {
+                let required = required.as_str().unwrap_or_default();
+
+                if !object.contains_key(required) {
+                    return Err(format!("{path}: missing required property {required}"));
+                }
+            }
+
+            for (key, value) in object {
+                let path = format!("{path}.{key}");
+
+                match properties
+                    .and_then(|p| p.get(key))
+                    .or_else(|| schema.get("additionalProperties"))
+                {
+                    Some(schema) => validate_value(schema, value, &path)?,
+                    None => return Err(format!("{path}: property is not described by the schema")),
+                }
+            }
+        }
+        Value::Array(items) => {
+            if let Some(schema) = schema.get("items") {
+                for (i, item) in items.iter().enumerate() {
+                    validate_value(schema, item, &format!("{path}[{i}]"))?;
+                }
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+
+    const ALL_OUTPUTS: &[Output] = &[
+        Output::Run,
+        Output::Inspect,
+        Output::List,
+        Output::ListUsers,
+        Output::Fsck,
+        Output::VerifyTag,
+    ];
+
+    /// Required fields and their types as of the current schema version.
+    /// Changing any of these is a breaking change, requiring SCHEMA_VERSION to be bumped and this list updated.
+    const COMPATIBLE_VERSION: u32 = 1;
+    const REQUIRED_FIELDS: &[(&str, &[(&str, &str)])] = &[
+        (
+            "run",
+            &[
+                ("source_repository_path", "string"),
+                ("working_repository_path", "string"),
+                ("branch", "string"),
+                ("skipped", "boolean"),
+            ],
+        ),
+        (
+            "inspect",
+            &[
+                ("metadata", "object|null"),
+                ("running", "boolean"),
+                ("usage", "object|null"),
+                ("log_tail", "array"),
+            ],
+        ),
+        (
+            "list",
+            &[
+                ("id", "string"),
+                ("working_repository_path", "string"),
+                ("source_repository_path", "string|null"),
+                ("running", "boolean"),
+                ("last_success", "boolean|null"),
+                ("size", "integer|null"),
+            ],
+        ),
+        (
+            "list-users",
+            &[
+                ("user", "string"),
+                ("work_dirs", "integer|null"),
+                ("size", "integer|null"),
+            ],
+        ),
+        ("fsck", &[("working_repository_path", "string"), ("problems", "array")]),
+        (
+            "verify-tag",
+            &[
+                ("source_repository_path", "string"),
+                ("tag", "string"),
+                ("commit", "string"),
+                ("signature", "object"),
+                ("build", "object"),
+            ],
+        ),
+    ];
+
+    fn parse(output: Output) -> Value {
+        serde_json::from_str(output.schema()).unwrap()
+    }
+
+    /// Get the schema of the objects in an output, which may be a list of them
+    fn object_schema(schema: &Value) -> &Value {
+        match schema.get("type").and_then(Value::as_str) {
+            Some("array") => &schema["items"],
+            _ => schema,
+        }
+    }
+
+    fn type_name(schema: &Value) -> String {
+        match &schema["type"] {
+            Value::Array(types) => types.iter().filter_map(Value::as_str).collect::<Vec<_>>().join("|"),
+            t => t.as_str().unwrap_or_default().to_owned(),
+        }
+    }
+
+    #[test]
+    fn schemas_declare_current_version() {
+        for output in ALL_OUTPUTS {
+            let schema = parse(*output);
+            let object = object_schema(&schema);
+
+            assert_eq!(
+                object["properties"]["schema_version"]["const"], SCHEMA_VERSION,
+                "{output:?}"
+            );
+            assert!(
+                object["required"]
+                    .as_array()
+                    .unwrap()
+                    .contains(&Value::from("schema_version")),
+                "{output:?}"
+            );
+
+            let id = schema["$id"].as_str().unwrap();
+            assert!(id.contains(&format!("/v{SCHEMA_VERSION}/")), "{output:?}: {id}");
+        }
+    }
+
+    #[test]
+    fn required_fields_are_stable() {
+        assert_eq!(
+            SCHEMA_VERSION, COMPATIBLE_VERSION,
+            "Schema version changed. Update the required fields for the new version."
+        );
+
+        for (name, fields) in REQUIRED_FIELDS {
+            let output = Output::from_str(name, false).unwrap();
+            let schema = parse(output);
+            let object = object_schema(&schema);
+            let required = object["required"].as_array().unwrap();
+
+            for (field, expected_type) in *fields {
+                assert!(
+                    required.contains(&Value::from(*field)),
+                    "{name}: {field} is no longer required"
+                );
+                assert_eq!(
+                    type_name(&object["properties"][field]),
+                    *expected_type,
+                    "{name}: type of {field} changed"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn validate_rejects_undocumented_and_missing_fields() {
+        let valid = serde_json::json!([{
+            "schema_version": 1,
+            "working_repository_path": "/work",
+            "problems": [],
+        }]);
+        assert!(validate(Output::Fsck, &valid).is_ok());
+
+        let mut undocumented = valid.clone();
+        undocumented[0]["extra"] = Value::from(true);
+        assert!(validate(Output::Fsck, &undocumented).is_err());
+
+        let mut missing = valid.clone();
+        missing[0].as_object_mut().unwrap().remove("problems");
+        assert!(validate(Output::Fsck, &missing).is_err());
+
+        let mut wrong_version = valid;
+        wrong_version[0]["schema_version"] = Value::from(0);
+        assert!(validate(Output::Fsck, &wrong_version).is_err());
+    }
+}
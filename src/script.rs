@@ -0,0 +1,182 @@
+//! Scripting hooks.
+//!
+//! A Rhai script can define any of these functions to observe or adjust a run:
+//! - `on_event(name, ctx)`: called on pipeline events ("checkout", "start", "finish")
+//! - `should_run(ctx)`: return `false` or a reason string to veto the run
+//! - `env(ctx)`: return a map of extra environment variables for the command
+//! - `upload_destination(ctx)`: return an upload destination, overriding the configured one
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+#[cfg(not(feature = "scripting"))]
+use anyhow::anyhow;
+
+/// Values passed to script functions
+#[cfg_attr(not(feature = "scripting"), allow(dead_code))]
+pub struct ScriptContext<'a> {
+    pub repo: &'a Path,
+    pub work_path: &'a Path,
+    pub branch: &'a str,
+    pub commit: &'a str,
+    pub command: &'a [String],
+    pub success: Option<bool>,
+}
+
+/// Loaded scripting hooks. Does nothing if no script is configured.
+pub struct Hooks {
+    #[cfg(feature = "scripting")]
+    script: Option<imp::Script>,
+}
+
+#[cfg(not(feature = "scripting"))]
+impl Hooks {
+    pub fn load(path: Option<&Path>) -> Result<Self, anyhow::Error> {
+        if let Some(path) = path {
+            return Err(anyhow!(
+                "Cannot load script {}: fersk was built without the `scripting` feature.",
+                path.display()
+            ));
+        }
+
+        Ok(Self {})
+    }
+
+    pub fn event(&self, _name: &str, _ctx: &ScriptContext) {}
+
+    pub fn veto(&self, _ctx: &ScriptContext) -> Result<Option<String>, anyhow::Error> {
+        Ok(None)
+    }
+
+    pub fn env(&self, _ctx: &ScriptContext) -> Result<BTreeMap<String, String>, anyhow::Error> {
+        Ok(BTreeMap::new())
+    }
+
+    pub fn upload_destination(&self, _ctx: &ScriptContext) -> Result<Option<String>, anyhow::Error> {
+        Ok(None)
+    }
+}
+
+#[cfg(feature = "scripting")]
+impl Hooks {
+    /// Load hooks from a script file, if specified
+    pub fn load(path: Option<&Path>) -> Result<Self, anyhow::Error> {
+        let script = path.map(imp::Script::load).transpose()?;
+
+        Ok(Self { script })
+    }
+
+    /// Notify the script of a pipeline event. Errors are logged, but otherwise ignored.
+    pub fn event(&self, name: &str, ctx: &ScriptContext) {
+        if let Some(script) = &self.script {
+            if let Err(err) = script.call("on_event", (name.to_owned(), imp::to_map(ctx))) {
+                tracing::warn!("Error in script event handler: {err:#}");
+            }
+        }
+    }
+
+    /// Ask the script whether the run should go ahead, returning the reason if it should not
+    pub fn veto(&self, ctx: &ScriptContext) -> Result<Option<String>, anyhow::Error> {
+        let Some(result) = self.call_with_context("should_run", ctx)? else {
+            return Ok(None);
+        };
+
+        if let Some(run) = result.clone().try_cast::<bool>() {
+            return Ok((!run).then(|| "Run vetoed by script.".to_owned()));
+        }
+
+        if result.is_string() {
+            return Ok(Some(result.to_string()));
+        }
+
+        Ok(None)
+    }
+
+    /// Get extra environment variables from the script
+    pub fn env(&self, ctx: &ScriptContext) -> Result<BTreeMap<String, String>, anyhow::Error> {
+        let Some(result) = self.call_with_context("env", ctx)? else {
+            return Ok(BTreeMap::new());
+        };
+
+        let map = result
+            .try_cast::<rhai::Map>()
+            .ok_or_else(|| anyhow::anyhow!("Script function env() must return a map"))?;
+
+        Ok(map.into_iter().map(|(k, v)| (k.to_string(), v.to_string())).collect())
+    }
+
+    /// Get an upload destination from the script
+    pub fn upload_destination(&self, ctx: &ScriptContext) -> Result<Option<String>, anyhow::Error> {
+        let Some(result) = self.call_with_context("upload_destination", ctx)? else {
+            return Ok(None);
+        };
+
+        Ok(result.is_string().then(|| result.to_string()))
+    }
+
+    fn call_with_context(&self, name: &str, ctx: &ScriptContext) -> Result<Option<rhai::Dynamic>, anyhow::Error> {
+        match &self.script {
+            Some(script) => script.call(name, (imp::to_map(ctx),)),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(feature = "scripting")]
+mod imp {
+    use std::path::Path;
+
+    use anyhow::anyhow;
+    use rhai::{Array, Dynamic, Engine, FuncArgs, Map, Scope, AST};
+
+    use super::ScriptContext;
+
+    pub struct Script {
+        engine: Engine,
+        ast: AST,
+    }
+
+    impl Script {
+        pub fn load(path: &Path) -> Result<Self, anyhow::Error> {
+            let engine = Engine::new();
+            let ast = engine
+                .compile_file(path.to_path_buf())
+                .map_err(|err| anyhow!("Error loading script {}: {err}", path.display()))?;
+
+            Ok(Self { engine, ast })
+        }
+
+        /// Call a script function, if the script defines it
+        pub fn call(&self, name: &str, args: impl FuncArgs) -> Result<Option<Dynamic>, anyhow::Error> {
+            if !self.ast.iter_functions().any(|f| f.name == name) {
+                return Ok(None);
+            }
+
+            let result = self
+                .engine
+                .call_fn::<Dynamic>(&mut Scope::new(), &self.ast, name, args)
+                .map_err(|err| anyhow!("Error calling script function {name}(): {err}"))?;
+
+            Ok(Some(result))
+        }
+    }
+
+    pub fn to_map(ctx: &ScriptContext) -> Map {
+        let mut map = Map::new();
+
+        map.insert("repo".into(), ctx.repo.to_string_lossy().to_string().into());
+        map.insert("work_path".into(), ctx.work_path.to_string_lossy().to_string().into());
+        map.insert("branch".into(), ctx.branch.to_owned().into());
+        map.insert("commit".into(), ctx.commit.to_owned().into());
+        map.insert(
+            "command".into(),
+            Dynamic::from_array(ctx.command.iter().map(|a| a.clone().into()).collect::<Array>()),
+        );
+        map.insert(
+            "success".into(),
+            ctx.success.map(Dynamic::from).unwrap_or(Dynamic::UNIT),
+        );
+
+        map
+    }
+}
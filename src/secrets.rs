@@ -0,0 +1,206 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use anyhow::{anyhow, Context};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::config::expand;
+
+/// Replacement for secret values in logs and reports
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Secrets shorter than this are not redacted, as they would match too much unrelated output
+const MIN_REDACT_LEN: usize = 4;
+
+/// Sources of secrets injected into the environment of the command, keyed by environment variable
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct SecretsConfig {
+    /// Variables passed through from fersk's own environment
+    pub env: Vec<String>,
+    /// Variables read from files
+    #[serde(deserialize_with = "expand::deserialize")]
+    pub files: BTreeMap<String, PathBuf>,
+    /// Variables read from the output of commands (ex. `op read`)
+    pub commands: BTreeMap<String, Vec<String>>,
+}
+
+/// Resolved secrets
+#[derive(Debug, Default)]
+pub struct Secrets {
+    pub vars: BTreeMap<String, String>,
+}
+
+impl SecretsConfig {
+    pub fn is_empty(&self) -> bool {
+        self.env.is_empty() && self.files.is_empty() && self.commands.is_empty()
+    }
+
+    /// Read the values of all secrets from their sources
+    pub fn resolve(&self) -> Result<Secrets, anyhow::Error> {
+        let mut vars = BTreeMap::new();
+
+        for name in &self.env {
+            let value = std::env::var(name).with_context(|| format!("Secret {name} is not set in the environment"))?;
+
+            vars.insert(name.clone(), value);
+        }
+
+        for (name, path) in &self.files {
+            let value = std::fs::read_to_string(path)
+                .with_context(|| format!("Error reading secret {name} from {}", path.display()))?;
+
+            vars.insert(name.clone(), trim_newline(value));
+        }
+
+        for (name, command) in &self.commands {
+            let value = run_secret_command(command).with_context(|| format!("Error getting secret {name}"))?;
+
+            vars.insert(name.clone(), value);
+        }
+
+        Ok(Secrets { vars })
+    }
+}
+
+impl Secrets {
+    /// Get the values to redact from output
+    pub fn redact_values(&self) -> Vec<Vec<u8>> {
+        self.vars
+            .values()
+            .filter(|v| v.len() >= MIN_REDACT_LEN)
+            .map(|v| v.as_bytes().to_vec())
+            .collect()
+    }
+
+    /// Replace any secret values in a string
+    pub fn redact(&self, s: &str) -> String {
+        self.vars
+            .values()
+            .filter(|v| v.len() >= MIN_REDACT_LEN)
+            .fold(s.to_owned(), |s, value| s.replace(value.as_str(), REDACTED))
+    }
+}
+
+/// Replace all occurrences of any of the values in data
+pub fn redact_bytes(data: &[u8], values: &[Vec<u8>]) -> Vec<u8> {
+    redact_until(data, values, true).0
+}
+
+/// Replace all occurrences of any of the values in data that is followed by more data.
+/// Stops where the rest of data could be the start of a value.
+/// Returns the redacted output and the number of bytes of data it covers.
+pub fn redact_partial(data: &[u8], values: &[Vec<u8>]) -> (Vec<u8>, usize) {
+    redact_until(data, values, false)
+}
+
+fn redact_until(data: &[u8], values: &[Vec<u8>], complete: bool) -> (Vec<u8>, usize) {
+    let mut output = Vec::with_capacity(data.len());
+    let mut i = 0;
+
+    'outer: while i < data.len() {
+        let rest = &data[i..];
+
+        for value in values {
+            if rest.starts_with(value) {
+                output.extend_from_slice(REDACTED.as_bytes());
+                i += value.len();
+                continue 'outer;
+            }
+        }
+
+        if !complete && values.iter().any(|value| value.starts_with(rest)) {
+            break;
+        }
+
+        output.push(data[i]);
+        i += 1;
+    }
+
+    (output, i)
+}
+
+fn run_secret_command(command: &[String]) -> Result<String, anyhow::Error> {
+    let (program, args) = command.split_first().ok_or_else(|| anyhow!("Command is empty"))?;
+
+    // Allow interactive unlocking (ex. password managers)
+    let output = Command::new(program)
+        .args(args)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+        .with_context(|| format!("Error executing {program}"))?;
+
+    if !output.status.success() {
+        return Err(anyhow!("{program} failed with exit code {:?}", output.status.code()));
+    }
+
+    let value = String::from_utf8(output.stdout).with_context(|| format!("Output of {program} is not valid UTF-8"))?;
+
+    Ok(trim_newline(value))
+}
+
+/// Remove the trailing newline files and command output usually end with
+fn trim_newline(mut value: String) -> String {
+    if value.ends_with('\n') {
+        value.pop();
+
+        if value.ends_with('\r') {
+            value.pop();
+        }
+    }
+
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secrets() -> Secrets {
+        Secrets {
+            vars: [
+                ("TOKEN".to_owned(), "hunter22".to_owned()),
+                ("KEY".to_owned(), "s3cr3t".to_owned()),
+                ("SHORT".to_owned(), "abc".to_owned()),
+            ]
+            .into(),
+        }
+    }
+
+    #[test]
+    fn redacts_values() {
+        let secrets = secrets();
+
+        assert_eq!(
+            secrets.redact("token hunter22, key s3cr3ts3cr3t, abc"),
+            "token [REDACTED], key [REDACTED][REDACTED], abc"
+        );
+        assert_eq!(
+            redact_bytes(b"hunter22\ns3cr3t hunter2", &secrets.redact_values()),
+            b"[REDACTED]\n[REDACTED] hunter2"
+        );
+        assert_eq!(redact_bytes(b"output", &[]), b"output");
+    }
+
+    #[test]
+    fn holds_back_possible_start_of_value() {
+        let values = secrets().redact_values();
+
+        assert_eq!(redact_partial(b"token hunt", &values), (b"token ".to_vec(), 6));
+        assert_eq!(
+            redact_partial(b"token hunter22", &values),
+            (b"token [REDACTED]".to_vec(), 14)
+        );
+        assert_eq!(redact_partial(b"hunter2!", &values), (b"hunter2!".to_vec(), 8));
+
+        // Values split across reads are found once the rest arrives
+        let mut pending = b"key: s3".to_vec();
+        let (output, consumed) = redact_partial(&pending, &values);
+        assert_eq!(output, b"key: ");
+        pending.drain(..consumed);
+        pending.extend_from_slice(b"cr3t\n");
+        assert_eq!(redact_partial(&pending, &values), (b"[REDACTED]\n".to_vec(), 7));
+    }
+}
@@ -0,0 +1,292 @@
+use std::io::{Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use anyhow::{anyhow, Context};
+use clap::Args;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::config::expand;
+use crate::config::Config;
+use crate::util::hash;
+
+const DEFAULT_FEED: &str = "https://api.github.com/repos/forbjok/fersk/releases/latest";
+
+/// Namespace release archives are signed in (`ssh-keygen -Y sign -n fersk-release`)
+const SIGNATURE_NAMESPACE: &str = "fersk-release";
+
+/// Where `fersk self-update` gets releases from, and how they are verified
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct SelfUpdateConfig {
+    /// URL or path of the latest release, in the format of GitHub's release API
+    pub feed: String,
+    /// SSH allowed signers file with the keys releases must be signed by
+    #[serde(deserialize_with = "expand::deserialize")]
+    pub allowed_signers: Option<PathBuf>,
+    /// Don't install releases without a valid signature.
+    /// The checksum is published with the release, so on its own it only detects corrupted downloads.
+    pub require_signature: bool,
+}
+
+impl Default for SelfUpdateConfig {
+    fn default() -> Self {
+        Self {
+            feed: DEFAULT_FEED.to_owned(),
+            allowed_signers: None,
+            require_signature: true,
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct SelfUpdateArgs {
+    #[clap(
+        long = "check",
+        help = "Only check if a newer release is available, without installing it"
+    )]
+    check: bool,
+}
+
+#[derive(Deserialize)]
+struct Release {
+    tag_name: String,
+    assets: Vec<Asset>,
+}
+
+#[derive(Deserialize)]
+struct Asset {
+    name: String,
+    browser_download_url: String,
+}
+
+impl Release {
+    fn asset_url(&self, name: &str) -> Option<&str> {
+        self.assets
+            .iter()
+            .find(|asset| asset.name == name)
+            .map(|asset| asset.browser_download_url.as_str())
+    }
+}
+
+/// Replace the fersk executable with the latest release for this platform,
+/// after verifying its checksum and signature
+pub fn self_update(cfg: &Config, args: SelfUpdateArgs) -> Result<(), anyhow::Error> {
+    let cfg = &cfg.self_update;
+    let current = env!("CARGO_PKG_VERSION");
+
+    // The release scripts only build Windows archives
+    if cfg.feed == DEFAULT_FEED && !cfg!(windows) {
+        return Err(anyhow!(
+            "Releases of fersk are only published for Windows. Update it the way it was installed \
+             (ex. cargo install fersk), or configure a feed with builds for this platform in [self-update]."
+        ));
+    }
+
+    let feed = download(&cfg.feed)?;
+    let release: Release =
+        serde_json::from_slice(&feed).with_context(|| format!("Error parsing release feed: {}", cfg.feed))?;
+    let version = release.tag_name.trim_start_matches('v');
+
+    if !is_newer(version, current) {
+        println!("fersk is up to date ({current}).");
+        return Ok(());
+    }
+
+    let archive_name = archive_name(version);
+    let archive_url = release
+        .asset_url(&archive_name)
+        .ok_or_else(|| anyhow!("Release {version} has no build for this platform ({archive_name})."))?;
+
+    if args.check {
+        println!("Update available: {current} -> {version} ({archive_url})");
+        return Ok(());
+    }
+
+    let checksum_url = release
+        .asset_url(&format!("{archive_name}.sha256.txt"))
+        .ok_or_else(|| anyhow!("Release {version} has no checksum for {archive_name}. Not updating."))?;
+
+    if cfg.allowed_signers.is_none() && cfg.require_signature {
+        return Err(anyhow!(
+            "No allowed-signers configured in [self-update] to verify the signature of releases with. \
+             Set require-signature = false to only verify their checksum, which only detects corrupted downloads."
+        ));
+    }
+
+    eprintln!("Downloading {archive_url}...");
+    let archive = download(archive_url)?;
+
+    let checksum = String::from_utf8_lossy(&download(checksum_url)?).to_string();
+    let expected = checksum.split_whitespace().next().unwrap_or_default();
+    let actual = hash::hash_bytes(&archive);
+
+    if !actual.eq_ignore_ascii_case(expected) {
+        return Err(anyhow!(
+            "Checksum mismatch for {archive_name}: expected {expected}, got {actual}. Not updating."
+        ));
+    }
+
+    if let Some(allowed_signers) = &cfg.allowed_signers {
+        let signature_url = release
+            .asset_url(&format!("{archive_name}.sig"))
+            .ok_or_else(|| anyhow!("Release {version} has no signature for {archive_name}. Not updating."))?;
+
+        verify_signature(&archive, &download(signature_url)?, allowed_signers)
+            .with_context(|| format!("Error verifying signature of {archive_name}. Not updating."))?;
+    } else {
+        eprintln!("Not verifying the signature of {archive_name}, as require-signature is disabled.");
+    }
+
+    let binary = extract_binary(&archive).with_context(|| format!("Error extracting {archive_name}"))?;
+
+    let exe = std::env::current_exe().with_context(|| "Error getting path of fersk executable")?;
+    replace_executable(&exe, &binary).with_context(|| format!("Error replacing {}", exe.display()))?;
+
+    println!("Updated fersk {current} -> {version}.");
+
+    Ok(())
+}
+
+/// Download a file from an http(s) URL, or read it from a local path
+fn download(location: &str) -> Result<Vec<u8>, anyhow::Error> {
+    let mut content = Vec::new();
+
+    if location.starts_with("http://") || location.starts_with("https://") {
+        ureq::get(location)
+            .set("User-Agent", concat!("fersk/", env!("CARGO_PKG_VERSION")))
+            .call()
+            .with_context(|| format!("Error downloading {location}"))?
+            .into_reader()
+            .read_to_end(&mut content)
+            .with_context(|| format!("Error downloading {location}"))?;
+    } else {
+        let path = Path::new(location.strip_prefix("file://").unwrap_or(location));
+        content = std::fs::read(path).with_context(|| format!("Error reading {}", path.display()))?;
+    }
+
+    Ok(content)
+}
+
+/// Name of the release archive for this platform, as built by the release scripts (ex. fersk-0.3.1-windows-x86_64.zip)
+fn archive_name(version: &str) -> String {
+    let arch = match std::env::consts::ARCH {
+        "x86" => "i686",
+        arch => arch,
+    };
+
+    format!("fersk-{version}-{}-{arch}.zip", std::env::consts::OS)
+}
+
+/// Check if a version is newer than another, comparing their numeric components
+fn is_newer(version: &str, than: &str) -> bool {
+    let parse = |v: &str| -> Vec<u64> { v.split(['.', '-', '+']).map_while(|part| part.parse().ok()).collect() };
+
+    parse(version) > parse(than)
+}
+
+/// Verify an SSH signature of a release archive, made by any of the allowed signers
+fn verify_signature(archive: &[u8], signature: &[u8], allowed_signers: &Path) -> Result<(), anyhow::Error> {
+    let mut signature_file = tempfile::NamedTempFile::new()?;
+    signature_file.write_all(signature)?;
+    let signature_path = signature_file.path();
+
+    let principals = Command::new("ssh-keygen")
+        .args(["-Y", "find-principals", "-s"])
+        .arg(signature_path)
+        .arg("-f")
+        .arg(allowed_signers)
+        .output()
+        .with_context(|| "Error executing ssh-keygen")?;
+
+    let principals = String::from_utf8_lossy(&principals.stdout).to_string();
+    let principal = principals
+        .lines()
+        .next()
+        .ok_or_else(|| anyhow!("Not signed by any of the allowed signers"))?;
+
+    let mut child = Command::new("ssh-keygen")
+        .args(["-Y", "verify", "-n", SIGNATURE_NAMESPACE, "-I", principal, "-s"])
+        .arg(signature_path)
+        .arg("-f")
+        .arg(allowed_signers)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| "Error executing ssh-keygen")?;
+
+    child.stdin.take().expect("stdin is piped").write_all(archive)?;
+    let output = child.wait_with_output()?;
+
+    if !output.status.success() {
+        return Err(anyhow!("{}", String::from_utf8_lossy(&output.stderr).trim_end()));
+    }
+
+    eprintln!("Good signature by {principal}.");
+
+    Ok(())
+}
+
+/// Get the fersk executable from a release archive
+fn extract_binary(archive: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+    let exe_name = format!("fersk{}", std::env::consts::EXE_SUFFIX);
+    let mut zip = zip::ZipArchive::new(Cursor::new(archive))?;
+
+    for i in 0..zip.len() {
+        let mut file = zip.by_index(i)?;
+
+        if file
+            .enclosed_name()
+            .and_then(|p| p.file_name())
+            .is_some_and(|n| *n == *exe_name)
+        {
+            let mut binary = Vec::new();
+            file.read_to_end(&mut binary)?;
+
+            return Ok(binary);
+        }
+    }
+
+    Err(anyhow!("Archive does not contain {exe_name}"))
+}
+
+/// Replace an executable without ever leaving it missing or partially written.
+/// The new executable is written next to it, and renamed over it.
+fn replace_executable(exe: &Path, binary: &[u8]) -> Result<(), anyhow::Error> {
+    let with_suffix = |suffix: &str| {
+        let mut name = exe.file_name().unwrap_or_default().to_os_string();
+        name.push(suffix);
+        exe.with_file_name(name)
+    };
+
+    let new_path = with_suffix(".new");
+    std::fs::write(&new_path, binary)?;
+    std::fs::set_permissions(&new_path, std::fs::metadata(exe)?.permissions())?;
+
+    // A running executable can't be replaced on Windows, but it can be renamed out of the way
+    if cfg!(windows) {
+        let old_path = with_suffix(".old");
+        let _ = std::fs::remove_file(&old_path);
+        std::fs::rename(exe, &old_path)?;
+    }
+
+    std::fs::rename(&new_path, exe)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compares_versions() {
+        assert!(is_newer("0.4.0", "0.3.1"));
+        assert!(is_newer("0.10.0", "0.9.9"));
+        assert!(is_newer("1.0.0", "0.3"));
+        assert!(!is_newer("0.3.1", "0.3.1"));
+        assert!(!is_newer("0.3.0", "0.3.1"));
+        assert!(!is_newer("0.3.1-beta", "0.3.1"));
+    }
+}
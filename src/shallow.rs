@@ -0,0 +1,49 @@
+use std::path::Path;
+
+use anyhow::Context;
+
+use crate::git::Git;
+use crate::journal::Journal;
+use crate::run::FERSK_ORIGIN;
+
+/// Number of times history is deepened before fetching all of it
+const MAX_DEEPEN_ATTEMPTS: usize = 4;
+
+/// Deepen the history of a shallow work directory until everything needed is reachable,
+/// doubling the amount fetched each time, and finally fetching the complete history
+pub fn ensure_reachable(
+    git: &Git,
+    work_path: &Path,
+    journal: &Journal,
+    is_reachable: impl Fn() -> bool,
+) -> Result<(), anyhow::Error> {
+    let Some(depth) = git.depth else {
+        return Ok(());
+    };
+
+    if !git.is_shallow(work_path) || is_reachable() {
+        return Ok(());
+    }
+
+    let mut commits = depth.max(1);
+
+    for _ in 0..MAX_DEEPEN_ATTEMPTS {
+        git.deepen(work_path, FERSK_ORIGIN, commits)
+            .with_context(|| "Error deepening history")?;
+
+        journal.record("deepen", format!("fetched {commits} more commit(s) of history"));
+
+        if is_reachable() {
+            return Ok(());
+        }
+
+        commits *= 2;
+    }
+
+    git.unshallow(work_path, FERSK_ORIGIN)
+        .with_context(|| "Error fetching complete history")?;
+
+    journal.record("deepen", "fetched the complete history");
+
+    Ok(())
+}
@@ -0,0 +1,46 @@
+use std::path::Path;
+
+use anyhow::Context;
+
+use crate::git::Git;
+use crate::journal::Journal;
+
+/// Limit the working tree to the specified directories (`--sparse`), or restore the full working tree
+/// if none are specified and a previous run left it sparse
+pub fn apply(git: &Git, work_path: &Path, dirs: &[String], journal: &Journal) -> Result<(), anyhow::Error> {
+    let is_sparse = git.is_sparse(work_path);
+
+    if dirs.is_empty() {
+        if is_sparse {
+            git.disable_sparse_checkout(work_path)
+                .with_context(|| "Error disabling sparse checkout")?;
+
+            journal.record("sparse", "restored the full working tree");
+        }
+
+        return Ok(());
+    }
+
+    let dirs: Vec<String> = dirs.iter().map(|d| d.trim_matches('/').to_owned()).collect();
+
+    let current = if is_sparse {
+        git.sparse_checkout_list(work_path).unwrap_or_default()
+    } else {
+        Vec::new()
+    };
+
+    let mut sorted = dirs.clone();
+    sorted.sort();
+
+    // Work directories made sparse by older versions don't have a sparse index yet
+    if current == sorted && git.is_sparse_index(work_path) == git.wants_sparse_index() {
+        return Ok(());
+    }
+
+    git.set_sparse_checkout(work_path, &dirs)
+        .with_context(|| "Error setting up sparse checkout")?;
+
+    journal.record("sparse", format!("limited the working tree to {}", dirs.join(", ")));
+
+    Ok(())
+}
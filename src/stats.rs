@@ -0,0 +1,155 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use chrono::{Local, TimeZone};
+use clap::Args;
+use serde_derive::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::config::Config;
+use crate::util::pid::PidLock;
+use crate::util::record::{self, Record};
+use crate::util::{self, quote};
+use crate::workroot::WorkRoot;
+
+/// How long to wait for another process updating the statistics
+const LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+const LOCK_WAIT_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Local usage statistics, kept in the work root if enabled. They are never sent anywhere.
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct UsageStats {
+    pub repositories: BTreeMap<PathBuf, RepositoryStats>,
+}
+
+impl Record for UsageStats {
+    const VERSION: u32 = 1;
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RepositoryStats {
+    /// Runs where the command was executed
+    pub runs: u64,
+    pub succeeded: u64,
+    /// Runs skipped without executing the command (ex. already up to date)
+    pub skipped: u64,
+    /// Runs that used the result of an identical concurrent run
+    pub coalesced: u64,
+    /// Total duration of executed runs, in seconds
+    pub total_duration: u64,
+    pub dependency_cache_hits: u64,
+    pub dependency_cache_misses: u64,
+    pub last_run_at: Option<u64>,
+}
+
+#[derive(Debug, Args)]
+pub struct StatsArgs {
+    #[clap(long = "reset", help = "Delete all collected statistics")]
+    reset: bool,
+}
+
+/// Update the statistics of a repository, if enabled.
+/// Statistics are best effort, so errors are only logged.
+pub fn record(cfg: &Config, work_root: &WorkRoot, source_path: &Path, f: impl FnOnce(&mut RepositoryStats)) {
+    if !cfg.usage_stats {
+        return;
+    }
+
+    let path = work_root.stats_path();
+
+    // Concurrent runs would otherwise overwrite each other's updates
+    let Some(_pidlock) = lock(work_root) else {
+        warn!("Statistics are being updated by another process. Not recording this run.");
+        return;
+    };
+
+    let result = record::load::<UsageStats>(&path).and_then(|stats| {
+        let mut stats = stats.unwrap_or_default();
+
+        let repository_stats = stats.repositories.entry(source_path.to_path_buf()).or_default();
+        f(repository_stats);
+        repository_stats.last_run_at = Some(util::time::unix_now());
+
+        record::save(&path, &stats)
+    });
+
+    if let Err(err) = result {
+        warn!("Error updating usage statistics: {err:#}");
+    }
+}
+
+/// Acquire the lock of the statistics file, waiting for other processes updating it
+fn lock(work_root: &WorkRoot) -> Option<PidLock> {
+    let lock_path = work_root.stats_lock_path();
+    util::create_parent_dir(&lock_path).ok()?;
+
+    let started = Instant::now();
+
+    loop {
+        if let Some(lock) = PidLock::acquire(&lock_path) {
+            return Some(lock);
+        }
+
+        if started.elapsed() > LOCK_TIMEOUT {
+            return None;
+        }
+
+        std::thread::sleep(LOCK_WAIT_INTERVAL);
+    }
+}
+
+pub fn stats(cfg: &Config, args: StatsArgs) -> Result<(), anyhow::Error> {
+    let work_root = WorkRoot::from_config(cfg);
+    let path = work_root.stats_path();
+
+    if args.reset {
+        let _pidlock = lock(&work_root).with_context(|| "Statistics are being updated by another process")?;
+
+        if path.exists() {
+            std::fs::remove_file(&path)?;
+        }
+
+        println!("Statistics reset.");
+        return Ok(());
+    }
+
+    if !cfg.usage_stats {
+        eprintln!("Usage statistics are not being collected. Set usage-stats = true in the config to enable them.");
+    }
+
+    let stats: UsageStats = record::load(&path)?.unwrap_or_default();
+
+    for (repository, s) in &stats.repositories {
+        println!("{}", quote::path(repository));
+        println!(
+            "  Runs: {} ({} succeeded, {} failed), {} skipped, {} coalesced",
+            s.runs,
+            s.succeeded,
+            s.runs - s.succeeded,
+            s.skipped,
+            s.coalesced
+        );
+
+        if let Some(average) = s.total_duration.checked_div(s.runs) {
+            println!("  Average duration: {average}s");
+        }
+
+        let lookups = s.dependency_cache_hits + s.dependency_cache_misses;
+        if let Some(hit_rate) = (s.dependency_cache_hits * 100).checked_div(lookups) {
+            println!(
+                "  Dependency cache: {} hit(s), {} miss(es) ({hit_rate}% hit rate)",
+                s.dependency_cache_hits, s.dependency_cache_misses
+            );
+        }
+
+        if let Some(time) = s.last_run_at.and_then(|t| Local.timestamp_opt(t as i64, 0).single()) {
+            println!("  Last run: {}", time.format("%Y-%m-%d %H:%M:%S"));
+        }
+    }
+
+    Ok(())
+}
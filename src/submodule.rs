@@ -0,0 +1,154 @@
+use std::path::Path;
+
+use anyhow::Context;
+
+use crate::git::Git;
+use crate::journal::Journal;
+
+/// Initialize and update submodules in a work directory, recursively, discarding any changes in them.
+///
+/// Relative submodule URLs are resolved against the URL of the source repository's `origin` remote
+/// (or the source repository itself, if it has none), as they would be in the source repository,
+/// rather than against the work directory's remote.
+///
+/// Local repositories are only cloned for submodules with relative URLs resolved against a local source repository,
+/// or with a local URL the submodule was initialized with in the source repository.
+/// Any other local URLs come from .gitmodules in the checked out commit, which can't be trusted with them.
+pub fn update_submodules(
+    git: &Git,
+    source_git: &Git,
+    source_path: &Path,
+    work_path: &Path,
+    journal: &Journal,
+) -> Result<(), anyhow::Error> {
+    if !work_path.join(".gitmodules").is_file() {
+        return Ok(());
+    }
+
+    let submodules = git.submodules(work_path).with_context(|| "Error reading submodules")?;
+
+    let base = if submodules.iter().any(|s| is_relative_url(&s.url)) {
+        source_git
+            .get_remote_url(source_path, "origin")
+            .unwrap_or_else(|_| source_path.to_string_lossy().to_string())
+    } else {
+        String::new()
+    };
+
+    let mut local_paths = Vec::new();
+
+    for submodule in &submodules {
+        let url = if is_relative_url(&submodule.url) {
+            Some(resolve_relative_url(&base, &submodule.url))
+        } else {
+            source_git
+                .get_config_all(source_path, &format!("submodule.{}.url", submodule.name))
+                .unwrap_or_default()
+                .pop()
+                .filter(|url| is_local_url(url))
+        };
+
+        let Some(url) = url else {
+            continue;
+        };
+
+        git.set_config(work_path, &format!("submodule.{}.url", submodule.name), &url)
+            .with_context(|| format!("Error setting URL of submodule {}", submodule.name))?;
+
+        if is_local_url(&url) && !submodule.path.is_empty() {
+            local_paths.push(submodule.path.clone());
+        }
+    }
+
+    git.update_submodules(work_path, &local_paths)
+        .with_context(|| "Error updating submodules")?;
+
+    journal.record("submodules", format!("updated {} submodule(s)", submodules.len()));
+
+    Ok(())
+}
+
+fn is_relative_url(url: &str) -> bool {
+    url.starts_with("./") || url.starts_with("../")
+}
+
+/// Check if a URL is a path on this machine, which git clones with the file transport
+fn is_local_url(url: &str) -> bool {
+    if url.starts_with("file://") {
+        return true;
+    }
+
+    if url.contains("://") {
+        return false;
+    }
+
+    // scp-like URLs (host:path) have a colon before the first slash, unlike paths (except Windows drive letters)
+    match url.find(':') {
+        None => true,
+        Some(1) => url.as_bytes()[0].is_ascii_alphabetic(),
+        Some(i) => url[..i].contains(['/', '\\']),
+    }
+}
+
+/// Resolve a relative submodule URL against the URL of the superproject, the same way git does
+fn resolve_relative_url(base: &str, relative: &str) -> String {
+    let mut base = base.trim_end_matches('/').to_owned();
+    let mut relative = relative;
+    let mut separator = '/';
+
+    loop {
+        if let Some(rest) = relative.strip_prefix("./") {
+            relative = rest;
+        } else if let Some(rest) = relative.strip_prefix("../") {
+            relative = rest;
+
+            // scp-like URLs (host:path) have a colon before the first path component
+            match base.rfind(['/', ':']) {
+                Some(i) => {
+                    separator = base[i..].chars().next().unwrap_or('/');
+                    base.truncate(i);
+                }
+                None => base.clear(),
+            }
+        } else {
+            break;
+        }
+    }
+
+    if base.is_empty() {
+        relative.to_owned()
+    } else {
+        format!("{base}{separator}{relative}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognizes_local_urls() {
+        assert!(is_local_url("/home/user/src/lib"));
+        assert!(is_local_url("file:///home/user/src/lib"));
+        assert!(is_local_url("C:\\src\\lib"));
+        assert!(is_local_url("./lib:v2"));
+        assert!(!is_local_url("https://example.com/org/lib.git"));
+        assert!(!is_local_url("git@example.com:org/lib.git"));
+    }
+
+    #[test]
+    fn resolves_relative_urls() {
+        assert_eq!(
+            resolve_relative_url("https://example.com/org/repo.git", "../lib.git"),
+            "https://example.com/org/lib.git"
+        );
+        assert_eq!(
+            resolve_relative_url("git@example.com:org/repo.git", "../../other/lib.git"),
+            "git@example.com:other/lib.git"
+        );
+        assert_eq!(
+            resolve_relative_url("/home/user/src/repo/", "./vendor/lib"),
+            "/home/user/src/repo/vendor/lib"
+        );
+    }
+}
@@ -0,0 +1,45 @@
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+/// Build a PATH for commands with tool directories prepended to the base one, or the inherited one if None.
+/// Relative directories are looked up in the work directory, then in the source repository,
+/// so tools installed in either (ex. node_modules/.bin) are found. Directories that don't exist are skipped.
+/// Returns None if no tool directories were found, in which case PATH should be left alone.
+pub fn tool_path_env<'a>(
+    tool_paths: impl IntoIterator<Item = &'a PathBuf>,
+    work_path: &Path,
+    source_path: &Path,
+    base_path: Option<&OsStr>,
+) -> Result<Option<OsString>, anyhow::Error> {
+    let mut dirs: Vec<PathBuf> = Vec::new();
+
+    for tool_path in tool_paths {
+        let candidates = if tool_path.is_absolute() {
+            vec![tool_path.clone()]
+        } else {
+            vec![work_path.join(tool_path), source_path.join(tool_path)]
+        };
+
+        for dir in candidates {
+            if dir.is_dir() && !dirs.contains(&dir) {
+                dirs.push(dir);
+            }
+        }
+    }
+
+    if dirs.is_empty() {
+        return Ok(None);
+    }
+
+    let inherited = match base_path {
+        Some(path) => path.to_owned(),
+        None => std::env::var_os("PATH").unwrap_or_default(),
+    };
+    dirs.extend(std::env::split_paths(&inherited));
+
+    let path = std::env::join_paths(dirs).with_context(|| "Error building PATH with tool directories")?;
+
+    Ok(Some(path))
+}
@@ -0,0 +1,120 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::command;
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct UploadConfig {
+    /// Destination URL template (`s3://`, `gs://`, `http(s)://` or a local path).
+    /// Supports the placeholders {repo}, {id}, {branch}, {sha} and {timestamp}.
+    pub destination: Option<String>,
+    /// Upload after every run, without having to specify `--upload`
+    pub automatic: bool,
+    /// Files to upload, relative to the working directory
+    pub artifacts: Vec<PathBuf>,
+    /// Upload artifacts as a single archive (artifacts.<extension>), created as configured in `[archive]`.
+    /// This also allows uploading directories.
+    pub archive_artifacts: bool,
+    /// Extra headers for HTTP uploads
+    pub headers: BTreeMap<String, String>,
+}
+
+/// Values available to the destination template
+pub struct UploadContext<'a> {
+    pub repo: &'a str,
+    pub id: &'a str,
+    pub branch: &'a str,
+    pub sha: &'a str,
+    pub timestamp: u64,
+    /// Overrides the configured destination
+    pub destination: Option<&'a str>,
+}
+
+impl UploadContext<'_> {
+    fn expand(&self, template: &str) -> String {
+        template
+            .replace("{repo}", self.repo)
+            .replace("{id}", self.id)
+            .replace("{branch}", &self.branch.replace('/', "-"))
+            .replace("{sha}", self.sha)
+            .replace("{timestamp}", &self.timestamp.to_string())
+    }
+}
+
+/// Upload files to the configured destination.
+/// Files are specified as pairs of destination name and local path.
+pub fn upload(cfg: &UploadConfig, ctx: &UploadContext, files: &[(String, PathBuf)]) -> Result<(), anyhow::Error> {
+    let destination = ctx
+        .destination
+        .or(cfg.destination.as_deref())
+        .ok_or_else(|| anyhow!("No upload destination configured."))?;
+
+    let base = ctx.expand(destination);
+    let base = base.trim_end_matches('/');
+
+    for (name, path) in files {
+        if !path.exists() {
+            return Err(anyhow!("File to upload does not exist: {}", path.display()));
+        }
+
+        let target = format!("{base}/{name}");
+
+        upload_file(cfg, path, &target).with_context(|| format!("Error uploading {} to {target}", path.display()))?;
+    }
+
+    Ok(())
+}
+
+fn upload_file(cfg: &UploadConfig, path: &Path, target: &str) -> Result<(), anyhow::Error> {
+    if target.starts_with("s3://") {
+        // Use the cloud CLIs, so their existing credential configuration is used
+        command::exec_command_timeout("aws", None, true, |c| {
+            c.args(["s3", "cp"]);
+            c.arg(path);
+            c.arg(target);
+        })
+        .and_then(check_status)
+    } else if target.starts_with("gs://") {
+        command::exec_command_timeout("gsutil", None, true, |c| {
+            c.arg("cp");
+            c.arg(path);
+            c.arg(target);
+        })
+        .and_then(check_status)
+    } else if target.starts_with("http://") || target.starts_with("https://") {
+        let mut request = ureq::put(target);
+        for (name, value) in &cfg.headers {
+            request = request.set(name, value);
+        }
+
+        let file = std::fs::File::open(path)?;
+        request.send(file)?;
+
+        Ok(())
+    } else {
+        let target = Path::new(target.strip_prefix("file://").unwrap_or(target));
+
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        std::fs::copy(path, target)?;
+
+        Ok(())
+    }
+}
+
+fn check_status(status: std::process::ExitStatus) -> Result<(), anyhow::Error> {
+    if status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "Upload command failed with error code: {}",
+            status.code().unwrap_or(-1)
+        ))
+    }
+}
@@ -0,0 +1,37 @@
+use std::path::Path;
+
+use anyhow::anyhow;
+use tracing::warn;
+
+use crate::git::Git;
+use crate::rev::GitRev;
+
+/// Warn if a local branch is behind its upstream, as of the last fetch in the source repository,
+/// or fail if it is required to be up to date
+pub fn check_behind_upstream(git: &Git, source_path: &Path, rev: &GitRev, require: bool) -> Result<(), anyhow::Error> {
+    let GitRev::Branch(branch) = rev else {
+        return Ok(());
+    };
+
+    let Some(upstream) = git.upstream(source_path, branch) else {
+        return Ok(());
+    };
+
+    let behind = git
+        .count_commits(source_path, &format!("refs/heads/{branch}..{upstream}"))
+        .unwrap_or_default();
+
+    if behind == 0 {
+        return Ok(());
+    }
+
+    let message = format!("{branch} is {behind} commit(s) behind {upstream}.");
+
+    if require {
+        return Err(anyhow!("{message} Update it, or run without --require-up-to-date."));
+    }
+
+    warn!("You are testing a branch that is not up to date: {message}");
+
+    Ok(())
+}
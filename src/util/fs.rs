@@ -55,6 +55,30 @@ pub fn open_file(path: impl AsRef<Path>) -> Result<fs::File, FileError> {
     })
 }
 
+/// Write a file by writing to a temporary file next to it and renaming it into place,
+/// so the file is never left partially written, even if the process or machine crashes
+pub fn write_atomic(path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    let path = path.as_ref();
+
+    let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+    temp_name.push(format!(".tmp{}", std::process::id()));
+    let temp_path = path.with_file_name(temp_name);
+
+    let result = (|| {
+        let mut file = fs::File::create(&temp_path)?;
+        io::Write::write_all(&mut file, contents.as_ref())?;
+        file.sync_all()?;
+
+        fs::rename(&temp_path, path)
+    })();
+
+    if result.is_err() {
+        fs::remove_file(&temp_path).ok();
+    }
+
+    result
+}
+
 pub fn create_parent_dir(path: impl AsRef<Path>) -> io::Result<()> {
     if let Some(parent_dir_path) = path.as_ref().parent() {
         fs::create_dir_all(parent_dir_path)?;
@@ -62,3 +86,152 @@ pub fn create_parent_dir(path: impl AsRef<Path>) -> io::Result<()> {
 
     Ok(())
 }
+
+/// Remove a directory and all its contents, including read-only files
+pub fn remove_dir_all(path: impl AsRef<Path>) -> io::Result<()> {
+    let path = path.as_ref();
+
+    if fs::remove_dir_all(path).is_ok() || !path.exists() {
+        return Ok(());
+    }
+
+    // Git marks object files read-only, which prevents deleting them on Windows
+    clear_readonly(path)?;
+
+    fs::remove_dir_all(path)
+}
+
+fn clear_readonly(path: &Path) -> io::Result<()> {
+    let metadata = fs::symlink_metadata(path)?;
+
+    if metadata.is_dir() {
+        for entry in fs::read_dir(path)? {
+            clear_readonly(&entry?.path())?;
+        }
+    }
+
+    let mut permissions = metadata.permissions();
+    if permissions.readonly() {
+        #[allow(clippy::permissions_set_readonly_false)]
+        permissions.set_readonly(false);
+        fs::set_permissions(path, permissions)?;
+    }
+
+    Ok(())
+}
+
+/// Recursively copy a directory, preserving symlinks where supported
+pub fn copy_dir_all(from: impl AsRef<Path>, to: impl AsRef<Path>) -> io::Result<()> {
+    let to = to.as_ref();
+
+    fs::create_dir_all(to)?;
+
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let target = to.join(entry.file_name());
+
+        if file_type.is_dir() {
+            copy_dir_all(entry.path(), &target)?;
+        } else if file_type.is_symlink() {
+            #[cfg(unix)]
+            std::os::unix::fs::symlink(fs::read_link(entry.path())?, &target)?;
+
+            #[cfg(not(unix))]
+            fs::copy(entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Recursively copy a directory, skipping files that were already copied by an earlier, interrupted call.
+/// Files are copied to a temporary name first, so a partially copied file is never mistaken for a complete one.
+pub fn copy_dir_resumable(from: impl AsRef<Path>, to: impl AsRef<Path>) -> io::Result<()> {
+    let to = to.as_ref();
+
+    fs::create_dir_all(to)?;
+
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let target = to.join(entry.file_name());
+
+        if file_type.is_dir() {
+            copy_dir_resumable(entry.path(), &target)?;
+            continue;
+        }
+
+        if fs::symlink_metadata(&target).is_ok() {
+            continue;
+        }
+
+        if file_type.is_symlink() {
+            #[cfg(unix)]
+            std::os::unix::fs::symlink(fs::read_link(entry.path())?, &target)?;
+
+            #[cfg(not(unix))]
+            fs::copy(entry.path(), &target)?;
+        } else {
+            let mut partial_name = entry.file_name();
+            partial_name.push(".partial");
+            let partial = to.join(partial_name);
+
+            fs::copy(entry.path(), &partial)?;
+            fs::rename(&partial, &target)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Set unix permission bits on a path. Does nothing on other platforms.
+pub fn set_mode(path: impl AsRef<Path>, mode: u32) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    }
+
+    #[cfg(not(unix))]
+    let _ = (path, mode);
+
+    Ok(())
+}
+
+/// Check if a file has any of the unix executable bits set. Always true on other platforms.
+pub fn is_executable(path: impl AsRef<Path>) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        fs::metadata(path).is_ok_and(|m| m.permissions().mode() & 0o111 != 0)
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        true
+    }
+}
+
+/// Get the total size of all files in a directory
+pub fn dir_size(path: impl AsRef<Path>) -> io::Result<u64> {
+    let mut size = 0;
+
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+
+        if file_type.is_dir() {
+            size += dir_size(entry.path())?;
+        } else if file_type.is_file() {
+            size += entry.metadata()?.len();
+        }
+    }
+
+    Ok(size)
+}
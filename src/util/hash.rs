@@ -1,3 +1,7 @@
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
 use sha2::{Digest, Sha256};
 
 pub fn hash_bytes(bytes: &[u8]) -> String {
@@ -7,3 +11,11 @@ pub fn hash_bytes(bytes: &[u8]) -> String {
 
     hex::encode(hash)
 }
+
+pub fn hash_file(path: impl AsRef<Path>) -> io::Result<String> {
+    let mut sha256 = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut sha256)?;
+    let hash = sha256.finalize();
+
+    Ok(hex::encode(hash))
+}
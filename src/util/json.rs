@@ -0,0 +1,107 @@
+use std::convert::Infallible;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::Context;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::util::{self, quote};
+
+/// Read a JSON file, if it exists
+pub fn read_json_file<T: DeserializeOwned>(path: impl AsRef<Path>) -> Result<Option<T>, anyhow::Error> {
+    let path = path.as_ref();
+
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let mut file = util::open_file(path).with_context(|| format!("Error opening file: {}", path.display()))?;
+
+    let mut json_str = String::new();
+    file.read_to_string(&mut json_str)
+        .with_context(|| format!("Error reading file: {}", path.display()))?;
+
+    let value = serde_json::from_str(&json_str).with_context(|| format!("Error parsing file: {}", path.display()))?;
+
+    Ok(Some(value))
+}
+
+/// Write a JSON file atomically, creating its parent directory if necessary
+pub fn write_json_file<T: Serialize>(path: impl AsRef<Path>, value: &T) -> Result<(), anyhow::Error> {
+    let path = path.as_ref();
+
+    util::create_parent_dir(path)
+        .with_context(|| format!("Error creating parent directory for: {}", path.display()))?;
+
+    let json = serde_json::to_string_pretty(value)?;
+    util::write_atomic(path, json).with_context(|| format!("Error writing file: {}", path.display()))?;
+
+    Ok(())
+}
+
+/// Where and how to write JSON output (`--json-out`)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum JsonOut {
+    /// Indented, to stdout
+    Pretty,
+    /// On a single line, to stdout
+    Compact,
+    /// Indented, to a file, leaving stdout to the command
+    File(PathBuf),
+}
+
+impl JsonOut {
+    /// Check if the output goes to stdout, which then can't be used for anything else
+    pub fn is_stdout(&self) -> bool {
+        !matches!(self, Self::File(_))
+    }
+}
+
+impl FromStr for JsonOut {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "" | "pretty" => Self::Pretty,
+            "compact" => Self::Compact,
+            path => Self::File(PathBuf::from(path)),
+        })
+    }
+}
+
+/// Write JSON output.
+/// Characters that can't be seen or that reorder text are escaped, so values read the same as they are.
+pub fn write_json_output<T: Serialize>(value: &T, out: &JsonOut) -> Result<(), anyhow::Error> {
+    let json = match out {
+        JsonOut::Compact => serde_json::to_string(value)?,
+        JsonOut::Pretty | JsonOut::File(_) => serde_json::to_string_pretty(value)?,
+    };
+
+    // Control characters in strings are already escaped, and the rest are never part of the JSON syntax itself
+    let json: String = json
+        .chars()
+        .map(|c| {
+            if !c.is_ascii() && quote::needs_escape(c) {
+                format!("\\u{:04x}", c as u32)
+            } else {
+                c.to_string()
+            }
+        })
+        .collect();
+
+    match out {
+        JsonOut::File(path) => {
+            util::create_parent_dir(path)
+                .with_context(|| format!("Error creating parent directory for: {}", path.display()))?;
+
+            std::fs::write(path, json).with_context(|| format!("Error writing json output: {}", path.display()))?;
+        }
+        // Compact output is one line per document, for log pipelines
+        JsonOut::Compact => writeln!(std::io::stdout().lock(), "{json}")?,
+        JsonOut::Pretty => std::io::stdout().lock().write_all(json.as_bytes())?,
+    }
+
+    Ok(())
+}
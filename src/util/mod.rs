@@ -1,7 +1,12 @@
 mod fs;
 pub mod hash;
+pub mod json;
 mod path;
 pub mod pid;
+pub mod process;
+pub mod quote;
+pub mod record;
+pub mod time;
 
 pub use self::fs::*;
 pub use self::path::*;
@@ -27,3 +27,24 @@ pub fn normalize_path<P: AsRef<Path>>(path: P) -> PathBuf {
 
     new_path
 }
+
+/// Find an executable in PATH
+pub fn find_in_path(name: &str) -> Option<PathBuf> {
+    let paths = env::var_os("PATH")?;
+
+    env::split_paths(&paths).find_map(|dir| {
+        let candidate = dir.join(name);
+        if candidate.is_file() {
+            return Some(candidate);
+        }
+
+        if cfg!(windows) {
+            let candidate = dir.join(format!("{name}.exe"));
+            if candidate.is_file() {
+                return Some(candidate);
+            }
+        }
+
+        None
+    })
+}
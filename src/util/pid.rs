@@ -1,70 +1,262 @@
 use std::fs;
-use std::io::{Read, Write};
+use std::io::{self, Read, Write};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use sysinfo::{Pid, ProcessRefreshKind};
 use tracing::{debug, error};
 
 use crate::util;
 
+/// How old a lock file that doesn't contain a PID must be before it is considered stale.
+/// Until then, it may be in the middle of being written.
+const UNREADABLE_STALE_AGE: Duration = Duration::from_secs(10);
+
 pub struct PidLock {
     path: PathBuf,
+    /// PID written to the lock file
+    pid: String,
 }
 
 impl PidLock {
     pub fn acquire(path: impl AsRef<Path>) -> Option<Self> {
+        Self::acquire_as(path, &std::process::id().to_string())
+    }
+
+    fn acquire_as(path: impl AsRef<Path>, pid: &str) -> Option<Self> {
         let path = util::normalize_path(path);
         debug!("Trying to acquire PID lock at {}", path.display());
 
-        if path.exists() {
-            debug!("PID file found at {:?}", path);
-            if let Ok(mut file) = fs::File::open(&path) {
-                let mut pid = String::new();
-
-                // Try to read content of PID-lock file into a string.
-                if let Err(err) = file.read_to_string(&mut pid) {
-                    error!("Could not read PID-lock file: {}", err.to_string());
-                    return None;
-                }
-
-                if let Ok(pid) = pid.parse::<Pid>() {
-                    debug!("File contains PID {}.", pid);
-                    if process_exists(pid) {
-                        // Process already exists, cannot get lock.
-                        debug!("Process with PID {} exists, cannot get lock.", pid);
-                        return None;
-                    }
-                }
+        // Create the PID file with its content in one step, so two processes can't both get the lock
+        match create_with_content(&path, pid) {
+            Ok(()) => {
+                return Some(Self {
+                    path,
+                    pid: pid.to_owned(),
+                })
+            }
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {}
+            Err(err) => {
+                error!("Could not create PID-lock file: {err}");
+                return None;
             }
         }
 
-        // Try to create a PID file...
-        if let Ok(mut file) = fs::File::create(&path) {
-            // Write our PID to the newly created file.
-            if let Err(err) = file.write(format!("{}", std::process::id()).as_bytes()) {
-                error!("Could not write PID-lock file: {}", err.to_string());
-                return None;
+        debug!("PID file found at {:?}", path);
+        if !is_stale(&path) {
+            return None;
+        }
+
+        // Only one process at a time may take over a stale lock,
+        // as the others would otherwise replace the lock it has just taken.
+        let guard_path = suffixed(&path, ".takeover");
+
+        if create_with_content(&guard_path, pid).is_err() {
+            // Whoever created it finishes in an instant, unless it crashed while doing so
+            if modified_age(&guard_path).is_some_and(|age| age > UNREADABLE_STALE_AGE) {
+                fs::remove_file(&guard_path).ok();
             }
-        } else {
-            error!("Could not create PID-lock file!");
+
             return None;
-        };
+        }
+
+        let taken = is_stale(&path) && replace_with_content(&path, pid).is_ok();
+        fs::remove_file(&guard_path).ok();
+
+        if !taken {
+            return None;
+        }
+
+        Some(Self {
+            path,
+            pid: pid.to_owned(),
+        })
+    }
+
+    /// Get the PID of the process currently holding the lock, without acquiring it
+    pub fn holder(path: impl AsRef<Path>) -> Option<Pid> {
+        let pid = fs::read_to_string(path).ok()?.parse::<Pid>().ok()?;
 
-        Some(Self { path })
+        if process_exists(pid) {
+            Some(pid)
+        } else {
+            None
+        }
+    }
+
+    /// Follow the lock file after the directory containing it has been moved
+    pub fn moved_to(&mut self, path: impl AsRef<Path>) {
+        self.path = util::normalize_path(path);
     }
 }
 
 impl Drop for PidLock {
     fn drop(&mut self) {
         debug!("Dropping PID-lock at {}", self.path.display());
+
+        // Never remove a lock another process has taken over
+        if fs::read_to_string(&self.path).is_ok_and(|pid| pid != self.pid) {
+            error!("PID-lock at {} was taken over by another process.", self.path.display());
+            return;
+        }
+
         fs::remove_file(&self.path).expect("Could not remove PID-lock file!");
     }
 }
 
-fn process_exists(pid: Pid) -> bool {
+/// Check if a lock file is stale, because the process in it no longer exists.
+/// Files without a PID are only stale once they are old enough that they can't be in the middle of being written.
+fn is_stale(path: &Path) -> bool {
+    let mut holder = String::new();
+
+    // Try to read content of PID-lock file into a string.
+    match fs::File::open(path).and_then(|mut file| file.read_to_string(&mut holder)) {
+        Ok(_) => {}
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return true,
+        Err(err) => {
+            error!("Could not read PID-lock file: {}", err.to_string());
+            return false;
+        }
+    }
+
+    match holder.parse::<Pid>() {
+        Ok(holder) => {
+            debug!("File contains PID {}.", holder);
+            if process_exists(holder) {
+                // Process already exists, cannot get lock.
+                debug!("Process with PID {} exists, cannot get lock.", holder);
+                return false;
+            }
+
+            true
+        }
+        Err(_) => modified_age(path).is_some_and(|age| age > UNREADABLE_STALE_AGE),
+    }
+}
+
+fn modified_age(path: &Path) -> Option<Duration> {
+    fs::metadata(path).ok()?.modified().ok()?.elapsed().ok()
+}
+
+fn suffixed(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// Create a file with content, failing if it already exists
+fn create_with_content(path: &Path, content: &str) -> io::Result<()> {
+    let temp_path = suffixed(path, &format!(".{content}.tmp"));
+
+    let result = fs::write(&temp_path, content).and_then(|_| fs::hard_link(&temp_path, path));
+    fs::remove_file(&temp_path).ok();
+
+    result
+}
+
+/// Replace a file with content in one step
+fn replace_with_content(path: &Path, content: &str) -> io::Result<()> {
+    let temp_path = suffixed(path, &format!(".{content}.tmp"));
+
+    let result = fs::write(&temp_path, content).and_then(|_| fs::rename(&temp_path, path));
+
+    match result {
+        // In a shared work root, the file may belong to another user, and can't be replaced in a sticky directory.
+        // It can still be overwritten, and readers treat it as held while it is empty.
+        Err(err) if err.kind() == io::ErrorKind::PermissionDenied => {
+            fs::remove_file(&temp_path).ok();
+            fs::File::create(path).and_then(|mut file| file.write_all(content.as_bytes()))
+        }
+        Err(err) => {
+            fs::remove_file(&temp_path).ok();
+            Err(err)
+        }
+        Ok(()) => Ok(()),
+    }
+}
+
+pub fn process_exists(pid: Pid) -> bool {
     use sysinfo::{RefreshKind, System, SystemExt};
 
     let sys = System::new_with_specifics(RefreshKind::new().with_processes(ProcessRefreshKind::new()));
 
     sys.process(pid).is_some()
 }
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+    use std::sync::{Arc, Barrier};
+    use std::time::SystemTime;
+
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn only_one_process_takes_over_stale_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("work.pid");
+
+        let mut exited = Command::new("true").spawn().unwrap();
+        exited.wait().unwrap();
+        let stale_pid = exited.id().to_string();
+
+        // Stand-ins for the processes competing for the lock, which must be alive to hold it
+        let mut contenders: Vec<_> = (0..8)
+            .map(|_| Command::new("sleep").arg("30").spawn().unwrap())
+            .collect();
+        let pids: Vec<String> = contenders.iter().map(|c| c.id().to_string()).collect();
+
+        for _ in 0..20 {
+            fs::write(&path, &stale_pid).unwrap();
+
+            let barrier = Arc::new(Barrier::new(pids.len()));
+            let locks: Vec<Option<PidLock>> = pids
+                .iter()
+                .map(|pid| {
+                    let (path, pid, barrier) = (path.clone(), pid.clone(), barrier.clone());
+
+                    std::thread::spawn(move || {
+                        barrier.wait();
+                        PidLock::acquire_as(&path, &pid)
+                    })
+                })
+                .collect::<Vec<_>>()
+                .into_iter()
+                .map(|thread| thread.join().unwrap())
+                .collect();
+
+            let holders: Vec<&PidLock> = locks.iter().flatten().collect();
+            assert_eq!(holders.len(), 1);
+            assert_eq!(fs::read_to_string(&path).unwrap(), holders[0].pid);
+        }
+
+        for contender in &mut contenders {
+            contender.kill().ok();
+            contender.wait().ok();
+        }
+    }
+
+    #[test]
+    fn lock_without_pid_is_held_until_old() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("work.pid");
+        let pid = std::process::id().to_string();
+
+        fs::write(&path, "").unwrap();
+        assert!(PidLock::acquire_as(&path, &pid).is_none());
+
+        fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(60))
+            .unwrap();
+
+        let lock = PidLock::acquire_as(&path, &pid).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), pid);
+
+        drop(lock);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,362 @@
+use std::collections::BTreeMap;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use serde_derive::Serialize;
+use sysinfo::{Pid, PidExt, ProcessExt, ProcessRefreshKind, ProcessStatus, RefreshKind, Signal, System, SystemExt};
+
+use crate::util;
+
+/// Resource usage of a process and all its descendants
+#[derive(Debug, Serialize)]
+pub struct ProcessUsage {
+    pub pid: u32,
+    pub processes: usize,
+    pub cpu_usage: f32,
+    pub memory: u64,
+    pub run_time: u64,
+}
+
+/// Get resource usage of a process tree
+pub fn process_usage(pid: u32) -> Option<ProcessUsage> {
+    let mut sys = System::new_with_specifics(RefreshKind::new().with_processes(ProcessRefreshKind::new().with_cpu()));
+
+    // CPU usage is computed from the difference between two refreshes
+    std::thread::sleep(System::MINIMUM_CPU_UPDATE_INTERVAL);
+    sys.refresh_processes_specifics(ProcessRefreshKind::new().with_cpu());
+
+    let root = sys.process(Pid::from_u32(pid))?;
+
+    let mut usage = ProcessUsage {
+        pid,
+        processes: 0,
+        cpu_usage: 0.0,
+        memory: 0,
+        run_time: root.run_time(),
+    };
+
+    for pid in descendants(&sys, Pid::from_u32(pid)) {
+        if let Some(process) = sys.process(pid) {
+            usage.processes += 1;
+            usage.cpu_usage += process.cpu_usage();
+            usage.memory += process.memory();
+        }
+    }
+
+    Some(usage)
+}
+
+/// How often descendants are listed, as it is relatively expensive
+const OBSERVE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A leftover process that was killed
+pub struct ReapedProcess {
+    pub pid: u32,
+    pub name: String,
+}
+
+/// Keeps track of the descendants of a process, so any that are left running after it exits can be killed.
+/// Descendants that detach from the tree between observations are still found on unix,
+/// as long as they stay in the process group or session the process leads.
+pub struct DescendantTracker {
+    root: Pid,
+    /// Descendants seen so far, with their start times to guard against PID reuse
+    seen: BTreeMap<Pid, u64>,
+    last_observed: Option<Instant>,
+}
+
+impl DescendantTracker {
+    /// Start tracking the descendants of a process.
+    /// This should be called before spawning it, so no orphans are missed.
+    pub fn new() -> Self {
+        become_subreaper();
+
+        Self {
+            root: Pid::from_u32(0),
+            seen: BTreeMap::new(),
+            last_observed: None,
+        }
+    }
+
+    /// Set the process to track the descendants of
+    pub fn set_root(&mut self, pid: u32) {
+        self.root = Pid::from_u32(pid);
+    }
+
+    /// Record the current descendants, unless they were recorded very recently
+    pub fn observe(&mut self) {
+        if self.last_observed.is_some_and(|t| t.elapsed() < OBSERVE_INTERVAL) {
+            return;
+        }
+
+        self.last_observed = Some(Instant::now());
+
+        let sys = System::new_with_specifics(RefreshKind::new().with_processes(ProcessRefreshKind::new()));
+
+        for pid in descendants(&sys, self.root).into_iter().skip(1) {
+            if let Some(process) = sys.process(pid) {
+                self.seen.insert(pid, process.start_time());
+            }
+        }
+    }
+
+    /// Kill all descendants that are still running
+    pub fn kill_remaining(&mut self) -> Vec<ReapedProcess> {
+        let sys = System::new_with_specifics(RefreshKind::new().with_processes(ProcessRefreshKind::new()));
+
+        let mut pids: Vec<Pid> = self
+            .seen
+            .iter()
+            .filter(|(pid, start_time)| sys.process(**pid).is_some_and(|p| p.start_time() == **start_time))
+            .map(|(pid, _)| *pid)
+            .collect();
+
+        // Other processes of the command, which may have been orphaned before they could be observed
+        #[cfg(unix)]
+        if self.root.as_u32() != 0 {
+            pids.extend(
+                sys.processes()
+                    .keys()
+                    .filter(|pid| **pid != self.root && in_group_or_session(**pid, self.root)),
+            );
+        }
+
+        pids.sort();
+        pids.dedup();
+
+        let reaped = pids
+            .iter()
+            .filter_map(|pid| sys.process(*pid))
+            .filter(|p| p.status() != ProcessStatus::Zombie && p.kill())
+            .map(|p| ReapedProcess {
+                pid: p.pid().as_u32(),
+                name: p.name().to_owned(),
+            })
+            .collect();
+
+        wait_for_orphans(&pids);
+
+        reaped
+    }
+}
+
+/// Check if a process is in the process group or session led by another
+#[cfg(unix)]
+fn in_group_or_session(pid: Pid, leader: Pid) -> bool {
+    let pid = pid.as_u32() as libc::pid_t;
+    let leader = leader.as_u32() as libc::pid_t;
+
+    unsafe { libc::getpgid(pid) == leader || libc::getsid(pid) == leader }
+}
+
+/// Wait for killed orphans that were reparented to this process, so they don't linger as zombies
+#[cfg(target_os = "linux")]
+fn wait_for_orphans(pids: &[Pid]) {
+    let deadline = Instant::now() + Duration::from_secs(1);
+
+    for pid in pids {
+        let pid = pid.as_u32() as libc::pid_t;
+
+        loop {
+            let result = unsafe { libc::waitpid(pid, std::ptr::null_mut(), libc::WNOHANG) };
+
+            // Exited, or not a child of this process
+            if result != 0 || Instant::now() >= deadline {
+                break;
+            }
+
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn wait_for_orphans(_pids: &[Pid]) {}
+
+/// Make orphaned descendants of this process get reparented to it rather than to init, so killed ones can be waited for
+#[cfg(target_os = "linux")]
+fn become_subreaper() {
+    unsafe {
+        libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1, 0, 0, 0);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn become_subreaper() {}
+
+/// Get the name of a running process
+pub fn process_name(pid: u32) -> Option<String> {
+    let mut sys = System::new();
+    sys.refresh_process_specifics(Pid::from_u32(pid), ProcessRefreshKind::new());
+
+    sys.process(Pid::from_u32(pid)).map(|p| p.name().to_owned())
+}
+
+/// Ask a process and all its descendants to terminate, killing any still running after the grace period
+pub fn terminate_tree(pid: u32, grace_period: Duration) {
+    let sys = System::new_with_specifics(RefreshKind::new().with_processes(ProcessRefreshKind::new()));
+    let pids = descendants(&sys, Pid::from_u32(pid));
+
+    for pid in &pids {
+        if let Some(process) = sys.process(*pid) {
+            // Platforms without SIGTERM go straight to killing
+            if process.kill_with(Signal::Term).is_none() {
+                process.kill();
+            }
+        }
+    }
+
+    let deadline = Instant::now() + grace_period;
+
+    loop {
+        let sys = System::new_with_specifics(RefreshKind::new().with_processes(ProcessRefreshKind::new()));
+
+        let remaining: Vec<_> = pids
+            .iter()
+            .filter_map(|pid| sys.process(*pid))
+            .filter(|p| p.status() != ProcessStatus::Zombie)
+            .collect();
+
+        if remaining.is_empty() {
+            return;
+        }
+
+        if Instant::now() >= deadline {
+            for process in remaining {
+                process.kill();
+            }
+
+            return;
+        }
+
+        std::thread::sleep(Duration::from_millis(100));
+    }
+}
+
+/// Get the name of the user running this process
+#[cfg(unix)]
+pub fn current_user() -> Option<String> {
+    let uid = current_uid();
+    let mut passwd = std::mem::MaybeUninit::<libc::passwd>::uninit();
+    let mut buf: Vec<libc::c_char> = vec![0; 1024];
+    let mut result = std::ptr::null_mut();
+
+    loop {
+        let err = unsafe { libc::getpwuid_r(uid, passwd.as_mut_ptr(), buf.as_mut_ptr(), buf.len(), &mut result) };
+
+        // The buffer is too small for the entry
+        if err == libc::ERANGE && buf.len() < 1024 * 1024 {
+            buf.resize(buf.len() * 2, 0);
+            continue;
+        }
+
+        if err != 0 || result.is_null() {
+            return None;
+        }
+
+        break;
+    }
+
+    let name = unsafe { std::ffi::CStr::from_ptr((*result).pw_name) };
+
+    Some(name.to_string_lossy().into_owned())
+}
+
+/// Get the name of the user running this process
+#[cfg(not(unix))]
+pub fn current_user() -> Option<String> {
+    use sysinfo::UserExt;
+
+    let mut sys = System::new_with_specifics(RefreshKind::new().with_processes(ProcessRefreshKind::new().with_user()));
+    sys.refresh_users_list();
+
+    let user_id = sys.process(Pid::from_u32(std::process::id()))?.user_id()?;
+
+    sys.get_user_by_id(user_id).map(|u| u.name().to_owned())
+}
+
+/// Get the user id of this process
+#[cfg(unix)]
+pub fn current_uid() -> u32 {
+    unsafe { libc::getuid() }
+}
+
+/// Capture a stack sample of a running process, if a supported tool is available
+pub fn stack_sample(pid: u32) -> Option<String> {
+    let pid = pid.to_string();
+
+    let (program, args): (&str, Vec<&str>) = if cfg!(target_os = "macos") {
+        ("sample", vec![&pid, "1"])
+    } else if util::find_in_path("eu-stack").is_some() {
+        ("eu-stack", vec!["-p", &pid])
+    } else if util::find_in_path("gdb").is_some() {
+        ("gdb", vec!["-batch", "-ex", "thread apply all bt", "-p", &pid])
+    } else {
+        return None;
+    };
+
+    let output = Command::new(program).args(args).stdin(Stdio::null()).output().ok()?;
+
+    Some(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Get a process and all its descendants
+fn descendants(sys: &System, pid: Pid) -> Vec<Pid> {
+    let mut pids = vec![pid];
+    let mut i = 0;
+
+    while i < pids.len() {
+        let parent = pids[i];
+
+        pids.extend(
+            sys.processes()
+                .iter()
+                .filter(|(_, p)| p.parent() == Some(parent))
+                .map(|(pid, _)| *pid),
+        );
+
+        i += 1;
+    }
+
+    pids
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kills_only_processes_left_by_command() {
+        use std::os::unix::process::CommandExt;
+
+        let temp = tempfile::tempdir().unwrap();
+        let mut unrelated = Command::new("sleep").arg("30").spawn().unwrap();
+
+        let mut tracker = DescendantTracker::new();
+
+        // Leaves a daemon behind, which is orphaned before it can be observed
+        let mut command = Command::new("sh")
+            .args(["-c", "sleep 30 & echo $! > daemon.pid"])
+            .current_dir(temp.path())
+            .process_group(0)
+            .spawn()
+            .unwrap();
+        tracker.set_root(command.id());
+        command.wait().unwrap();
+
+        let daemon: u32 = std::fs::read_to_string(temp.path().join("daemon.pid"))
+            .unwrap()
+            .trim()
+            .parse()
+            .unwrap();
+
+        let reaped = tracker.kill_remaining();
+        let still_running = unrelated.try_wait().unwrap().is_none();
+
+        unrelated.kill().ok();
+        unrelated.wait().ok();
+
+        assert_eq!(reaped.iter().map(|p| p.pid).collect::<Vec<_>>(), [daemon]);
+        assert!(still_running);
+    }
+}
@@ -0,0 +1,129 @@
+use std::borrow::Cow;
+use std::fmt::Write;
+use std::path::Path;
+
+/// Quote a path for display, so it can be copied into the shell as it is
+pub fn path(path: &Path) -> Cow<'_, str> {
+    match path.to_string_lossy() {
+        Cow::Borrowed(s) => arg(s),
+        Cow::Owned(s) => Cow::Owned(arg(&s).into_owned()),
+    }
+}
+
+/// Quote a command line for display
+pub fn command(args: &[String]) -> String {
+    args.iter().map(|a| arg(a)).collect::<Vec<_>>().join(" ")
+}
+
+/// Quote an argument for display, for the shell of the platform
+pub fn arg(arg: &str) -> Cow<'_, str> {
+    if cfg!(windows) {
+        powershell(arg)
+    } else {
+        sh(arg)
+    }
+}
+
+/// Quote an argument for POSIX shells, if needed.
+/// Characters that can't be seen or that change the direction of the text are escaped with ANSI-C quoting.
+pub fn sh(arg: &str) -> Cow<'_, str> {
+    if !needs_quoting(arg) {
+        return Cow::Borrowed(arg);
+    }
+
+    if !arg.chars().any(needs_escape) {
+        return Cow::Owned(format!("'{}'", arg.replace('\'', r"'\''")));
+    }
+
+    let mut quoted = String::from("$'");
+
+    for c in arg.chars() {
+        match c {
+            '\'' => quoted.push_str(r"\'"),
+            '\\' => quoted.push_str(r"\\"),
+            '\n' => quoted.push_str(r"\n"),
+            '\t' => quoted.push_str(r"\t"),
+            c if needs_escape(c) => escape_unicode(&mut quoted, c, r"\u", ""),
+            c => quoted.push(c),
+        }
+    }
+
+    quoted.push('\'');
+
+    Cow::Owned(quoted)
+}
+
+/// Quote an argument for PowerShell, if needed.
+/// Characters that can't be seen or that change the direction of the text are escaped in a double-quoted string.
+pub fn powershell(arg: &str) -> Cow<'_, str> {
+    if !needs_quoting(arg) {
+        return Cow::Borrowed(arg);
+    }
+
+    if !arg.chars().any(needs_escape) {
+        return Cow::Owned(format!("'{}'", arg.replace('\'', "''")));
+    }
+
+    let mut quoted = String::from("\"");
+
+    for c in arg.chars() {
+        match c {
+            '"' | '`' | '$' => {
+                quoted.push('`');
+                quoted.push(c);
+            }
+            '\n' => quoted.push_str("`n"),
+            '\t' => quoted.push_str("`t"),
+            c if needs_escape(c) => escape_unicode(&mut quoted, c, "`u{", "}"),
+            c => quoted.push(c),
+        }
+    }
+
+    quoted.push('"');
+
+    Cow::Owned(quoted)
+}
+
+fn needs_quoting(arg: &str) -> bool {
+    arg.is_empty()
+        || arg
+            .chars()
+            .any(|c| !(c.is_alphanumeric() || "-_./:@%+=,".contains(c)) || needs_escape(c))
+}
+
+/// Check if a character is invisible or can reorder the text around it (ex. right-to-left overrides)
+pub fn needs_escape(c: char) -> bool {
+    c.is_control()
+        || matches!(
+            c,
+            '\u{200b}'..='\u{200f}' | '\u{202a}'..='\u{202e}' | '\u{2066}'..='\u{2069}' | '\u{061c}' | '\u{feff}'
+        )
+}
+
+fn escape_unicode(s: &mut String, c: char, prefix: &str, suffix: &str) {
+    // Writing to a string can't fail
+    let _ = write!(s, "{prefix}{:04x}{suffix}", c as u32);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_for_sh() {
+        assert_eq!(sh("/home/user/repo"), "/home/user/repo");
+        assert_eq!(sh("日本語/リポジトリ"), "日本語/リポジトリ");
+        assert_eq!(sh(""), "''");
+        assert_eq!(sh("my repo"), "'my repo'");
+        assert_eq!(sh("it's"), r"'it'\''s'");
+        assert_eq!(sh("a\u{202e}b'c"), r"$'a\u202eb\'c'");
+    }
+
+    #[test]
+    fn quotes_for_powershell() {
+        assert_eq!(powershell("C:/repo"), "C:/repo");
+        assert_eq!(powershell("my repo"), "'my repo'");
+        assert_eq!(powershell("it's"), "'it''s'");
+        assert_eq!(powershell("a\u{202e}$b"), "\"a`u{202e}`$b\"");
+    }
+}
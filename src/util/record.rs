@@ -0,0 +1,205 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Context};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use tracing::warn;
+
+use crate::util;
+
+/// Field holding the format version of a record file
+const FORMAT_KEY: &str = "fersk_format";
+/// Field holding the checksum of the rest of a record file
+const CHECKSUM_KEY: &str = "crc32";
+
+/// Bookkeeping kept in the work root (metadata, run history, ...).
+/// Record files are JSON objects with the format version and a checksum added to them,
+/// written atomically, and migrated forward when read by a newer version of fersk.
+pub trait Record: Serialize + DeserializeOwned {
+    /// Current format version. Bump it and handle the previous version in `migrate`
+    /// when a change can't be handled by defaults for missing fields.
+    const VERSION: u32;
+
+    /// Migrate data from a format version to the next one.
+    /// Version 0 is a file written before record files had a version.
+    fn migrate(_from: u32, data: Value) -> Result<Value, anyhow::Error> {
+        Ok(data)
+    }
+}
+
+/// Load a record file, if it exists.
+/// Files that are damaged (ex. by a crash of an older version) are set aside with a warning, and treated as missing.
+pub fn load<T: Record>(path: impl AsRef<Path>) -> Result<Option<T>, anyhow::Error> {
+    let path = path.as_ref();
+
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(anyhow::Error::new(err).context(format!("Error reading file: {}", path.display()))),
+    };
+
+    let (version, mut data) = match parse(&content) {
+        Ok(parsed) => parsed,
+        Err(reason) => {
+            set_aside(path, &reason);
+            return Ok(None);
+        }
+    };
+
+    if version > T::VERSION {
+        return Err(anyhow!(
+            "{} was written by a newer version of fersk (format {version}, supported up to {}).",
+            path.display(),
+            T::VERSION
+        ));
+    }
+
+    for from in version..T::VERSION {
+        data = T::migrate(from, data).with_context(|| format!("Error migrating file: {}", path.display()))?;
+    }
+
+    let value = serde_json::from_value(data).with_context(|| format!("Error parsing file: {}", path.display()))?;
+
+    Ok(Some(value))
+}
+
+/// Save a record file atomically, creating its parent directory if necessary
+pub fn save<T: Record>(path: impl AsRef<Path>, value: &T) -> Result<(), anyhow::Error> {
+    let path = path.as_ref();
+
+    let Value::Object(mut object) = serde_json::to_value(value)? else {
+        return Err(anyhow!("Record is not an object: {}", path.display()));
+    };
+
+    let checksum = checksum(&Value::Object(object.clone()));
+    object.insert(FORMAT_KEY.to_owned(), Value::from(T::VERSION));
+    object.insert(CHECKSUM_KEY.to_owned(), Value::from(checksum));
+
+    util::create_parent_dir(path)
+        .with_context(|| format!("Error creating parent directory for: {}", path.display()))?;
+
+    let json = serde_json::to_string_pretty(&object)?;
+    util::write_atomic(path, json).with_context(|| format!("Error writing file: {}", path.display()))?;
+
+    Ok(())
+}
+
+/// Get the format version and data of a record file, verifying its checksum
+fn parse(content: &str) -> Result<(u32, Value), String> {
+    let value: Value = serde_json::from_str(content).map_err(|err| format!("invalid JSON ({err})"))?;
+
+    let Value::Object(mut object) = value else {
+        return Err("not a JSON object".to_owned());
+    };
+
+    let Some(version) = object.remove(FORMAT_KEY) else {
+        return Ok((0, Value::Object(object)));
+    };
+
+    let version = version
+        .as_u64()
+        .and_then(|v| u32::try_from(v).ok())
+        .ok_or_else(|| "invalid format version".to_owned())?;
+
+    let expected = object.remove(CHECKSUM_KEY);
+    let data = Value::Object(object);
+
+    if expected.as_ref().and_then(Value::as_str) != Some(checksum(&data).as_str()) {
+        return Err("checksum mismatch".to_owned());
+    }
+
+    Ok((version, data))
+}
+
+/// Checksum of the data of a record, independent of formatting
+fn checksum(data: &Value) -> String {
+    format!("{:08x}", crc32fast::hash(data.to_string().as_bytes()))
+}
+
+/// Move a damaged record file out of the way, keeping it for inspection
+fn set_aside(path: &Path, reason: &str) {
+    let mut corrupt_name = path.file_name().unwrap_or_default().to_os_string();
+    corrupt_name.push(".corrupt");
+    let corrupt_path = path.with_file_name(corrupt_name);
+
+    warn!(
+        "Ignoring damaged file {} ({reason}). It was moved to {}.",
+        path.display(),
+        corrupt_path.display()
+    );
+
+    std::fs::rename(path, corrupt_path).ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_derive::{Deserialize, Serialize};
+
+    use super::*;
+
+    #[derive(Debug, Default, Deserialize, PartialEq, Serialize)]
+    struct Example {
+        name: String,
+        count: u32,
+    }
+
+    impl Record for Example {
+        const VERSION: u32 = 2;
+
+        /// Version 1 called the count "total"
+        fn migrate(from: u32, mut data: Value) -> Result<Value, anyhow::Error> {
+            if from == 1 {
+                if let Some(total) = data.as_object_mut().and_then(|o| o.remove("total")) {
+                    data["count"] = total;
+                }
+            }
+
+            Ok(data)
+        }
+    }
+
+    #[test]
+    fn round_trips_and_migrates() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("example.json");
+
+        let example = Example {
+            name: "a".to_owned(),
+            count: 3,
+        };
+        save(&path, &example).unwrap();
+        assert_eq!(load::<Example>(&path).unwrap(), Some(example));
+
+        // Files without a header are from before versioning
+        std::fs::write(&path, r#"{ "name": "legacy", "count": 1 }"#).unwrap();
+        assert_eq!(load::<Example>(&path).unwrap().unwrap().name, "legacy");
+
+        let data = serde_json::json!({ "name": "old", "total": 7 });
+        let mut object = data.as_object().unwrap().clone();
+        object.insert(FORMAT_KEY.to_owned(), Value::from(1));
+        object.insert(CHECKSUM_KEY.to_owned(), Value::from(checksum(&data)));
+        std::fs::write(&path, Value::Object(object).to_string()).unwrap();
+        assert_eq!(load::<Example>(&path).unwrap().unwrap().count, 7);
+    }
+
+    #[test]
+    fn sets_aside_damaged_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("example.json");
+
+        save(&path, &Example::default()).unwrap();
+
+        let tampered = std::fs::read_to_string(&path)
+            .unwrap()
+            .replace("\"count\": 0", "\"count\": 9");
+        std::fs::write(&path, tampered).unwrap();
+
+        assert_eq!(load::<Example>(&path).unwrap(), None);
+        assert!(!path.exists());
+        assert!(dir.path().join("example.json.corrupt").exists());
+
+        std::fs::write(&path, r#"{ "name": "trunc"#).unwrap();
+        assert_eq!(load::<Example>(&path).unwrap(), None);
+    }
+}
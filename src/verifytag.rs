@@ -0,0 +1,302 @@
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use clap::Args;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::config::expand;
+use crate::config::Config;
+use crate::git::Git;
+use crate::rev::GitRev;
+use crate::run::{self, RunArgs};
+use crate::schema::SCHEMA_VERSION;
+use crate::util::{self, json::JsonOut, quote};
+
+/// How releases are verified by `fersk verify-tag`
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct ReleaseConfig {
+    /// Command building the release
+    pub command: Vec<String>,
+    /// SSH allowed signers file. The one from the git config is used if not specified.
+    #[serde(deserialize_with = "expand::deserialize")]
+    pub allowed_signers: Option<PathBuf>,
+    /// GnuPG home directory with the keyring of trusted keys. The default one is used if not specified.
+    #[serde(deserialize_with = "expand::deserialize")]
+    pub gnupg_home: Option<PathBuf>,
+    /// Don't build unsigned tags. Tags with a bad signature are never built.
+    pub require_signature: bool,
+}
+
+impl Default for ReleaseConfig {
+    fn default() -> Self {
+        Self {
+            command: Vec::new(),
+            allowed_signers: None,
+            gnupg_home: None,
+            require_signature: true,
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct VerifyTagArgs {
+    #[clap(help = "Tag to verify")]
+    tag: String,
+    #[clap(long = "path", help = "Specify repository path")]
+    path: Option<PathBuf>,
+    #[clap(
+        long = "json-out",
+        value_name = "FORMAT",
+        value_parser = JsonOut::from_str,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "pretty",
+        help = "Output json information (pretty, compact, or a file path to write it to)"
+    )]
+    json_out: Option<JsonOut>,
+    #[clap(last = true, help = "Release command, instead of the one in the config")]
+    args: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum SignatureStatus {
+    Good,
+    Bad,
+    Unsigned,
+}
+
+#[derive(Debug, Serialize)]
+struct SignatureReport {
+    status: SignatureStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    signer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct BuildReport {
+    ran: bool,
+    success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct JsonOutput {
+    schema_version: u32,
+    source_repository_path: PathBuf,
+    tag: String,
+    commit: String,
+    signature: SignatureReport,
+    build: BuildReport,
+}
+
+/// Verify the signature of a tag, and build the tagged commit with the release command in a work directory
+pub fn verify_tag(cfg: &Config, args: VerifyTagArgs) -> Result<(), anyhow::Error> {
+    let VerifyTagArgs {
+        tag,
+        path,
+        json_out,
+        args,
+    } = args;
+
+    let quiet = json_out.as_ref().is_some_and(JsonOut::is_stdout);
+
+    let git = Git {
+        silent: quiet,
+        read_only: true,
+        ..Default::default()
+    };
+
+    let source_path = run::resolve_source_repository(&git, path)?;
+
+    let rev = GitRev::Tag(tag.clone());
+    let commit = git
+        .rev_parse(&source_path, &rev.source_ref())
+        .map_err(|_| rev.not_found_error(&git, &source_path))?;
+
+    let command = if args.is_empty() {
+        cfg.release.command.clone()
+    } else {
+        args
+    };
+
+    if command.is_empty() {
+        return Err(anyhow!(
+            "No release command specified. Configure one in [release], or specify it after --."
+        ));
+    }
+
+    let signature = verify_signature(&git, &source_path, &tag, &cfg.release)?;
+
+    // The tag must still be the one whose signature was verified
+    if git.rev_parse(&source_path, &rev.source_ref()).ok().as_ref() != Some(&commit) {
+        return Err(anyhow!("{tag} was moved while verifying its signature."));
+    }
+
+    if !quiet {
+        match (&signature.status, &signature.signer) {
+            (SignatureStatus::Good, Some(signer)) => eprintln!("Good signature on {tag} by {signer}."),
+            (SignatureStatus::Good, None) => eprintln!("Good signature on {tag}."),
+            (SignatureStatus::Bad, _) => eprintln!("Bad signature on {tag}."),
+            (SignatureStatus::Unsigned, _) => eprintln!("{tag} is not signed."),
+        }
+    }
+
+    let allowed = match signature.status {
+        SignatureStatus::Good => true,
+        SignatureStatus::Unsigned => !cfg.release.require_signature,
+        SignatureStatus::Bad => false,
+    };
+
+    let result = if allowed {
+        if !quiet {
+            eprintln!("Building {tag} ({commit}): {}", quote::command(&command));
+        }
+
+        // Built by commit, so the tag can't be moved to an unverified commit in the meantime
+        Some(run::run(
+            cfg,
+            RunArgs::for_rev(source_path.clone(), GitRev::Commit(commit.clone()), command, quiet),
+        ))
+    } else {
+        None
+    };
+
+    let build = BuildReport {
+        ran: result.is_some(),
+        success: result.as_ref().is_some_and(Result::is_ok),
+        error: result
+            .as_ref()
+            .and_then(|r| r.as_ref().err())
+            .map(|err| format!("{err:#}")),
+    };
+
+    if let Some(json_out) = &json_out {
+        let output = JsonOutput {
+            schema_version: SCHEMA_VERSION,
+            source_repository_path: source_path,
+            tag: tag.clone(),
+            commit,
+            signature,
+            build,
+        };
+
+        util::json::write_json_output(&output, json_out)?;
+    }
+
+    match result {
+        Some(result) => result,
+        None => Err(anyhow!(
+            "Not building {tag}, as it has no valid signature. Set require-signature = false in [release] \
+             to build unsigned tags."
+        )),
+    }
+}
+
+/// Verify the signature of a tag in the source repository
+fn verify_signature(
+    git: &Git,
+    source_path: &Path,
+    tag: &str,
+    cfg: &ReleaseConfig,
+) -> Result<SignatureReport, anyhow::Error> {
+    let (valid, report) = git.verify_tag(
+        source_path,
+        tag,
+        cfg.allowed_signers.as_deref(),
+        cfg.gnupg_home.as_deref(),
+    )?;
+
+    Ok(classify_signature(valid, &report))
+}
+
+/// Interpret git's report of a tag signature, from GnuPG (raw status lines) or ssh-keygen
+fn classify_signature(valid: bool, report: &str) -> SignatureReport {
+    if valid {
+        let signer = report.lines().find_map(|line| {
+            if let Some(status) = line.strip_prefix("[GNUPG:] GOODSIG ") {
+                // Key id, followed by the user id
+                status.split_once(' ').map(|(_, uid)| uid.to_owned())
+            } else {
+                line.strip_prefix("Good \"git\" signature for ")
+                    .and_then(|rest| rest.split_once(" with "))
+                    .map(|(principal, _)| principal.to_owned())
+            }
+        });
+
+        return SignatureReport {
+            status: SignatureStatus::Good,
+            signer,
+            error: None,
+        };
+    }
+
+    let unsigned = report.contains("no signature found") || report.contains("cannot verify a non-tag object");
+
+    SignatureReport {
+        status: if unsigned {
+            SignatureStatus::Unsigned
+        } else {
+            SignatureStatus::Bad
+        },
+        signer: None,
+        error: (!report.is_empty()).then(|| report.to_owned()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::{self, Output};
+
+    #[test]
+    fn classifies_signatures() {
+        let gpg = classify_signature(
+            true,
+            "[GNUPG:] NEWSIG\n[GNUPG:] GOODSIG 0123456789ABCDEF Jane Doe <jane@example.com>\n[GNUPG:] TRUST_FULLY 0 pgp",
+        );
+        assert_eq!(gpg.status, SignatureStatus::Good);
+        assert_eq!(gpg.signer.as_deref(), Some("Jane Doe <jane@example.com>"));
+
+        let ssh = classify_signature(
+            true,
+            "Good \"git\" signature for jane@example.com with ED25519 key SHA256:abcdef",
+        );
+        assert_eq!(ssh.signer.as_deref(), Some("jane@example.com"));
+
+        assert_eq!(
+            classify_signature(false, "error: no signature found").status,
+            SignatureStatus::Unsigned
+        );
+        assert_eq!(
+            classify_signature(false, "[GNUPG:] BADSIG 0123456789ABCDEF Jane Doe").status,
+            SignatureStatus::Bad
+        );
+    }
+
+    #[test]
+    fn json_output_matches_schema() {
+        let output = JsonOutput {
+            schema_version: SCHEMA_VERSION,
+            source_repository_path: PathBuf::from("/src"),
+            tag: "v1.0.0".to_owned(),
+            commit: "0123456789abcdef0123456789abcdef01234567".to_owned(),
+            signature: SignatureReport {
+                status: SignatureStatus::Good,
+                signer: Some("jane@example.com".to_owned()),
+                error: None,
+            },
+            build: BuildReport {
+                ran: true,
+                success: false,
+                error: Some("Command failed with exit code 1".to_owned()),
+            },
+        };
+        schema::validate(Output::VerifyTag, &serde_json::to_value(&output).unwrap()).unwrap();
+    }
+}
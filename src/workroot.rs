@@ -0,0 +1,356 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::anyhow;
+
+use crate::config::Config;
+use crate::util;
+
+/// Marker file placed in the git directory of every work directory created by fersk
+const WORK_DIR_MARKER: &str = "fersk-work-dir";
+
+/// Layout of the work root directory
+pub struct WorkRoot {
+    path: PathBuf,
+}
+
+impl WorkRoot {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// Get the work root for the current user, as configured
+    pub fn from_config(cfg: &Config) -> Self {
+        if cfg.shared_work_root {
+            Self::new(cfg.work_path.join(current_user()))
+        } else {
+            Self::new(&cfg.work_path)
+        }
+    }
+
+    /// Get the work root path
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Create the work root directory.
+    /// In a shared work root, each user's directory is only accessible to that user.
+    pub fn create(&self, cfg: &Config) -> Result<(), anyhow::Error> {
+        if !self.path.exists() {
+            if cfg.shared_work_root && !cfg.work_path.exists() {
+                fs::create_dir_all(&cfg.work_path)?;
+
+                // Allow all users to create their own directory, but not remove others'
+                util::set_mode(&cfg.work_path, 0o1777)?;
+            }
+
+            fs::create_dir_all(&self.path)?;
+
+            if cfg.shared_work_root {
+                util::set_mode(&self.path, 0o700)?;
+            }
+        }
+
+        self.validate(cfg)
+    }
+
+    /// Make sure the work root can be trusted, if it exists.
+    /// In a shared work root, another user could have created the current user's directory in advance.
+    pub fn validate(&self, cfg: &Config) -> Result<(), anyhow::Error> {
+        if cfg.shared_work_root && fs::symlink_metadata(&self.path).is_ok() {
+            validate_user_dir(&self.path)?;
+        }
+
+        Ok(())
+    }
+
+    /// Make sure the work root and the source repository do not overlap.
+    /// Otherwise, cleansing a work directory could destroy uncommitted work in the source repository.
+    pub fn validate_source(&self, source_path: impl AsRef<Path>) -> Result<(), anyhow::Error> {
+        let source_path = source_path.as_ref();
+
+        let work_root_path = fs::canonicalize(&self.path).unwrap_or_else(|_| util::normalize_path(&self.path));
+        let source_path = fs::canonicalize(source_path).unwrap_or_else(|_| util::normalize_path(source_path));
+
+        if work_root_path.starts_with(&source_path) || source_path.starts_with(&work_root_path) {
+            return Err(anyhow!(
+                "Work path ({}) overlaps with source repository ({}). Configure a different work-path.",
+                work_root_path.display(),
+                source_path.display()
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Get the ids of all existing work directories
+    pub fn work_ids(&self) -> io::Result<Vec<String>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut ids = Vec::new();
+
+        for entry in fs::read_dir(&self.path)? {
+            let entry = entry?;
+
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+
+            let name = entry.file_name().to_string_lossy().to_string();
+
+            // Skip internal directories (locks, metadata, logs, caches)
+            if name.starts_with('.') {
+                continue;
+            }
+
+            ids.push(name);
+        }
+
+        ids.sort();
+
+        Ok(ids)
+    }
+
+    /// Get the ids of all work directories with state in the work root,
+    /// including ones created at custom paths (--into), which are only known by their locks and metadata
+    pub fn state_ids(&self) -> io::Result<Vec<String>> {
+        let mut ids: BTreeSet<String> = self.work_ids()?.into_iter().collect();
+
+        for dir in [self.locks_dir(), self.metadata_dir()] {
+            let entries = match fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(err),
+            };
+
+            for entry in entries {
+                let name = entry?.file_name().to_string_lossy().to_string();
+
+                // Files are named after the id, followed by an extension or suffix
+                if let Some(id) = name.get(..64).filter(|id| is_work_id(id)) {
+                    ids.insert(id.to_owned());
+                }
+            }
+        }
+
+        Ok(ids.into_iter().collect())
+    }
+
+    /// Get the work directory id for a source repository
+    pub fn source_id(&self, source_path: impl AsRef<Path>) -> String {
+        util::hash::hash_bytes(source_path.as_ref().to_string_lossy().as_bytes())
+    }
+
+    /// Get the working repository path
+    pub fn work_path(&self, id: &str) -> PathBuf {
+        self.path.join(id)
+    }
+
+    /// Get the PID lock path
+    pub fn lock_path(&self, id: &str) -> PathBuf {
+        self.path.join(format!(".locks/{id}.pid"))
+    }
+
+    /// Get the path of the information about the current lock holder
+    pub fn lock_info_path(&self, id: &str) -> PathBuf {
+        self.path.join(format!(".locks/{id}.json"))
+    }
+
+    /// Get the directory of tickets of processes waiting for the lock, in arrival order
+    pub fn lock_queue_path(&self, id: &str) -> PathBuf {
+        self.path.join(format!(".locks/{id}.queue"))
+    }
+
+    /// Get the path of the request to cancel a run
+    pub fn cancel_path(&self, run_id: &str) -> PathBuf {
+        self.path.join(format!(".cancel/{run_id}"))
+    }
+
+    /// Get the metadata file path
+    pub fn metadata_path(&self, id: &str) -> PathBuf {
+        self.path.join(format!(".meta/{id}.json"))
+    }
+
+    /// Get the path of the record of the config a work directory was last used with
+    pub fn work_config_path(&self, id: &str) -> PathBuf {
+        self.path.join(format!(".meta/{id}.config.json"))
+    }
+
+    /// Get the path of the last successful commit record
+    pub fn success_path(&self, id: &str) -> PathBuf {
+        self.path.join(format!(".meta/{id}.success.json"))
+    }
+
+    /// Get the directory of config files for isolated git operations
+    pub fn git_config_dir(&self) -> PathBuf {
+        self.path.join(".config")
+    }
+
+    /// Get the empty hooks directory used to disable repository hooks
+    pub fn empty_hooks_dir(&self) -> PathBuf {
+        self.path.join(".config/hooks")
+    }
+
+    /// Get the directory containing metadata files
+    pub fn metadata_dir(&self) -> PathBuf {
+        self.path.join(".meta")
+    }
+
+    /// Get the directory containing PID locks and lock queues
+    pub fn locks_dir(&self) -> PathBuf {
+        self.path.join(".locks")
+    }
+
+    /// Get the directory containing the slots of a concurrency group.
+    /// Groups are shared by all users of a shared work root, so limits apply to the whole machine.
+    pub fn group_path(cfg: &Config, group: &str) -> PathBuf {
+        cfg.work_path.join(".groups").join(group)
+    }
+
+    /// Get the scheduler PID lock path
+    pub fn schedule_lock_path(&self) -> PathBuf {
+        self.path.join(".locks/schedule.pid")
+    }
+
+    /// Get the PID lock path of the usage statistics file
+    pub fn stats_lock_path(&self) -> PathBuf {
+        self.path.join(".locks/stats.pid")
+    }
+
+    /// Get the path of the usage statistics file
+    pub fn stats_path(&self) -> PathBuf {
+        self.path.join(".meta/stats.json")
+    }
+
+    /// Get the path of the marker recording that the first run notice has been shown
+    pub fn first_run_notice_path(&self) -> PathBuf {
+        self.path.join(".meta/first-run-notice")
+    }
+
+    /// Get the path of the scheduler state file
+    pub fn schedule_state_path(&self) -> PathBuf {
+        self.path.join(".meta/schedule.json")
+    }
+
+    /// Get the path of a dependency cache entry
+    pub fn dependency_cache_path(&self, key: &str) -> PathBuf {
+        self.path.join(format!(".cache/dependencies/{key}"))
+    }
+
+    /// Get the temporary directory used by the command during a run
+    pub fn temp_path(&self, id: &str) -> PathBuf {
+        self.path.join(format!(".tmp/{id}"))
+    }
+
+    /// Get the path of an archive of artifacts created for uploading
+    pub fn artifacts_archive_path(&self, id: &str, extension: &str) -> PathBuf {
+        self.path.join(format!(".tmp/{id}-artifacts.{extension}"))
+    }
+
+    /// Get the directory with the history of past runs in a work directory
+    pub fn history_path(&self, id: &str) -> PathBuf {
+        self.path.join(format!(".history/{id}"))
+    }
+
+    /// Get the captured output log path
+    pub fn log_path(&self, id: &str) -> PathBuf {
+        self.path.join(format!(".logs/{id}.log"))
+    }
+
+    /// Get the directory of additional revisions checked out next to a work directory
+    pub fn checkouts_path(&self, id: &str) -> PathBuf {
+        self.path.join(format!(".checkouts/{id}"))
+    }
+
+    /// Get the path of the journal of operations performed on a work directory
+    pub fn journal_path(&self, id: &str) -> PathBuf {
+        self.path.join(format!(".journal/{id}/fersk-journal.log"))
+    }
+}
+
+/// Check if a name is a work directory id, which is the hex SHA-256 of the source repository path
+pub fn is_work_id(name: &str) -> bool {
+    name.len() == 64 && name.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Mark a directory as a work directory created by fersk
+pub fn mark_work_dir(work_path: impl AsRef<Path>) -> io::Result<()> {
+    fs::File::create(marker_path(work_path.as_ref()))?;
+
+    Ok(())
+}
+
+/// Check if a directory has been marked as a work directory created by fersk
+pub fn is_work_dir(work_path: impl AsRef<Path>) -> bool {
+    marker_path(work_path.as_ref()).is_file()
+}
+
+fn marker_path(work_path: &Path) -> PathBuf {
+    work_path.join(".git").join(WORK_DIR_MARKER)
+}
+
+/// Make sure a user's directory in a shared work root belongs to the current user, and is only accessible to them.
+/// Otherwise, another user could have created it in advance to get at their work.
+#[cfg(unix)]
+fn validate_user_dir(path: &Path) -> Result<(), anyhow::Error> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = fs::symlink_metadata(path)?;
+
+    if !metadata.is_dir() || metadata.uid() != util::process::current_uid() || metadata.mode() & 0o777 != 0o700 {
+        return Err(anyhow!(
+            "Refusing to use {}, as it is not a directory owned by the current user with mode 0700.",
+            path.display()
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn validate_user_dir(_path: &Path) -> Result<(), anyhow::Error> {
+    Ok(())
+}
+
+/// Get the name of the current user.
+/// Derived from the user id of the process, as the environment can name any user.
+pub fn current_user() -> String {
+    if let Some(user) = util::process::current_user() {
+        return user;
+    }
+
+    // Users without a name (ex. in containers) are known by their id
+    #[cfg(unix)]
+    {
+        format!("uid-{}", util::process::current_uid())
+    }
+
+    #[cfg(not(unix))]
+    {
+        "unknown".to_owned()
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refuses_user_dirs_accessible_to_others() {
+        let dir = tempfile::tempdir().unwrap();
+
+        util::set_mode(dir.path(), 0o755).unwrap();
+        assert!(validate_user_dir(dir.path()).is_err());
+
+        util::set_mode(dir.path(), 0o700).unwrap();
+        validate_user_dir(dir.path()).unwrap();
+
+        let link = dir.path().join("link");
+        std::os::unix::fs::symlink(dir.path(), &link).unwrap();
+        assert!(validate_user_dir(&link).is_err());
+    }
+}
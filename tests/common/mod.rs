@@ -0,0 +1,180 @@
+#![allow(dead_code)]
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use tempfile::TempDir;
+
+/// Content of a git LFS pointer file, as committed when git-lfs is not installed
+pub const LFS_POINTER: &str = "version https://git-lfs.github.com/spec/v1\n\
+                               oid sha256:4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393\n\
+                               size 12345\n";
+
+/// Isolated environment with its own home, config and work root, and a source repository to run fersk against
+pub struct Fixture {
+    dir: TempDir,
+    pub source: PathBuf,
+}
+
+impl Fixture {
+    /// Create an environment with an empty source repository on branch main
+    pub fn new() -> Self {
+        let dir = tempfile::tempdir().expect("error creating temporary directory");
+        let root = dir.path();
+
+        for d in ["home", "config/fersk", "cache", "source"] {
+            std::fs::create_dir_all(root.join(d)).unwrap();
+        }
+
+        std::fs::write(
+            root.join("config/fersk/config.toml"),
+            format!("work-path = {:?}\n", root.join("work").to_str().unwrap()),
+        )
+        .unwrap();
+
+        let fixture = Self {
+            source: root.join("source"),
+            dir,
+        };
+
+        fixture.git(["init", "-q", "-b", "main"]);
+
+        fixture
+    }
+
+    /// Create an environment with a source repository containing two commits on main and a feature branch
+    pub fn with_branches() -> Self {
+        let fixture = Self::new();
+
+        fixture.commit_file("README.md", "readme\n", "Initial commit");
+        fixture.commit_file("src/lib.txt", "library\n", "Add library");
+
+        fixture.git(["checkout", "-q", "-b", "feature"]);
+        fixture.commit_file("feature.txt", "feature\n", "Add feature");
+        fixture.git(["checkout", "-q", "main"]);
+
+        fixture
+    }
+
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// Work root configured for fersk
+    pub fn work_root(&self) -> PathBuf {
+        self.path().join("work")
+    }
+
+    /// Append settings to the fersk config file
+    pub fn configure(&self, toml: &str) {
+        let path = self.path().join("config/fersk/config.toml");
+        let mut config = std::fs::read_to_string(&path).unwrap();
+        config.push_str(toml);
+        std::fs::write(path, config).unwrap();
+    }
+
+    /// Apply the isolated environment to a command
+    pub fn env<'a>(&self, cmd: &'a mut Command) -> &'a mut Command {
+        cmd.env_clear()
+            .env("PATH", std::env::var_os("PATH").unwrap_or_default())
+            .env("HOME", self.path().join("home"))
+            .env("XDG_CONFIG_HOME", self.path().join("config"))
+            .env("XDG_CACHE_HOME", self.path().join("cache"))
+            .env("GIT_AUTHOR_NAME", "Fixture")
+            .env("GIT_AUTHOR_EMAIL", "fixture@example.com")
+            .env("GIT_COMMITTER_NAME", "Fixture")
+            .env("GIT_COMMITTER_EMAIL", "fixture@example.com")
+            .env("GIT_CONFIG_NOSYSTEM", "1")
+    }
+
+    /// Run git in the source repository, panicking on failure, and return its output
+    pub fn git<const N: usize>(&self, args: [&str; N]) -> String {
+        self.git_in(&self.source, args)
+    }
+
+    /// Run git in a directory, panicking on failure, and return its output
+    pub fn git_in<const N: usize>(&self, path: &Path, args: [&str; N]) -> String {
+        let output = self
+            .env(&mut Command::new("git"))
+            .arg("-C")
+            .arg(path)
+            .args(args)
+            .output()
+            .expect("error executing git");
+
+        assert!(
+            output.status.success(),
+            "git {args:?} failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+
+        String::from_utf8(output.stdout).unwrap().trim_end().to_owned()
+    }
+
+    /// Write a file in the source repository without committing it
+    pub fn write_file(&self, path: &str, content: &str) {
+        let path = self.source.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+
+    /// Write and commit a file in the source repository
+    pub fn commit_file(&self, path: &str, content: &str, message: &str) {
+        self.write_file(path, content);
+        self.git(["add", path]);
+        self.git(["commit", "-q", "-m", message]);
+    }
+
+    /// Commit an LFS-tracked file as a pointer
+    pub fn commit_lfs_pointer(&self, path: &str) {
+        self.write_file(
+            ".gitattributes",
+            &format!("{path} filter=lfs diff=lfs merge=lfs -text\n"),
+        );
+        self.write_file(path, LFS_POINTER);
+        self.git(["add", ".gitattributes", path]);
+        self.git(["commit", "-q", "-m", "Add LFS file"]);
+    }
+
+    /// Create a separate repository and add it as a submodule of the source repository
+    pub fn commit_submodule(&self, path: &str) {
+        let submodule_source = self.path().join("submodule");
+        std::fs::create_dir_all(&submodule_source).unwrap();
+
+        self.git_in(&submodule_source, ["init", "-q", "-b", "main"]);
+        std::fs::write(submodule_source.join("sub.txt"), "submodule\n").unwrap();
+        self.git_in(&submodule_source, ["add", "sub.txt"]);
+        self.git_in(&submodule_source, ["commit", "-q", "-m", "Submodule"]);
+
+        self.git([
+            "-c",
+            "protocol.file.allow=always",
+            "submodule",
+            "add",
+            "-q",
+            submodule_source.to_str().unwrap(),
+            path,
+        ]);
+        self.git(["commit", "-q", "-m", "Add submodule"]);
+    }
+
+    /// Build a fersk command running in the source repository
+    pub fn fersk(&self) -> assert_cmd::Command {
+        self.fersk_process().into()
+    }
+
+    /// Build a fersk command running in the source repository, for running in the background
+    pub fn fersk_process(&self) -> Command {
+        let mut cmd = Command::new(env!("CARGO_BIN_EXE_fersk"));
+        self.env(&mut cmd).current_dir(&self.source);
+
+        cmd
+    }
+
+    /// Run fersk with the given arguments and parse its json output
+    pub fn fersk_json<const N: usize>(&self, args: [&str; N]) -> serde_json::Value {
+        let output = self.fersk().args(args).assert().success().get_output().stdout.clone();
+
+        serde_json::from_slice(&output).expect("output is not valid json")
+    }
+}
@@ -0,0 +1,1797 @@
+mod common;
+
+use std::path::PathBuf;
+
+use predicates::prelude::*;
+
+use common::{Fixture, LFS_POINTER};
+
+/// Run a command in a fresh work directory and return the json output
+fn run_json(fixture: &Fixture, args: &[&str]) -> serde_json::Value {
+    let output = fixture
+        .fersk()
+        .arg("run")
+        .arg("--json-out")
+        .args(args)
+        .args(["--", "true"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    serde_json::from_slice(&output).expect("output is not valid json")
+}
+
+fn work_path(output: &serde_json::Value) -> PathBuf {
+    PathBuf::from(output["working_repository_path"].as_str().unwrap())
+}
+
+#[test]
+fn run_checks_out_current_branch_in_work_directory() {
+    let fixture = Fixture::with_branches();
+
+    let output = run_json(&fixture, &[]);
+
+    assert_eq!(output["schema_version"], 1);
+    assert_eq!(output["branch"], "fersk-origin/main");
+    assert_eq!(output["skipped"], false);
+    assert_eq!(
+        PathBuf::from(output["source_repository_path"].as_str().unwrap()),
+        fixture.source.canonicalize().unwrap()
+    );
+
+    let work_path = work_path(&output);
+    assert!(work_path.starts_with(fixture.work_root()));
+    assert_eq!(
+        std::fs::read_to_string(work_path.join("README.md")).unwrap(),
+        "readme\n"
+    );
+    assert_eq!(
+        std::fs::read_to_string(work_path.join("src/lib.txt")).unwrap(),
+        "library\n"
+    );
+    assert!(!work_path.join("feature.txt").exists());
+
+    assert_eq!(
+        fixture.git_in(&work_path, ["rev-parse", "HEAD"]),
+        fixture.git(["rev-parse", "HEAD"])
+    );
+}
+
+#[test]
+fn run_prunes_refs_deleted_in_source_repository() {
+    let fixture = Fixture::with_branches();
+    fixture.git(["tag", "v1"]);
+
+    let work_path = work_path(&run_json(&fixture, &[]));
+    let refs = || fixture.git_in(&work_path, ["for-each-ref", "--format=%(refname)"]);
+    assert!(refs().contains("refs/remotes/fersk-origin/feature"));
+    assert!(refs().contains("refs/tags/v1"));
+
+    fixture.git(["branch", "-q", "-D", "feature"]);
+    fixture.git(["tag", "-d", "v1"]);
+
+    run_json(&fixture, &[]);
+    assert!(!refs().contains("refs/remotes/fersk-origin/feature"));
+    assert!(refs().contains("refs/tags/v1"));
+
+    fixture.configure("fetch-prune-tags = true\n");
+
+    run_json(&fixture, &[]);
+    assert!(!refs().contains("refs/tags/v1"));
+}
+
+#[test]
+fn run_auto_prunes_branches_in_its_own_work_directory() {
+    let fixture = Fixture::with_branches();
+    fixture.configure("fetch-prune = false\nauto-prune-branches = true\n");
+
+    let work_path = work_path(&run_json(&fixture, &[]));
+    let refs = || fixture.git_in(&work_path, ["for-each-ref", "--format=%(refname)"]);
+    assert!(refs().contains("refs/remotes/fersk-origin/feature"));
+
+    fixture.git(["branch", "-q", "-D", "feature"]);
+
+    run_json(&fixture, &[]);
+    assert!(!refs().contains("refs/remotes/fersk-origin/feature"));
+}
+
+#[test]
+fn run_executes_command_in_work_directory() {
+    let fixture = Fixture::with_branches();
+
+    fixture
+        .fersk()
+        .args(["run", "--", "git", "rev-parse", "--show-toplevel"])
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with(fixture.work_root().to_str().unwrap()));
+}
+
+#[test]
+fn run_resolves_relative_command_in_work_directory() {
+    let fixture = Fixture::with_branches();
+    fixture.commit_file("scripts/ci.sh", "#!/bin/sh\necho ci\n", "Add CI script");
+    fixture.commit_file("scripts/plain.sh", "#!/bin/sh\n", "Add non-executable script");
+    fixture.git(["update-index", "--chmod=+x", "scripts/ci.sh"]);
+    fixture.git(["commit", "-q", "-m", "Make CI script executable"]);
+
+    fixture
+        .fersk()
+        .args(["run", "--", "./scripts/ci.sh"])
+        .assert()
+        .success()
+        .stdout("ci\n");
+
+    fixture
+        .fersk()
+        .args(["run", "--", "./scripts/plain.sh"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Command is not executable: ./scripts/plain.sh",
+        ));
+
+    fixture
+        .fersk()
+        .args(["run", "--", "./scripts/missing.sh"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Command not found in the work directory"));
+}
+
+#[test]
+fn run_script_runs_repository_script_with_pre_and_post() {
+    let fixture = Fixture::with_branches();
+    fixture.commit_file(
+        ".fersk.toml",
+        "[scripts]\n\
+         pretest = \"echo pre\"\n\
+         test = [\"echo\", \"test\"]\n\
+         posttest = \"echo post\"\n\
+         fail = \"exit 3\"\n",
+        "Add scripts",
+    );
+
+    fixture
+        .fersk()
+        .args(["run-script", "test", "--", "arg"])
+        .assert()
+        .success()
+        .stdout("pre\ntest arg\npost\n");
+
+    fixture.fersk().args(["run-script", "fail"]).assert().failure();
+
+    fixture
+        .fersk()
+        .args(["run-script", "missing"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Available scripts: fail, posttest, pretest, test",
+        ));
+}
+
+#[cfg(unix)]
+#[test]
+fn run_script_passes_arguments_to_shell_scripts_as_they_are() {
+    let fixture = Fixture::with_branches();
+    fixture.commit_file(".fersk.toml", "[scripts]\nargs = \"printf '%s|'\"\n", "Add scripts");
+
+    fixture
+        .fersk()
+        .args(["run-script", "args", "--", "a b", "it's", "$(false)", "line\nbreak"])
+        .assert()
+        .success()
+        .stdout("a b|it's|$(false)|line\nbreak|");
+}
+
+#[test]
+fn run_script_refuses_destructive_scripts_on_protected_branches() {
+    let fixture = Fixture::with_branches();
+    fixture.configure("\nprotected-branches = [\"main\", \"release/*\"]\n");
+    fixture.commit_file(
+        ".fersk.toml",
+        "[scripts]\n\
+         deploy = { command = \"echo deployed\", destructive = true }\n",
+        "Add scripts",
+    );
+
+    fixture
+        .fersk()
+        .args(["run-script", "deploy"])
+        .assert()
+        .failure()
+        .stderr(
+            predicate::str::contains("main, which is protected").and(predicate::str::contains("--confirm-protected")),
+        );
+
+    fixture
+        .fersk()
+        .args(["run-script", "deploy", "--confirm-protected"])
+        .assert()
+        .success()
+        .stdout("deployed\n");
+
+    fixture.git(["checkout", "-q", "-b", "staging"]);
+
+    fixture
+        .fersk()
+        .args(["run-script", "deploy"])
+        .assert()
+        .success()
+        .stdout("deployed\n");
+
+    // The tip of a protected branch is protected, however it is checked out
+    fixture.git(["checkout", "-q", "--detach", "main"]);
+
+    fixture
+        .fersk()
+        .args(["run-script", "deploy"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("main, which is protected"));
+}
+
+#[test]
+fn run_fails_when_command_fails() {
+    let fixture = Fixture::with_branches();
+
+    fixture
+        .fersk()
+        .args(["run", "--", "sh", "-c", "exit 3"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("non-success error code: 3"));
+}
+
+#[test]
+fn run_ignores_uncommitted_changes_in_source() {
+    let fixture = Fixture::with_branches();
+
+    fixture.write_file("README.md", "modified\n");
+    fixture.write_file("untracked.txt", "untracked\n");
+    fixture.git(["add", "README.md"]);
+    fixture.write_file("src/lib.txt", "unstaged\n");
+
+    let work_path = work_path(&run_json(&fixture, &[]));
+
+    assert_eq!(
+        std::fs::read_to_string(work_path.join("README.md")).unwrap(),
+        "readme\n"
+    );
+    assert_eq!(
+        std::fs::read_to_string(work_path.join("src/lib.txt")).unwrap(),
+        "library\n"
+    );
+    assert!(!work_path.join("untracked.txt").exists());
+
+    // The source repository is left as it was
+    assert_eq!(
+        std::fs::read_to_string(fixture.source.join("README.md")).unwrap(),
+        "modified\n"
+    );
+    assert!(fixture.source.join("untracked.txt").exists());
+}
+
+#[test]
+fn run_cleanses_work_directory_between_runs() {
+    let fixture = Fixture::with_branches();
+
+    let work_path = work_path(&run_json(&fixture, &[]));
+    std::fs::write(work_path.join("README.md"), "changed by command\n").unwrap();
+    std::fs::write(work_path.join("build-output.txt"), "output\n").unwrap();
+
+    fixture.commit_file("new.txt", "new\n", "Add new file");
+    let output = run_json(&fixture, &[]);
+
+    assert_eq!(self::work_path(&output), work_path);
+    assert_eq!(
+        std::fs::read_to_string(work_path.join("README.md")).unwrap(),
+        "readme\n"
+    );
+    assert!(!work_path.join("build-output.txt").exists());
+    assert!(work_path.join("new.txt").exists());
+}
+
+#[test]
+fn run_keeps_preserved_paths_when_cleansing() {
+    let fixture = Fixture::with_branches();
+    fixture.configure("preserve-paths = [\"cache\"]\n");
+
+    let work_path = work_path(&run_json(&fixture, &[]));
+    std::fs::create_dir(work_path.join("cache")).unwrap();
+    std::fs::write(work_path.join("cache/data.txt"), "cached\n").unwrap();
+    std::fs::write(work_path.join("build-output.txt"), "output\n").unwrap();
+
+    run_json(&fixture, &[]);
+
+    assert!(work_path.join("cache/data.txt").exists());
+    assert!(!work_path.join("build-output.txt").exists());
+
+    // Full cleanses keep them too
+    fixture.configure("cleanse-mode = \"full\"\n");
+
+    fixture
+        .fersk()
+        .args(["run", "--", "true"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(
+            "Cleanse mode changed from Differential to Full",
+        ));
+
+    assert!(work_path.join("cache/data.txt").exists());
+
+    // Paths that are no longer preserved are removed
+    let config_path = fixture.path().join("config/fersk/config.toml");
+    let config = std::fs::read_to_string(&config_path).unwrap();
+    std::fs::write(&config_path, config.replace("[\"cache\"]", "[]")).unwrap();
+
+    fixture
+        .fersk()
+        .args(["run", "--", "true"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("No longer preserving [\"cache\"]"));
+
+    assert!(!work_path.join("cache").exists());
+}
+
+#[test]
+fn run_records_operations_in_journal() {
+    let fixture = Fixture::with_branches();
+    let work_path = work_path(&run_json(&fixture, &[]));
+
+    std::fs::write(work_path.join("scratch.txt"), "left behind\n").unwrap();
+    run_json(&fixture, &["--branch", "feature"]);
+
+    let journal_dir = fixture.work_root().join(".journal");
+    let journal_path = std::fs::read_dir(journal_dir).unwrap().next().unwrap().unwrap().path();
+    let journal = std::fs::read_to_string(journal_path.join("fersk-journal.log")).unwrap();
+
+    let operations: Vec<&str> = journal
+        .lines()
+        .map(|l| l.split_whitespace().find(|w| w.ends_with(':')).unwrap())
+        .collect();
+    assert_eq!(operations, ["create:", "checkout:", "cleanse:", "checkout:"]);
+    assert!(journal.contains("restored or removed 1 path(s)"));
+    assert!(journal.contains("fersk-origin/feature at"));
+}
+
+#[test]
+fn run_checks_out_specified_branch() {
+    let fixture = Fixture::with_branches();
+
+    let output = run_json(&fixture, &["--branch", "feature"]);
+
+    assert_eq!(output["branch"], "fersk-origin/feature");
+    assert_eq!(
+        std::fs::read_to_string(work_path(&output).join("feature.txt")).unwrap(),
+        "feature\n"
+    );
+}
+
+#[test]
+fn run_checks_out_specified_commit() {
+    let fixture = Fixture::with_branches();
+    let commit = fixture.git(["rev-parse", "main~1"]);
+
+    let output = run_json(&fixture, &["--commit", &commit]);
+    let work_path = work_path(&output);
+
+    assert_eq!(fixture.git_in(&work_path, ["rev-parse", "HEAD"]), commit);
+    assert!(!work_path.join("src/lib.txt").exists());
+}
+
+#[test]
+fn run_checks_out_merge_base() {
+    let fixture = Fixture::with_branches();
+    let fork_point = fixture.git(["rev-parse", "main"]);
+    fixture.commit_file("later.txt", "later\n", "Later commit on main");
+
+    let output = run_json(&fixture, &["--branch", "feature", "--merge-base", "main"]);
+    let work_path = work_path(&output);
+
+    assert_eq!(output["merge_base"], fork_point.as_str());
+    assert_eq!(fixture.git_in(&work_path, ["rev-parse", "HEAD"]), fork_point);
+    assert!(!work_path.join("feature.txt").exists());
+    assert!(!work_path.join("later.txt").exists());
+}
+
+#[test]
+fn run_suggests_similar_branch_names() {
+    let fixture = Fixture::with_branches();
+
+    fixture
+        .fersk()
+        .args(["run", "--branch", "featur", "--", "true"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("feature"));
+}
+
+#[test]
+fn run_rejects_revision_ranges() {
+    let fixture = Fixture::with_branches();
+
+    fixture
+        .fersk()
+        .args(["run", "--branch", "main..feature", "--", "true"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Revision ranges are not supported"));
+}
+
+#[test]
+fn run_fails_outside_repository() {
+    let fixture = Fixture::new();
+    let outside = fixture.path().join("home");
+
+    fixture
+        .fersk()
+        .current_dir(outside)
+        .args(["run", "--", "true"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Not a git repository"));
+}
+
+#[test]
+fn run_checks_out_lfs_pointers_as_committed() {
+    let fixture = Fixture::with_branches();
+    fixture.commit_lfs_pointer("assets/large.bin");
+
+    let work_path = work_path(&run_json(&fixture, &["--no-lfs"]));
+
+    assert_eq!(
+        std::fs::read_to_string(work_path.join("assets/large.bin")).unwrap(),
+        LFS_POINTER
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn run_warns_when_git_lfs_is_missing() {
+    let fixture = Fixture::with_branches();
+    fixture.commit_lfs_pointer("assets/large.bin");
+
+    // Only the programs needed for the run, so git-lfs can't be found even if it is installed
+    let bin = fixture.path().join("bin");
+    std::fs::create_dir(&bin).unwrap();
+
+    for program in ["git", "sh", "true"] {
+        let path = std::env::split_paths(&std::env::var_os("PATH").unwrap())
+            .map(|dir| dir.join(program))
+            .find(|path| path.is_file())
+            .unwrap();
+        std::os::unix::fs::symlink(path, bin.join(program)).unwrap();
+    }
+
+    let output = fixture
+        .fersk()
+        .env("PATH", &bin)
+        .args(["run", "--json-out", "--", "true"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("git-lfs is not installed"))
+        .get_output()
+        .stdout
+        .clone();
+
+    let work_path = work_path(&serde_json::from_slice(&output).unwrap());
+    assert_eq!(
+        std::fs::read_to_string(work_path.join("assets/large.bin")).unwrap(),
+        LFS_POINTER
+    );
+}
+
+#[test]
+fn run_fetches_lfs_objects_from_source_repository() {
+    let fixture = Fixture::with_branches();
+
+    let lfs_installed = fixture
+        .env(&mut std::process::Command::new("git"))
+        .args(["lfs", "version"])
+        .output()
+        .is_ok_and(|output| output.status.success());
+
+    if !lfs_installed {
+        eprintln!("git-lfs is not available. Skipping.");
+        return;
+    }
+
+    fixture.git(["lfs", "install", "--local"]);
+    fixture.git(["lfs", "track", "*.bin"]);
+    fixture.write_file("assets/large.bin", "large content\n");
+    fixture.git(["add", ".gitattributes", "assets/large.bin"]);
+    fixture.git(["commit", "-q", "-m", "Add LFS file"]);
+
+    let work_path = work_path(&run_json(&fixture, &[]));
+
+    assert_eq!(
+        std::fs::read_to_string(work_path.join("assets/large.bin")).unwrap(),
+        "large content\n"
+    );
+}
+
+#[test]
+fn run_only_replaces_url_rewrites_of_work_directory() {
+    let fixture = Fixture::with_branches();
+    std::fs::write(
+        fixture.path().join("home/.gitconfig"),
+        "[url \"https://mirror.example.com/\"]\n\tinsteadOf = https://example.com/\n",
+    )
+    .unwrap();
+    fixture.configure("\n[submodule-url-rewrite]\n\"https://example.org/\" = \"https://mirror.example.org/\"\n");
+
+    let work_path = work_path(&run_json(&fixture, &[]));
+    run_json(&fixture, &[]);
+
+    assert_eq!(
+        fixture.git_in(&work_path, ["config", "--local", "--get-regexp", r"^url\."]),
+        "url.https://mirror.example.org/.insteadof https://example.org/"
+    );
+    assert_eq!(
+        fixture.git_in(&work_path, ["config", "--global", "--get-regexp", r"^url\."]),
+        "url.https://mirror.example.com/.insteadof https://example.com/"
+    );
+}
+
+#[test]
+fn run_leaves_submodules_uninitialized() {
+    let fixture = Fixture::with_branches();
+    fixture.commit_submodule("vendor/sub");
+
+    let work_path = work_path(&run_json(&fixture, &[]));
+
+    assert!(work_path.join(".gitmodules").exists());
+    assert!(work_path.join("vendor/sub").is_dir());
+    assert!(!work_path.join("vendor/sub/sub.txt").exists());
+}
+
+#[test]
+fn run_only_clones_local_submodules_initialized_in_source() {
+    let fixture = Fixture::with_branches();
+    fixture.commit_submodule("vendor/sub");
+
+    // Only known from .gitmodules in the commit, which could point at any repository on the machine
+    fixture.git(["config", "--remove-section", "submodule.vendor/sub"]);
+
+    fixture
+        .fersk()
+        .args(["run", "--recurse-submodules", "--", "true"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Error updating submodules").and(predicate::str::contains("not allowed")));
+}
+
+#[test]
+fn run_updates_submodules_when_requested() {
+    let fixture = Fixture::with_branches();
+    fixture.commit_submodule("vendor/sub");
+
+    let work_path = work_path(&run_json(&fixture, &["--recurse-submodules"]));
+
+    assert_eq!(
+        std::fs::read_to_string(work_path.join("vendor/sub/sub.txt")).unwrap(),
+        "submodule\n"
+    );
+
+    // Changes in submodules are discarded by the next run
+    std::fs::write(work_path.join("vendor/sub/sub.txt"), "changed\n").unwrap();
+    std::fs::write(work_path.join("vendor/sub/untracked.txt"), "untracked\n").unwrap();
+
+    fixture.configure("recurse-submodules = true\n");
+    run_json(&fixture, &[]);
+
+    assert_eq!(
+        std::fs::read_to_string(work_path.join("vendor/sub/sub.txt")).unwrap(),
+        "submodule\n"
+    );
+    assert!(!work_path.join("vendor/sub/untracked.txt").exists());
+}
+
+#[test]
+fn run_skips_when_already_succeeded() {
+    let fixture = Fixture::with_branches();
+
+    let first = run_json(&fixture, &["--since-last-success"]);
+    assert_eq!(first["skipped"], false);
+
+    let second = run_json(&fixture, &["--since-last-success"]);
+    assert_eq!(second["skipped"], true);
+
+    fixture.commit_file("new.txt", "new\n", "Add new file");
+
+    let third = run_json(&fixture, &["--since-last-success"]);
+    assert_eq!(third["skipped"], false);
+}
+
+#[test]
+fn run_uses_separate_work_directories_per_repository() {
+    let first = Fixture::with_branches();
+    let second = Fixture::with_branches();
+
+    let first_path = work_path(&run_json(&first, &[]));
+
+    // Use the config, and thereby the work root, of the first fixture
+    let output = second
+        .fersk()
+        .env("XDG_CONFIG_HOME", first.path().join("config"))
+        .args(["run", "--json-out", "--", "true"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let output: serde_json::Value = serde_json::from_slice(&output).unwrap();
+
+    assert_ne!(work_path(&output), first_path);
+    assert!(work_path(&output).starts_with(first.work_root()));
+}
+
+#[test]
+fn run_creates_work_directory_at_custom_path() {
+    let fixture = Fixture::with_branches();
+    let into = fixture.path().join("deploy");
+    std::fs::create_dir(&into).unwrap();
+
+    let output = run_json(&fixture, &["--into", into.to_str().unwrap()]);
+
+    assert_eq!(work_path(&output), into);
+    assert_eq!(std::fs::read_to_string(into.join("README.md")).unwrap(), "readme\n");
+
+    // Directories not created by fersk are left alone
+    let foreign = fixture.path().join("foreign");
+    std::fs::create_dir(&foreign).unwrap();
+    std::fs::write(foreign.join("index.html"), "content\n").unwrap();
+
+    fixture
+        .fersk()
+        .args(["run", "--into", foreign.to_str().unwrap(), "--", "true"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("not created by fersk"));
+
+    assert_eq!(
+        std::fs::read_to_string(foreign.join("index.html")).unwrap(),
+        "content\n"
+    );
+}
+
+#[test]
+fn run_adopts_existing_clones_of_the_source() {
+    let fixture = Fixture::with_branches();
+
+    let clone = fixture.path().join("clone");
+    fixture.git_in(
+        fixture.path(),
+        ["clone", "-q", fixture.source.to_str().unwrap(), "clone"],
+    );
+
+    // Work in the clone is not discarded without --adopt
+    std::fs::write(clone.join("wip.txt"), "wip\n").unwrap();
+    std::fs::write(clone.join("README.md"), "changed\n").unwrap();
+
+    fixture
+        .fersk()
+        .args(["run", "--into", clone.to_str().unwrap(), "--", "true"])
+        .assert()
+        .failure()
+        .stderr(
+            predicate::str::contains("has 2 modified or untracked path(s)").and(predicate::str::contains("--adopt")),
+        );
+
+    assert_eq!(std::fs::read_to_string(clone.join("wip.txt")).unwrap(), "wip\n");
+    assert_eq!(std::fs::read_to_string(clone.join("README.md")).unwrap(), "changed\n");
+
+    let output = fixture
+        .fersk()
+        .args([
+            "run",
+            "--json-out",
+            "--into",
+            clone.to_str().unwrap(),
+            "--adopt",
+            "--",
+            "true",
+        ])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("No such remote").not())
+        .get_output()
+        .stdout
+        .clone();
+    let output: serde_json::Value = serde_json::from_slice(&output).unwrap();
+
+    assert_eq!(work_path(&output), clone);
+    assert_eq!(output["branch"], "fersk-origin/main");
+    assert!(!clone.join("wip.txt").exists());
+
+    // Once adopted, it is a work directory like any other
+    run_json(&fixture, &["--into", clone.to_str().unwrap()]);
+
+    // Unrelated repositories are refused
+    let unrelated = fixture.path().join("unrelated");
+    std::fs::create_dir(&unrelated).unwrap();
+    fixture.git_in(&unrelated, ["init", "-q", "-b", "main"]);
+    fixture.git_in(&unrelated, ["commit", "-q", "--allow-empty", "-m", "Unrelated"]);
+
+    fixture
+        .fersk()
+        .args(["run", "--into", unrelated.to_str().unwrap(), "--adopt", "--", "true"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("is not a clone of"));
+
+    assert_eq!(fixture.git_in(&unrelated, ["log", "--format=%s"]), "Unrelated");
+}
+
+/// Set or clear write permission on a directory tree
+#[cfg(unix)]
+fn set_writable(path: &std::path::Path, writable: bool) {
+    use std::os::unix::fs::PermissionsExt;
+
+    let metadata = std::fs::symlink_metadata(path).unwrap();
+    if metadata.file_type().is_symlink() {
+        return;
+    }
+
+    // Directories must be writable before their contents can be changed, and stay readable until after
+    if writable {
+        std::fs::set_permissions(
+            path,
+            std::fs::Permissions::from_mode(metadata.permissions().mode() | 0o200),
+        )
+        .unwrap();
+    }
+
+    if metadata.is_dir() {
+        for entry in std::fs::read_dir(path).unwrap() {
+            set_writable(&entry.unwrap().path(), writable);
+        }
+    }
+
+    if !writable {
+        std::fs::set_permissions(
+            path,
+            std::fs::Permissions::from_mode(metadata.permissions().mode() & !0o222),
+        )
+        .unwrap();
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn run_works_on_read_only_source() {
+    let fixture = Fixture::with_branches();
+
+    // Make the index stale, so git status would refresh it if allowed to
+    std::thread::sleep(std::time::Duration::from_secs(1));
+    fixture.write_file("README.md", "readme\n");
+
+    set_writable(&fixture.source, false);
+
+    let result = fixture
+        .fersk()
+        .args([
+            "run",
+            "--source-read-only",
+            "--require-clean-source",
+            "--branch",
+            "feature",
+        ])
+        .args(["--", "true"])
+        .ok();
+
+    set_writable(&fixture.source, true);
+
+    result.unwrap();
+}
+
+#[test]
+fn run_detects_source_modification() {
+    let fixture = Fixture::with_branches();
+
+    // A hook writing to the source repository while the work directory is being checked out
+    let hooks_path = fixture.path().join("hooks");
+    let marker = fixture.source.join(".git/modified");
+    std::fs::create_dir(&hooks_path).unwrap();
+    std::fs::write(
+        hooks_path.join("post-checkout"),
+        format!("#!/bin/sh\ntouch '{}'\n", marker.display()),
+    )
+    .unwrap();
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(hooks_path.join("post-checkout"), std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    std::fs::write(
+        fixture.path().join("home/.gitconfig"),
+        format!("[core]\n\thooksPath = {}\n", hooks_path.display()),
+    )
+    .unwrap();
+
+    fixture
+        .fersk()
+        .args(["run", "--with-hooks", "--source-read-only", "--", "true"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("source repository was modified"))
+        .stderr(predicate::str::contains(".git/modified"));
+}
+
+#[test]
+fn run_copies_and_adds_remotes() {
+    let fixture = Fixture::with_branches();
+    fixture.git(["remote", "add", "origin", "https://example.com/origin.git"]);
+
+    fixture
+        .fersk()
+        .args(["run", "--copy-remote", "upstream", "--", "true"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Available remotes: origin"));
+
+    let output = run_json(
+        &fixture,
+        &[
+            "--copy-remote",
+            "upstream",
+            "--copy-remote-optional",
+            "--add-remote",
+            "mirror=https://example.com/mirror.git",
+        ],
+    );
+    let work_path = work_path(&output);
+
+    assert_eq!(
+        fixture.git_in(&work_path, ["remote", "get-url", "mirror"]),
+        "https://example.com/mirror.git"
+    );
+    assert!(!fixture.git_in(&work_path, ["remote"]).contains("upstream"));
+}
+
+#[test]
+fn run_passes_seed_and_reproducible_environment() {
+    let fixture = Fixture::with_branches();
+    let commit_time = fixture.git(["log", "-1", "--format=%ct"]);
+
+    fixture
+        .fersk()
+        .args(["run", "--seed", "1234", "--reproducible", "--"])
+        .args(["sh", "-c", "echo $FERSK_SEED $SOURCE_DATE_EPOCH $TZ"])
+        .assert()
+        .success()
+        .stdout(format!("1234 {commit_time} UTC\n"));
+
+    let output = fixture.fersk_json(["inspect", "--json-out"]);
+    assert_eq!(output["metadata"]["seed"], 1234);
+}
+
+#[test]
+fn run_disables_background_maintenance() {
+    let fixture = Fixture::with_branches();
+    let work_path = work_path(&run_json(&fixture, &[]));
+
+    assert_eq!(fixture.git_in(&work_path, ["config", "maintenance.auto"]), "false");
+    assert_eq!(fixture.git_in(&work_path, ["config", "gc.auto"]), "0");
+}
+
+#[test]
+fn run_annotates_output_for_ci() {
+    let fixture = Fixture::with_branches();
+
+    fixture
+        .fersk()
+        .args(["run", "--ci-annotations", "github", "--", "sh", "-c", "exit 3"])
+        .assert()
+        .failure()
+        .stderr(
+            predicate::str::contains("::group::Prepare work directory\n")
+                .and(predicate::str::contains("::endgroup::\n::group::sh -c 'exit 3'\n"))
+                .and(predicate::str::contains(
+                    "::error title=fersk::Command returned with a non-success error code: 3\n",
+                )),
+        );
+
+    fixture
+        .fersk()
+        .args(["run", "--ci-annotations", "gitlab", "--", "true"])
+        .assert()
+        .success()
+        .stderr(
+            predicate::str::contains("section_start:")
+                .and(predicate::str::contains(
+                    ":fersk_command[collapsed=true]\r\x1b[0Ktrue\n",
+                ))
+                .and(predicate::str::contains(":fersk_command\r\x1b[0K\n"))
+                .and(predicate::str::contains("fersk:").not()),
+        );
+}
+
+#[test]
+fn run_loads_env_files() {
+    let fixture = Fixture::with_branches();
+    fixture.configure("env-files = [\".env\"]\n");
+
+    // Uncommitted, so only found in the source repository
+    fixture.write_file(".env", "NAME=source\nGREETING=\"hello $NAME\"\n");
+    fixture.write_file("override.env", "export NAME='override'\n");
+
+    fixture
+        .fersk()
+        .args(["run", "--env-file", "override.env", "--"])
+        .args(["sh", "-c", "echo $GREETING $NAME"])
+        .assert()
+        .success()
+        .stdout("hello source override\n");
+
+    fixture
+        .fersk()
+        .args(["run", "--env-file", "missing.env", "--", "true"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Env file not found: missing.env"));
+}
+
+#[test]
+fn run_captures_base_env_from_source_repository() {
+    let fixture = Fixture::with_branches();
+
+    // Uncommitted, so only found in the source repository
+    fixture.write_file("env.json", r#"{ "TOOL": "direnv", "NAME": "base", "HOME": null }"#);
+    fixture.write_file(".env", "NAME=envfile\n");
+
+    fixture
+        .fersk()
+        .args(["run", "--base-env-from", "env.json", "--env-file", ".env", "--"])
+        .args(["sh", "-c", "echo $TOOL $NAME ${HOME-unset}"])
+        .assert()
+        .success()
+        .stdout("direnv envfile unset\n");
+
+    // Repositories can only name a file, as their commands would run unsandboxed in the source repository
+    fixture.commit_file(
+        ".fersk.toml",
+        "base-env-from = \"touch pwned\"\n",
+        "Add base env command",
+    );
+
+    fixture
+        .fersk()
+        .args(["run", "--", "true"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Commands can only be given with --base-env-from",
+        ));
+    assert!(!fixture.source.join("pwned").exists());
+
+    fixture.commit_file(".fersk.toml", "base-env-from = \"env.json\"\n", "Add base env file");
+
+    fixture
+        .fersk()
+        .args(["run", "--", "sh", "-c", "echo $TOOL"])
+        .assert()
+        .success()
+        .stdout("direnv\n");
+
+    fixture.configure("base-env-from = \"git rev-parse --is-inside-work-tree | sed 's/^/INSIDE=/'\"\n");
+
+    fixture
+        .fersk()
+        .args(["run", "--", "sh", "-c", "echo $INSIDE ${TOOL-unset}"])
+        .assert()
+        .success()
+        .stdout("true unset\n");
+
+    fixture
+        .fersk()
+        .args(["run", "--base-env-from", "echo broken >&2; exit 1", "--", "true"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Base environment command failed").and(predicate::str::contains("broken")));
+}
+
+#[test]
+fn run_waits_for_slot_in_concurrency_group() {
+    let first = Fixture::with_branches();
+    let second = Fixture::with_branches();
+    first.configure("[concurrency-groups]\ngpu = 1\n");
+
+    let started = first.path().join("started");
+    let release = first.path().join("release");
+    let second_ran = first.path().join("second-ran");
+
+    let hold = format!(
+        "touch {}; while [ ! -e {} ]; do sleep 0.1; done",
+        started.display(),
+        release.display()
+    );
+    let holder = first
+        .fersk_process()
+        .args(["run", "--group", "gpu", "--", "sh", "-c", &hold])
+        .spawn()
+        .unwrap();
+
+    while !started.exists() {
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+
+    // A run in another repository, using the same config
+    let waiter = second
+        .fersk_process()
+        .env("XDG_CONFIG_HOME", first.path().join("config"))
+        .args(["run", "--group", "gpu", "--", "touch", second_ran.to_str().unwrap()])
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    std::thread::sleep(std::time::Duration::from_secs(2));
+    assert!(!second_ran.exists());
+
+    std::fs::write(&release, "").unwrap();
+
+    let output = waiter.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert!(second_ran.exists());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Waiting for a slot in concurrency group gpu"));
+
+    holder.wait_with_output().unwrap();
+
+    first
+        .fersk()
+        .args(["run", "--group", "cpu", "--", "true"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Unknown concurrency group: cpu. Available groups: gpu",
+        ));
+}
+
+#[test]
+fn coalesced_run_prints_work_path() {
+    use std::io::BufRead;
+
+    let fixture = Fixture::with_branches();
+
+    let started = fixture.path().join("started");
+    let release = fixture.path().join("release");
+
+    let hold = format!(
+        "touch {}; while [ ! -e {} ]; do sleep 0.1; done",
+        started.display(),
+        release.display()
+    );
+    let holder = fixture
+        .fersk_process()
+        .args(["run", "--json-out", "--", "sh", "-c", &hold])
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+
+    while !started.exists() {
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+
+    let mut waiter = fixture
+        .fersk_process()
+        .args(["run", "--wait", "--print-work-path", "--", "sh", "-c", &hold])
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let coalescing = std::io::BufReader::new(waiter.stderr.take().unwrap())
+        .lines()
+        .map_while(Result::ok)
+        .any(|line| line.contains("Identical run in progress"));
+
+    std::fs::write(&release, "").unwrap();
+
+    let holder_output = holder.wait_with_output().unwrap();
+    let waiter_output = waiter.wait_with_output().unwrap();
+    assert!(coalescing);
+    assert!(waiter_output.status.success());
+
+    let holder_json: serde_json::Value = serde_json::from_slice(&holder_output.stdout).unwrap();
+    assert_eq!(
+        String::from_utf8_lossy(&waiter_output.stdout).trim_end(),
+        work_path(&holder_json).to_str().unwrap()
+    );
+}
+
+#[test]
+fn concurrent_runs_all_count_in_usage_statistics() {
+    let fixture = Fixture::with_branches();
+    fixture.configure("usage-stats = true\n");
+
+    let runs: Vec<_> = (0..4)
+        .map(|i| {
+            let into = fixture.path().join(format!("into{i}"));
+
+            fixture
+                .fersk_process()
+                .args(["run", "--into", into.to_str().unwrap(), "--", "true"])
+                .stderr(std::process::Stdio::piped())
+                .spawn()
+                .unwrap()
+        })
+        .collect();
+
+    for run in runs {
+        let output = run.wait_with_output().unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    }
+
+    fixture
+        .fersk()
+        .arg("stats")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Runs: 4 (4 succeeded, 0 failed)"));
+}
+
+#[test]
+fn cancel_stops_running_command() {
+    let fixture = Fixture::with_branches();
+
+    let run = fixture
+        .fersk_process()
+        .args(["run", "--json-out", "--", "sleep", "60"])
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+
+    // Wait for the command to start
+    let run_id = loop {
+        let output = fixture.fersk().args(["cancel", "--list"]).output().unwrap();
+        let list = String::from_utf8(output.stdout).unwrap();
+
+        if let Some(line) = list.lines().find(|l| l.contains("running")) {
+            break line.split_whitespace().next().unwrap().to_owned();
+        }
+
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    };
+
+    fixture
+        .fersk()
+        .args(["cancel", &run_id])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Cancelled run"));
+
+    let output = run.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(130));
+
+    let output: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(output["cancelled"], true);
+
+    let inspect = fixture.fersk_json(["inspect", "--json-out"]);
+    assert_eq!(inspect["metadata"]["run_id"], run_id.as_str());
+    assert_eq!(inspect["metadata"]["cancelled"], true);
+}
+
+#[test]
+fn run_writes_json_output_compact_or_to_file() {
+    let fixture = Fixture::with_branches();
+
+    let output = fixture
+        .fersk()
+        .args(["run", "--json-out=compact", "--", "true"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let output = String::from_utf8(output).unwrap();
+
+    assert_eq!(output.lines().count(), 1);
+    let output: serde_json::Value = serde_json::from_str(&output).unwrap();
+    assert_eq!(output["branch"], "fersk-origin/main");
+
+    // The command's own output stays on stdout when json output goes to a file
+    let json_path = fixture.path().join("out").join("run.json");
+    fixture
+        .fersk()
+        .args([
+            "run",
+            &format!("--json-out={}", json_path.display()),
+            "--",
+            "echo",
+            "hello",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("hello"));
+
+    let output: serde_json::Value = serde_json::from_slice(&std::fs::read(&json_path).unwrap()).unwrap();
+    assert_eq!(output["branch"], "fersk-origin/main");
+}
+
+#[test]
+fn run_checks_out_additional_revisions() {
+    let fixture = Fixture::with_branches();
+
+    let output = fixture
+        .fersk()
+        .args([
+            "run",
+            "--also-checkout",
+            "feature=new",
+            "--",
+            "sh",
+            "-c",
+            "cat \"$FERSK_CHECKOUT_NEW/feature.txt\"; test ! -e feature.txt",
+        ])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    assert_eq!(String::from_utf8(output).unwrap(), "feature\n");
+
+    // Checkouts are recreated on every run
+    fixture
+        .fersk()
+        .args([
+            "run",
+            "--also-checkout",
+            "main=old",
+            "--",
+            "sh",
+            "-c",
+            "test ! -e \"$FERSK_CHECKOUT_OLD/feature.txt\"",
+        ])
+        .assert()
+        .success();
+
+    fixture
+        .fersk()
+        .args(["run", "--also-checkout", "nonexistent=old", "--", "true"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("does not exist"));
+}
+
+#[test]
+fn run_warns_when_branch_is_behind_upstream() {
+    let fixture = Fixture::with_branches();
+
+    // Make main track feature, which is one commit ahead of it
+    fixture.git(["branch", "-q", "--set-upstream-to", "feature", "main"]);
+
+    fixture
+        .fersk()
+        .args(["run", "--", "true"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("main is 1 commit(s) behind feature"));
+
+    fixture
+        .fersk()
+        .args(["run", "--require-up-to-date", "--", "true"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--require-up-to-date"));
+}
+
+#[test]
+fn run_clones_shallowly_and_deepens_when_needed() {
+    let fixture = Fixture::with_branches();
+    let first_commit = fixture.git(["rev-list", "--max-parents=0", "HEAD"]);
+
+    let work_path = work_path(&run_json(&fixture, &["--depth", "1"]));
+
+    assert_eq!(
+        fixture.git_in(&work_path, ["rev-parse", "--is-shallow-repository"]),
+        "true"
+    );
+    assert_eq!(fixture.git_in(&work_path, ["rev-list", "--count", "HEAD"]), "1");
+
+    // The first commit is not in the shallow history, so more of it is fetched
+    run_json(&fixture, &["--depth", "1", "--branch", &first_commit]);
+
+    assert_eq!(fixture.git_in(&work_path, ["rev-parse", "HEAD"]), first_commit);
+}
+
+#[test]
+fn run_creates_partial_clones() {
+    let fixture = Fixture::with_branches();
+
+    let work_path = work_path(&run_json(&fixture, &["--filter", "blob:none"]));
+
+    assert_eq!(
+        fixture.git_in(&work_path, ["config", "remote.fersk-origin.promisor"]),
+        "true"
+    );
+
+    // Blobs of other branches are only fetched when checked out
+    let missing = fixture.git_in(&work_path, ["rev-list", "--objects", "--all", "--missing=print"]);
+    assert!(missing.lines().any(|line| line.starts_with('?')));
+
+    run_json(&fixture, &["--branch", "feature"]);
+
+    assert_eq!(
+        std::fs::read_to_string(work_path.join("feature.txt")).unwrap(),
+        "feature\n"
+    );
+}
+
+#[test]
+fn run_checks_out_tags() {
+    let fixture = Fixture::with_branches();
+    fixture.git(["tag", "-a", "v1.0.0", "-m", "Release 1.0.0"]);
+    let tagged = fixture.git(["rev-parse", "HEAD"]);
+    fixture.commit_file("after.txt", "after\n", "After release");
+
+    let output = run_json(&fixture, &["--tag", "v1.0.0"]);
+    let work_path = work_path(&output);
+
+    assert_eq!(output["branch"], "refs/tags/v1.0.0");
+    assert_eq!(fixture.git_in(&work_path, ["rev-parse", "HEAD"]), tagged);
+    assert!(!work_path.join("after.txt").exists());
+
+    fixture
+        .fersk()
+        .args(["run", "--tag", "v1.0", "--", "true"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Did you mean"));
+}
+
+#[test]
+fn run_creates_worktree_work_directories() {
+    let fixture = Fixture::with_branches();
+
+    let work_path = work_path(&run_json(&fixture, &["--worktree"]));
+
+    // Objects are used from the source repository instead of being copied
+    let alternates = std::fs::read_to_string(work_path.join(".git/objects/info/alternates")).unwrap();
+    assert!(alternates.contains("source"));
+    assert_eq!(fixture.git_in(&work_path, ["count-objects"]), "0 objects, 0 kilobytes");
+
+    fixture.commit_file("new.txt", "new\n", "Add new file");
+    run_json(&fixture, &[]);
+
+    assert_eq!(std::fs::read_to_string(work_path.join("new.txt")).unwrap(), "new\n");
+
+    // It stays a worktree until it is purged, however many runs it is used by since
+    fixture
+        .fersk()
+        .args(["run", "--", "true"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Materialization changed from Worktree"));
+}
+
+#[cfg(unix)]
+#[test]
+fn run_clones_with_configured_clone_mode() {
+    use std::os::unix::fs::MetadataExt;
+
+    for (mode, links, shared) in [("hardlink", 2, false), ("full-copy", 1, false), ("shared", 0, true)] {
+        let fixture = Fixture::with_branches();
+        fixture.configure(&format!("clone-mode = \"{mode}\"\n"));
+
+        let work_path = work_path(&run_json(&fixture, &[]));
+
+        let commit = fixture.git(["rev-parse", "HEAD"]);
+        let object = work_path.join(".git/objects").join(&commit[..2]).join(&commit[2..]);
+
+        assert_eq!(std::fs::metadata(&object).map_or(0, |m| m.nlink()), links, "{mode}");
+        assert_eq!(
+            work_path.join(".git/objects/info/alternates").exists(),
+            shared,
+            "{mode}"
+        );
+    }
+}
+
+#[test]
+fn run_clones_with_reference_repository() {
+    let fixture = Fixture::with_branches();
+
+    let mirror = fixture.path().join("mirror.git");
+    fixture.git(["clone", "-q", "--bare", ".", mirror.to_str().unwrap()]);
+    fixture.commit_file("new.txt", "new\n", "Add new file");
+
+    let work_path = work_path(&run_json(&fixture, &["--reference", mirror.to_str().unwrap()]));
+
+    let alternates = std::fs::read_to_string(work_path.join(".git/objects/info/alternates")).unwrap();
+    assert!(alternates.contains("mirror.git"));
+    assert_eq!(std::fs::read_to_string(work_path.join("new.txt")).unwrap(), "new\n");
+
+    // Only the objects of the new commit (commit, tree and blob) were copied
+    let local = fixture.git_in(&work_path, ["count-objects", "-v"]);
+    assert!(
+        local.contains("count: 0\n") && local.contains("in-pack: 3\n"),
+        "{local}"
+    );
+}
+
+#[test]
+fn run_uploads_artifacts_as_archive() {
+    let fixture = Fixture::with_branches();
+    let destination = fixture.path().join("uploads");
+
+    fixture.configure(&format!(
+        "\n[upload]\ndestination = {:?}\nartifacts = [\"src\"]\narchive-artifacts = true\n\n[archive]\nformat = \"zip\"\n",
+        destination.to_str().unwrap()
+    ));
+
+    fixture
+        .fersk()
+        .args(["run", "--upload", "--", "true"])
+        .assert()
+        .success();
+
+    assert!(destination.join("report.json").is_file());
+    assert!(destination.join("artifacts.zip").is_file());
+}
+
+#[test]
+fn verify_tag_builds_only_signed_tags() {
+    let fixture = Fixture::with_branches();
+    let key = fixture.path().join("key");
+    let allowed_signers = fixture.path().join("allowed_signers");
+
+    let keygen = std::process::Command::new("ssh-keygen")
+        .args(["-q", "-t", "ed25519", "-N", "", "-f"])
+        .arg(&key)
+        .status();
+    if !keygen.is_ok_and(|status| status.success()) {
+        eprintln!("ssh-keygen is not available. Skipping.");
+        return;
+    }
+
+    let public_key = std::fs::read_to_string(key.with_extension("pub")).unwrap();
+    std::fs::write(&allowed_signers, format!("fixture@example.com {public_key}")).unwrap();
+
+    fixture.git([
+        "-c",
+        "gpg.format=ssh",
+        "-c",
+        &format!("user.signingkey={}", key.display()),
+        "tag",
+        "-s",
+        "v1.0.0",
+        "-m",
+        "Release 1.0.0",
+    ]);
+    fixture.git(["tag", "-a", "v1.0.1", "-m", "Release 1.0.1"]);
+
+    fixture.configure(&format!(
+        "\n[release]\ncommand = [\"sh\", \"-c\", \"echo built > built.txt\"]\nallowed-signers = {:?}\n",
+        allowed_signers.to_str().unwrap()
+    ));
+
+    let json = fixture.fersk_json(["verify-tag", "v1.0.0", "--json-out"]);
+    assert_eq!(json["signature"]["status"], "good");
+    assert_eq!(json["signature"]["signer"], "fixture@example.com");
+    assert_eq!(json["build"]["success"], true);
+    assert_eq!(json["commit"], fixture.git(["rev-parse", "main"]));
+
+    let output = fixture
+        .fersk()
+        .args(["verify-tag", "v1.0.1", "--json-out"])
+        .assert()
+        .failure()
+        .get_output()
+        .stdout
+        .clone();
+    let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(json["signature"]["status"], "unsigned");
+    assert_eq!(json["build"]["ran"], false);
+
+    // Signed by a key that isn't allowed
+    let other_key = fixture.path().join("other_key");
+    std::process::Command::new("ssh-keygen")
+        .args(["-q", "-t", "ed25519", "-N", "", "-f"])
+        .arg(&other_key)
+        .status()
+        .unwrap();
+    fixture.git([
+        "-c",
+        "gpg.format=ssh",
+        "-c",
+        &format!("user.signingkey={}", other_key.display()),
+        "tag",
+        "-s",
+        "v1.0.2",
+        "-m",
+        "Release 1.0.2",
+    ]);
+
+    // Without require-signature, unsigned tags are built, but tags with a bad signature still aren't
+    fixture.configure("require-signature = false\n");
+
+    let json = fixture.fersk_json(["verify-tag", "v1.0.1", "--json-out"]);
+    assert_eq!(json["signature"]["status"], "unsigned");
+    assert_eq!(json["build"]["ran"], true);
+
+    let output = fixture
+        .fersk()
+        .args(["verify-tag", "v1.0.2", "--json-out"])
+        .assert()
+        .failure()
+        .get_output()
+        .stdout
+        .clone();
+    let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(json["signature"]["status"], "bad");
+    assert_eq!(json["build"]["ran"], false);
+}
+
+#[test]
+fn run_checks_out_sparsely() {
+    let fixture = Fixture::with_branches();
+    fixture.commit_file("docs/guide.txt", "guide\n", "Add docs");
+
+    fixture
+        .fersk()
+        .args([
+            "run",
+            "--sparse",
+            "docs",
+            "--",
+            "sh",
+            "-c",
+            "test -f docs/guide.txt && test -f README.md && test ! -e src",
+        ])
+        .assert()
+        .success();
+
+    let work_path = work_path(&run_json(&fixture, &["--sparse", "docs"]));
+    assert_eq!(fixture.git_in(&work_path, ["config", "index.sparse"]), "true");
+
+    // The full working tree is restored without --sparse
+    fixture
+        .fersk()
+        .args(["run", "--", "sh", "-c", "test -f docs/guide.txt && test -f src/lib.txt"])
+        .assert()
+        .success();
+}
+
+#[test]
+fn run_checks_out_revision_expressions() {
+    let fixture = Fixture::with_branches();
+    let first = fixture.git(["rev-parse", "main~1"]);
+
+    let output = run_json(&fixture, &["--rev", "main~1"]);
+    assert_eq!(fixture.git_in(&work_path(&output), ["rev-parse", "HEAD"]), first);
+
+    fixture
+        .fersk()
+        .args(["run", "--rev", "main~5", "--", "true"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("main~5 is not a valid revision"));
+}
+
+#[test]
+fn run_logs_git_commands_when_very_verbose() {
+    let fixture = Fixture::with_branches();
+
+    fixture
+        .fersk()
+        .args(["-vv", "run", "--", "true"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Running git").and(predicate::str::contains("exited with code 0 after")));
+
+    fixture
+        .fersk()
+        .args(["run", "--", "true"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Running git").not());
+}
+
+#[cfg(feature = "libgit2")]
+#[test]
+fn run_prepares_work_directories_with_libgit2() {
+    let fixture = Fixture::with_branches();
+    fixture.configure("\ngit-backend = \"libgit2\"\n");
+
+    let work_path = work_path(&run_json(&fixture, &[]));
+    std::fs::write(work_path.join("leftover.txt"), "leftover\n").unwrap();
+    std::fs::write(work_path.join("README.md"), "modified\n").unwrap();
+
+    fixture.commit_file("main.txt", "main\n", "Add main");
+
+    let output = run_json(&fixture, &["--branch", "feature"]);
+
+    assert_eq!(output["branch"], "fersk-origin/feature");
+    assert!(work_path.join("feature.txt").exists());
+    assert!(!work_path.join("leftover.txt").exists());
+    assert_eq!(
+        std::fs::read_to_string(work_path.join("README.md")).unwrap(),
+        "readme\n"
+    );
+
+    run_json(&fixture, &[]);
+    assert!(work_path.join("main.txt").exists());
+
+    // Sparse checkouts fall back to the git command line, as libgit2 would check out the whole tree
+    std::fs::write(work_path.join("leftover.txt"), "leftover\n").unwrap();
+    run_json(&fixture, &["--sparse", "src", "--branch", "feature"]);
+    assert!(work_path.join("src/lib.txt").exists());
+    assert!(work_path.join("feature.txt").exists());
+    assert!(!work_path.join("main.txt").exists());
+    assert!(!work_path.join("leftover.txt").exists());
+
+    fixture.commit_file("docs/guide.txt", "guide\n", "Add docs");
+    run_json(&fixture, &["--sparse", "src"]);
+    assert!(work_path.join("main.txt").exists());
+    assert!(!work_path.join("docs").exists());
+}
+
+#[cfg(not(feature = "libgit2"))]
+#[test]
+fn run_requires_libgit2_feature_for_libgit2_backend() {
+    let fixture = Fixture::with_branches();
+    fixture.configure("\ngit-backend = \"libgit2\"\n");
+
+    fixture
+        .fersk()
+        .args(["run", "--", "true"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("built without the `libgit2` feature"));
+}
+
+#[test]
+fn attestation_digests_environment_given_to_command() {
+    let fixture = Fixture::with_branches();
+    let attestation = fixture.path().join("attestation.json");
+    let env_file = fixture.path().join("extra.env");
+    std::fs::write(&env_file, "EXTRA=1\n").unwrap();
+
+    let digest = |args: &[&str], parent_var: &str| {
+        fixture
+            .fersk()
+            .env("UNRELATED", parent_var)
+            .args(["run", "--seed", "1", "--attest", attestation.to_str().unwrap()])
+            .args(args)
+            .args(["--", "true"])
+            .assert()
+            .success();
+
+        let statement: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&attestation).unwrap()).unwrap();
+        statement["predicate"]["buildDefinition"]["internalParameters"]["environmentDigest"]["sha256"]
+            .as_str()
+            .unwrap()
+            .to_owned()
+    };
+
+    let first = digest(&[], "a");
+    assert_eq!(digest(&[], "b"), first);
+    assert_ne!(digest(&["--env-file", env_file.to_str().unwrap()], "a"), first);
+}
+
+#[test]
+fn run_redacts_secrets_from_output_and_log() {
+    let fixture = Fixture::with_branches();
+    fixture.configure("\n[secrets]\nenv = [\"FERSK_TEST_SECRET\"]\n");
+
+    let script = r#"echo "token $FERSK_TEST_SECRET"; printf %s "$FERSK_TEST_SECRET" >&2"#;
+
+    // Redacted whether or not the output is captured
+    for capture in [false, true] {
+        let mut cmd = fixture.fersk();
+        cmd.env("FERSK_TEST_SECRET", "hunter22").arg("run");
+        if capture {
+            cmd.arg("--capture-log");
+        }
+
+        cmd.args(["--", "sh", "-c", script])
+            .assert()
+            .success()
+            .stdout("token [REDACTED]\n")
+            .stderr(predicate::str::contains("[REDACTED]").and(predicate::str::contains("hunter22").not()));
+    }
+
+    let inspect = fixture.fersk_json(["inspect", "--json-out"]);
+    let log_tail = inspect["log_tail"].to_string();
+    assert!(log_tail.contains("token [REDACTED]"));
+    assert!(!log_tail.contains("hunter22"));
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn run_kills_daemons_left_by_command() {
+    let fixture = Fixture::with_branches();
+    let pid_path = fixture.path().join("daemon.pid");
+
+    // The daemon is orphaned when the shell exits
+    fixture
+        .fersk()
+        .args(["run", "--kill-descendants", "--", "sh", "-c"])
+        .arg(format!("sleep 30 & echo $! > {}", pid_path.display()))
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(
+            "Killed 1 process(es) left running by the command",
+        ));
+
+    let pid = std::fs::read_to_string(&pid_path).unwrap();
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid.trim())).unwrap_or_default();
+
+    // Gone, or a zombie waiting to be reaped by init
+    assert!(
+        stat.is_empty() || stat.contains(") Z "),
+        "daemon is still running: {stat}"
+    );
+}
+
+#[test]
+fn diff_output_reports_regressions() {
+    let fixture = Fixture::with_branches();
+
+    let run = |script: &str| -> String {
+        fixture
+            .fersk()
+            .args(["run", "--capture-log", "--", "sh", "-c", script])
+            .assert()
+            .success();
+
+        let inspect = fixture.fersk_json(["inspect", "--json-out"]);
+        inspect["metadata"]["run_id"].as_str().unwrap().to_owned()
+    };
+
+    let old = run("echo 'test result: ok. 3 passed; 0 failed; 0 ignored'");
+    let new = run("echo 'warning: unused variable'; echo 'test result: FAILED. 2 passed; 1 failed; 0 ignored'");
+
+    fixture
+        .fersk()
+        .args(["diff-output", "--lines", "--fail-on-regression", &old, &new])
+        .assert()
+        .failure()
+        .stdout(
+            predicate::str::contains("Tests passed: 3 -> 2 (-1)  REGRESSION\n")
+                .and(predicate::str::contains("Tests failed: 0 -> 1 (+1)  REGRESSION\n"))
+                .and(predicate::str::contains("Warnings: 0 -> 1 (+1)  REGRESSION\n"))
+                .and(predicate::str::contains(
+                    "- test result: ok. 3 passed; 0 failed; 0 ignored\n",
+                ))
+                .and(predicate::str::contains("+ warning: unused variable\n")),
+        )
+        .stderr(predicate::str::contains("3 regression(s) found."));
+
+    // Compared the other way around, nothing regressed
+    fixture
+        .fersk()
+        .args(["diff-output", "--fail-on-regression", &new, &old])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("No regressions.\n"));
+}
+
+#[test]
+fn run_passes_mount_locations_to_command() {
+    let fixture = Fixture::with_branches();
+    let data = fixture.path().join("data");
+    std::fs::create_dir(&data).unwrap();
+
+    fixture
+        .fersk()
+        .args(["run", "--mount"])
+        .arg(format!("{}:shared-data", data.display()))
+        .args(["--", "sh", "-c", "echo $FERSK_MOUNT_SHARED_DATA"])
+        .assert()
+        .success()
+        .stdout(format!("{}\n", data.display()))
+        .stderr(predicate::str::contains("only read-only with --sandbox"));
+}
@@ -0,0 +1,177 @@
+mod common;
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use predicates::prelude::*;
+use sha2::{Digest, Sha256};
+
+use common::Fixture;
+
+/// Publish a release of a fake fersk executable printing its version, returning the feed path.
+/// The archive is signed if a signing key is given.
+fn publish_release(fixture: &Fixture, version: &str, checksum: Option<&str>, signing_key: Option<&Path>) -> PathBuf {
+    let dir = fixture.path().join("releases");
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let arch = match std::env::consts::ARCH {
+        "x86" => "i686",
+        arch => arch,
+    };
+    let archive_name = format!("fersk-{version}-{}-{arch}.zip", std::env::consts::OS);
+    let archive_path = dir.join(&archive_name);
+
+    let mut zip = zip::ZipWriter::new(std::fs::File::create(&archive_path).unwrap());
+    zip.start_file("fersk", Default::default()).unwrap();
+    zip.write_all(format!("#!/bin/sh\necho fersk {version}\n").as_bytes())
+        .unwrap();
+    zip.finish().unwrap();
+
+    let hash = hex::encode(Sha256::digest(std::fs::read(&archive_path).unwrap()));
+    let checksum_path = dir.join(format!("{archive_name}.sha256.txt"));
+    std::fs::write(&checksum_path, checksum.unwrap_or(&hash.to_uppercase())).unwrap();
+
+    let mut assets = vec![
+        serde_json::json!({ "name": archive_name, "browser_download_url": archive_path }),
+        serde_json::json!({ "name": format!("{archive_name}.sha256.txt"), "browser_download_url": checksum_path }),
+    ];
+
+    if let Some(signing_key) = signing_key {
+        let status = Command::new("ssh-keygen")
+            .args(["-q", "-Y", "sign", "-n", "fersk-release", "-f"])
+            .arg(signing_key)
+            .arg(&archive_path)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let signature_path = dir.join(format!("{archive_name}.sig"));
+        assets
+            .push(serde_json::json!({ "name": format!("{archive_name}.sig"), "browser_download_url": signature_path }));
+    }
+
+    let feed = serde_json::json!({ "tag_name": format!("v{version}"), "assets": assets });
+
+    let feed_path = dir.join("latest.json");
+    std::fs::write(&feed_path, feed.to_string()).unwrap();
+
+    feed_path
+}
+
+/// Copy the fersk executable, so updating it doesn't replace the one being tested
+fn copy_fersk(fixture: &Fixture) -> PathBuf {
+    let exe = fixture.path().join("bin/fersk");
+    std::fs::create_dir_all(exe.parent().unwrap()).unwrap();
+    std::fs::copy(env!("CARGO_BIN_EXE_fersk"), &exe).unwrap();
+
+    exe
+}
+
+fn fersk_at(fixture: &Fixture, exe: &Path) -> assert_cmd::Command {
+    let mut cmd = Command::new(exe);
+    fixture.env(&mut cmd).current_dir(&fixture.source);
+
+    cmd.into()
+}
+
+#[cfg(unix)]
+#[test]
+fn self_update_replaces_executable_with_verified_release() {
+    let fixture = Fixture::new();
+    let exe = copy_fersk(&fixture);
+
+    let feed = publish_release(&fixture, "99.0.0", None, None);
+    fixture.configure(&format!(
+        "\n[self-update]\nfeed = {:?}\nrequire-signature = false\n",
+        feed.to_str().unwrap()
+    ));
+
+    fersk_at(&fixture, &exe)
+        .args(["self-update", "--check"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Update available").and(predicate::str::contains("-> 99.0.0")));
+
+    fersk_at(&fixture, &exe).arg("self-update").assert().success();
+
+    fersk_at(&fixture, &exe).assert().success().stdout("fersk 99.0.0\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn self_update_refuses_release_with_wrong_checksum() {
+    let fixture = Fixture::new();
+    let exe = copy_fersk(&fixture);
+
+    let feed = publish_release(&fixture, "99.0.0", Some(&"0".repeat(64)), None);
+    fixture.configure(&format!(
+        "\n[self-update]\nfeed = {:?}\nrequire-signature = false\n",
+        feed.to_str().unwrap()
+    ));
+
+    fersk_at(&fixture, &exe)
+        .arg("self-update")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Checksum mismatch"));
+
+    fersk_at(&fixture, &exe).arg("--version").assert().success();
+
+    // Releases that aren't newer are left alone
+    publish_release(&fixture, "0.0.1", None, None);
+
+    fersk_at(&fixture, &exe)
+        .arg("self-update")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("fersk is up to date"));
+}
+
+#[cfg(unix)]
+#[test]
+fn self_update_requires_signature_by_allowed_signer() {
+    let fixture = Fixture::new();
+    let exe = copy_fersk(&fixture);
+
+    let key = fixture.path().join("release_key");
+    let keygen = Command::new("ssh-keygen")
+        .args(["-q", "-t", "ed25519", "-N", "", "-f"])
+        .arg(&key)
+        .status();
+
+    if !keygen.is_ok_and(|status| status.success()) {
+        eprintln!("ssh-keygen is not available. Skipping.");
+        return;
+    }
+
+    let feed = publish_release(&fixture, "99.0.0", None, None);
+    fixture.configure(&format!("\n[self-update]\nfeed = {:?}\n", feed.to_str().unwrap()));
+
+    fersk_at(&fixture, &exe)
+        .arg("self-update")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("No allowed-signers configured"));
+
+    let allowed_signers = fixture.path().join("release_signers");
+    let public_key = std::fs::read_to_string(key.with_extension("pub")).unwrap();
+    std::fs::write(&allowed_signers, format!("releases@example.com {public_key}")).unwrap();
+    fixture.configure(&format!("allowed-signers = {:?}\n", allowed_signers.to_str().unwrap()));
+
+    fersk_at(&fixture, &exe)
+        .arg("self-update")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("has no signature"));
+
+    publish_release(&fixture, "99.0.0", None, Some(&key));
+
+    fersk_at(&fixture, &exe)
+        .arg("self-update")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Good signature by releases@example.com"));
+
+    fersk_at(&fixture, &exe).assert().success().stdout("fersk 99.0.0\n");
+}
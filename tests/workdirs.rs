@@ -0,0 +1,504 @@
+mod common;
+
+use predicates::prelude::*;
+
+use common::Fixture;
+
+/// Run a command in the work directory of the fixture's source repository, returning the work directory path
+fn run(fixture: &Fixture) -> String {
+    let output = fixture.fersk_json(["run", "--json-out", "--", "true"]);
+
+    output["working_repository_path"].as_str().unwrap().to_owned()
+}
+
+#[test]
+fn list_shows_work_directories() {
+    let fixture = Fixture::with_branches();
+
+    let list = fixture.fersk_json(["list", "--json-out"]);
+    assert_eq!(list.as_array().unwrap().len(), 0);
+
+    let work_path = run(&fixture);
+
+    let list = fixture.fersk_json(["list", "--json-out"]);
+    let entries = list.as_array().unwrap();
+
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0]["working_repository_path"], work_path.as_str());
+    assert_eq!(
+        entries[0]["source_repository_path"],
+        fixture.source.canonicalize().unwrap().to_str().unwrap()
+    );
+    assert_eq!(entries[0]["running"], false);
+}
+
+#[test]
+fn inspect_reports_idle_work_directory() {
+    let fixture = Fixture::with_branches();
+    let work_path = run(&fixture);
+
+    let output = fixture.fersk_json(["inspect", "--json-out"]);
+
+    assert_eq!(output["schema_version"], 1);
+    assert_eq!(output["metadata"]["working_repository_path"], work_path.as_str());
+    assert_eq!(output["running"], false);
+}
+
+#[test]
+fn inspect_reports_work_directory_at_custom_path() {
+    let fixture = Fixture::with_branches();
+    let into = fixture.path().join("deploy");
+    let into = into.to_str().unwrap();
+
+    fixture
+        .fersk()
+        .args(["run", "--into", into, "--", "true"])
+        .assert()
+        .success();
+
+    let output = fixture.fersk_json(["inspect", "--into", into, "--json-out"]);
+    assert_eq!(output["metadata"]["working_repository_path"], into);
+
+    fixture
+        .fersk()
+        .args(["inspect", "--into", into])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(format!("Working directory: {into}\n")));
+
+    // The work directory in the work root has no runs
+    let output = fixture.fersk_json(["inspect", "--json-out"]);
+    assert_eq!(output["metadata"], serde_json::Value::Null);
+}
+
+#[test]
+fn inspect_reports_preparing_run_without_state_of_previous_run() {
+    let fixture = Fixture::with_branches();
+    run(&fixture);
+
+    // Inspected by the preflight check, while the run is being prepared
+    let inspected = fixture.path().join("inspected.json");
+    let inspect = format!(
+        "{:?} inspect --path {:?} --json-out > {:?}",
+        env!("CARGO_BIN_EXE_fersk"),
+        fixture.source,
+        inspected
+    );
+    fixture.commit_file(
+        ".fersk.toml",
+        &format!("[preflight]\ncommand = [\"sh\", \"-c\", {inspect:?}]\n"),
+        "Add preflight check",
+    );
+
+    run(&fixture);
+
+    let output: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(inspected).unwrap()).unwrap();
+    assert_eq!(output["running"], true);
+    assert_eq!(output["metadata"]["command_pid"], serde_json::Value::Null);
+    assert_eq!(output["metadata"]["success"], serde_json::Value::Null);
+    assert_eq!(output["metadata"]["finished_at"], serde_json::Value::Null);
+}
+
+#[test]
+fn fsck_finds_no_problems_after_run() {
+    let fixture = Fixture::with_branches();
+    run(&fixture);
+
+    let reports = fixture.fersk_json(["fsck", "--json-out"]);
+
+    for report in reports.as_array().unwrap() {
+        assert_eq!(report["problems"].as_array().unwrap().len(), 0, "{report}");
+    }
+}
+
+#[test]
+fn purge_deletes_work_directory() {
+    let fixture = Fixture::with_branches();
+    let work_path = run(&fixture);
+
+    fixture
+        .fersk()
+        .arg("purge")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Purged"));
+
+    assert!(!std::path::Path::new(&work_path).exists());
+
+    let list = fixture.fersk_json(["list", "--json-out"]);
+    assert_eq!(list.as_array().unwrap().len(), 0);
+}
+
+#[test]
+fn schema_describes_run_output() {
+    let fixture = Fixture::with_branches();
+
+    fixture
+        .fersk()
+        .args(["schema", "run"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("working_repository_path"));
+}
+
+#[test]
+fn first_run_offers_to_write_default_config() {
+    let fixture = Fixture::with_branches();
+    let config_path = fixture.path().join("config/fersk/config.toml");
+    std::fs::remove_file(&config_path).unwrap();
+
+    fixture
+        .fersk()
+        .args(["list"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("No config file found").and(predicate::str::contains("--accept-defaults")));
+
+    assert!(!config_path.exists());
+
+    // The notice is only shown once
+    fixture
+        .fersk()
+        .args(["list"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("No config file found").not());
+
+    fixture
+        .fersk()
+        .args(["list", "--accept-defaults"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Wrote default config"));
+
+    assert!(config_path.exists());
+
+    fixture
+        .fersk()
+        .args(["list"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("No config file found").not());
+}
+
+#[cfg(unix)]
+#[test]
+fn config_paths_are_expanded_and_platform_sections_applied() {
+    let fixture = Fixture::with_branches();
+    fixture.configure("\n[target.unix]\nwork-path = \"~/unix-work\"\n");
+
+    let work_path = run(&fixture);
+
+    assert!(std::path::Path::new(&work_path).starts_with(fixture.path().join("home/unix-work")));
+}
+
+#[cfg(unix)]
+#[test]
+fn runs_as_git_and_cargo_subcommand() {
+    let fixture = Fixture::with_branches();
+
+    let bin = fixture.path().join("bin");
+    std::fs::create_dir_all(&bin).unwrap();
+
+    for name in ["git-fersk", "cargo-fersk"] {
+        std::os::unix::fs::symlink(env!("CARGO_BIN_EXE_fersk"), bin.join(name)).unwrap();
+    }
+
+    let path = std::env::join_paths(
+        std::iter::once(bin.clone()).chain(std::env::split_paths(&std::env::var_os("PATH").unwrap_or_default())),
+    )
+    .unwrap();
+
+    // Git runs fersk in the directory given with -C
+    let mut git = std::process::Command::new("git");
+    fixture.env(&mut git).env("PATH", &path).current_dir(fixture.path());
+
+    assert_cmd::Command::from_std(git)
+        .arg("-C")
+        .arg(&fixture.source)
+        .args(["fersk", "run", "--", "true"])
+        .assert()
+        .success();
+
+    // Cargo passes the subcommand name as the first argument
+    let mut cargo_fersk = std::process::Command::new(bin.join("cargo-fersk"));
+    fixture.env(&mut cargo_fersk).current_dir(fixture.path());
+
+    assert_cmd::Command::from_std(cargo_fersk)
+        .arg("fersk")
+        .arg("-C")
+        .arg(&fixture.source)
+        .args(["run", "--", "true"])
+        .assert()
+        .success();
+
+    let mut cargo_fersk = std::process::Command::new(bin.join("cargo-fersk"));
+    fixture.env(&mut cargo_fersk);
+
+    assert_cmd::Command::from_std(cargo_fersk)
+        .args(["fersk", "--help"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Usage: cargo fersk"));
+}
+
+#[test]
+fn maintain_cleans_up_work_root() {
+    let fixture = Fixture::with_branches();
+    let work_path = run(&fixture);
+    let work_root = fixture.work_root();
+
+    // Left behind by a process that no longer exists, and by a work directory that was deleted by hand
+    std::fs::write(work_root.join(".locks/stale.pid"), "4194303").unwrap();
+    let orphan_id = "0".repeat(64);
+    std::fs::write(work_root.join(format!(".meta/{orphan_id}.json")), "{}").unwrap();
+
+    // Work directories at custom paths are not in the work root, but still exist
+    let into = fixture.path().join("into");
+    fixture
+        .fersk()
+        .args(["run", "--into"])
+        .arg(&into)
+        .args(["--", "true"])
+        .assert()
+        .success();
+
+    fixture.fersk().arg("maintain").assert().success().stdout(
+        predicate::str::contains("Garbage collected: 1 repositories")
+            .and(predicate::str::contains("Compacted metadata: 1 entries")),
+    );
+
+    assert!(std::path::Path::new(&work_path).exists());
+    assert!(!work_root.join(".locks/stale.pid").exists());
+    assert!(!work_root.join(format!(".meta/{orphan_id}.json")).exists());
+    let into_metadata = std::fs::read_dir(work_root.join(".meta"))
+        .unwrap()
+        .map(|entry| std::fs::read_to_string(entry.unwrap().path()).unwrap())
+        .filter(|content| content.contains(into.to_str().unwrap()))
+        .count();
+    assert_eq!(into_metadata, 1);
+
+    fixture.configure("\n[maintenance]\nevict-after = 0\n");
+
+    fixture
+        .fersk()
+        .arg("maintain")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Evicted work directories: 1"));
+
+    assert!(!std::path::Path::new(&work_path).exists());
+}
+
+#[test]
+fn maintain_leaves_locked_state_alone() {
+    let fixture = Fixture::with_branches();
+    run(&fixture);
+    let work_root = fixture.work_root();
+
+    // Metadata of a work directory that is being created by a process that is still running
+    let id = "1".repeat(64);
+    std::fs::write(work_root.join(format!(".meta/{id}.json")), "{}").unwrap();
+    std::fs::write(
+        work_root.join(format!(".locks/{id}.pid")),
+        std::process::id().to_string(),
+    )
+    .unwrap();
+
+    fixture
+        .fersk()
+        .arg("maintain")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Compacted metadata: 0 entries"));
+
+    assert!(work_root.join(format!(".meta/{id}.json")).exists());
+    assert!(work_root.join(format!(".locks/{id}.pid")).exists());
+}
+
+#[test]
+fn maintain_stops_at_max_io() {
+    let fixture = Fixture::with_branches();
+    let work_path = run(&fixture);
+    fixture.configure("\n[maintenance]\nmax-io = 0\n");
+
+    fixture.fersk().arg("maintain").assert().success().stdout(
+        predicate::str::contains("Garbage collected: 0 repositories")
+            .and(predicate::str::contains("Not garbage collected, max-io reached: 1")),
+    );
+
+    assert!(std::path::Path::new(&work_path).exists());
+}
+
+#[test]
+fn maintain_stops_at_max_runtime() {
+    let fixture = Fixture::with_branches();
+    let work_path = run(&fixture);
+    fixture.configure("\n[maintenance]\nmax-runtime = 0\nevict-after = 0\n");
+
+    fixture.fersk().arg("maintain").assert().success().stdout(
+        predicate::str::contains("Evicted work directories: 0")
+            .and(predicate::str::contains("Not maintained, max-runtime reached: 1")),
+    );
+
+    assert!(std::path::Path::new(&work_path).exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn shared_work_root_keeps_user_directories_private() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let fixture = Fixture::with_branches();
+    fixture.configure("shared-work-root = true\n");
+
+    // Nobody has used the shared work root yet
+    let users = fixture.fersk_json(["list", "--all-users", "--json-out"]);
+    assert_eq!(users.as_array().unwrap().len(), 0);
+
+    let work_path = run(&fixture);
+
+    let users = fixture.fersk_json(["list", "--all-users", "--json-out"]);
+    assert_eq!(users.as_array().unwrap().len(), 1);
+
+    // A user directory others can get into may have been created by someone else
+    let user_dir = std::path::Path::new(&work_path).parent().unwrap().to_owned();
+    std::fs::set_permissions(&user_dir, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    fixture
+        .fersk()
+        .args(["run", "--", "true"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "not a directory owned by the current user with mode 0700",
+        ));
+
+    // Neither is it used by commands operating on all work directories
+    for args in [&["fetch"][..], &["maintain"], &["prune-branches"], &["purge", "--all"]] {
+        fixture
+            .fersk()
+            .args(args)
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains(
+                "not a directory owned by the current user with mode 0700",
+            ));
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn move_work_root_holds_work_directory_locks() {
+    let fixture = Fixture::with_branches();
+    run(&fixture);
+
+    let started = fixture.path().join("started");
+    let release = fixture.path().join("release");
+
+    let hold = format!(
+        "touch {}; while [ ! -e {} ]; do sleep 0.1; done",
+        started.display(),
+        release.display()
+    );
+    let mut holder = fixture
+        .fersk_process()
+        .args(["run", "--", "sh", "-c", &hold])
+        .spawn()
+        .unwrap();
+
+    while !started.exists() {
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+
+    let new_root = fixture.path().join("moved");
+
+    let refused = fixture.fersk().arg("move-workroot").arg(&new_root).assert();
+
+    std::fs::write(&release, "").unwrap();
+    holder.wait().unwrap();
+
+    refused
+        .failure()
+        .stderr(predicate::str::contains("Work directory is in use by process"));
+
+    fixture.fersk().arg("move-workroot").arg(&new_root).assert().success();
+
+    assert!(!fixture.work_root().exists());
+
+    // The locks held during the move are released at the new location
+    let locks: Vec<_> = std::fs::read_dir(new_root.join(".locks"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .filter(|name| name.ends_with(".pid"))
+        .collect();
+    assert_eq!(locks, Vec::<String>::new());
+
+    let work_path = run(&fixture);
+    assert!(work_path.starts_with(new_root.to_str().unwrap()));
+}
+
+#[cfg(unix)]
+#[test]
+fn move_work_root_holds_locks_of_work_directories_at_custom_paths() {
+    let fixture = Fixture::with_branches();
+    let into = fixture.path().join("deploy");
+
+    let started = fixture.path().join("started");
+    let release = fixture.path().join("release");
+
+    let hold = format!(
+        "touch {}; while [ ! -e {} ]; do sleep 0.1; done",
+        started.display(),
+        release.display()
+    );
+    let mut holder = fixture
+        .fersk_process()
+        .args(["run", "--into", into.to_str().unwrap(), "--", "sh", "-c", &hold])
+        .spawn()
+        .unwrap();
+
+    while !started.exists() {
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+
+    let new_root = fixture.path().join("moved");
+
+    let refused = fixture.fersk().arg("move-workroot").arg(&new_root).assert();
+
+    std::fs::write(&release, "").unwrap();
+    holder.wait().unwrap();
+
+    refused.failure().stderr(predicate::str::contains(format!(
+        "Work directory is in use by process {}: {}",
+        holder.id(),
+        into.display()
+    )));
+
+    fixture.fersk().arg("move-workroot").arg(&new_root).assert().success();
+
+    // The work directory stays where it is, with its state in the new work root
+    let output = fixture.fersk_json(["inspect", "--into", into.to_str().unwrap(), "--json-out"]);
+    assert_eq!(output["metadata"]["working_repository_path"], into.to_str().unwrap());
+}
+
+#[test]
+fn fetch_updates_work_directories() {
+    let fixture = Fixture::with_branches();
+    let work_path = run(&fixture);
+
+    fixture.commit_file("new.txt", "new\n", "Add new file");
+
+    fixture
+        .fersk()
+        .arg("fetch")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Fetched 1 of 1 remote(s)"));
+
+    let work_path = std::path::Path::new(&work_path);
+    assert_eq!(
+        fixture.git_in(work_path, ["rev-parse", "fersk-origin/main"]),
+        fixture.git(["rev-parse", "main"])
+    );
+}